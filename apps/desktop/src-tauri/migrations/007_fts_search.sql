@@ -0,0 +1,74 @@
+-- Migration 007: Full-text search for memories and lorebook entries
+-- Adds FTS5 indexes used by hybrid (BM25 + vector) retrieval
+
+-- ============================================
+-- Memory Entries FTS
+-- Self-contained FTS table keyed by memory id (implicit rowids are not
+-- stable across VACUUM, so we don't use external content here)
+-- ============================================
+CREATE VIRTUAL TABLE IF NOT EXISTS memory_entries_fts USING fts5(
+    content,
+    memory_id UNINDEXED,
+    character_id UNINDEXED,
+    tokenize = 'porter unicode61'
+);
+
+CREATE TRIGGER IF NOT EXISTS memory_entries_fts_insert
+AFTER INSERT ON memory_entries
+BEGIN
+    INSERT INTO memory_entries_fts (content, memory_id, character_id)
+    VALUES (new.content, new.id, new.character_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS memory_entries_fts_update
+AFTER UPDATE OF content, character_id ON memory_entries
+BEGIN
+    DELETE FROM memory_entries_fts WHERE memory_id = old.id;
+    INSERT INTO memory_entries_fts (content, memory_id, character_id)
+    VALUES (new.content, new.id, new.character_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS memory_entries_fts_delete
+AFTER DELETE ON memory_entries
+BEGIN
+    DELETE FROM memory_entries_fts WHERE memory_id = old.id;
+END;
+
+INSERT INTO memory_entries_fts (content, memory_id, character_id)
+SELECT content, id, character_id FROM memory_entries;
+
+-- ============================================
+-- Lorebook Entries FTS
+-- ============================================
+CREATE VIRTUAL TABLE IF NOT EXISTS lorebook_entries_fts USING fts5(
+    name,
+    keywords,
+    content,
+    entry_id UNINDEXED,
+    lorebook_id UNINDEXED,
+    tokenize = 'porter unicode61'
+);
+
+CREATE TRIGGER IF NOT EXISTS lorebook_entries_fts_insert
+AFTER INSERT ON lorebook_entries
+BEGIN
+    INSERT INTO lorebook_entries_fts (name, keywords, content, entry_id, lorebook_id)
+    VALUES (new.name, new.keywords, new.content, new.id, new.lorebook_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS lorebook_entries_fts_update
+AFTER UPDATE OF name, keywords, content ON lorebook_entries
+BEGIN
+    DELETE FROM lorebook_entries_fts WHERE entry_id = old.id;
+    INSERT INTO lorebook_entries_fts (name, keywords, content, entry_id, lorebook_id)
+    VALUES (new.name, new.keywords, new.content, new.id, new.lorebook_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS lorebook_entries_fts_delete
+AFTER DELETE ON lorebook_entries
+BEGIN
+    DELETE FROM lorebook_entries_fts WHERE entry_id = old.id;
+END;
+
+INSERT INTO lorebook_entries_fts (name, keywords, content, entry_id, lorebook_id)
+SELECT name, keywords, content, id, lorebook_id FROM lorebook_entries;
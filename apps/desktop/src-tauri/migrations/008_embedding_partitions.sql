@@ -0,0 +1,21 @@
+-- Migration 008: Embedding partitions and quantization
+-- Lets the in-memory vector index load one character/lorebook at a time
+
+ALTER TABLE embeddings ADD COLUMN partition_key TEXT NOT NULL DEFAULT '';
+ALTER TABLE embeddings ADD COLUMN encoding TEXT NOT NULL DEFAULT 'f32'
+    CHECK (encoding IN ('f32', 'f16', 'int8'));
+
+-- Memories are partitioned by character
+UPDATE embeddings
+SET partition_key = COALESCE(
+    (SELECT character_id FROM memory_entries WHERE memory_entries.id = embeddings.entity_id), '')
+WHERE entity_type = 'memory';
+
+-- Lorebook entries are partitioned by lorebook
+UPDATE embeddings
+SET partition_key = COALESCE(
+    (SELECT lorebook_id FROM lorebook_entries WHERE lorebook_entries.id = embeddings.entity_id), '')
+WHERE entity_type = 'lorebook';
+
+CREATE INDEX IF NOT EXISTS idx_embeddings_partition
+ON embeddings(entity_type, partition_key);
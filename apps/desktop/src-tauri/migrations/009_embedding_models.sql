@@ -0,0 +1,10 @@
+-- Migration 009: Record which model produced each embedding
+-- Vectors from different models (or with different dimensions) are not
+-- comparable; rows whose model_id differs from the active embedding model
+-- are excluded from search and re-embedded in the background.
+-- Existing rows have unknown provenance and start out empty (stale).
+
+ALTER TABLE embeddings ADD COLUMN model_id TEXT NOT NULL DEFAULT '';
+
+CREATE INDEX IF NOT EXISTS idx_embeddings_model
+ON embeddings(model_id);
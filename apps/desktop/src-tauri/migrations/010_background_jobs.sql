@@ -0,0 +1,26 @@
+-- Migration 010: Durable background jobs
+-- Memory extraction and summarization used to be fire-and-forget tasks;
+-- persisting them lets them survive restarts and retry with backoff
+
+CREATE TABLE IF NOT EXISTS background_jobs (
+    id TEXT PRIMARY KEY NOT NULL,
+    job_type TEXT NOT NULL,
+    conversation_id TEXT REFERENCES conversations(id) ON DELETE CASCADE,
+    character_id TEXT REFERENCES characters(id) ON DELETE CASCADE,
+    payload TEXT NOT NULL DEFAULT '{}',
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'processing', 'completed', 'failed')),
+    priority INTEGER NOT NULL DEFAULT 0,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 5,
+    next_run_at INTEGER NOT NULL,
+    last_error TEXT,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_background_jobs_ready
+    ON background_jobs(status, priority DESC, next_run_at) WHERE status = 'pending';
+
+CREATE INDEX IF NOT EXISTS idx_background_jobs_conversation
+    ON background_jobs(conversation_id, job_type, status);
@@ -0,0 +1,12 @@
+-- Migration 011: Hierarchical, editable conversation summaries
+-- Old summaries roll up into chapter summaries (level 1+); children keep
+-- a link to their chapter so the context builder can trade detail for space
+
+ALTER TABLE conversation_summaries ADD COLUMN level INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE conversation_summaries ADD COLUMN parent_id TEXT REFERENCES conversation_summaries(id) ON DELETE SET NULL;
+ALTER TABLE conversation_summaries ADD COLUMN is_edited INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE conversation_summaries ADD COLUMN updated_at INTEGER;
+
+UPDATE conversation_summaries SET updated_at = created_at;
+
+CREATE INDEX IF NOT EXISTS idx_summaries_parent ON conversation_summaries(parent_id);
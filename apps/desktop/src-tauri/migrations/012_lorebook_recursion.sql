@@ -0,0 +1,6 @@
+-- Migration 012: Recursive lorebook activation flags
+-- exclude_recursion: entry only activates from chat text
+-- prevent_recursion: entry content is never scanned for further activations
+
+ALTER TABLE lorebook_entries ADD COLUMN exclude_recursion INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE lorebook_entries ADD COLUMN prevent_recursion INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,10 @@
+-- Migration 013: Advanced lorebook keys
+-- Secondary keywords with selective logic, and per-lorebook / per-entry
+-- scan depth (NULL keeps the previous 10 message window)
+
+ALTER TABLE lorebook_entries ADD COLUMN secondary_keywords TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE lorebook_entries ADD COLUMN selective_logic TEXT NOT NULL DEFAULT 'and_any'
+    CHECK (selective_logic IN ('and_any', 'and_all', 'not_any', 'not_all'));
+ALTER TABLE lorebook_entries ADD COLUMN scan_depth INTEGER;
+
+ALTER TABLE lorebooks ADD COLUMN scan_depth INTEGER;
@@ -0,0 +1,11 @@
+-- Migration 014: Constant entries, activation probability and inclusion groups
+-- Only one entry per inclusion group is inserted: the highest priority one
+-- when any member sets group_prioritize, otherwise a weighted random pick
+
+ALTER TABLE lorebook_entries ADD COLUMN constant INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE lorebook_entries ADD COLUMN probability INTEGER NOT NULL DEFAULT 100
+    CHECK (probability BETWEEN 0 AND 100);
+ALTER TABLE lorebook_entries ADD COLUMN inclusion_group TEXT;
+ALTER TABLE lorebook_entries ADD COLUMN group_weight INTEGER NOT NULL DEFAULT 100
+    CHECK (group_weight >= 0);
+ALTER TABLE lorebook_entries ADD COLUMN group_prioritize INTEGER NOT NULL DEFAULT 0;
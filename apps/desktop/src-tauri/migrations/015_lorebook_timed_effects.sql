@@ -0,0 +1,22 @@
+-- Migration 015: Timed lorebook effects
+-- sticky: stays active for N messages after triggering
+-- cooldown: cannot trigger again for N messages after that
+-- delay: cannot trigger until the conversation has N messages
+--
+-- Triggers are anchored to the newest message at the time, so timers are
+-- measured along the active branch and switching branches rolls them back
+
+ALTER TABLE lorebook_entries ADD COLUMN sticky INTEGER NOT NULL DEFAULT 0 CHECK (sticky >= 0);
+ALTER TABLE lorebook_entries ADD COLUMN cooldown INTEGER NOT NULL DEFAULT 0 CHECK (cooldown >= 0);
+ALTER TABLE lorebook_entries ADD COLUMN delay INTEGER NOT NULL DEFAULT 0 CHECK (delay >= 0);
+
+CREATE TABLE IF NOT EXISTS lorebook_activations (
+    conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    entry_id TEXT NOT NULL REFERENCES lorebook_entries(id) ON DELETE CASCADE,
+    anchor_message_id TEXT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (entry_id, anchor_message_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_lorebook_activations_conv
+    ON lorebook_activations(conversation_id);
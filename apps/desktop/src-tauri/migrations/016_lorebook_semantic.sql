@@ -0,0 +1,13 @@
+-- Migration 016: Semantic lorebook activation
+-- Entries can activate by keyword, by similarity of their embedding to the
+-- recent conversation, or both. has_embedding (from the never-registered
+-- 004_lorebook_vectors.sql) is added by the migration runner if missing.
+
+ALTER TABLE lorebook_entries ADD COLUMN activation_mode TEXT NOT NULL DEFAULT 'keyword'
+    CHECK (activation_mode IN ('keyword', 'vector', 'both'));
+
+-- NULL uses the default threshold
+ALTER TABLE lorebooks ADD COLUMN similarity_threshold REAL;
+
+UPDATE lorebook_entries SET has_embedding = 1
+WHERE id IN (SELECT entity_id FROM embeddings WHERE entity_type = 'lorebook');
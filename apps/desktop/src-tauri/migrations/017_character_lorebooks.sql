@@ -0,0 +1,13 @@
+-- Migration 017: Character-bound lorebooks
+-- A lorebook linked to a character is active in every conversation that
+-- character takes part in, alongside global and conversation lorebooks
+
+CREATE TABLE IF NOT EXISTS character_lorebooks (
+    character_id TEXT NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    lorebook_id TEXT NOT NULL REFERENCES lorebooks(id) ON DELETE CASCADE,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (character_id, lorebook_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_character_lorebooks_lorebook
+    ON character_lorebooks(lorebook_id);
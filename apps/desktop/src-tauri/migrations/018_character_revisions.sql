@@ -0,0 +1,13 @@
+-- Migration 018: Character revisions
+-- Full snapshot of a character after each change, so edits, imports and
+-- generated rewrites can be compared and rolled back
+
+CREATE TABLE IF NOT EXISTS character_revisions (
+    id TEXT PRIMARY KEY,
+    character_id TEXT NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    revision INTEGER NOT NULL,
+    source TEXT NOT NULL,
+    snapshot TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    UNIQUE (character_id, revision)
+);
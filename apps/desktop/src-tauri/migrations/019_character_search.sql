@@ -0,0 +1,101 @@
+-- Migration 019: Character library search
+-- Full-text index over name, description and creator, a tag lookup table
+-- kept in sync with the JSON tag arrays, and indexes for the remaining
+-- filters and sorts
+
+-- ============================================
+-- Characters FTS
+-- ============================================
+CREATE VIRTUAL TABLE IF NOT EXISTS characters_fts USING fts5(
+    name,
+    description,
+    creator,
+    character_id UNINDEXED,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS characters_fts_insert
+AFTER INSERT ON characters
+BEGIN
+    INSERT INTO characters_fts (name, description, creator, character_id)
+    VALUES (new.name, new.description, COALESCE(json_extract(new.metadata, '$.creatorName'), ''), new.id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS characters_fts_update
+AFTER UPDATE OF name, description, metadata ON characters
+BEGIN
+    DELETE FROM characters_fts WHERE character_id = old.id;
+    INSERT INTO characters_fts (name, description, creator, character_id)
+    VALUES (new.name, new.description, COALESCE(json_extract(new.metadata, '$.creatorName'), ''), new.id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS characters_fts_delete
+AFTER DELETE ON characters
+BEGIN
+    DELETE FROM characters_fts WHERE character_id = old.id;
+END;
+
+INSERT INTO characters_fts (name, description, creator, character_id)
+SELECT name, description, COALESCE(json_extract(metadata, '$.creatorName'), ''), id FROM characters;
+
+-- ============================================
+-- Tag lookup
+-- One row per (character, tag) from `tags` and `metadata.genreTags`.
+-- Temporary: migration 020 drops this table and its triggers in favour of
+-- the normalized `tags` and `character_tags` tables.
+-- ============================================
+CREATE TABLE IF NOT EXISTS character_tag_index (
+    character_id TEXT NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('tag', 'genre')),
+    tag TEXT NOT NULL COLLATE NOCASE,
+    PRIMARY KEY (kind, tag, character_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_character_tag_index_character
+    ON character_tag_index(character_id);
+
+CREATE TRIGGER IF NOT EXISTS character_tag_index_insert
+AFTER INSERT ON characters
+BEGIN
+    INSERT OR IGNORE INTO character_tag_index (character_id, kind, tag)
+    SELECT new.id, 'tag', trim(value) FROM json_each(new.tags)
+    WHERE trim(value) != '';
+    INSERT OR IGNORE INTO character_tag_index (character_id, kind, tag)
+    SELECT new.id, 'genre', trim(value) FROM json_each(new.metadata, '$.genreTags')
+    WHERE trim(value) != '';
+END;
+
+CREATE TRIGGER IF NOT EXISTS character_tag_index_update
+AFTER UPDATE OF tags, metadata ON characters
+BEGIN
+    DELETE FROM character_tag_index WHERE character_id = old.id;
+    INSERT OR IGNORE INTO character_tag_index (character_id, kind, tag)
+    SELECT new.id, 'tag', trim(value) FROM json_each(new.tags)
+    WHERE trim(value) != '';
+    INSERT OR IGNORE INTO character_tag_index (character_id, kind, tag)
+    SELECT new.id, 'genre', trim(value) FROM json_each(new.metadata, '$.genreTags')
+    WHERE trim(value) != '';
+END;
+
+INSERT OR IGNORE INTO character_tag_index (character_id, kind, tag)
+SELECT c.id, 'tag', trim(t.value) FROM characters c, json_each(c.tags) t
+WHERE trim(t.value) != '';
+
+INSERT OR IGNORE INTO character_tag_index (character_id, kind, tag)
+SELECT c.id, 'genre', trim(g.value) FROM characters c, json_each(c.metadata, '$.genreTags') g
+WHERE trim(g.value) != '';
+
+-- ============================================
+-- Filter and sort indexes
+-- Rating and POV live in metadata; empty values read as the defaults
+-- ============================================
+CREATE INDEX IF NOT EXISTS idx_characters_rating
+    ON characters(COALESCE(NULLIF(json_extract(metadata, '$.rating'), ''), 'sfw'))
+    WHERE deleted_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_characters_pov
+    ON characters(COALESCE(NULLIF(json_extract(metadata, '$.povType'), ''), 'any'))
+    WHERE deleted_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_characters_updated
+    ON characters(updated_at DESC) WHERE deleted_at IS NULL;
@@ -0,0 +1,80 @@
+-- Migration 020: Normalized tags
+-- A tag registry with character links, replacing character_tag_index.
+-- Characters keep their JSON tag arrays (cards and prompts read them);
+-- the triggers below keep the links in step with every write.
+
+DROP TRIGGER IF EXISTS character_tag_index_insert;
+DROP TRIGGER IF EXISTS character_tag_index_update;
+DROP TABLE IF EXISTS character_tag_index;
+
+CREATE TABLE IF NOT EXISTS tags (
+    id INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL CHECK (kind IN ('tag', 'genre')),
+    name TEXT NOT NULL COLLATE NOCASE,
+    created_at INTEGER NOT NULL,
+    UNIQUE (kind, name)
+);
+
+CREATE TABLE IF NOT EXISTS character_tags (
+    character_id TEXT NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (tag_id, character_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_character_tags_character
+    ON character_tags(character_id);
+
+CREATE TRIGGER IF NOT EXISTS character_tags_insert
+AFTER INSERT ON characters
+BEGIN
+    INSERT OR IGNORE INTO tags (kind, name, created_at)
+    SELECT 'tag', trim(value), strftime('%s', 'now') FROM json_each(new.tags)
+    WHERE trim(value) != '';
+    INSERT OR IGNORE INTO tags (kind, name, created_at)
+    SELECT 'genre', trim(value), strftime('%s', 'now') FROM json_each(new.metadata, '$.genreTags')
+    WHERE trim(value) != '';
+    
+    INSERT OR IGNORE INTO character_tags (character_id, tag_id)
+    SELECT new.id, t.id FROM json_each(new.tags) j
+    JOIN tags t ON t.kind = 'tag' AND t.name = trim(j.value);
+    INSERT OR IGNORE INTO character_tags (character_id, tag_id)
+    SELECT new.id, t.id FROM json_each(new.metadata, '$.genreTags') j
+    JOIN tags t ON t.kind = 'genre' AND t.name = trim(j.value);
+END;
+
+CREATE TRIGGER IF NOT EXISTS character_tags_update
+AFTER UPDATE OF tags, metadata ON characters
+BEGIN
+    DELETE FROM character_tags WHERE character_id = old.id;
+    
+    INSERT OR IGNORE INTO tags (kind, name, created_at)
+    SELECT 'tag', trim(value), strftime('%s', 'now') FROM json_each(new.tags)
+    WHERE trim(value) != '';
+    INSERT OR IGNORE INTO tags (kind, name, created_at)
+    SELECT 'genre', trim(value), strftime('%s', 'now') FROM json_each(new.metadata, '$.genreTags')
+    WHERE trim(value) != '';
+    
+    INSERT OR IGNORE INTO character_tags (character_id, tag_id)
+    SELECT new.id, t.id FROM json_each(new.tags) j
+    JOIN tags t ON t.kind = 'tag' AND t.name = trim(j.value);
+    INSERT OR IGNORE INTO character_tags (character_id, tag_id)
+    SELECT new.id, t.id FROM json_each(new.metadata, '$.genreTags') j
+    JOIN tags t ON t.kind = 'genre' AND t.name = trim(j.value);
+END;
+
+-- Existing JSON tags
+INSERT OR IGNORE INTO tags (kind, name, created_at)
+SELECT 'tag', trim(j.value), strftime('%s', 'now') FROM characters c, json_each(c.tags) j
+WHERE trim(j.value) != '';
+
+INSERT OR IGNORE INTO tags (kind, name, created_at)
+SELECT 'genre', trim(j.value), strftime('%s', 'now') FROM characters c, json_each(c.metadata, '$.genreTags') j
+WHERE trim(j.value) != '';
+
+INSERT OR IGNORE INTO character_tags (character_id, tag_id)
+SELECT c.id, t.id FROM characters c, json_each(c.tags) j
+JOIN tags t ON t.kind = 'tag' AND t.name = trim(j.value);
+
+INSERT OR IGNORE INTO character_tags (character_id, tag_id)
+SELECT c.id, t.id FROM characters c, json_each(c.metadata, '$.genreTags') j
+JOIN tags t ON t.kind = 'genre' AND t.name = trim(j.value);
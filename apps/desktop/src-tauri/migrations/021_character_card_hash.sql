@@ -0,0 +1,7 @@
+-- Migration 021: Card hashes
+-- SHA-256 of the card a character was imported from, so importing the
+-- same card again can be spotted. Characters imported earlier have none.
+
+ALTER TABLE characters ADD COLUMN card_hash TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_characters_card_hash ON characters(card_hash) WHERE card_hash IS NOT NULL;
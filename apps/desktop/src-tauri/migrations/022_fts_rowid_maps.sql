@@ -0,0 +1,99 @@
+-- Migration 022: Point lookups for memory and lorebook FTS upkeep
+-- The 007 triggers deleted by `memory_id` / `entry_id`, UNINDEXED columns,
+-- so every write scanned the whole FTS table. Each FTS row now gets an
+-- explicit rowid from a map table keyed by the base id. The map's INTEGER
+-- PRIMARY KEY survives VACUUM, unlike the base tables' implicit rowids.
+
+DROP TRIGGER IF EXISTS memory_entries_fts_insert;
+DROP TRIGGER IF EXISTS memory_entries_fts_update;
+DROP TRIGGER IF EXISTS memory_entries_fts_delete;
+DROP TRIGGER IF EXISTS lorebook_entries_fts_insert;
+DROP TRIGGER IF EXISTS lorebook_entries_fts_update;
+DROP TRIGGER IF EXISTS lorebook_entries_fts_delete;
+
+DELETE FROM memory_entries_fts;
+DELETE FROM lorebook_entries_fts;
+
+-- ============================================
+-- Memory Entries FTS
+-- ============================================
+CREATE TABLE IF NOT EXISTS memory_entries_fts_rowids (
+    fts_rowid INTEGER PRIMARY KEY,
+    memory_id TEXT NOT NULL UNIQUE
+);
+
+CREATE TRIGGER IF NOT EXISTS memory_entries_fts_insert
+AFTER INSERT ON memory_entries
+BEGIN
+    INSERT OR IGNORE INTO memory_entries_fts_rowids (memory_id) VALUES (new.id);
+    INSERT INTO memory_entries_fts (rowid, content, memory_id, character_id)
+    VALUES ((SELECT fts_rowid FROM memory_entries_fts_rowids WHERE memory_id = new.id),
+            new.content, new.id, new.character_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS memory_entries_fts_update
+AFTER UPDATE OF content, character_id ON memory_entries
+BEGIN
+    DELETE FROM memory_entries_fts
+    WHERE rowid = (SELECT fts_rowid FROM memory_entries_fts_rowids WHERE memory_id = old.id);
+    INSERT INTO memory_entries_fts (rowid, content, memory_id, character_id)
+    VALUES ((SELECT fts_rowid FROM memory_entries_fts_rowids WHERE memory_id = new.id),
+            new.content, new.id, new.character_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS memory_entries_fts_delete
+AFTER DELETE ON memory_entries
+BEGIN
+    DELETE FROM memory_entries_fts
+    WHERE rowid = (SELECT fts_rowid FROM memory_entries_fts_rowids WHERE memory_id = old.id);
+    DELETE FROM memory_entries_fts_rowids WHERE memory_id = old.id;
+END;
+
+INSERT OR IGNORE INTO memory_entries_fts_rowids (memory_id)
+SELECT id FROM memory_entries;
+
+INSERT INTO memory_entries_fts (rowid, content, memory_id, character_id)
+SELECT r.fts_rowid, m.content, m.id, m.character_id
+FROM memory_entries m JOIN memory_entries_fts_rowids r ON r.memory_id = m.id;
+
+-- ============================================
+-- Lorebook Entries FTS
+-- ============================================
+CREATE TABLE IF NOT EXISTS lorebook_entries_fts_rowids (
+    fts_rowid INTEGER PRIMARY KEY,
+    entry_id TEXT NOT NULL UNIQUE
+);
+
+CREATE TRIGGER IF NOT EXISTS lorebook_entries_fts_insert
+AFTER INSERT ON lorebook_entries
+BEGIN
+    INSERT OR IGNORE INTO lorebook_entries_fts_rowids (entry_id) VALUES (new.id);
+    INSERT INTO lorebook_entries_fts (rowid, name, keywords, content, entry_id, lorebook_id)
+    VALUES ((SELECT fts_rowid FROM lorebook_entries_fts_rowids WHERE entry_id = new.id),
+            new.name, new.keywords, new.content, new.id, new.lorebook_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS lorebook_entries_fts_update
+AFTER UPDATE OF name, keywords, content ON lorebook_entries
+BEGIN
+    DELETE FROM lorebook_entries_fts
+    WHERE rowid = (SELECT fts_rowid FROM lorebook_entries_fts_rowids WHERE entry_id = old.id);
+    INSERT INTO lorebook_entries_fts (rowid, name, keywords, content, entry_id, lorebook_id)
+    VALUES ((SELECT fts_rowid FROM lorebook_entries_fts_rowids WHERE entry_id = new.id),
+            new.name, new.keywords, new.content, new.id, new.lorebook_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS lorebook_entries_fts_delete
+AFTER DELETE ON lorebook_entries
+BEGIN
+    DELETE FROM lorebook_entries_fts
+    WHERE rowid = (SELECT fts_rowid FROM lorebook_entries_fts_rowids WHERE entry_id = old.id);
+    DELETE FROM lorebook_entries_fts_rowids WHERE entry_id = old.id;
+END;
+
+INSERT OR IGNORE INTO lorebook_entries_fts_rowids (entry_id)
+SELECT id FROM lorebook_entries;
+
+INSERT INTO lorebook_entries_fts (rowid, name, keywords, content, entry_id, lorebook_id)
+SELECT r.fts_rowid, e.name, e.keywords, e.content, e.id, e.lorebook_id
+FROM lorebook_entries e JOIN lorebook_entries_fts_rowids r ON r.entry_id = e.id;
@@ -0,0 +1,12 @@
+-- Migration 023: Opt-in regex keys
+-- Keys shaped like `/pattern/flags` were read as regexes for every entry,
+-- changing how older literal keys matched. Regex reading is now a per-entry
+-- setting, on for entries imported from SillyTavern formats (which keep
+-- their leftovers under `metadata.worldInfo`, on the entry or its lorebook)
+-- and off for everything else.
+
+ALTER TABLE lorebook_entries ADD COLUMN regex_keys INTEGER NOT NULL DEFAULT 0;
+
+UPDATE lorebook_entries SET regex_keys = 1
+WHERE json_extract(metadata, '$.worldInfo') IS NOT NULL
+   OR lorebook_id IN (SELECT id FROM lorebooks WHERE json_extract(metadata, '$.worldInfo') IS NOT NULL);
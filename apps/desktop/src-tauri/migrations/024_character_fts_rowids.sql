@@ -0,0 +1,48 @@
+-- Migration 024: Point lookups for character FTS upkeep
+-- Same change as 022 for `characters_fts`: explicit rowids from a map
+-- keyed by character id, so updates and deletes skip the table scan
+
+DROP TRIGGER IF EXISTS characters_fts_insert;
+DROP TRIGGER IF EXISTS characters_fts_update;
+DROP TRIGGER IF EXISTS characters_fts_delete;
+
+DELETE FROM characters_fts;
+
+CREATE TABLE IF NOT EXISTS characters_fts_rowids (
+    fts_rowid INTEGER PRIMARY KEY,
+    character_id TEXT NOT NULL UNIQUE
+);
+
+CREATE TRIGGER IF NOT EXISTS characters_fts_insert
+AFTER INSERT ON characters
+BEGIN
+    INSERT OR IGNORE INTO characters_fts_rowids (character_id) VALUES (new.id);
+    INSERT INTO characters_fts (rowid, name, description, creator, character_id)
+    VALUES ((SELECT fts_rowid FROM characters_fts_rowids WHERE character_id = new.id),
+            new.name, new.description, COALESCE(json_extract(new.metadata, '$.creatorName'), ''), new.id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS characters_fts_update
+AFTER UPDATE OF name, description, metadata ON characters
+BEGIN
+    DELETE FROM characters_fts
+    WHERE rowid = (SELECT fts_rowid FROM characters_fts_rowids WHERE character_id = old.id);
+    INSERT INTO characters_fts (rowid, name, description, creator, character_id)
+    VALUES ((SELECT fts_rowid FROM characters_fts_rowids WHERE character_id = new.id),
+            new.name, new.description, COALESCE(json_extract(new.metadata, '$.creatorName'), ''), new.id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS characters_fts_delete
+AFTER DELETE ON characters
+BEGIN
+    DELETE FROM characters_fts
+    WHERE rowid = (SELECT fts_rowid FROM characters_fts_rowids WHERE character_id = old.id);
+    DELETE FROM characters_fts_rowids WHERE character_id = old.id;
+END;
+
+INSERT OR IGNORE INTO characters_fts_rowids (character_id)
+SELECT id FROM characters;
+
+INSERT INTO characters_fts (rowid, name, description, creator, character_id)
+SELECT r.fts_rowid, c.name, c.description, COALESCE(json_extract(c.metadata, '$.creatorName'), ''), c.id
+FROM characters c JOIN characters_fts_rowids r ON r.character_id = c.id;
@@ -1,9 +1,11 @@
-use tauri::State;
+use tauri::{Emitter, State};
 use serde::{Deserialize, Serialize};
 use crate::entities::*;
 use crate::error::AppError;
-use crate::services::CharacterService;
+use crate::services::avatars;
+use crate::services::{AvatarService, BatchImportService, CardService, CharacterLintService, CharacterRevisionService, CharacterService, RefinementService};
 use crate::state::AppState;
+use crate::workers::embedding_worker::request_reembed_if_stale;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -39,6 +41,10 @@ pub struct GeneratedCharacterInput {
     pub rating: Option<String>,
     #[serde(default)]
     pub genre_tags: Vec<String>,
+    /// Always `Generated`, so creating the character from this records
+    /// where it came from
+    #[serde(default)]
+    pub source: Option<RevisionSource>,
 }
 
 #[tauri::command]
@@ -130,12 +136,25 @@ IMPORTANT: Return ONLY valid JSON, no additional text or markdown. The response
         content
     };
     
-    let generated: GeneratedCharacterInput = serde_json::from_str(content)
+    let mut generated: GeneratedCharacterInput = serde_json::from_str(content)
         .map_err(|e| AppError::Llm(format!("Failed to parse generated character: {}. Raw response: {}", e, content)))?;
+    generated.source = Some(RevisionSource::Generated);
     
     Ok(generated)
 }
 
+/// Propose a regenerated or rewritten version of one field. Nothing is
+/// saved; pass the proposal's `update` to `update_character` to accept it.
+#[tauri::command]
+pub async fn refine_character_field(
+    state: State<'_, AppState>,
+    input: RefineCharacterFieldInput,
+) -> Result<CharacterFieldProposal, AppError> {
+    let sidecar = state.get_sidecar()
+        .ok_or_else(|| AppError::Sidecar("Model not loaded. Please load a model first.".to_string()))?;
+    RefinementService::propose(&state.db, &sidecar, input).await
+}
+
 #[tauri::command]
 pub async fn create_character(
     state: State<'_, AppState>,
@@ -159,6 +178,15 @@ pub async fn list_characters(
     CharacterService::list(&state.db)
 }
 
+/// Page through the library with search, filters and sorting
+#[tauri::command]
+pub async fn query_characters(
+    state: State<'_, AppState>,
+    query: CharacterQuery,
+) -> Result<CharacterPage, AppError> {
+    CharacterService::query(&state.db, query)
+}
+
 #[tauri::command]
 pub async fn update_character(
     state: State<'_, AppState>,
@@ -183,25 +211,141 @@ pub async fn import_character_card(
     avatar_base64: Option<String>,
 ) -> Result<Character, AppError> {
     // If avatar provided, save it first
-    let avatar_path = if let Some(ref data) = avatar_base64 {
-        let avatar_id = crate::entities::new_id();
-        let filename = format!("{}.png", avatar_id);
-        
-        let data = data
-            .strip_prefix("data:image/png;base64,")
-            .or_else(|| data.strip_prefix("data:image/jpeg;base64,"))
-            .unwrap_or(data);
-        
-        let bytes = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, data)
-            .map_err(|e| AppError::Import(format!("Invalid avatar: {}", e)))?;
-        
-        let path = state.paths.avatar_file_path(&filename);
-        std::fs::write(&path, &bytes)?;
-        
-        Some(filename)
-    } else {
-        None
+    let avatar_path = match avatar_base64 {
+        Some(ref data) => Some(AvatarService::store(&state.paths, &avatars::decode_base64(data)?)?),
+        None => None,
     };
     
-    CharacterService::import_card(&state.db, &json_data, avatar_path)
-}
\ No newline at end of file
+    let character = CharacterService::import_card(&state.db, &json_data, avatar_path)?;
+    // Linked lorebooks may have brought vector entries
+    request_reembed_if_stale(&state);
+    Ok(character)
+}
+
+/// Import a PNG character card (base64, optionally as a data URL).
+/// The card JSON is read from the image and the image becomes the avatar.
+#[tauri::command]
+pub async fn import_character_png(
+    state: State<'_, AppState>,
+    png_base64: String,
+) -> Result<Character, AppError> {
+    let data = png_base64
+        .strip_prefix("data:image/png;base64,")
+        .unwrap_or(&png_base64);
+    let bytes = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, data.trim())
+        .map_err(|e| AppError::Import(format!("Invalid PNG data: {}", e)))?;
+
+    let character = CardService::import_png(&state.db, &state.paths, &bytes)?;
+    request_reembed_if_stale(&state);
+    Ok(character)
+}
+
+/// Import a CharX archive (base64). The main icon becomes the avatar.
+#[tauri::command]
+pub async fn import_character_charx(
+    state: State<'_, AppState>,
+    charx_base64: String,
+) -> Result<Character, AppError> {
+    let bytes = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, charx_base64.trim())
+        .map_err(|e| AppError::Import(format!("Invalid CharX data: {}", e)))?;
+
+    let character = CardService::import_charx(&state.db, &state.paths, &bytes)?;
+    request_reembed_if_stale(&state);
+    Ok(character)
+}
+
+/// Import every card in a folder or zip archive. Emits `import:progress`
+/// after each file and returns what happened to all of them.
+#[tauri::command]
+pub async fn import_character_batch(
+    app_handle: tauri::AppHandle,  // AppHandle MUST come before State
+    state: State<'_, AppState>,
+    path: String,
+    duplicates: Option<DuplicatePolicy>,
+) -> Result<BatchImportReport, AppError> {
+    let import_state = state.inner().clone();
+    let report = tauri::async_runtime::spawn_blocking(move || {
+        BatchImportService::import(
+            &import_state.db,
+            &import_state.paths,
+            std::path::Path::new(&path),
+            duplicates.unwrap_or_default(),
+            |event| {
+                let _ = app_handle.emit("import:progress", event);
+            },
+        )
+    })
+    .await
+    .map_err(|e| AppError::Other(format!("Batch import task failed: {}", e)))??;
+
+    request_reembed_if_stale(&state);
+    Ok(report)
+}
+
+/// Saved versions of a character, newest first
+#[tauri::command]
+pub async fn list_character_revisions(
+    state: State<'_, AppState>,
+    character_id: String,
+) -> Result<Vec<CharacterRevision>, AppError> {
+    CharacterRevisionService::list(&state.db, &character_id)
+}
+
+/// Field-by-field changes between two revisions; without `to_revision`,
+/// against the character as it is now
+#[tauri::command]
+pub async fn diff_character_revisions(
+    state: State<'_, AppState>,
+    character_id: String,
+    from_revision: i32,
+    to_revision: Option<i32>,
+) -> Result<Vec<CharacterFieldChange>, AppError> {
+    CharacterRevisionService::diff(&state.db, &character_id, from_revision, to_revision)
+}
+
+#[tauri::command]
+pub async fn restore_character_revision(
+    state: State<'_, AppState>,
+    character_id: String,
+    revision: i32,
+) -> Result<Character, AppError> {
+    CharacterRevisionService::restore(&state.db, &character_id, revision)
+}
+
+/// Token cost per field against the context window, plus card problems
+/// worth fixing
+#[tauri::command]
+pub async fn lint_character(
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<CharacterLintReport, AppError> {
+    CharacterLintService::lint(&state.db, &id)
+}
+
+/// Validate and normalize an uploaded avatar (base64 or data URL).
+/// Returns the file name to use as `avatarPath`.
+#[tauri::command]
+pub async fn save_avatar(
+    state: State<'_, AppState>,
+    image_base64: String,
+) -> Result<String, AppError> {
+    AvatarService::store(&state.paths, &avatars::decode_base64(&image_base64)?)
+}
+
+/// Absolute path of an avatar's thumbnail, made on first request
+#[tauri::command]
+pub async fn get_avatar_thumbnail(
+    state: State<'_, AppState>,
+    avatar_path: String,
+) -> Result<Option<String>, AppError> {
+    let path = AvatarService::thumbnail(&state.paths, &avatar_path)?;
+    Ok(path.map(|p| p.to_string_lossy().into_owned()))
+}
+
+/// Delete avatar files no character uses. Returns how many were removed.
+#[tauri::command]
+pub async fn collect_unused_avatars(
+    state: State<'_, AppState>,
+) -> Result<usize, AppError> {
+    AvatarService::collect_unused(&state.db, &state.paths)
+}
@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
 use tauri::State;
 use crate::entities::*;
 use crate::error::AppError;
-use crate::services::ExportService;
+use crate::services::{CardService, ExportService};
 use crate::state::AppState;
+use crate::workers::embedding_worker::request_reembed_if_stale;
 
 #[tauri::command]
 pub async fn export_character(
@@ -13,6 +16,37 @@ pub async fn export_character(
     serde_json::to_string_pretty(&exported).map_err(AppError::from)
 }
 
+/// Export a character as a PNG card, base64-encoded
+#[tauri::command]
+pub async fn export_character_png(
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<String, AppError> {
+    let png = CardService::export_png(&state.db, &state.paths, &id)?;
+    Ok(base64::Engine::encode(&base64::engine::general_purpose::STANDARD, png))
+}
+
+/// Export a character as Character Card JSON. Without `spec`, the card
+/// keeps the version it was imported with (V2 for characters made here).
+#[tauri::command]
+pub async fn export_character_card(
+    state: State<'_, AppState>,
+    id: String,
+    spec: Option<CardSpec>,
+) -> Result<String, AppError> {
+    CardService::export_json(&state.db, &id, spec)
+}
+
+/// Export a character as a CharX archive, base64-encoded
+#[tauri::command]
+pub async fn export_character_charx(
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<String, AppError> {
+    let charx = CardService::export_charx(&state.db, &state.paths, &id)?;
+    Ok(base64::Engine::encode(&base64::engine::general_purpose::STANDARD, charx))
+}
+
 #[tauri::command]
 pub async fn export_conversation(
     state: State<'_, AppState>,
@@ -31,6 +65,13 @@ pub async fn export_all_data(
     let conversations = crate::repositories::ConversationRepo::find_all(&state.db)?;
     let personas = crate::repositories::PersonaRepo::find_all(&state.db)?;
     let lorebooks = crate::repositories::LorebookRepo::find_all(&state.db)?;
+    let character_lorebooks: Vec<serde_json::Value> = crate::repositories::CharacterRepo::find_all_lorebook_links(&state.db)?
+        .into_iter()
+        .map(|(character_id, lorebook_id)| serde_json::json!({
+            "character_id": character_id,
+            "lorebook_id": lorebook_id,
+        }))
+        .collect();
     
     let export = serde_json::json!({
         "glee_export_version": "1.0",
@@ -40,6 +81,7 @@ pub async fn export_all_data(
         "conversations": conversations,
         "personas": personas,
         "lorebooks": lorebooks,
+        "character_lorebooks": character_lorebooks,
     });
     
     serde_json::to_string_pretty(&export).map_err(AppError::from)
@@ -50,7 +92,10 @@ pub async fn import_character(
     state: State<'_, AppState>,
     data: String,
 ) -> Result<Character, AppError> {
-    ExportService::import_character(&state.db, &state.paths, &data)
+    let character = ExportService::import_character(&state.db, &state.paths, &data)?;
+    // Linked lorebooks may have brought vector entries
+    request_reembed_if_stale(&state);
+    Ok(character)
 }
 
 #[tauri::command]
@@ -69,6 +114,7 @@ pub async fn import_data(
     match export_type {
         "character" => {
             let character = ExportService::import_character(&state.db, &state.paths, &data)?;
+            request_reembed_if_stale(&state);
             Ok(format!("Imported character: {}", character.name))
         }
         "full_backup" => {
@@ -81,11 +127,20 @@ pub async fn import_data(
                 }
             }
             
+            // Imported records get new ids; remember them to restore the links
+            let mut character_ids: HashMap<String, String> = HashMap::new();
+            let mut lorebook_ids: HashMap<String, String> = HashMap::new();
+            let old_id = |v: &serde_json::Value| v.get("id").and_then(|id| id.as_str()).map(String::from);
+            
             // Import characters
             if let Some(characters) = json.get("characters").and_then(|v| v.as_array()) {
                 for c in characters {
                     if let Ok(input) = serde_json::from_value::<CreateCharacterInput>(c.clone()) {
-                        let _ = crate::services::CharacterService::create(&state.db, input);
+                        if let Ok(character) = crate::services::CharacterService::create(&state.db, input) {
+                            if let Some(id) = old_id(c) {
+                                character_ids.insert(id, character.id);
+                            }
+                        }
                     }
                 }
             }
@@ -93,12 +148,38 @@ pub async fn import_data(
             // Import lorebooks
             if let Some(lorebooks) = json.get("lorebooks").and_then(|v| v.as_array()) {
                 for lb in lorebooks {
-                    if let Ok(input) = serde_json::from_value::<CreateLorebookInput>(lb.clone()) {
-                        let _ = crate::services::LorebookService::create(&state.db, input);
+                    let Ok(input) = serde_json::from_value::<CreateLorebookInput>(lb.clone()) else {
+                        continue;
+                    };
+                    let Ok(lorebook) = crate::services::LorebookService::create(&state.db, input) else {
+                        continue;
+                    };
+                    if let Some(id) = old_id(lb) {
+                        lorebook_ids.insert(id, lorebook.id.clone());
+                    }
+                    if let Some(metadata) = lb.get("metadata").filter(|m| m.is_object()) {
+                        let _ = crate::repositories::LorebookRepo::update_metadata(&state.db, &lorebook.id, metadata);
+                    }
+                    // Entries are backed up in full, so they load straight back in
+                    for entry in lb.get("entries").and_then(|v| v.as_array()).into_iter().flatten() {
+                        if let Ok(mut input) = serde_json::from_value::<CreateEntryInput>(entry.clone()) {
+                            input.lorebook_id = lorebook.id.clone();
+                            let _ = crate::repositories::LorebookRepo::create_entry(&state.db, &input);
+                        }
                     }
                 }
             }
             
+            // Character lorebook links, where both ends came across
+            for link in json.get("character_lorebooks").and_then(|v| v.as_array()).into_iter().flatten() {
+                let character = link.get("character_id").and_then(|v| v.as_str()).and_then(|id| character_ids.get(id));
+                let lorebook = link.get("lorebook_id").and_then(|v| v.as_str()).and_then(|id| lorebook_ids.get(id));
+                if let (Some(character), Some(lorebook)) = (character, lorebook) {
+                    let _ = crate::repositories::CharacterRepo::attach_lorebook(&state.db, character, lorebook);
+                }
+            }
+            request_reembed_if_stale(&state);
+            
             Ok("Backup imported successfully".to_string())
         }
         _ => Err(AppError::Import(format!("Unknown export type: {}", export_type))),
@@ -1,8 +1,9 @@
 use tauri::State;
 use crate::entities::*;
 use crate::error::AppError;
-use crate::services::LorebookService;
+use crate::services::{LorebookService, WorldInfoService};
 use crate::state::AppState;
+use crate::workers::embedding_worker::request_reembed_if_stale;
 
 #[tauri::command]
 pub async fn create_lorebook(
@@ -49,7 +50,11 @@ pub async fn create_entry(
     state: State<'_, AppState>,
     input: CreateEntryInput,
 ) -> Result<LorebookEntry, AppError> {
-    LorebookService::create_entry(&state.db, input)
+    let entry = LorebookService::create_entry(&state.db, input)?;
+    if entry.activation_mode.uses_vectors() {
+        request_reembed_if_stale(&state);
+    }
+    Ok(entry)
 }
 
 #[tauri::command]
@@ -58,7 +63,11 @@ pub async fn update_entry(
     id: String,
     input: UpdateEntryInput,
 ) -> Result<LorebookEntry, AppError> {
-    LorebookService::update_entry(&state.db, &id, input)
+    let entry = LorebookService::update_entry(&state.db, &id, input)?;
+    if entry.activation_mode.uses_vectors() && !entry.has_embedding {
+        request_reembed_if_stale(&state);
+    }
+    Ok(entry)
 }
 
 #[tauri::command]
@@ -86,3 +95,52 @@ pub async fn detach_from_conversation(
 ) -> Result<(), AppError> {
     LorebookService::detach_from_conversation(&state.db, &conversation_id, &lorebook_id)
 }
+
+#[tauri::command]
+pub async fn attach_to_character(
+    state: State<'_, AppState>,
+    character_id: String,
+    lorebook_id: String,
+) -> Result<(), AppError> {
+    LorebookService::attach_to_character(&state.db, &character_id, &lorebook_id)
+}
+
+#[tauri::command]
+pub async fn detach_from_character(
+    state: State<'_, AppState>,
+    character_id: String,
+    lorebook_id: String,
+) -> Result<(), AppError> {
+    LorebookService::detach_from_character(&state.db, &character_id, &lorebook_id)
+}
+
+#[tauri::command]
+pub async fn list_character_lorebooks(
+    state: State<'_, AppState>,
+    character_id: String,
+) -> Result<Vec<Lorebook>, AppError> {
+    LorebookService::list_for_character(&state.db, &character_id)
+}
+
+#[tauri::command]
+pub async fn import_lorebook(
+    state: State<'_, AppState>,
+    data: String,
+    name: Option<String>,
+) -> Result<Lorebook, AppError> {
+    let lorebook = WorldInfoService::import(&state.db, &data, name.as_deref())?;
+    if lorebook.entries.iter().any(|e| e.activation_mode.uses_vectors()) {
+        request_reembed_if_stale(&state);
+    }
+    Ok(lorebook)
+}
+
+#[tauri::command]
+pub async fn export_lorebook(
+    state: State<'_, AppState>,
+    id: String,
+    format: LorebookFormat,
+) -> Result<String, AppError> {
+    let value = WorldInfoService::export(&state.db, &id, format)?;
+    serde_json::to_string_pretty(&value).map_err(AppError::from)
+}
@@ -0,0 +1,129 @@
+use tauri::State;
+use crate::entities::{EmbeddingStatus, LorebookEntry};
+use crate::error::AppError;
+use crate::services::embeddings::{Embedding, EmbeddingEncoding};
+use crate::services::{ConversationSummary, EmbeddingService, MemoryEntry, RetrievalService, SettingsService, SummaryService};
+use crate::state::{AppState, EmbeddingMessage};
+
+/// Embed the query when a model is loaded; lexical search still works without it
+async fn query_embedding(state: &AppState, query: &str) -> Option<Embedding> {
+    let sidecar = state.embedding_handle()?;
+    EmbeddingService::generate(&sidecar, query).await.ok()
+}
+
+#[tauri::command]
+pub async fn search_memories(
+    state: State<'_, AppState>,
+    character_id: String,
+    query: String,
+    limit: Option<usize>,
+) -> Result<Vec<(MemoryEntry, f32)>, AppError> {
+    let embedding = query_embedding(&state, &query).await;
+    RetrievalService::search_memories(
+        &state.db,
+        &character_id,
+        &query,
+        embedding.as_ref(),
+        limit.unwrap_or(20),
+        0.4,
+    )
+}
+
+#[tauri::command]
+pub async fn search_lorebook_entries(
+    state: State<'_, AppState>,
+    lorebook_ids: Vec<String>,
+    query: String,
+    limit: Option<usize>,
+) -> Result<Vec<(LorebookEntry, f32)>, AppError> {
+    let embedding = query_embedding(&state, &query).await;
+    RetrievalService::search_lorebook_entries(
+        &state.db,
+        &lorebook_ids,
+        &query,
+        embedding.as_ref(),
+        limit.unwrap_or(20),
+        0.4,
+    )
+}
+
+/// Change how embeddings are stored and re-encode existing vectors.
+/// Accepts "f32" (lossless), "f16" or "int8". Returns the number of rows rewritten.
+#[tauri::command]
+pub async fn set_embedding_quantization(
+    state: State<'_, AppState>,
+    encoding: String,
+) -> Result<usize, AppError> {
+    let parsed: EmbeddingEncoding = encoding
+        .parse()
+        .map_err(|_| AppError::Validation(format!("Unknown embedding encoding: {}", encoding)))?;
+    SettingsService::set(&state.db, "embeddings.quantization", parsed.as_str())?;
+    EmbeddingService::requantize_all(&state.db, parsed)
+}
+
+#[tauri::command]
+pub async fn get_embedding_status(
+    state: State<'_, AppState>,
+) -> Result<EmbeddingStatus, AppError> {
+    let embedder = state.embedding_handle();
+    let pending = match &embedder {
+        Some(handle) => EmbeddingService::count_reembed_targets(&state.db, &handle.model_id)?,
+        None => 0,
+    };
+    
+    Ok(EmbeddingStatus {
+        model_id: embedder.map(|h| h.model_id),
+        dedicated: state.get_embedding_sidecar().is_some(),
+        total: EmbeddingService::count(&state.db)?,
+        pending,
+    })
+}
+
+/// Queue a background re-embed of missing or stale vectors.
+/// Progress is reported through `embeddings:reembed` events.
+#[tauri::command]
+pub async fn start_reembed(
+    state: State<'_, AppState>,
+) -> Result<(), AppError> {
+    if state.embedding_handle().is_none() {
+        return Err(AppError::Sidecar("No model loaded to generate embeddings".into()));
+    }
+    state.embedding_tx.send(EmbeddingMessage::Reembed).await?;
+    Ok(())
+}
+
+/// Every summary of a conversation, chapters included, in story order
+#[tauri::command]
+pub async fn list_summaries(
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> Result<Vec<ConversationSummary>, AppError> {
+    SummaryService::list_for_conversation(&state.db, &conversation_id)
+}
+
+#[tauri::command]
+pub async fn update_summary(
+    state: State<'_, AppState>,
+    id: String,
+    content: String,
+) -> Result<ConversationSummary, AppError> {
+    SummaryService::update_content(&state.db, &id, &content)
+}
+
+#[tauri::command]
+pub async fn regenerate_summary(
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<ConversationSummary, AppError> {
+    let sidecar = state.get_sidecar()
+        .ok_or_else(|| AppError::Sidecar("Model not loaded".into()))?;
+    SummaryService::regenerate(&state.db, &sidecar, &id).await
+}
+
+#[tauri::command]
+pub async fn delete_summary(
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<(), AppError> {
+    SummaryService::delete(&state.db, &id)
+}
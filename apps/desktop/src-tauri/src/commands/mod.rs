@@ -1,4 +1,5 @@
 pub mod character;
+pub mod tag;
 pub mod persona;
 pub mod conversation;
 pub mod message;
@@ -8,6 +9,7 @@ pub mod system;
 pub mod download;
 pub mod export;
 pub mod setup;
+pub mod memory;
 
 // Re-export for lib.rs
 pub use system::restart_sidecar;
\ No newline at end of file
@@ -3,7 +3,8 @@ use crate::entities::*;
 use crate::error::AppError;
 use crate::sidecar;
 use crate::state::AppState;
-use crate::services::SettingsService;
+use crate::services::{JobService, SettingsService};
+use crate::workers::embedding_worker::request_reembed_if_stale;
 
 #[tauri::command]
 pub async fn get_app_info(
@@ -113,6 +114,22 @@ pub async fn start_sidecar(
     state.set_sidecar(Some(handle));
     
     tracing::info!("Sidecar started successfully");
+    
+    // The embedding model is optional; without it embeddings come from the chat model
+    if let Some(embedding_path) = settings.model.embedding_path.as_deref() {
+        if state.get_embedding_sidecar().is_none() {
+            match sidecar::start_embedding_sidecar(
+                &app_handle,
+                std::path::Path::new(embedding_path),
+                settings.model.sidecar_path.as_deref(),
+            ).await {
+                Ok(handle) => state.set_embedding_sidecar(Some(handle)),
+                Err(e) => tracing::warn!("Embedding sidecar failed to start, using chat model: {}", e),
+            }
+        }
+    }
+    
+    request_reembed_if_stale(&state);
     Ok(())
 }
 
@@ -128,6 +145,10 @@ pub async fn stop_sidecar(
         state.set_sidecar(None);
         tracing::info!("Sidecar stopped");
     }
+    if let Some(handle) = state.take_embedding_sidecar() {
+        sidecar::stop_sidecar(handle).await?;
+        tracing::info!("Embedding sidecar stopped");
+    }
     Ok(())
 }
 
@@ -154,10 +175,21 @@ pub async fn restart_sidecar(
         sidecar::stop_sidecar(handle).await?;
         state.set_sidecar(None);
     }
+    if let Some(handle) = state.take_embedding_sidecar() {
+        sidecar::stop_sidecar(handle).await?;
+    }
     
     // Wait a moment for port to be released
     tokio::time::sleep(std::time::Duration::from_millis(500)).await;
     
     // Start again
     start_sidecar(app_handle, state).await
-}
\ No newline at end of file
+}
+
+/// Background job queue (memory extraction, summarization) for the status view
+#[tauri::command]
+pub async fn get_job_status(
+    state: State<'_, AppState>,
+) -> Result<JobQueueStatus, AppError> {
+    JobService::status(&state.db)
+}
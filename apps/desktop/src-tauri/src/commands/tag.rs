@@ -0,0 +1,50 @@
+use tauri::State;
+use crate::entities::*;
+use crate::error::AppError;
+use crate::services::TagService;
+use crate::state::AppState;
+
+/// All tags with how many characters use them, optionally of one kind
+#[tauri::command]
+pub async fn list_tags(
+    state: State<'_, AppState>,
+    kind: Option<TagKind>,
+) -> Result<Vec<Tag>, AppError> {
+    TagService::list(&state.db, kind)
+}
+
+#[tauri::command]
+pub async fn rename_tag(
+    state: State<'_, AppState>,
+    id: i64,
+    name: String,
+) -> Result<Tag, AppError> {
+    TagService::rename(&state.db, id, &name)
+}
+
+/// Replace `source_ids` with `target_id` on every character
+#[tauri::command]
+pub async fn merge_tags(
+    state: State<'_, AppState>,
+    source_ids: Vec<i64>,
+    target_id: i64,
+) -> Result<Tag, AppError> {
+    TagService::merge(&state.db, &source_ids, target_id)
+}
+
+#[tauri::command]
+pub async fn delete_tag(
+    state: State<'_, AppState>,
+    id: i64,
+) -> Result<(), AppError> {
+    TagService::delete(&state.db, id)
+}
+
+/// Returns the characters whose tags changed
+#[tauri::command]
+pub async fn bulk_edit_tags(
+    state: State<'_, AppState>,
+    input: BulkTagEditInput,
+) -> Result<Vec<Character>, AppError> {
+    TagService::bulk_edit(&state.db, input)
+}
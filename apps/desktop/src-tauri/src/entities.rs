@@ -49,6 +49,9 @@ pub struct Character {
     pub speech_patterns: String,
     #[serde(default)]
     pub alternate_greetings: Vec<String>,
+    /// Instructions sent after the chat history (card `post_history_instructions`)
+    #[serde(default)]
+    pub post_history_instructions: String,
     
     // Creator attribution
     #[serde(default)]
@@ -75,6 +78,22 @@ fn default_rating() -> String {
     "sfw".to_string()
 }
 
+/// A stored character for tests: Aria the bard, with `fields` (camelCase,
+/// as in the card JSON) laid over her
+#[cfg(test)]
+pub fn test_character(fields: serde_json::Value) -> Character {
+    let mut character = serde_json::json!({
+        "id": "c1", "name": "Aria", "description": "A bard", "personality": "Cheerful",
+        "systemPrompt": "", "firstMessage": "Hello!", "exampleDialogues": "",
+        "avatarPath": null, "tags": [], "isBundled": false,
+        "createdAt": 1, "updatedAt": 1, "deletedAt": null, "metadata": {},
+    });
+    if let (Some(character), Some(fields)) = (character.as_object_mut(), fields.as_object()) {
+        character.extend(fields.clone());
+    }
+    serde_json::from_value(character).expect("test character fields")
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateCharacterInput {
@@ -109,6 +128,8 @@ pub struct CreateCharacterInput {
     pub speech_patterns: String,
     #[serde(default)]
     pub alternate_greetings: Vec<String>,
+    #[serde(default)]
+    pub post_history_instructions: String,
     
     // Creator info
     #[serde(default)]
@@ -125,9 +146,18 @@ pub struct CreateCharacterInput {
     pub rating: String,
     #[serde(default)]
     pub genre_tags: Vec<String>,
+    
+    /// Card fields with no home on `Character`, kept so exports can
+    /// reproduce the card (see `CardMetadata`)
+    #[serde(default)]
+    pub card: Option<serde_json::Value>,
+    
+    /// Recorded on the first revision; defaults to a manual edit
+    #[serde(default)]
+    pub source: Option<RevisionSource>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateCharacterInput {
     pub name: Option<String>,
@@ -147,6 +177,8 @@ pub struct UpdateCharacterInput {
     pub physical_traits: Option<String>,
     pub speech_patterns: Option<String>,
     pub alternate_greetings: Option<Vec<String>>,
+    #[serde(default)]
+    pub post_history_instructions: Option<String>,
     
     // Creator info
     pub creator_name: Option<String>,
@@ -157,44 +189,454 @@ pub struct UpdateCharacterInput {
     pub pov_type: Option<String>,
     pub rating: Option<String>,
     pub genre_tags: Option<Vec<String>>,
+    
+    /// Recorded on the revision this update creates; defaults to a manual edit
+    #[serde(default)]
+    pub source: Option<RevisionSource>,
+}
+
+/// What changed a character, as recorded on its revisions
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum RevisionSource {
+    #[default]
+    Manual,
+    Import,
+    Generated,
+    Restore,
+}
+
+impl RevisionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RevisionSource::Manual => "manual",
+            RevisionSource::Import => "import",
+            RevisionSource::Generated => "generated",
+            RevisionSource::Restore => "restore",
+        }
+    }
+}
+
+impl FromStr for RevisionSource {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "manual" => Ok(RevisionSource::Manual),
+            "import" => Ok(RevisionSource::Import),
+            "generated" => Ok(RevisionSource::Generated),
+            "restore" => Ok(RevisionSource::Restore),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Snapshot of a character as it stood after one change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CharacterRevision {
+    pub id: String,
+    pub character_id: String,
+    /// Counts up from 1 per character
+    pub revision: i32,
+    pub source: RevisionSource,
+    pub created_at: i64,
+    pub snapshot: Character,
+}
+
+/// One field that differs between two revisions
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CharacterFieldChange {
+    /// camelCase field name; card-only metadata shows as `metadata.<key>`
+    pub field: String,
+    pub before: serde_json::Value,
+    pub after: serde_json::Value,
+}
+
+/// Character fields that can be regenerated or rewritten on their own
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum RefinableField {
+    Description,
+    Personality,
+    Scenario,
+    Backstory,
+    PhysicalTraits,
+    SpeechPatterns,
+    ExampleDialogues,
+    FirstMessage,
+    AlternateGreeting,
+}
+
+/// Ask for a new version of one field of a character
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefineCharacterFieldInput {
+    pub character_id: String,
+    pub field: RefinableField,
+    /// Free-text direction such as "make her more sarcastic"; without one
+    /// the field is written afresh
+    #[serde(default)]
+    pub instruction: Option<String>,
+    /// Which alternate greeting to rewrite; None writes an extra one
+    #[serde(default)]
+    pub greeting_index: Option<usize>,
+}
+
+/// A suggested new value for one field. Nothing is saved until `update`
+/// is passed to `update_character`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CharacterFieldProposal {
+    pub character_id: String,
+    pub field: RefinableField,
+    pub before: String,
+    pub after: String,
+    pub update: UpdateCharacterInput,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub enum LintSeverity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// The check a lint issue comes from
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LintRule {
+    /// The character's name written out where `{{char}}` belongs
+    LiteralName,
+    /// A greeting that speaks or acts for `{{user}}`
+    UserInGreeting,
+    /// Example dialogues not split into `<START>` blocks
+    MissingStart,
+    EmptyField,
+    OverBudget,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LintIssue {
+    pub rule: LintRule,
+    pub severity: LintSeverity,
+    /// camelCase character field, when the issue is about one
+    pub field: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldTokenCost {
+    pub field: String,
+    pub tokens: i32,
+    /// Sent with every prompt, rather than once as a chat message
+    pub in_prompt: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CharacterLintReport {
+    pub character_id: String,
+    pub fields: Vec<FieldTokenCost>,
+    /// Tokens the card takes from every prompt
+    pub prompt_tokens: i32,
+    pub context_size: i32,
+    /// `prompt_tokens` as a fraction of `context_size`
+    pub context_share: f32,
+    /// What is left for chat history once the card, the response reserve
+    /// and the lorebook, memory and summary budgets are taken out
+    pub history_tokens: i32,
+    pub issues: Vec<LintIssue>,
+}
+
+/// Sort orders for the character library
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum CharacterSort {
+    #[default]
+    Name,
+    Created,
+    Updated,
+    LastChatted,
+    MessageCount,
+    /// Best full-text match first; same as `Name` without a search
+    Relevance,
+}
+
+impl CharacterSort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CharacterSort::Name => "name",
+            CharacterSort::Created => "created",
+            CharacterSort::Updated => "updated",
+            CharacterSort::LastChatted => "lastChatted",
+            CharacterSort::MessageCount => "messageCount",
+            CharacterSort::Relevance => "relevance",
+        }
+    }
+}
+
+impl FromStr for CharacterSort {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(CharacterSort::Name),
+            "created" => Ok(CharacterSort::Created),
+            "updated" => Ok(CharacterSort::Updated),
+            "lastChatted" => Ok(CharacterSort::LastChatted),
+            "messageCount" => Ok(CharacterSort::MessageCount),
+            "relevance" => Ok(CharacterSort::Relevance),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Character library query. Filters combine with AND; within `tags` and
+/// `genre_tags` every tag must be present, within `ratings` and
+/// `pov_types` any value matches.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CharacterQuery {
+    /// Full-text search over name, description and creator
+    #[serde(default)]
+    pub search: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub genre_tags: Vec<String>,
+    #[serde(default)]
+    pub ratings: Vec<String>,
+    #[serde(default)]
+    pub pov_types: Vec<String>,
+    #[serde(default)]
+    pub sort: CharacterSort,
+    #[serde(default)]
+    pub descending: bool,
+    #[serde(default)]
+    pub offset: u32,
+    /// Page size; defaults to 50, capped at 200
+    #[serde(default)]
+    pub limit: Option<u32>,
 }
 
-// Character Card V2 - wrapper structure
+/// A character in query results, with its chat activity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CharacterListing {
+    #[serde(flatten)]
+    pub character: Character,
+    pub last_chatted_at: Option<i64>,
+    /// Messages on the active branches of the character's conversations
+    pub message_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CharacterPage {
+    pub characters: Vec<CharacterListing>,
+    /// Matches across all pages
+    pub total: i64,
+    pub offset: u32,
+    pub limit: u32,
+}
+
+/// Plain tags (`Character::tags`) and genre tags (`Character::genre_tags`)
+/// are kept apart
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum TagKind {
+    #[default]
+    Tag,
+    Genre,
+}
+
+impl TagKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TagKind::Tag => "tag",
+            TagKind::Genre => "genre",
+        }
+    }
+}
+
+impl FromStr for TagKind {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tag" => Ok(TagKind::Tag),
+            "genre" => Ok(TagKind::Genre),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub id: i64,
+    pub kind: TagKind,
+    pub name: String,
+    /// Characters (not deleted) carrying the tag
+    pub usage_count: i64,
+    pub created_at: i64,
+}
+
+/// Add and remove tags on many characters at once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkTagEditInput {
+    pub character_ids: Vec<String>,
+    #[serde(default)]
+    pub kind: TagKind,
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+/// What a batch import does with a card that was imported before
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum DuplicatePolicy {
+    #[default]
+    Skip,
+    /// Overwrite the earlier import in place, keeping its chats
+    Replace,
+    /// Import it again as a separate character
+    KeepBoth,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum BatchImportStatus {
+    Imported,
+    Replaced,
+    Skipped,
+    Failed,
+}
+
+/// Outcome for one file of a batch import
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchImportItem {
+    /// Path relative to the folder or archive
+    pub file: String,
+    pub status: BatchImportStatus,
+    pub character_id: Option<String>,
+    pub character_name: Option<String>,
+    /// The earlier import this card matched
+    pub duplicate_of: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchImportReport {
+    pub imported: usize,
+    pub replaced: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub items: Vec<BatchImportItem>,
+}
+
+/// Character card spec versions
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum CardSpec {
+    #[default]
+    V2,
+    V3,
+}
+
+impl CardSpec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CardSpec::V2 => "chara_card_v2",
+            CardSpec::V3 => "chara_card_v3",
+        }
+    }
+
+    pub fn version(&self) -> &'static str {
+        match self {
+            CardSpec::V2 => "2.0",
+            CardSpec::V3 => "3.0",
+        }
+    }
+}
+
+impl FromStr for CardSpec {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chara_card_v2" => Ok(CardSpec::V2),
+            "chara_card_v3" => Ok(CardSpec::V3),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Card exporters sometimes write `null` for empty fields
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+// Character Card V2/V3 - wrapper structure
 // Note: No rename_all to accept both snake_case and camelCase field names
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CharacterCardV2 {
     pub spec: String,
-    #[serde(alias = "specVersion")]
+    #[serde(default, alias = "specVersion")]
     pub spec_version: String,
     pub data: CharacterCardDataV2,
+    /// Anything outside `data`, such as the V1 copy SillyTavern writes
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
-// Character Card V2 Data - the actual character data
+// Character Card V2 Data - the actual character data. V3 adds fields
+// (assets, nickname, group_only_greetings, ...) which land in `extra`.
 // Note: No rename_all to accept snake_case by default with camelCase aliases
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CharacterCardDataV2 {
     pub name: String,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub description: String,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub personality: String,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub scenario: String,
-    #[serde(default, alias = "firstMes")]
+    #[serde(default, alias = "firstMes", deserialize_with = "null_as_default")]
     pub first_mes: String,
-    #[serde(default, alias = "mesExample")]
+    #[serde(default, alias = "mesExample", deserialize_with = "null_as_default")]
     pub mes_example: String,
-    #[serde(default, alias = "systemPrompt")]
+    #[serde(default, alias = "creatorNotes", deserialize_with = "null_as_default")]
+    pub creator_notes: String,
+    #[serde(default, alias = "systemPrompt", deserialize_with = "null_as_default")]
     pub system_prompt: String,
-    #[serde(default)]
+    #[serde(default, alias = "postHistoryInstructions", deserialize_with = "null_as_default")]
+    pub post_history_instructions: String,
+    #[serde(default, alias = "alternateGreetings", deserialize_with = "null_as_default")]
+    pub alternate_greetings: Vec<String>,
+    /// Embedded lorebook, imported as a lorebook linked to the character
+    #[serde(default, alias = "characterBook", skip_serializing_if = "Option::is_none")]
+    pub character_book: Option<serde_json::Value>,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub tags: Vec<String>,
-    // Additional V2 fields that may be present
-    #[serde(default, alias = "creatorNotes")]
-    pub creator_notes: Option<String>,
-    #[serde(default)]
-    pub creator: Option<String>,
-    #[serde(default, alias = "characterVersion")]
-    pub character_version: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub creator: String,
+    #[serde(default, alias = "characterVersion", deserialize_with = "null_as_default")]
+    pub character_version: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -218,6 +660,29 @@ pub struct CharacterCardV1 {
     
     #[serde(default, alias = "example_dialogue")]
     pub mes_example: String,
+    
+    // Not in the V1 spec, but some exporters add it
+    #[serde(default)]
+    pub system_prompt: String,
+    
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// What an imported card had beyond the `Character` fields, stored in
+/// character metadata under `card`
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CardMetadata {
+    pub spec: CardSpec,
+    #[serde(default)]
+    pub spec_version: String,
+    /// Unmapped fields inside `data`, `extensions` included
+    #[serde(default)]
+    pub data: serde_json::Map<String, serde_json::Value>,
+    /// Unmapped fields next to `data`
+    #[serde(default)]
+    pub root: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -273,6 +738,12 @@ pub struct CreateConversationInput {
     pub character_ids: Vec<String>,
     pub title: Option<String>,
     pub persona_id: Option<String>,
+    /// Opening to start on: 0 is `first_message`, 1.. index `alternate_greetings`
+    #[serde(default)]
+    pub greeting_index: Option<usize>,
+    /// Start on a random opening instead
+    #[serde(default)]
+    pub random_greeting: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -366,17 +837,102 @@ pub struct Lorebook {
     pub created_at: i64,
     pub updated_at: i64,
     pub deleted_at: Option<i64>,
+    /// Messages scanned for keywords; entries may override
+    pub scan_depth: Option<i32>,
+    /// Minimum similarity for vector activation; None uses the default
+    pub similarity_threshold: Option<f32>,
     pub metadata: serde_json::Value,
     pub entries: Vec<LorebookEntry>,
 }
 
+/// How secondary keys combine with a primary key match
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum SelectiveLogic {
+    /// At least one secondary key must match
+    #[default]
+    AndAny,
+    /// Every secondary key must match
+    AndAll,
+    /// No secondary key may match
+    NotAny,
+    /// At least one secondary key must be missing
+    NotAll,
+}
+
+impl SelectiveLogic {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SelectiveLogic::AndAny => "and_any",
+            SelectiveLogic::AndAll => "and_all",
+            SelectiveLogic::NotAny => "not_any",
+            SelectiveLogic::NotAll => "not_all",
+        }
+    }
+}
+
+impl FromStr for SelectiveLogic {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "and_any" => Ok(SelectiveLogic::AndAny),
+            "and_all" => Ok(SelectiveLogic::AndAll),
+            "not_any" => Ok(SelectiveLogic::NotAny),
+            "not_all" => Ok(SelectiveLogic::NotAll),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What can activate a lorebook entry
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ActivationMode {
+    #[default]
+    Keyword,
+    /// Similarity of the entry's embedding to the recent conversation
+    Vector,
+    /// Either of the above
+    Both,
+}
+
+impl ActivationMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActivationMode::Keyword => "keyword",
+            ActivationMode::Vector => "vector",
+            ActivationMode::Both => "both",
+        }
+    }
+
+    pub fn uses_vectors(&self) -> bool {
+        !matches!(self, ActivationMode::Keyword)
+    }
+}
+
+impl FromStr for ActivationMode {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keyword" => Ok(ActivationMode::Keyword),
+            "vector" => Ok(ActivationMode::Vector),
+            "both" => Ok(ActivationMode::Both),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LorebookEntry {
     pub id: String,
     pub lorebook_id: String,
     pub name: String,
+    /// Primary keys; `/pattern/flags` is a regex when `regex_keys` is set
     pub keywords: Vec<String>,
+    /// Checked against the same text once a primary key matches
+    pub secondary_keywords: Vec<String>,
+    pub selective_logic: SelectiveLogic,
     pub content: String,
     pub priority: i32,
     pub is_enabled: bool,
@@ -384,6 +940,34 @@ pub struct LorebookEntry {
     pub match_whole_word: bool,
     pub insertion_position: String,
     pub token_budget: Option<i32>,
+    /// Keys written as `/pattern/flags` are regexes; otherwise every key
+    /// matches literally
+    pub regex_keys: bool,
+    /// Only activates from chat text, never from other entries' content
+    pub exclude_recursion: bool,
+    /// Content is not scanned for further activations
+    pub prevent_recursion: bool,
+    /// Messages scanned for this entry; None uses the lorebook's depth
+    pub scan_depth: Option<i32>,
+    /// Always active, regardless of keywords
+    pub constant: bool,
+    /// Chance (0-100) that a matched entry actually activates
+    pub probability: i32,
+    /// At most one entry of a group is inserted
+    pub inclusion_group: Option<String>,
+    /// Relative weight for the random pick within a group
+    pub group_weight: i32,
+    /// Pick the group's highest priority entry instead of a random one
+    pub group_prioritize: bool,
+    /// Messages the entry stays active for after triggering
+    pub sticky: i32,
+    /// Messages after that before it can trigger again
+    pub cooldown: i32,
+    /// Messages the conversation needs before the entry can trigger
+    pub delay: i32,
+    pub activation_mode: ActivationMode,
+    /// Content has an embedding from some model
+    pub has_embedding: bool,
     pub created_at: i64,
     pub metadata: serde_json::Value,
 }
@@ -394,6 +978,10 @@ pub struct CreateLorebookInput {
     pub name: String,
     pub description: Option<String>,
     pub is_global: Option<bool>,
+    #[serde(default)]
+    pub scan_depth: Option<i32>,
+    #[serde(default)]
+    pub similarity_threshold: Option<f32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -403,6 +991,10 @@ pub struct UpdateLorebookInput {
     pub description: Option<String>,
     pub is_global: Option<bool>,
     pub is_enabled: Option<bool>,
+    #[serde(default)]
+    pub scan_depth: Option<i32>,
+    #[serde(default)]
+    pub similarity_threshold: Option<f32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -417,6 +1009,40 @@ pub struct CreateEntryInput {
     pub match_whole_word: Option<bool>,
     pub insertion_position: Option<String>,
     pub token_budget: Option<i32>,
+    #[serde(default)]
+    pub regex_keys: Option<bool>,
+    #[serde(default)]
+    pub exclude_recursion: Option<bool>,
+    #[serde(default)]
+    pub prevent_recursion: Option<bool>,
+    #[serde(default)]
+    pub secondary_keywords: Option<Vec<String>>,
+    #[serde(default)]
+    pub selective_logic: Option<SelectiveLogic>,
+    #[serde(default)]
+    pub scan_depth: Option<i32>,
+    #[serde(default)]
+    pub constant: Option<bool>,
+    #[serde(default)]
+    pub probability: Option<i32>,
+    #[serde(default)]
+    pub inclusion_group: Option<String>,
+    #[serde(default)]
+    pub group_weight: Option<i32>,
+    #[serde(default)]
+    pub group_prioritize: Option<bool>,
+    #[serde(default)]
+    pub sticky: Option<i32>,
+    #[serde(default)]
+    pub cooldown: Option<i32>,
+    #[serde(default)]
+    pub delay: Option<i32>,
+    #[serde(default)]
+    pub activation_mode: Option<ActivationMode>,
+    #[serde(default)]
+    pub is_enabled: Option<bool>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -431,6 +1057,80 @@ pub struct UpdateEntryInput {
     pub match_whole_word: Option<bool>,
     pub insertion_position: Option<String>,
     pub token_budget: Option<i32>,
+    #[serde(default)]
+    pub regex_keys: Option<bool>,
+    #[serde(default)]
+    pub exclude_recursion: Option<bool>,
+    #[serde(default)]
+    pub prevent_recursion: Option<bool>,
+    #[serde(default)]
+    pub secondary_keywords: Option<Vec<String>>,
+    #[serde(default)]
+    pub selective_logic: Option<SelectiveLogic>,
+    #[serde(default)]
+    pub scan_depth: Option<i32>,
+    #[serde(default)]
+    pub constant: Option<bool>,
+    #[serde(default)]
+    pub probability: Option<i32>,
+    #[serde(default)]
+    pub inclusion_group: Option<String>,
+    #[serde(default)]
+    pub group_weight: Option<i32>,
+    #[serde(default)]
+    pub group_prioritize: Option<bool>,
+    #[serde(default)]
+    pub sticky: Option<i32>,
+    #[serde(default)]
+    pub cooldown: Option<i32>,
+    #[serde(default)]
+    pub delay: Option<i32>,
+    #[serde(default)]
+    pub activation_mode: Option<ActivationMode>,
+}
+
+/// Lorebook JSON formats from other frontends
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LorebookFormat {
+    /// SillyTavern World Info file, entries keyed by uid
+    WorldInfo,
+    /// `character_book` from Character Card V2
+    CharacterBook,
+}
+
+/// What happened to a matched lorebook entry while building a prompt
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LoreTraceStatus {
+    Inserted,
+    /// Inserted, cut down to the entry's token budget
+    Truncated,
+    /// Dropped because the lorebook budget ran out
+    OverBudget,
+    FailedProbability,
+    /// Another entry of its inclusion group was picked
+    LostGroup,
+}
+
+/// Why one lorebook entry did or didn't make it into a prompt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoreTraceEntry {
+    pub entry_id: String,
+    pub lorebook_id: String,
+    pub name: String,
+    /// "keyword", "vector", "constant" or "sticky"
+    pub trigger: String,
+    pub keyword: Option<String>,
+    pub similarity: Option<f32>,
+    /// 0 = matched the chat, n = pulled in by recursion
+    pub depth: i32,
+    pub status: LoreTraceStatus,
+    /// Insertion position, when inserted
+    pub position: Option<String>,
+    /// Tokens used in the prompt
+    pub tokens: i32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -459,7 +1159,47 @@ pub struct GenerationSettings {
     #[serde(default)]
     pub example_dialogue_budget: Option<i32>,
     #[serde(default)]
+    pub example_dialogue_mode: Option<ExampleDialogueMode>,
+    /// Injected example messages are dropped once the chat has this many
+    /// character replies of its own
+    #[serde(default)]
+    pub example_dialogue_drop_after: Option<i32>,
+    #[serde(default)]
     pub stop_sequences: Option<Vec<String>>,
+    /// Extra lorebook scan passes over activated entry content (0 disables recursion)
+    #[serde(default)]
+    pub lorebook_recursion_depth: Option<i32>,
+}
+
+/// Where a character's example dialogues go in the prompt
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ExampleDialogueMode {
+    /// Pasted into the system prompt
+    #[default]
+    SystemPrompt,
+    /// Sent as user/assistant messages ahead of the history
+    Messages,
+}
+
+impl ExampleDialogueMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExampleDialogueMode::SystemPrompt => "systemPrompt",
+            ExampleDialogueMode::Messages => "messages",
+        }
+    }
+}
+
+impl FromStr for ExampleDialogueMode {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "systemPrompt" => Ok(ExampleDialogueMode::SystemPrompt),
+            "messages" => Ok(ExampleDialogueMode::Messages),
+            _ => Err(()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -476,6 +1216,9 @@ pub struct ModelSettings {
     pub gpu_layers: i32,
     #[serde(default)]
     pub sidecar_path: Option<String>,
+    /// Small GGUF used only for embeddings; falls back to the chat model when unset
+    #[serde(default)]
+    pub embedding_path: Option<String>,
 }
 
 impl Default for Settings {
@@ -491,7 +1234,10 @@ impl Default for Settings {
                 summary_budget: Some(300),
                 response_reserve: Some(512),
                 example_dialogue_budget: Some(500),
+                example_dialogue_mode: Some(ExampleDialogueMode::SystemPrompt),
+                example_dialogue_drop_after: Some(8),
                 stop_sequences: None,
+                lorebook_recursion_depth: Some(3),
             },
             app: AppSettings {
                 theme: "dark".to_string(),
@@ -501,11 +1247,23 @@ impl Default for Settings {
                 path: String::new(),
                 gpu_layers: 99,
                 sidecar_path: None,
+                embedding_path: None,
             },
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddingStatus {
+    /// Model that new vectors come from, if any model is loaded
+    pub model_id: Option<String>,
+    pub dedicated: bool,
+    pub total: i64,
+    /// Rows with no vector or a vector from a different model
+    pub pending: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AppInfo {
@@ -575,6 +1333,100 @@ pub struct QueueTask {
     pub metadata: serde_json::Value,
 }
 
+// ============================================
+// Background Jobs
+// ============================================
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum JobType {
+    MemoryExtraction,
+    Summarization,
+}
+
+impl JobType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobType::MemoryExtraction => "memory_extraction",
+            JobType::Summarization => "summarization",
+        }
+    }
+}
+
+impl FromStr for JobType {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory_extraction" => Ok(JobType::MemoryExtraction),
+            "summarization" => Ok(JobType::Summarization),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    Pending,
+    Processing,
+    Completed,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Processing => "processing",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(JobStatus::Pending),
+            "processing" => Ok(JobStatus::Processing),
+            "completed" => Ok(JobStatus::Completed),
+            "failed" => Ok(JobStatus::Failed),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Persisted low-priority work (memory extraction, summarization) that runs
+/// while no chat generation is active
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackgroundJob {
+    pub id: String,
+    pub job_type: JobType,
+    pub conversation_id: Option<String>,
+    pub character_id: Option<String>,
+    pub payload: serde_json::Value,
+    pub status: JobStatus,
+    pub priority: i32,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub next_run_at: i64,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobQueueStatus {
+    pub pending: i64,
+    pub processing: i64,
+    pub failed: i64,
+    /// Unfinished and recently failed jobs, most urgent first
+    pub jobs: Vec<BackgroundJob>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum DownloadStatus {
@@ -646,6 +1498,9 @@ pub struct ExportedCharacter {
     pub exported_at: String,
     pub character: Character,
     pub avatar_base64: Option<String>,
+    /// Lorebooks linked to the character, recreated and linked on import
+    #[serde(default)]
+    pub lorebooks: Vec<Lorebook>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -683,6 +1538,14 @@ pub struct ChatErrorEvent {
     pub error: String,
 }
 
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LorebookActivatedEvent {
+    pub conversation_id: String,
+    pub message_id: String,
+    pub entries: Vec<LoreTraceEntry>,
+}
+
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DownloadProgressEvent {
@@ -697,4 +1560,12 @@ pub struct DownloadProgressEvent {
 pub struct ModelStatusEvent {
     pub status: String,
     pub message: Option<String>,
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchImportProgressEvent {
+    pub processed: usize,
+    pub total: usize,
+    pub item: BatchImportItem,
+}
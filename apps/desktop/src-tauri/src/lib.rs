@@ -44,6 +44,7 @@ pub fn run() {
             
             let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(100);
             let (download_tx, download_rx) = tokio::sync::mpsc::channel(100);
+            let (embedding_tx, embedding_rx) = tokio::sync::mpsc::channel(100);
             
             let shutdown_notify = Arc::new(Notify::new());
             
@@ -52,6 +53,7 @@ pub fn run() {
                 paths, 
                 queue_tx, 
                 download_tx,
+                embedding_tx,
                 shutdown_notify.clone()
             );
             app.manage(state.clone());
@@ -63,6 +65,13 @@ pub fn run() {
                 }
             });
             
+            let state_for_gc = state.clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                if let Err(e) = crate::services::AvatarService::collect_unused(&state_for_gc.db, &state_for_gc.paths) {
+                    tracing::warn!("Avatar cleanup failed: {}", e);
+                }
+            });
+            
             let app_handle = app.handle().clone();
             let state_clone = state.clone();
             let shutdown_clone = shutdown_notify.clone();
@@ -77,6 +86,13 @@ pub fn run() {
                 crate::workers::download_worker::run(state_clone, app_handle, download_rx, shutdown_clone).await;
             });
             
+            let app_handle = app.handle().clone();
+            let state_clone = state.clone();
+            let shutdown_clone = shutdown_notify.clone();
+            tauri::async_runtime::spawn(async move {
+                crate::workers::embedding_worker::run(state_clone, app_handle, embedding_rx, shutdown_clone).await;
+            });
+            
             tracing::info!("Glee setup complete");
             
             // Explicitly show the main window after setup is complete
@@ -92,10 +108,28 @@ pub fn run() {
             crate::commands::character::create_character,
             crate::commands::character::get_character,
             crate::commands::character::list_characters,
+            crate::commands::character::query_characters,
             crate::commands::character::update_character,
             crate::commands::character::delete_character,
             crate::commands::character::import_character_card,
+            crate::commands::character::import_character_png,
+            crate::commands::character::import_character_charx,
+            crate::commands::character::import_character_batch,
+            crate::commands::character::list_character_revisions,
+            crate::commands::character::diff_character_revisions,
+            crate::commands::character::restore_character_revision,
+            crate::commands::character::lint_character,
             crate::commands::character::generate_character_from_prompt,
+            crate::commands::character::refine_character_field,
+            crate::commands::character::save_avatar,
+            crate::commands::character::get_avatar_thumbnail,
+            crate::commands::character::collect_unused_avatars,
+            // Tag commands
+            crate::commands::tag::list_tags,
+            crate::commands::tag::rename_tag,
+            crate::commands::tag::merge_tags,
+            crate::commands::tag::delete_tag,
+            crate::commands::tag::bulk_edit_tags,
             // Persona commands
             crate::commands::persona::create_persona,
             crate::commands::persona::get_persona,
@@ -131,6 +165,11 @@ pub fn run() {
             crate::commands::lorebook::delete_entry,
             crate::commands::lorebook::attach_to_conversation,
             crate::commands::lorebook::detach_from_conversation,
+            crate::commands::lorebook::attach_to_character,
+            crate::commands::lorebook::detach_from_character,
+            crate::commands::lorebook::list_character_lorebooks,
+            crate::commands::lorebook::import_lorebook,
+            crate::commands::lorebook::export_lorebook,
             // Settings commands
             crate::commands::settings::get_settings,
             crate::commands::settings::get_setting,
@@ -143,6 +182,7 @@ pub fn run() {
             crate::commands::system::stop_sidecar,
             crate::commands::system::restart_sidecar,
             crate::commands::system::health_check,
+            crate::commands::system::get_job_status,
             // Download commands
             crate::commands::download::start_model_download,
             crate::commands::download::pause_download,
@@ -151,12 +191,25 @@ pub fn run() {
             crate::commands::download::get_download_status,
             // Export commands
             crate::commands::export::export_character,
+            crate::commands::export::export_character_png,
+            crate::commands::export::export_character_card,
+            crate::commands::export::export_character_charx,
             crate::commands::export::export_conversation,
             crate::commands::export::export_all_data,
             crate::commands::export::import_character,
             crate::commands::export::import_data,
             // Setup commands
             crate::commands::setup::check_setup_status,
+            // Memory commands
+            crate::commands::memory::search_memories,
+            crate::commands::memory::search_lorebook_entries,
+            crate::commands::memory::set_embedding_quantization,
+            crate::commands::memory::get_embedding_status,
+            crate::commands::memory::start_reembed,
+            crate::commands::memory::list_summaries,
+            crate::commands::memory::update_summary,
+            crate::commands::memory::regenerate_summary,
+            crate::commands::memory::delete_summary,
         ]);
 
     builder
@@ -176,12 +229,20 @@ pub fn run() {
                     // Signal shutdown to all workers
                     state.shutdown();
                     
-                    // Take and stop sidecar synchronously
+                    // Take and stop sidecars synchronously
+                    let mut handles = Vec::new();
                     if let Some(sidecar_handle) = state.take_sidecar() {
                         tracing::info!("Stopping sidecar process...");
                         sidecar_handle.cancel_generation();
-                        
-                        // Block until sidecar is stopped
+                        handles.push(sidecar_handle);
+                    }
+                    if let Some(embedding_handle) = state.take_embedding_sidecar() {
+                        tracing::info!("Stopping embedding sidecar process...");
+                        handles.push(embedding_handle);
+                    }
+                    
+                    if !handles.is_empty() {
+                        // Block until sidecars are stopped
                         std::thread::spawn(move || {
                             let rt = tokio::runtime::Builder::new_current_thread()
                                 .enable_all()
@@ -189,15 +250,16 @@ pub fn run() {
                                 .unwrap();
                             
                             rt.block_on(async {
-                                if let Err(e) = crate::sidecar::stop_sidecar(sidecar_handle).await {
-                                    tracing::error!("Failed to stop sidecar: {}", e);
-                                } else {
-                                    tracing::info!("Sidecar stopped successfully");
+                                for sidecar_handle in handles {
+                                    if let Err(e) = crate::sidecar::stop_sidecar(sidecar_handle).await {
+                                        tracing::error!("Failed to stop sidecar: {}", e);
+                                    } else {
+                                        tracing::info!("Sidecar stopped successfully");
+                                    }
                                 }
                             });
                         }).join().ok();
                     }
-                    
                     tracing::info!("Cleanup complete");
                 }
                 tauri::RunEvent::Exit => {
@@ -13,7 +13,7 @@ pub struct CharacterRepo;
 
 impl CharacterRepo {
     fn build_metadata(character: &CreateCharacterInput) -> serde_json::Value {
-        serde_json::json!({
+        let mut metadata = serde_json::json!({
             "scenario": character.scenario,
             "backstory": character.backstory,
             "likes": character.likes,
@@ -21,13 +21,18 @@ impl CharacterRepo {
             "physicalTraits": character.physical_traits,
             "speechPatterns": character.speech_patterns,
             "alternateGreetings": character.alternate_greetings,
+            "postHistoryInstructions": character.post_history_instructions,
             "creatorName": character.creator_name,
             "creatorNotes": character.creator_notes,
             "characterVersion": character.character_version,
             "povType": character.pov_type,
             "rating": character.rating,
             "genreTags": character.genre_tags,
-        })
+        });
+        if let Some(card) = &character.card {
+            metadata["card"] = card.clone();
+        }
+        metadata
     }
     
     pub fn create(db: &Database, character: &CreateCharacterInput) -> AppResult<Character> {
@@ -77,6 +82,29 @@ impl CharacterRepo {
         )
     }
     
+    pub fn find_by_id_with_conn(conn: &rusqlite::Connection, id: &str) -> AppResult<Character> {
+        conn.query_row(
+            "SELECT * FROM characters WHERE id = ?1 AND deleted_at IS NULL",
+            params![id],
+            Self::row_to_character,
+        ).map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Record not found".to_string()),
+            _ => AppError::Database(e),
+        })
+    }
+    
+    /// Replace a character's tags or genre tags
+    pub fn set_tags_with_conn(conn: &rusqlite::Connection, id: &str, kind: TagKind, tags: &[String]) -> AppResult<Character> {
+        let json = serde_json::to_string(tags)?;
+        let sql = match kind {
+            TagKind::Tag => "UPDATE characters SET tags = ?1, updated_at = ?2 WHERE id = ?3",
+            TagKind::Genre => "UPDATE characters SET metadata = json_set(metadata, '$.genreTags', json(?1)), updated_at = ?2
+                               WHERE id = ?3",
+        };
+        conn.execute(sql, params![json, now_timestamp(), id])?;
+        Self::find_by_id_with_conn(conn, id)
+    }
+    
     pub fn find_all(db: &Database) -> AppResult<Vec<Character>> {
         db.query_all(
             "SELECT * FROM characters WHERE deleted_at IS NULL ORDER BY name ASC",
@@ -85,6 +113,90 @@ impl CharacterRepo {
         )
     }
     
+    /// One page of characters matching `query`. `fts_query` is the MATCH
+    /// expression for `query.search`, already made safe by the caller.
+    pub fn query(db: &Database, query: &CharacterQuery, fts_query: Option<&str>, limit: u32) -> AppResult<CharacterPage> {
+        let mut from = "characters c".to_string();
+        let mut conditions = vec!["c.deleted_at IS NULL".to_string()];
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        
+        if let Some(fts) = fts_query {
+            from.push_str(
+                " JOIN (SELECT character_id, bm25(characters_fts, 10.0, 1.0, 5.0) AS rank
+                  FROM characters_fts WHERE characters_fts MATCH ?) f ON f.character_id = c.id",
+            );
+            params.push(Box::new(fts.to_string()));
+        }
+        for (kind, tags) in [(TagKind::Tag, &query.tags), (TagKind::Genre, &query.genre_tags)] {
+            for tag in tags {
+                conditions.push(
+                    "c.id IN (SELECT ct.character_id FROM character_tags ct JOIN tags t ON t.id = ct.tag_id
+                     WHERE t.kind = ? AND t.name = ?)".to_string(),
+                );
+                params.push(Box::new(kind.as_str()));
+                params.push(Box::new(tag.trim().to_string()));
+            }
+        }
+        // Expressions match idx_characters_rating / idx_characters_pov
+        for (expr, values) in [
+            ("COALESCE(NULLIF(json_extract(c.metadata, '$.rating'), ''), 'sfw')", &query.ratings),
+            ("COALESCE(NULLIF(json_extract(c.metadata, '$.povType'), ''), 'any')", &query.pov_types),
+        ] {
+            if !values.is_empty() {
+                conditions.push(format!("{} IN ({})", expr, vec!["?"; values.len()].join(", ")));
+                params.extend(values.iter().map(|v| Box::new(v.clone()) as Box<dyn rusqlite::ToSql>));
+            }
+        }
+        let where_clause = conditions.join(" AND ");
+        
+        let total = {
+            let refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+            db.query_one(
+                &format!("SELECT COUNT(*) FROM {} WHERE {}", from, where_clause),
+                refs.as_slice(),
+                |row| row.get::<_, i64>(0),
+            )?
+        };
+        
+        let direction = if query.descending { "DESC" } else { "ASC" };
+        let order = match query.sort {
+            CharacterSort::Relevance if fts_query.is_some() => format!("f.rank {}", direction),
+            CharacterSort::Name | CharacterSort::Relevance => format!("c.name COLLATE NOCASE {}", direction),
+            CharacterSort::Created => format!("c.created_at {}", direction),
+            CharacterSort::Updated => format!("c.updated_at {}", direction),
+            CharacterSort::LastChatted => format!("last_chatted_at IS NULL, last_chatted_at {}", direction),
+            CharacterSort::MessageCount => format!("message_count {}", direction),
+        };
+        
+        let sql = format!(
+            "SELECT c.*,
+                (SELECT MAX(cv.updated_at) FROM conversation_characters cc
+                 JOIN conversations cv ON cv.id = cc.conversation_id
+                 WHERE cc.character_id = c.id AND cv.deleted_at IS NULL) AS last_chatted_at,
+                (SELECT COUNT(*) FROM conversation_characters cc
+                 JOIN conversations cv ON cv.id = cc.conversation_id
+                 JOIN messages m ON m.conversation_id = cc.conversation_id AND m.is_active_branch = 1
+                 WHERE cc.character_id = c.id AND cv.deleted_at IS NULL) AS message_count
+             FROM {} WHERE {}
+             ORDER BY {}, c.name COLLATE NOCASE ASC, c.id ASC
+             LIMIT ? OFFSET ?",
+            from, where_clause, order,
+        );
+        params.push(Box::new(limit));
+        params.push(Box::new(query.offset));
+        
+        let refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let characters = db.query_all(&sql, refs.as_slice(), |row| {
+            Ok(CharacterListing {
+                character: Self::row_to_character(row)?,
+                last_chatted_at: row.get("last_chatted_at")?,
+                message_count: row.get("message_count")?,
+            })
+        })?;
+        
+        Ok(CharacterPage { characters, total, offset: query.offset, limit })
+    }
+    
     pub fn update(db: &Database, id: &str, input: &UpdateCharacterInput) -> AppResult<Character> {
         let now = now_timestamp();
         let mut query = "UPDATE characters SET updated_at = ?".to_string();
@@ -128,7 +240,8 @@ impl CharacterRepo {
         let has_metadata_updates = input.scenario.is_some() || input.backstory.is_some() ||
             input.likes.is_some() || input.dislikes.is_some() ||
             input.physical_traits.is_some() || input.speech_patterns.is_some() ||
-            input.alternate_greetings.is_some() || input.creator_name.is_some() ||
+            input.alternate_greetings.is_some() || input.post_history_instructions.is_some() ||
+            input.creator_name.is_some() ||
             input.creator_notes.is_some() || input.character_version.is_some() ||
             input.pov_type.is_some() || input.rating.is_some() || input.genre_tags.is_some();
         
@@ -144,6 +257,7 @@ impl CharacterRepo {
             if let Some(v) = &input.physical_traits { metadata["physicalTraits"] = serde_json::json!(v); }
             if let Some(v) = &input.speech_patterns { metadata["speechPatterns"] = serde_json::json!(v); }
             if let Some(v) = &input.alternate_greetings { metadata["alternateGreetings"] = serde_json::json!(v); }
+            if let Some(v) = &input.post_history_instructions { metadata["postHistoryInstructions"] = serde_json::json!(v); }
             if let Some(v) = &input.creator_name { metadata["creatorName"] = serde_json::json!(v); }
             if let Some(v) = &input.creator_notes { metadata["creatorNotes"] = serde_json::json!(v); }
             if let Some(v) = &input.character_version { metadata["characterVersion"] = serde_json::json!(v); }
@@ -165,6 +279,77 @@ impl CharacterRepo {
         Self::find_by_id(db, id)
     }
     
+    /// Avatar files in use by live characters or their revisions
+    pub fn find_avatar_paths(db: &Database) -> AppResult<Vec<String>> {
+        db.query_all(
+            "SELECT avatar_path FROM characters WHERE deleted_at IS NULL AND avatar_path IS NOT NULL
+             UNION
+             SELECT json_extract(r.snapshot, '$.avatarPath') FROM character_revisions r
+             JOIN characters c ON c.id = r.character_id
+             WHERE c.deleted_at IS NULL AND json_extract(r.snapshot, '$.avatarPath') IS NOT NULL",
+            [],
+            |row| row.get(0),
+        )
+    }
+    
+    /// The most recently updated live character imported from the card with this hash
+    pub fn find_by_card_hash(db: &Database, hash: &str) -> AppResult<Option<Character>> {
+        db.query_optional(
+            "SELECT * FROM characters WHERE card_hash = ?1 AND deleted_at IS NULL
+             ORDER BY updated_at DESC LIMIT 1",
+            params![hash],
+            Self::row_to_character,
+        )
+    }
+
+    pub fn set_card_hash(db: &Database, id: &str, hash: &str) -> AppResult<()> {
+        db.execute(
+            "UPDATE characters SET card_hash = ?1 WHERE id = ?2",
+            params![hash, id],
+        )?;
+        Ok(())
+    }
+
+    /// Overwrite a character with freshly imported fields. Metadata keys the
+    /// input doesn't cover are kept.
+    pub fn replace(db: &Database, id: &str, character: &CreateCharacterInput) -> AppResult<Character> {
+        let mut metadata = Self::find_by_id(db, id)?.metadata;
+        if let (Some(current), serde_json::Value::Object(fresh)) = (metadata.as_object_mut(), Self::build_metadata(character)) {
+            current.extend(fresh);
+        } else {
+            metadata = Self::build_metadata(character);
+        }
+
+        db.execute(
+            "UPDATE characters SET name = ?1, description = ?2, personality = ?3, system_prompt = ?4,
+             first_message = ?5, example_dialogues = ?6, avatar_path = ?7, tags = ?8, metadata = ?9,
+             updated_at = ?10 WHERE id = ?11",
+            params![
+                character.name, character.description, character.personality, character.system_prompt,
+                character.first_message, character.example_dialogues, character.avatar_path,
+                serde_json::to_string(&character.tags)?, serde_json::to_string(&metadata)?,
+                now_timestamp(), id
+            ],
+        )?;
+        Self::find_by_id(db, id)
+    }
+
+    /// Overwrite every stored field with those of `snapshot` (a revision)
+    pub fn restore(db: &Database, snapshot: &Character) -> AppResult<Character> {
+        db.execute(
+            "UPDATE characters SET name = ?1, description = ?2, personality = ?3, system_prompt = ?4,
+             first_message = ?5, example_dialogues = ?6, avatar_path = ?7, tags = ?8, metadata = ?9,
+             updated_at = ?10 WHERE id = ?11",
+            params![
+                snapshot.name, snapshot.description, snapshot.personality, snapshot.system_prompt,
+                snapshot.first_message, snapshot.example_dialogues, snapshot.avatar_path,
+                serde_json::to_string(&snapshot.tags)?, serde_json::to_string(&snapshot.metadata)?,
+                now_timestamp(), snapshot.id
+            ],
+        )?;
+        Self::find_by_id(db, &snapshot.id)
+    }
+    
     pub fn delete(db: &Database, id: &str) -> AppResult<()> {
         let now = now_timestamp();
         db.execute(
@@ -174,6 +359,43 @@ impl CharacterRepo {
         Ok(())
     }
     
+    pub fn attach_lorebook(db: &Database, character_id: &str, lorebook_id: &str) -> AppResult<()> {
+        db.execute(
+            "INSERT OR IGNORE INTO character_lorebooks (character_id, lorebook_id, created_at) VALUES (?1, ?2, ?3)",
+            params![character_id, lorebook_id, now_timestamp()],
+        )?;
+        Ok(())
+    }
+    
+    pub fn detach_lorebook(db: &Database, character_id: &str, lorebook_id: &str) -> AppResult<()> {
+        db.execute(
+            "DELETE FROM character_lorebooks WHERE character_id = ?1 AND lorebook_id = ?2",
+            params![character_id, lorebook_id],
+        )?;
+        Ok(())
+    }
+    
+    /// Lorebooks linked to a character, oldest link first
+    pub fn find_lorebook_ids(db: &Database, character_id: &str) -> AppResult<Vec<String>> {
+        db.query_all(
+            "SELECT cl.lorebook_id FROM character_lorebooks cl
+             JOIN lorebooks l ON l.id = cl.lorebook_id
+             WHERE cl.character_id = ?1 AND l.deleted_at IS NULL
+             ORDER BY cl.created_at ASC",
+            params![character_id],
+            |row| row.get::<_, String>(0),
+        )
+    }
+    
+    /// Every (character_id, lorebook_id) link, oldest first
+    pub fn find_all_lorebook_links(db: &Database) -> AppResult<Vec<(String, String)>> {
+        db.query_all(
+            "SELECT character_id, lorebook_id FROM character_lorebooks ORDER BY created_at ASC",
+            [],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+        )
+    }
+    
     pub fn row_to_character(row: &rusqlite::Row<'_>) -> rusqlite::Result<Character> {
         let tags_str: String = row.get("tags")?;
         let metadata_str: String = row.get("metadata")?;
@@ -202,6 +424,7 @@ impl CharacterRepo {
             physical_traits: metadata.get("physicalTraits").and_then(|v| v.as_str()).unwrap_or("").to_string(),
             speech_patterns: metadata.get("speechPatterns").and_then(|v| v.as_str()).unwrap_or("").to_string(),
             alternate_greetings: metadata.get("alternateGreetings").and_then(|v| serde_json::from_value(v.clone()).ok()).unwrap_or_default(),
+            post_history_instructions: metadata.get("postHistoryInstructions").and_then(|v| v.as_str()).unwrap_or("").to_string(),
             
             // Creator info from metadata
             creator_name: metadata.get("creatorName").and_then(|v| v.as_str()).unwrap_or("").to_string(),
@@ -218,6 +441,156 @@ impl CharacterRepo {
     }
 }
 
+// ============================================
+// Character Revision Repository
+// ============================================
+
+pub struct CharacterRevisionRepo;
+
+impl CharacterRevisionRepo {
+    /// Store `character` as its next revision. Run inside a transaction so
+    /// the number can't be taken twice.
+    pub fn create_with_conn(conn: &rusqlite::Connection, character: &Character, source: RevisionSource) -> AppResult<CharacterRevision> {
+        let id = new_id();
+        let now = now_timestamp();
+        let snapshot = serde_json::to_string(character)?;
+        
+        let revision: i32 = conn.query_row(
+            "SELECT COALESCE(MAX(revision), 0) + 1 FROM character_revisions WHERE character_id = ?1",
+            params![character.id],
+            |row| row.get(0),
+        )?;
+        conn.execute(
+            "INSERT INTO character_revisions (id, character_id, revision, source, snapshot, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id, character.id, revision, source.as_str(), snapshot, now],
+        )?;
+        
+        Ok(CharacterRevision {
+            id,
+            character_id: character.id.clone(),
+            revision,
+            source,
+            created_at: now,
+            snapshot: character.clone(),
+        })
+    }
+    
+    /// Newest first
+    pub fn find_by_character(db: &Database, character_id: &str) -> AppResult<Vec<CharacterRevision>> {
+        db.query_all(
+            "SELECT * FROM character_revisions WHERE character_id = ?1 ORDER BY revision DESC",
+            params![character_id],
+            Self::row_to_revision,
+        )
+    }
+    
+    pub fn find(db: &Database, character_id: &str, revision: i32) -> AppResult<CharacterRevision> {
+        db.query_optional(
+            "SELECT * FROM character_revisions WHERE character_id = ?1 AND revision = ?2",
+            params![character_id, revision],
+            Self::row_to_revision,
+        )?.ok_or_else(|| AppError::NotFound(format!("Revision {} of character {}", revision, character_id)))
+    }
+    
+    pub fn find_latest_with_conn(conn: &rusqlite::Connection, character_id: &str) -> AppResult<Option<CharacterRevision>> {
+        use rusqlite::OptionalExtension;
+        
+        conn.query_row(
+            "SELECT * FROM character_revisions WHERE character_id = ?1 ORDER BY revision DESC LIMIT 1",
+            params![character_id],
+            Self::row_to_revision,
+        ).optional().map_err(AppError::from)
+    }
+    
+    fn row_to_revision(row: &rusqlite::Row<'_>) -> rusqlite::Result<CharacterRevision> {
+        let source: String = row.get("source")?;
+        let snapshot: String = row.get("snapshot")?;
+        
+        Ok(CharacterRevision {
+            id: row.get("id")?,
+            character_id: row.get("character_id")?,
+            revision: row.get("revision")?,
+            source: RevisionSource::from_str(&source).unwrap_or_default(),
+            created_at: row.get("created_at")?,
+            snapshot: serde_json::from_str(&snapshot).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+            })?,
+        })
+    }
+}
+
+// ============================================
+// Tag Repository
+// Links are written by triggers from the characters' JSON tag arrays
+// ============================================
+
+pub struct TagRepo;
+
+impl TagRepo {
+    const SELECT: &'static str =
+        "SELECT t.*, (SELECT COUNT(*) FROM character_tags ct JOIN characters c ON c.id = ct.character_id
+                      WHERE ct.tag_id = t.id AND c.deleted_at IS NULL) AS usage_count
+         FROM tags t";
+    
+    pub fn find_all(db: &Database, kind: Option<TagKind>) -> AppResult<Vec<Tag>> {
+        db.query_all(
+            &format!("{} WHERE ?1 IS NULL OR t.kind = ?1 ORDER BY t.kind, t.name", Self::SELECT),
+            params![kind.map(|k| k.as_str())],
+            Self::row_to_tag,
+        )
+    }
+    
+    pub fn find_by_id(db: &Database, id: i64) -> AppResult<Tag> {
+        db.query_optional(&format!("{} WHERE t.id = ?1", Self::SELECT), params![id], Self::row_to_tag)?
+            .ok_or_else(|| AppError::NotFound(format!("Tag {}", id)))
+    }
+    
+    /// Case-insensitive
+    pub fn find_by_name(db: &Database, kind: TagKind, name: &str) -> AppResult<Option<Tag>> {
+        db.query_optional(
+            &format!("{} WHERE t.kind = ?1 AND t.name = ?2", Self::SELECT),
+            params![kind.as_str(), name],
+            Self::row_to_tag,
+        )
+    }
+    
+    // Transaction-aware methods, so a tag change and the character
+    // rewrites it causes land together
+    
+    /// Characters (not deleted) carrying the tag
+    pub fn find_character_ids_with_conn(conn: &rusqlite::Connection, id: i64) -> AppResult<Vec<String>> {
+        let mut stmt = conn.prepare(
+            "SELECT ct.character_id FROM character_tags ct JOIN characters c ON c.id = ct.character_id
+             WHERE ct.tag_id = ?1 AND c.deleted_at IS NULL",
+        )?;
+        let ids = stmt.query_map(params![id], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(ids)
+    }
+    
+    pub fn rename_with_conn(conn: &rusqlite::Connection, id: i64, name: &str) -> AppResult<()> {
+        conn.execute("UPDATE tags SET name = ?1 WHERE id = ?2", params![name, id])?;
+        Ok(())
+    }
+    
+    pub fn delete_with_conn(conn: &rusqlite::Connection, id: i64) -> AppResult<()> {
+        conn.execute("DELETE FROM tags WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+    
+    fn row_to_tag(row: &rusqlite::Row<'_>) -> rusqlite::Result<Tag> {
+        let kind: String = row.get("kind")?;
+        Ok(Tag {
+            id: row.get("id")?,
+            kind: TagKind::from_str(&kind).unwrap_or_default(),
+            name: row.get("name")?,
+            usage_count: row.get("usage_count")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
 // ============================================
 // Persona Repository
 // ============================================
@@ -540,6 +913,15 @@ impl ConversationRepo {
         Self::find_by_id(db, id)
     }
     
+    pub fn update_metadata(db: &Database, id: &str, metadata: &serde_json::Value) -> AppResult<()> {
+        let json = serde_json::to_string(metadata)?;
+        db.execute(
+            "UPDATE conversations SET metadata = ?1 WHERE id = ?2",
+            params![json, id],
+        )?;
+        Ok(())
+    }
+    
     pub fn update_active_message(db: &Database, id: &str, message_id: &str) -> AppResult<()> {
         let now = now_timestamp();
         db.execute(
@@ -937,11 +1319,12 @@ impl LorebookRepo {
         let now = now_timestamp();
         
         db.execute(
-            "INSERT INTO lorebooks (id, name, description, is_global, is_enabled, created_at, updated_at, metadata)
-             VALUES (?1, ?2, ?3, ?4, 1, ?5, ?6, '{}')",
+            "INSERT INTO lorebooks (id, name, description, is_global, is_enabled, created_at, updated_at, metadata,
+             scan_depth, similarity_threshold)
+             VALUES (?1, ?2, ?3, ?4, 1, ?5, ?6, '{}', ?7, ?8)",
             params![
                 id, input.name, input.description.clone().unwrap_or_default(),
-                input.is_global.unwrap_or(false), now, now
+                input.is_global.unwrap_or(false), now, now, input.scan_depth, input.similarity_threshold
             ],
         )?;
         
@@ -1035,6 +1418,14 @@ impl LorebookRepo {
             query.push_str(", is_enabled = ?");
             params.push(Box::new(v));
         }
+        if let Some(v) = input.scan_depth {
+            query.push_str(", scan_depth = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = input.similarity_threshold {
+            query.push_str(", similarity_threshold = ?");
+            params.push(Box::new(v));
+        }
         
         query.push_str(" WHERE id = ?");
         params.push(Box::new(id.to_string()));
@@ -1045,27 +1436,64 @@ impl LorebookRepo {
         Self::find_by_id(db, id)
     }
     
+    pub fn update_metadata(db: &Database, id: &str, metadata: &serde_json::Value) -> AppResult<()> {
+        let json = serde_json::to_string(metadata)?;
+        db.execute(
+            "UPDATE lorebooks SET metadata = ?1, updated_at = ?2 WHERE id = ?3",
+            params![json, now_timestamp(), id],
+        )?;
+        Ok(())
+    }
+    
     pub fn delete(db: &Database, id: &str) -> AppResult<()> {
         let now = now_timestamp();
         db.execute("UPDATE lorebooks SET deleted_at = ?1 WHERE id = ?2", params![now, id])?;
         Ok(())
     }
     
+    /// Whether any live character or conversation still uses the lorebook
+    pub fn is_linked(db: &Database, id: &str) -> AppResult<bool> {
+        db.query_one(
+            "SELECT EXISTS (SELECT 1 FROM character_lorebooks cl JOIN characters c ON c.id = cl.character_id
+                            WHERE cl.lorebook_id = ?1 AND c.deleted_at IS NULL)
+                 OR EXISTS (SELECT 1 FROM conversation_lorebooks cl JOIN conversations c ON c.id = cl.conversation_id
+                            WHERE cl.lorebook_id = ?1 AND c.deleted_at IS NULL)",
+            params![id],
+            |row| row.get(0),
+        )
+    }
+    
     pub fn create_entry(db: &Database, input: &CreateEntryInput) -> AppResult<LorebookEntry> {
         let id = new_id();
         let now = now_timestamp();
         let keywords_json = serde_json::to_string(&input.keywords)?;
+        let secondary_json = serde_json::to_string(&input.secondary_keywords.clone().unwrap_or_default())?;
+        let metadata_json = serde_json::to_string(&input.metadata.clone().unwrap_or_else(|| serde_json::json!({})))?;
         
         db.execute(
             "INSERT INTO lorebook_entries (id, lorebook_id, name, keywords, content, priority, 
-             case_sensitive, match_whole_word, insertion_position, token_budget, created_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+             case_sensitive, match_whole_word, insertion_position, token_budget, created_at,
+             exclude_recursion, prevent_recursion, secondary_keywords, selective_logic, scan_depth,
+             constant, probability, inclusion_group, group_weight, group_prioritize,
+             sticky, cooldown, delay, activation_mode, is_enabled, metadata, regex_keys)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21,
+                     ?22, ?23, ?24, ?25, ?26, ?27, ?28)",
             params![
                 id, input.lorebook_id, input.name, keywords_json, input.content,
                 input.priority.unwrap_or(50), input.case_sensitive.unwrap_or(false),
                 input.match_whole_word.unwrap_or(true),
                 input.insertion_position.as_deref().unwrap_or("after_system"),
-                input.token_budget, now
+                input.token_budget, now,
+                input.exclude_recursion.unwrap_or(false), input.prevent_recursion.unwrap_or(false),
+                secondary_json, input.selective_logic.unwrap_or_default().as_str(), input.scan_depth,
+                input.constant.unwrap_or(false), input.probability.unwrap_or(100),
+                input.inclusion_group.as_deref().filter(|g| !g.trim().is_empty()),
+                input.group_weight.unwrap_or(100),
+                input.group_prioritize.unwrap_or(false),
+                input.sticky.unwrap_or(0), input.cooldown.unwrap_or(0), input.delay.unwrap_or(0),
+                input.activation_mode.unwrap_or_default().as_str(),
+                input.is_enabled.unwrap_or(true), metadata_json,
+                input.regex_keys.unwrap_or(false)
             ],
         )?;
         
@@ -1117,6 +1545,68 @@ impl LorebookRepo {
             query.push_str(", token_budget = ?");
             params.push(Box::new(v));
         }
+        if let Some(v) = input.regex_keys {
+            query.push_str(", regex_keys = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = input.exclude_recursion {
+            query.push_str(", exclude_recursion = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = input.prevent_recursion {
+            query.push_str(", prevent_recursion = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = &input.secondary_keywords {
+            let json = serde_json::to_string(v)?;
+            query.push_str(", secondary_keywords = ?");
+            params.push(Box::new(json));
+        }
+        if let Some(v) = input.selective_logic {
+            query.push_str(", selective_logic = ?");
+            params.push(Box::new(v.as_str()));
+        }
+        if let Some(v) = input.scan_depth {
+            query.push_str(", scan_depth = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = input.constant {
+            query.push_str(", constant = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = input.probability {
+            query.push_str(", probability = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = &input.inclusion_group {
+            // An empty group name takes the entry out of its group
+            query.push_str(", inclusion_group = ?");
+            params.push(Box::new(Some(v.clone()).filter(|g| !g.trim().is_empty())));
+        }
+        if let Some(v) = input.group_weight {
+            query.push_str(", group_weight = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = input.group_prioritize {
+            query.push_str(", group_prioritize = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = input.sticky {
+            query.push_str(", sticky = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = input.cooldown {
+            query.push_str(", cooldown = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = input.delay {
+            query.push_str(", delay = ?");
+            params.push(Box::new(v));
+        }
+        if let Some(v) = input.activation_mode {
+            query.push_str(", activation_mode = ?");
+            params.push(Box::new(v.as_str()));
+        }
         
         query.push_str(" WHERE id = ?");
         params.push(Box::new(id.to_string()));
@@ -1131,11 +1621,60 @@ impl LorebookRepo {
         )
     }
     
+    pub fn find_entry(db: &Database, id: &str) -> AppResult<LorebookEntry> {
+        db.query_one(
+            "SELECT * FROM lorebook_entries WHERE id = ?1",
+            params![id],
+            Self::row_to_entry,
+        )
+    }
+    
     pub fn delete_entry(db: &Database, id: &str) -> AppResult<()> {
         db.execute("DELETE FROM lorebook_entries WHERE id = ?1", params![id])?;
         Ok(())
     }
     
+    pub fn set_has_embedding(db: &Database, id: &str, has_embedding: bool) -> AppResult<()> {
+        db.execute(
+            "UPDATE lorebook_entries SET has_embedding = ?1 WHERE id = ?2",
+            params![has_embedding, id],
+        )?;
+        Ok(())
+    }
+    
+    /// Record entries that triggered while `anchor_message_id` was the newest message
+    pub fn record_activations(
+        db: &Database,
+        conversation_id: &str,
+        anchor_message_id: &str,
+        entry_ids: &[&str],
+    ) -> AppResult<()> {
+        if entry_ids.is_empty() {
+            return Ok(());
+        }
+        
+        let now = now_timestamp();
+        db.transaction(|conn| {
+            let mut stmt = conn.prepare(
+                "INSERT OR IGNORE INTO lorebook_activations (conversation_id, entry_id, anchor_message_id, created_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for entry_id in entry_ids {
+                stmt.execute(params![conversation_id, entry_id, anchor_message_id, now])?;
+            }
+            Ok(())
+        })
+    }
+    
+    /// All recorded triggers for a conversation as (entry_id, anchor_message_id)
+    pub fn find_activations(db: &Database, conversation_id: &str) -> AppResult<Vec<(String, String)>> {
+        db.query_all(
+            "SELECT entry_id, anchor_message_id FROM lorebook_activations WHERE conversation_id = ?1",
+            params![conversation_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+    
     fn row_to_lorebook(row: &rusqlite::Row<'_>) -> rusqlite::Result<Lorebook> {
         let metadata_str: String = row.get("metadata")?;
         Ok(Lorebook {
@@ -1147,6 +1686,8 @@ impl LorebookRepo {
             created_at: row.get("created_at")?,
             updated_at: row.get("updated_at")?,
             deleted_at: row.get("deleted_at")?,
+            scan_depth: row.get("scan_depth")?,
+            similarity_threshold: row.get::<_, Option<f64>>("similarity_threshold")?.map(|t| t as f32),
             metadata: serde_json::from_str(&metadata_str).unwrap_or_default(),
             entries: vec![],
         })
@@ -1154,6 +1695,9 @@ impl LorebookRepo {
     
     fn row_to_entry(row: &rusqlite::Row<'_>) -> rusqlite::Result<LorebookEntry> {
         let keywords_str: String = row.get("keywords")?;
+        let secondary_str: String = row.get("secondary_keywords")?;
+        let logic_str: String = row.get("selective_logic")?;
+        let mode_str: String = row.get("activation_mode")?;
         let metadata_str: String = row.get("metadata")?;
         
         Ok(LorebookEntry {
@@ -1161,6 +1705,8 @@ impl LorebookRepo {
             lorebook_id: row.get("lorebook_id")?,
             name: row.get("name")?,
             keywords: serde_json::from_str(&keywords_str).unwrap_or_default(),
+            secondary_keywords: serde_json::from_str(&secondary_str).unwrap_or_default(),
+            selective_logic: SelectiveLogic::from_str(&logic_str).unwrap_or_default(),
             content: row.get("content")?,
             priority: row.get("priority")?,
             is_enabled: row.get::<_, i32>("is_enabled")? != 0,
@@ -1168,6 +1714,20 @@ impl LorebookRepo {
             match_whole_word: row.get::<_, i32>("match_whole_word")? != 0,
             insertion_position: row.get("insertion_position")?,
             token_budget: row.get("token_budget")?,
+            regex_keys: row.get::<_, i32>("regex_keys")? != 0,
+            exclude_recursion: row.get::<_, i32>("exclude_recursion")? != 0,
+            prevent_recursion: row.get::<_, i32>("prevent_recursion")? != 0,
+            scan_depth: row.get("scan_depth")?,
+            constant: row.get::<_, i32>("constant")? != 0,
+            probability: row.get("probability")?,
+            inclusion_group: row.get("inclusion_group")?,
+            group_weight: row.get("group_weight")?,
+            group_prioritize: row.get::<_, i32>("group_prioritize")? != 0,
+            sticky: row.get("sticky")?,
+            cooldown: row.get("cooldown")?,
+            delay: row.get("delay")?,
+            activation_mode: ActivationMode::from_str(&mode_str).unwrap_or_default(),
+            has_embedding: row.get::<_, Option<i32>>("has_embedding")?.unwrap_or(0) != 0,
             created_at: row.get("created_at")?,
             metadata: serde_json::from_str(&metadata_str).unwrap_or_default(),
         })
@@ -1216,12 +1776,20 @@ impl SettingsRepo {
         settings.generation.max_tokens = parse_i32("generation.max_tokens", 512);
         settings.generation.top_p = parse_f32("generation.top_p", 0.9);
         settings.generation.context_size = parse_i32("generation.context_size", 4096);
+        settings.generation.lorebook_recursion_depth = Some(parse_i32("generation.lorebook_recursion_depth", 3));
+        settings.generation.example_dialogue_budget = Some(parse_i32("generation.example_dialogue_budget", 500));
+        settings.generation.example_dialogue_mode = Some(
+            parse("generation.example_dialogue_mode", String::new()).replace('"', "").parse().unwrap_or_default()
+        );
+        settings.generation.example_dialogue_drop_after = Some(parse_i32("generation.example_dialogue_drop_after", 8));
         
         settings.app.theme = parse("app.theme", "\"dark\"".to_string()).replace("\"", "");
         settings.app.first_run = parse_bool("app.first_run", true);
         
         settings.model.path = parse("model.path", "".to_string());
         settings.model.gpu_layers = parse_i32("model.gpu_layers", 99);
+        let embedding_path = parse("model.embedding_path", String::new()).replace("\"", "");
+        settings.model.embedding_path = (!embedding_path.is_empty()).then_some(embedding_path);
         
         Ok(settings)
     }
@@ -1329,6 +1897,175 @@ impl QueueRepo {
     }
 }
 
+// ============================================
+// Background Job Repository
+// ============================================
+
+pub struct JobRepo;
+
+impl JobRepo {
+    pub fn enqueue(db: &Database, job: &BackgroundJob) -> AppResult<BackgroundJob> {
+        db.execute(
+            "INSERT INTO background_jobs (id, job_type, conversation_id, character_id, payload, status,
+             priority, attempts, max_attempts, next_run_at, last_error, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                job.id, job.job_type.as_str(), job.conversation_id, job.character_id,
+                job.payload.to_string(), job.status.as_str(), job.priority, job.attempts,
+                job.max_attempts, job.next_run_at, job.last_error, job.created_at, job.updated_at
+            ],
+        )?;
+        Ok(job.clone())
+    }
+    
+    /// Pending (not yet started) job of a type for a conversation/character, if any
+    pub fn find_pending(
+        db: &Database,
+        job_type: JobType,
+        conversation_id: &str,
+        character_id: Option<&str>,
+    ) -> AppResult<Option<BackgroundJob>> {
+        db.query_optional(
+            "SELECT * FROM background_jobs
+             WHERE job_type = ?1 AND conversation_id = ?2 AND character_id IS ?3 AND status = 'pending'
+             ORDER BY created_at ASC LIMIT 1",
+            params![job_type.as_str(), conversation_id, character_id],
+            Self::row_to_job,
+        )
+    }
+    
+    /// Replace the payload of a job that has not started yet. Returns false if it already started.
+    pub fn update_payload(db: &Database, id: &str, payload: &serde_json::Value) -> AppResult<bool> {
+        let updated = db.execute(
+            "UPDATE background_jobs SET payload = ?1, updated_at = ?2 WHERE id = ?3 AND status = 'pending'",
+            params![payload.to_string(), now_timestamp(), id],
+        )?;
+        Ok(updated > 0)
+    }
+    
+    /// Take the most urgent job that is due and mark it processing
+    pub fn claim_next_ready(db: &Database) -> AppResult<Option<BackgroundJob>> {
+        use rusqlite::OptionalExtension;
+        
+        let now = now_timestamp();
+        db.transaction(|conn| {
+            let job = conn.query_row(
+                "SELECT * FROM background_jobs WHERE status = 'pending' AND next_run_at <= ?1
+                 ORDER BY priority DESC, next_run_at ASC LIMIT 1",
+                params![now],
+                Self::row_to_job,
+            ).optional()?;
+            
+            let Some(mut job) = job else {
+                return Ok(None);
+            };
+            
+            conn.execute(
+                "UPDATE background_jobs SET status = 'processing', attempts = attempts + 1, updated_at = ?1
+                 WHERE id = ?2",
+                params![now, job.id],
+            )?;
+            job.status = JobStatus::Processing;
+            job.attempts += 1;
+            Ok(Some(job))
+        })
+    }
+    
+    pub fn complete(db: &Database, id: &str) -> AppResult<()> {
+        db.execute(
+            "UPDATE background_jobs SET status = 'completed', last_error = NULL, updated_at = ?1 WHERE id = ?2",
+            params![now_timestamp(), id],
+        )?;
+        Ok(())
+    }
+    
+    /// Record a failure. With `retry_at` the job goes back to pending, otherwise it fails for good.
+    pub fn fail(db: &Database, id: &str, error: &str, retry_at: Option<i64>) -> AppResult<()> {
+        let now = now_timestamp();
+        match retry_at {
+            Some(at) => db.execute(
+                "UPDATE background_jobs SET status = 'pending', next_run_at = ?1, last_error = ?2, updated_at = ?3
+                 WHERE id = ?4",
+                params![at, error, now, id],
+            )?,
+            None => db.execute(
+                "UPDATE background_jobs SET status = 'failed', last_error = ?1, updated_at = ?2 WHERE id = ?3",
+                params![error, now, id],
+            )?,
+        };
+        Ok(())
+    }
+    
+    /// Return a processing job to pending without counting the attempt
+    pub fn release(db: &Database, id: &str) -> AppResult<()> {
+        db.execute(
+            "UPDATE background_jobs SET status = 'pending', attempts = MAX(attempts - 1, 0), updated_at = ?1
+             WHERE id = ?2 AND status = 'processing'",
+            params![now_timestamp(), id],
+        )?;
+        Ok(())
+    }
+    
+    /// Jobs left 'processing' by a crash or exit are requeued on startup
+    pub fn requeue_interrupted(db: &Database) -> AppResult<usize> {
+        db.execute(
+            "UPDATE background_jobs SET status = 'pending', updated_at = ?1 WHERE status = 'processing'",
+            params![now_timestamp()],
+        )
+    }
+    
+    /// Drop finished jobs last touched before `before`
+    pub fn prune_finished(db: &Database, before: i64) -> AppResult<usize> {
+        db.execute(
+            "DELETE FROM background_jobs WHERE status IN ('completed', 'failed') AND updated_at < ?1",
+            params![before],
+        )
+    }
+    
+    pub fn count_by_status(db: &Database, status: JobStatus) -> AppResult<i64> {
+        db.query_one(
+            "SELECT COUNT(*) FROM background_jobs WHERE status = ?1",
+            params![status.as_str()],
+            |row| row.get(0),
+        )
+    }
+    
+    /// Unfinished and failed jobs, most urgent first
+    pub fn list_unfinished(db: &Database, limit: usize) -> AppResult<Vec<BackgroundJob>> {
+        db.query_all(
+            "SELECT * FROM background_jobs WHERE status IN ('pending', 'processing', 'failed')
+             ORDER BY CASE status WHEN 'processing' THEN 0 WHEN 'pending' THEN 1 ELSE 2 END,
+                      priority DESC, next_run_at ASC
+             LIMIT ?1",
+            params![limit as i64],
+            Self::row_to_job,
+        )
+    }
+    
+    fn row_to_job(row: &rusqlite::Row<'_>) -> rusqlite::Result<BackgroundJob> {
+        let job_type: String = row.get("job_type")?;
+        let status: String = row.get("status")?;
+        let payload: String = row.get("payload")?;
+        
+        Ok(BackgroundJob {
+            id: row.get("id")?,
+            // Summarization only re-checks thresholds, so it is a harmless fallback
+            job_type: JobType::from_str(&job_type).unwrap_or(JobType::Summarization),
+            conversation_id: row.get("conversation_id")?,
+            character_id: row.get("character_id")?,
+            payload: serde_json::from_str(&payload).unwrap_or_default(),
+            status: JobStatus::from_str(&status).unwrap_or(JobStatus::Failed),
+            priority: row.get("priority")?,
+            attempts: row.get("attempts")?,
+            max_attempts: row.get("max_attempts")?,
+            next_run_at: row.get("next_run_at")?,
+            last_error: row.get("last_error")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+}
+
 // ============================================
 // Download Repository
 // ============================================
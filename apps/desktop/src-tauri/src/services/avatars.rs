@@ -0,0 +1,277 @@
+// ============================================
+// Avatar Service
+// Validates, normalizes and thumbnails avatar images, and cleans up
+// files no character uses any more
+// ============================================
+
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+
+use crate::database::Database;
+use crate::entities::new_id;
+use crate::error::{AppError, AppResult};
+use crate::repositories::CharacterRepo;
+use crate::setup::paths::AppPaths;
+
+/// Largest upload accepted before decoding
+const MAX_AVATAR_BYTES: usize = 20 * 1024 * 1024;
+
+/// Images bigger than this on either side are rejected without decoding
+const MAX_SOURCE_DIMENSION: u32 = 16_384;
+
+/// Stored avatars are scaled down to fit this box
+const MAX_AVATAR_DIMENSION: u32 = 2048;
+
+/// Thumbnails for list views fit this box
+const THUMBNAIL_DIMENSION: u32 = 256;
+
+const JPEG_QUALITY: u8 = 90;
+
+/// Unreferenced files younger than this are left alone; the editor saves
+/// the image before the character that points at it
+const GARBAGE_GRACE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// An avatar ready to write to disk
+pub struct ProcessedAvatar {
+    pub data: Vec<u8>,
+    pub thumbnail: Vec<u8>,
+    /// File extension matching `data`
+    pub extension: &'static str,
+}
+
+/// Decode an uploaded image and re-encode it: EXIF orientation is applied
+/// and all metadata (EXIF, text chunks, embedded cards) dropped. JPEG stays
+/// JPEG; PNG, WebP and GIF (first frame) become PNG.
+pub fn process(bytes: &[u8]) -> AppResult<ProcessedAvatar> {
+    if bytes.len() > MAX_AVATAR_BYTES {
+        return Err(AppError::Validation(format!(
+            "Avatar is too large ({} MB, max {} MB)",
+            bytes.len() / (1024 * 1024),
+            MAX_AVATAR_BYTES / (1024 * 1024),
+        )));
+    }
+    let format = sniff(bytes)?;
+    let image = decode(bytes, format)?;
+
+    let image = if image.width() > MAX_AVATAR_DIMENSION || image.height() > MAX_AVATAR_DIMENSION {
+        image.resize(MAX_AVATAR_DIMENSION, MAX_AVATAR_DIMENSION, image::imageops::FilterType::Lanczos3)
+    } else {
+        image
+    };
+    let thumbnail = image.thumbnail(THUMBNAIL_DIMENSION, THUMBNAIL_DIMENSION);
+
+    let (data, thumbnail, extension) = if format == ImageFormat::Jpeg {
+        (encode_jpeg(&image)?, encode_jpeg(&thumbnail)?, "jpg")
+    } else {
+        (encode_png(&image)?, encode_png(&thumbnail)?, "png")
+    };
+    Ok(ProcessedAvatar { data, thumbnail, extension })
+}
+
+/// The image as PNG, for formats (like PNG cards) that need one
+pub fn to_png(bytes: &[u8]) -> AppResult<Vec<u8>> {
+    let format = sniff(bytes)?;
+    if format == ImageFormat::Png {
+        return Ok(bytes.to_vec());
+    }
+    encode_png(&decode(bytes, format)?)
+}
+
+/// MIME type from the image's content, not its file name
+pub fn mime_type(bytes: &[u8]) -> Option<&'static str> {
+    sniff(bytes).ok().map(|format| format.to_mime_type())
+}
+
+/// Bytes from base64 or a `data:` URL
+pub fn decode_base64(data: &str) -> AppResult<Vec<u8>> {
+    let raw = data.split_once("base64,").map(|(_, raw)| raw).unwrap_or(data);
+    base64::engine::general_purpose::STANDARD
+        .decode(raw.trim())
+        .map_err(|e| AppError::Validation(format!("Invalid image data: {}", e)))
+}
+
+fn sniff(bytes: &[u8]) -> AppResult<ImageFormat> {
+    match image::guess_format(bytes) {
+        Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Gif)) => Ok(format),
+        Ok(format) => Err(AppError::Validation(format!("Unsupported avatar format: {:?}", format))),
+        Err(_) => Err(AppError::Validation("Avatar is not a recognized image".to_string())),
+    }
+}
+
+fn decode(bytes: &[u8], format: ImageFormat) -> AppResult<DynamicImage> {
+    let invalid = |e: image::ImageError| AppError::Validation(format!("Invalid avatar image: {}", e));
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().map_err(invalid)?;
+    let orientation = decoder.orientation().ok();
+    let mut image = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+    if let Some(orientation) = orientation {
+        image.apply_orientation(orientation);
+    }
+    Ok(image)
+}
+
+fn encode_png(image: &DynamicImage) -> AppResult<Vec<u8>> {
+    let mut out = Cursor::new(Vec::new());
+    image.write_to(&mut out, ImageFormat::Png)
+        .map_err(|e| AppError::Validation(format!("Failed to encode avatar: {}", e)))?;
+    Ok(out.into_inner())
+}
+
+fn encode_jpeg(image: &DynamicImage) -> AppResult<Vec<u8>> {
+    let mut out = Vec::new();
+    // JPEG has no alpha channel
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))
+        .map_err(|e| AppError::Validation(format!("Failed to encode avatar: {}", e)))?;
+    Ok(out)
+}
+
+/// Only plain file names live in the avatars directory; anything with a
+/// path in it is a legacy absolute path or an attempt to escape
+fn is_avatar_file_name(name: &str) -> bool {
+    !name.is_empty() && Path::new(name).file_name().and_then(|n| n.to_str()) == Some(name) && name != ".."
+}
+
+pub struct AvatarService;
+
+impl AvatarService {
+    /// Process and save an uploaded image with its thumbnail. Returns the
+    /// file name to store as `avatar_path`.
+    pub fn store(paths: &AppPaths, bytes: &[u8]) -> AppResult<String> {
+        let avatar = process(bytes)?;
+        let filename = format!("{}.{}", new_id(), avatar.extension);
+
+        std::fs::write(paths.avatar_file_path(&filename), &avatar.data)?;
+        if let Err(e) = std::fs::write(paths.avatar_thumbnail_path(&filename), &avatar.thumbnail) {
+            // Thumbnails are rebuilt on demand
+            tracing::warn!("Failed to write thumbnail for {}: {}", filename, e);
+        }
+        Ok(filename)
+    }
+
+    /// Path of the avatar's thumbnail, creating it for avatars stored
+    /// before thumbnails existed. None if the avatar file is missing.
+    pub fn thumbnail(paths: &AppPaths, avatar_path: &str) -> AppResult<Option<PathBuf>> {
+        if !is_avatar_file_name(avatar_path) {
+            return Ok(None);
+        }
+        let thumbnail_path = paths.avatar_thumbnail_path(avatar_path);
+        if thumbnail_path.exists() {
+            return Ok(Some(thumbnail_path));
+        }
+        let source = paths.avatar_file_path(avatar_path);
+        if !source.exists() {
+            return Ok(None);
+        }
+
+        let bytes = std::fs::read(&source)?;
+        let format = sniff(&bytes)?;
+        let thumbnail = decode(&bytes, format)?.thumbnail(THUMBNAIL_DIMENSION, THUMBNAIL_DIMENSION);
+        let data = if format == ImageFormat::Jpeg { encode_jpeg(&thumbnail)? } else { encode_png(&thumbnail)? };
+        std::fs::write(&thumbnail_path, data)?;
+        Ok(Some(thumbnail_path))
+    }
+
+    /// Delete avatar files (and thumbnails) that no character or saved
+    /// revision points at. Returns how many avatars were removed.
+    pub fn collect_unused(db: &Database, paths: &AppPaths) -> AppResult<usize> {
+        let referenced: HashSet<String> = CharacterRepo::find_avatar_paths(db)?
+            .iter()
+            .filter_map(|p| Path::new(p).file_name()?.to_str().map(str::to_string))
+            .collect();
+        let now = SystemTime::now();
+        let mut removed = 0;
+
+        for entry in std::fs::read_dir(&paths.avatars_dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else { continue };
+            if !metadata.is_file() || referenced.contains(&name) {
+                continue;
+            }
+            let age = metadata.modified().ok().and_then(|m| now.duration_since(m).ok());
+            if age.is_none_or(|age| age < GARBAGE_GRACE) {
+                continue;
+            }
+
+            std::fs::remove_file(entry.path())?;
+            let _ = std::fs::remove_file(paths.avatar_thumbnail_path(&name));
+            removed += 1;
+        }
+
+        // Thumbnails whose avatar is gone
+        for entry in std::fs::read_dir(&paths.avatar_thumbnails_dir)? {
+            let entry = entry?;
+            if !paths.avatars_dir.join(entry.file_name()).exists() {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+
+        if removed > 0 {
+            tracing::info!("Removed {} unused avatar(s)", removed);
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([200, 40, 40, 128])));
+        encode_png(&image).unwrap()
+    }
+
+    #[test]
+    fn test_process_downscales_and_thumbnails() {
+        let avatar = process(&png(2560, 1280)).unwrap();
+        assert_eq!(avatar.extension, "png");
+
+        let stored = image::load_from_memory(&avatar.data).unwrap();
+        assert_eq!((stored.width(), stored.height()), (2048, 1024));
+        let thumbnail = image::load_from_memory(&avatar.thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (256, 128));
+    }
+
+    #[test]
+    fn test_process_keeps_jpeg() {
+        let jpeg = encode_jpeg(&image::load_from_memory(&png(64, 64)).unwrap()).unwrap();
+        let avatar = process(&jpeg).unwrap();
+        assert_eq!(avatar.extension, "jpg");
+        assert_eq!(mime_type(&avatar.data), Some("image/jpeg"));
+    }
+
+    #[test]
+    fn test_process_rejects_non_images() {
+        assert!(process(b"<svg xmlns='http://www.w3.org/2000/svg'/>").is_err());
+        assert!(process(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_decode_base64_data_url() {
+        assert_eq!(decode_base64("data:image/webp;base64,AAEC").unwrap(), vec![0, 1, 2]);
+        assert_eq!(decode_base64("AAEC").unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_avatar_file_names() {
+        assert!(is_avatar_file_name("a1b2.png"));
+        assert!(!is_avatar_file_name("../secret.png"));
+        assert!(!is_avatar_file_name("/home/me/pic.png"));
+        assert!(!is_avatar_file_name(".."));
+    }
+}
@@ -0,0 +1,235 @@
+// ============================================
+// Batch Import Service
+// Imports every card in a folder or zip archive, spotting cards that
+// were imported before by their hash
+// ============================================
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::database::Database;
+use crate::entities::*;
+use crate::error::{AppError, AppResult};
+use crate::repositories::CharacterRepo;
+use crate::services::cards::{self, Charx};
+use crate::services::CardService;
+use crate::setup::paths::AppPaths;
+
+/// Files bigger than this fail without being read
+const MAX_FILE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Same limit as single JSON card imports
+const MAX_JSON_BYTES: usize = 2_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CardFormat {
+    Png,
+    Json,
+    Charx,
+}
+
+impl CardFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        let extension = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Self::Png),
+            "json" => Some(Self::Json),
+            "charx" => Some(Self::Charx),
+            _ => None,
+        }
+    }
+}
+
+/// Cards by extension, leaving out hidden files and the `__MACOSX`
+/// folder macOS adds to archives
+fn is_card_file(name: &str) -> bool {
+    let hidden = name.split('/').any(|part| part.starts_with('.') || part == "__MACOSX");
+    !hidden && CardFormat::from_name(name).is_some()
+}
+
+/// The card files of a batch, by path relative to the folder or archive
+enum Source {
+    Folder(Vec<(String, PathBuf)>),
+    Archive(zip::ZipArchive<File>, Vec<(String, usize)>),
+}
+
+impl Source {
+    fn open(path: &Path) -> AppResult<Self> {
+        if path.is_dir() {
+            let mut files = cards::list_files(path)?;
+            files.retain(|(name, _)| is_card_file(name));
+            files.sort();
+            return Ok(Self::Folder(files));
+        }
+        if !path.is_file() {
+            return Err(AppError::NotFound(format!("No folder or archive at {}", path.display())));
+        }
+
+        let mut archive = zip::ZipArchive::new(File::open(path)?)
+            .map_err(|e| AppError::Import(format!("Not a folder or zip archive: {}", e)))?;
+        let mut files = Vec::new();
+        for i in 0..archive.len() {
+            let Ok(file) = archive.by_index(i) else { continue };
+            if file.is_dir() {
+                continue;
+            }
+            // Paths that would escape the archive are skipped
+            let Some(name) = file.enclosed_name() else { continue };
+            let name = name.to_string_lossy().replace('\\', "/");
+            if is_card_file(&name) {
+                files.push((name, i));
+            }
+        }
+        files.sort();
+        Ok(Self::Archive(archive, files))
+    }
+
+    fn names(&self) -> Vec<String> {
+        match self {
+            Self::Folder(files) => files.iter().map(|(name, _)| name.clone()).collect(),
+            Self::Archive(_, files) => files.iter().map(|(name, _)| name.clone()).collect(),
+        }
+    }
+
+    fn read(&mut self, i: usize) -> AppResult<Vec<u8>> {
+        let mut data = Vec::new();
+        match self {
+            Self::Folder(files) => {
+                File::open(&files[i].1)?.take(MAX_FILE_BYTES + 1).read_to_end(&mut data)?;
+            }
+            Self::Archive(archive, files) => {
+                let file = archive.by_index(files[i].1)
+                    .map_err(|e| AppError::Import(format!("Unreadable archive entry: {}", e)))?;
+                file.take(MAX_FILE_BYTES + 1).read_to_end(&mut data)?;
+            }
+        }
+        if data.len() as u64 > MAX_FILE_BYTES {
+            return Err(AppError::Import("File is too large".to_string()));
+        }
+        Ok(data)
+    }
+}
+
+fn item(file: &str, status: BatchImportStatus) -> BatchImportItem {
+    BatchImportItem {
+        file: file.to_string(),
+        status,
+        character_id: None,
+        character_name: None,
+        duplicate_of: None,
+        error: None,
+    }
+}
+
+pub struct BatchImportService;
+
+impl BatchImportService {
+    /// Import every PNG, JSON and CharX card in `path`, a folder (searched
+    /// recursively) or a zip archive. A bad file is reported and the rest
+    /// carry on; `on_progress` hears about each file once it's done.
+    pub fn import(
+        db: &Database,
+        paths: &AppPaths,
+        path: &Path,
+        duplicates: DuplicatePolicy,
+        mut on_progress: impl FnMut(&BatchImportProgressEvent),
+    ) -> AppResult<BatchImportReport> {
+        let mut source = Source::open(path)?;
+        let names = source.names();
+        let total = names.len();
+        let mut report = BatchImportReport::default();
+
+        for (i, name) in names.iter().enumerate() {
+            let result = source.read(i)
+                .and_then(|bytes| Self::import_file(db, paths, name, &bytes, duplicates));
+            let item = result.unwrap_or_else(|e| {
+                tracing::warn!("Batch import of {} failed: {}", name, e);
+                BatchImportItem { error: Some(e.to_string()), ..item(name, BatchImportStatus::Failed) }
+            });
+
+            match item.status {
+                BatchImportStatus::Imported => report.imported += 1,
+                BatchImportStatus::Replaced => report.replaced += 1,
+                BatchImportStatus::Skipped => report.skipped += 1,
+                BatchImportStatus::Failed => report.failed += 1,
+            }
+            on_progress(&BatchImportProgressEvent { processed: i + 1, total, item: item.clone() });
+            report.items.push(item);
+        }
+
+        tracing::info!(
+            "Batch import from {}: {} imported, {} replaced, {} skipped, {} failed",
+            path.display(), report.imported, report.replaced, report.skipped, report.failed
+        );
+        Ok(report)
+    }
+
+    fn import_file(
+        db: &Database,
+        paths: &AppPaths,
+        name: &str,
+        bytes: &[u8],
+        duplicates: DuplicatePolicy,
+    ) -> AppResult<BatchImportItem> {
+        let format = CardFormat::from_name(name)
+            .ok_or_else(|| AppError::Import("Not a card file".to_string()))?;
+        let (parsed, files) = match format {
+            CardFormat::Png => (cards::parse_card(&cards::read_card(bytes)?)?, Vec::new()),
+            CardFormat::Json => {
+                if bytes.len() > MAX_JSON_BYTES {
+                    return Err(AppError::Import("Character card data too large".to_string()));
+                }
+                let json = std::str::from_utf8(bytes)
+                    .map_err(|_| AppError::Import("Card JSON is not UTF-8".to_string()))?;
+                (cards::parse_card(json)?, Vec::new())
+            }
+            CardFormat::Charx => {
+                let Charx { card_json, files } = cards::read_charx(bytes)?;
+                (cards::parse_card(&card_json)?, files)
+            }
+        };
+
+        let existing = CharacterRepo::find_by_card_hash(db, &parsed.hash)?;
+        let replace = match (&existing, duplicates) {
+            (Some(existing), DuplicatePolicy::Skip) => {
+                return Ok(BatchImportItem {
+                    character_name: Some(parsed.input.name),
+                    duplicate_of: Some(existing.id.clone()),
+                    ..item(name, BatchImportStatus::Skipped)
+                });
+            }
+            (Some(existing), DuplicatePolicy::Replace) => Some(existing.id.as_str()),
+            _ => None,
+        };
+
+        let character = match format {
+            CardFormat::Png => CardService::save_png(db, paths, parsed, bytes, replace)?,
+            CardFormat::Json => CardService::save(db, parsed, None, replace)?,
+            CardFormat::Charx => CardService::save_charx(db, paths, parsed, files, replace)?,
+        };
+        let status = if replace.is_some() { BatchImportStatus::Replaced } else { BatchImportStatus::Imported };
+        Ok(BatchImportItem {
+            character_id: Some(character.id),
+            character_name: Some(character.name),
+            duplicate_of: existing.map(|c| c.id),
+            ..item(name, status)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_card_files() {
+        assert!(is_card_file("cards/Aria.PNG"));
+        assert!(is_card_file("Aria.json"));
+        assert!(is_card_file("nested/deeper/Aria.charx"));
+        assert!(!is_card_file("readme.txt"));
+        assert!(!is_card_file("__MACOSX/cards/._Aria.png"));
+        assert!(!is_card_file(".hidden/Aria.png"));
+        assert!(!is_card_file("noextension"));
+    }
+}
@@ -0,0 +1,917 @@
+// ============================================
+// Character Card Service
+// Character Card V1/V2/V3 import and export, as JSON, as PNG (card JSON
+// base64-encoded in `chara`/`ccv3` text chunks, the image is the avatar)
+// and as CharX zip archives
+// ============================================
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::database::Database;
+use crate::entities::*;
+use crate::error::{AppError, AppResult};
+use crate::repositories::{CharacterRepo, LorebookRepo};
+use crate::services::world_info::{self, ParsedLorebook};
+use crate::services::avatars;
+use crate::services::{AvatarService, CharacterRevisionService, ExportService, LorebookService, WorldInfoService};
+use crate::setup::paths::AppPaths;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Text chunk keywords that hold card JSON, newest spec first
+const CARD_KEYWORDS: [&str; 2] = ["ccv3", "chara"];
+
+/// Decompressed text chunks can't be larger than this
+const MAX_TEXT_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Glee's own character fields ride along in `extensions.glee`
+const GLEE_EXTENSION: &str = "glee";
+
+/// Where a CharX archive keeps its card
+const CHARX_CARD: &str = "card.json";
+
+/// Uncompressed size limit for a whole CharX archive
+const MAX_CHARX_BYTES: u64 = 256 * 1024 * 1024;
+
+pub fn is_png(bytes: &[u8]) -> bool {
+    bytes.starts_with(&PNG_SIGNATURE)
+}
+
+/// A chunk as it appears in the file: type and data, CRC already checked
+struct Chunk<'a> {
+    kind: [u8; 4],
+    data: &'a [u8],
+}
+
+fn chunks(bytes: &[u8]) -> AppResult<Vec<Chunk<'_>>> {
+    if !is_png(bytes) {
+        return Err(AppError::Import("Not a PNG file".into()));
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < bytes.len() {
+        let header = bytes.get(pos..pos + 8)
+            .ok_or_else(|| AppError::Import("Truncated PNG chunk".into()))?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = [header[4], header[5], header[6], header[7]];
+
+        let end = pos.checked_add(12 + len)
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(|| AppError::Import("Truncated PNG chunk".into()))?;
+        let data = &bytes[pos + 8..pos + 8 + len];
+        let crc = u32::from_be_bytes([bytes[end - 4], bytes[end - 3], bytes[end - 2], bytes[end - 1]]);
+        if crc != chunk_crc(&kind, data) {
+            return Err(AppError::Import(format!("Corrupt PNG chunk {}", String::from_utf8_lossy(&kind))));
+        }
+
+        chunks.push(Chunk { kind, data });
+        pos = end;
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    Ok(chunks)
+}
+
+fn chunk_crc(kind: &[u8; 4], data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&chunk_crc(kind, data).to_be_bytes());
+}
+
+fn inflate(data: &[u8]) -> AppResult<Vec<u8>> {
+    let mut out = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_TEXT_BYTES)
+        .read_to_end(&mut out)
+        .map_err(|e| AppError::Import(format!("Bad compressed text chunk: {}", e)))?;
+    Ok(out)
+}
+
+/// tEXt and zTXt are Latin-1
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn split_nul(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let i = data.iter().position(|&b| b == 0)?;
+    Some((&data[..i], &data[i + 1..]))
+}
+
+/// Keyword and text of a tEXt, zTXt or iTXt chunk; None for other chunks
+fn text_chunk(chunk: &Chunk) -> AppResult<Option<(String, String)>> {
+    let bad = || AppError::Import(format!("Malformed {} chunk", String::from_utf8_lossy(&chunk.kind)));
+    match &chunk.kind {
+        b"tEXt" => {
+            let (keyword, text) = split_nul(chunk.data).ok_or_else(bad)?;
+            Ok(Some((latin1(keyword), latin1(text))))
+        }
+        b"zTXt" => {
+            let (keyword, rest) = split_nul(chunk.data).ok_or_else(bad)?;
+            let compressed = rest.get(1..).ok_or_else(bad)?;
+            Ok(Some((latin1(keyword), latin1(&inflate(compressed)?))))
+        }
+        b"iTXt" => {
+            // keyword\0 flag method language\0 translated\0 text
+            let (keyword, rest) = split_nul(chunk.data).ok_or_else(bad)?;
+            let (&compressed, rest) = rest.split_first().ok_or_else(bad)?;
+            let rest = rest.get(1..).ok_or_else(bad)?;
+            let (_, rest) = split_nul(rest).ok_or_else(bad)?;
+            let (_, text) = split_nul(rest).ok_or_else(bad)?;
+            let text = if compressed == 1 { inflate(text)? } else { text.to_vec() };
+            Ok(Some((latin1(keyword), String::from_utf8_lossy(&text).into_owned())))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Card JSON embedded in a PNG, preferring V3 over V2.
+/// The text is normally base64; some tools write the JSON as-is.
+pub fn read_card(png: &[u8]) -> AppResult<String> {
+    let mut found: Vec<(String, String)> = Vec::new();
+    for chunk in chunks(png)? {
+        if let Some((keyword, text)) = text_chunk(&chunk)? {
+            if CARD_KEYWORDS.contains(&keyword.as_str()) {
+                found.push((keyword, text));
+            }
+        }
+    }
+
+    let text = CARD_KEYWORDS.iter()
+        .find_map(|k| found.iter().find(|(keyword, _)| keyword == k))
+        .map(|(_, text)| text.trim())
+        .ok_or_else(|| AppError::Import("PNG has no character card data".into()))?;
+
+    if text.starts_with('{') {
+        return Ok(text.to_string());
+    }
+    let decoded = base64::engine::general_purpose::STANDARD.decode(text)
+        .map_err(|e| AppError::Import(format!("Invalid card data: {}", e)))?;
+    String::from_utf8(decoded)
+        .map_err(|_| AppError::Import("Card data is not UTF-8".into()))
+}
+
+/// Copy of `png` with the given cards embedded as `(keyword, json)` text
+/// chunks. Any card chunks already in the image are dropped.
+pub fn write_card(png: &[u8], cards: &[(&str, &str)]) -> AppResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(png.len() + cards.iter().map(|(_, j)| j.len() * 4 / 3 + 32).sum::<usize>());
+    out.extend_from_slice(&PNG_SIGNATURE);
+
+    for chunk in chunks(png)? {
+        if let Some((keyword, _)) = text_chunk(&chunk)? {
+            if CARD_KEYWORDS.contains(&keyword.as_str()) {
+                continue;
+            }
+        }
+        if &chunk.kind == b"IEND" {
+            for (keyword, card_json) in cards {
+                let mut data = keyword.as_bytes().to_vec();
+                data.push(0);
+                data.extend_from_slice(base64::engine::general_purpose::STANDARD.encode(card_json).as_bytes());
+                write_chunk(&mut out, b"tEXt", &data);
+            }
+        }
+        write_chunk(&mut out, &chunk.kind, chunk.data);
+    }
+    Ok(out)
+}
+
+/// 1x1 transparent PNG for characters without a usable avatar
+pub fn placeholder_png() -> Vec<u8> {
+    let mut out = PNG_SIGNATURE.to_vec();
+
+    // width, height, bit depth 8, RGBA, default compression/filter/interlace
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&1u32.to_be_bytes());
+    ihdr.extend_from_slice(&1u32.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &ihdr);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    // Writing to a Vec can't fail
+    let _ = encoder.write_all(&[0, 0, 0, 0, 0]);
+    let idat = encoder.finish().unwrap_or_default();
+    write_chunk(&mut out, b"IDAT", &idat);
+
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+// ============================================
+// Card <-> Character
+// ============================================
+
+/// A card read into Glee's shapes
+#[derive(Debug)]
+pub struct ParsedCard {
+    pub input: CreateCharacterInput,
+    pub book: Option<ParsedLorebook>,
+    /// See `card_hash`
+    pub hash: String,
+}
+
+/// Read a V1, V2 or V3 card from JSON
+pub fn parse_card(json: &str) -> AppResult<ParsedCard> {
+    let value: Value = serde_json::from_str(json)
+        .map_err(|e| AppError::Import(format!("Invalid JSON: {}", e)))?;
+    let hash = card_hash(&value);
+
+    if value.get("spec").is_some() && value.get("data").is_some() {
+        let card: CharacterCardV2 = serde_json::from_value(value)
+            .map_err(|e| AppError::Import(format!("Invalid character card: {}", e)))?;
+        return from_v2(card, hash);
+    }
+
+    let card: CharacterCardV1 = serde_json::from_value(value)
+        .map_err(|_| AppError::Import("Invalid character card format".to_string()))?;
+    Ok(from_v1(card, hash))
+}
+
+/// SHA-256 of the card's JSON with keys sorted, so the same card hashes
+/// the same whether it came as JSON, PNG or CharX
+pub fn card_hash(card: &Value) -> String {
+    fn sorted(value: &Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                Value::Object(keys.into_iter().map(|k| (k.clone(), sorted(&map[k]))).collect())
+            }
+            Value::Array(items) => Value::Array(items.iter().map(sorted).collect()),
+            other => other.clone(),
+        }
+    }
+    let canonical = serde_json::to_string(&sorted(card)).unwrap_or_default();
+    format!("{:x}", Sha256::digest(canonical.as_bytes()))
+}
+
+fn glee_str(glee: &Value, key: &str) -> String {
+    glee.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string()
+}
+
+fn glee_list(glee: &Value, key: &str) -> Vec<String> {
+    glee.get(key).and_then(|v| serde_json::from_value(v.clone()).ok()).unwrap_or_default()
+}
+
+fn from_v2(card: CharacterCardV2, hash: String) -> AppResult<ParsedCard> {
+    let CharacterCardV2 { spec, spec_version, mut data, extra: root } = card;
+
+    let book = data.character_book.take()
+        .filter(|b| !b.is_null())
+        .map(|b| world_info::parse(&b))
+        .transpose()?;
+
+    let glee = data.extensions.remove(GLEE_EXTENSION).unwrap_or_default();
+    let mut extra = std::mem::take(&mut data.extra);
+    if !data.extensions.is_empty() {
+        extra.insert("extensions".into(), Value::Object(std::mem::take(&mut data.extensions)));
+    }
+
+    let meta = CardMetadata {
+        spec: spec.parse().unwrap_or_default(),
+        spec_version,
+        data: extra,
+        root,
+    };
+
+    let input = CreateCharacterInput {
+        name: data.name.trim().to_string(),
+        description: data.description,
+        personality: data.personality,
+        scenario: data.scenario,
+        system_prompt: data.system_prompt,
+        post_history_instructions: data.post_history_instructions,
+        first_message: data.first_mes,
+        alternate_greetings: data.alternate_greetings,
+        example_dialogues: data.mes_example,
+        avatar_path: None,
+        tags: data.tags,
+        creator_name: data.creator,
+        creator_notes: data.creator_notes,
+        character_version: data.character_version,
+        backstory: glee_str(&glee, "backstory"),
+        likes: glee_list(&glee, "likes"),
+        dislikes: glee_list(&glee, "dislikes"),
+        physical_traits: glee_str(&glee, "physicalTraits"),
+        speech_patterns: glee_str(&glee, "speechPatterns"),
+        pov_type: glee_str(&glee, "povType"),
+        rating: glee_str(&glee, "rating"),
+        genre_tags: glee_list(&glee, "genreTags"),
+        card: Some(serde_json::to_value(&meta)?),
+        source: Some(RevisionSource::Import),
+    };
+
+    Ok(ParsedCard { input, book, hash })
+}
+
+fn from_v1(card: CharacterCardV1, hash: String) -> ParsedCard {
+    let meta = CardMetadata { root: card.extra, ..Default::default() };
+    let input = CreateCharacterInput {
+        name: card.name.trim().to_string(),
+        description: card.description,
+        personality: card.personality,
+        scenario: card.scenario,
+        system_prompt: card.system_prompt,
+        first_message: card.first_mes,
+        example_dialogues: card.mes_example,
+        card: serde_json::to_value(&meta).ok(),
+        source: Some(RevisionSource::Import),
+        ..Default::default()
+    };
+    ParsedCard { input, book: None, hash }
+}
+
+/// Glee's own fields, for `extensions.glee`; None when they're all unset
+fn glee_extension(character: &Character) -> Option<Value> {
+    let mut glee = Map::new();
+    for (key, value) in [
+        ("backstory", &character.backstory),
+        ("physicalTraits", &character.physical_traits),
+        ("speechPatterns", &character.speech_patterns),
+    ] {
+        if !value.is_empty() {
+            glee.insert(key.into(), json!(value));
+        }
+    }
+    for (key, value) in [
+        ("likes", &character.likes),
+        ("dislikes", &character.dislikes),
+        ("genreTags", &character.genre_tags),
+    ] {
+        if !value.is_empty() {
+            glee.insert(key.into(), json!(value));
+        }
+    }
+    if !character.pov_type.is_empty() && character.pov_type != "any" {
+        glee.insert("povType".into(), json!(character.pov_type));
+    }
+    if !character.rating.is_empty() && character.rating != "sfw" {
+        glee.insert("rating".into(), json!(character.rating));
+    }
+    (!glee.is_empty()).then_some(Value::Object(glee))
+}
+
+/// Card for a character in `spec`, or in the spec it was imported with.
+/// Fields kept from the import are written back as they came in.
+/// A card has room for one book, so several linked lorebooks are merged.
+pub fn to_card(character: &Character, lorebooks: &[Lorebook], spec: Option<CardSpec>) -> CharacterCardV2 {
+    let meta: CardMetadata = character.metadata.get("card")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+    let spec = spec.unwrap_or(meta.spec);
+    let spec_version = if spec == meta.spec && !meta.spec_version.is_empty() {
+        meta.spec_version
+    } else {
+        spec.version().to_string()
+    };
+
+    let book = match lorebooks {
+        [] => None,
+        [only] => Some(world_info::to_character_book(only)),
+        [first, ..] => Some(world_info::to_character_book(&Lorebook {
+            name: format!("{} Lorebook", character.name),
+            entries: lorebooks.iter().flat_map(|lb| lb.entries.clone()).collect(),
+            ..first.clone()
+        })),
+    };
+
+    let mut extra = meta.data;
+    let mut extensions = match extra.remove("extensions") {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    if let Some(glee) = glee_extension(character) {
+        extensions.insert(GLEE_EXTENSION.into(), glee);
+    }
+    if spec == CardSpec::V3 {
+        extra.entry("group_only_greetings").or_insert_with(|| json!([]));
+        extra.entry("assets").or_insert_with(|| json!([
+            { "type": "icon", "uri": "ccdefault:", "name": "main", "ext": "png" }
+        ]));
+    }
+
+    // SillyTavern writes a V1 copy next to `data`; refresh whatever is there
+    let mut root = meta.root;
+    for (key, value) in [
+        ("name", json!(character.name)),
+        ("description", json!(character.description)),
+        ("personality", json!(character.personality)),
+        ("scenario", json!(character.scenario)),
+        ("first_mes", json!(character.first_message)),
+        ("mes_example", json!(character.example_dialogues)),
+        ("creatorcomment", json!(character.creator_notes)),
+        ("tags", json!(character.tags)),
+    ] {
+        if let Some(slot) = root.get_mut(key) {
+            *slot = value;
+        }
+    }
+
+    CharacterCardV2 {
+        spec: spec.as_str().to_string(),
+        spec_version,
+        data: CharacterCardDataV2 {
+            name: character.name.clone(),
+            description: character.description.clone(),
+            personality: character.personality.clone(),
+            scenario: character.scenario.clone(),
+            first_mes: character.first_message.clone(),
+            mes_example: character.example_dialogues.clone(),
+            creator_notes: character.creator_notes.clone(),
+            system_prompt: character.system_prompt.clone(),
+            post_history_instructions: character.post_history_instructions.clone(),
+            alternate_greetings: character.alternate_greetings.clone(),
+            character_book: book,
+            tags: character.tags.clone(),
+            creator: character.creator_name.clone(),
+            character_version: character.character_version.clone(),
+            extensions,
+            extra,
+        },
+        extra: root,
+    }
+}
+
+// ============================================
+// CharX
+// ============================================
+
+/// A CharX archive: `card.json` and every other file in it, by path
+#[derive(Debug)]
+pub struct Charx {
+    pub card_json: String,
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+pub fn read_charx(bytes: &[u8]) -> AppResult<Charx> {
+    let bad = |e: zip::result::ZipError| AppError::Import(format!("Invalid CharX archive: {}", e));
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).map_err(bad)?;
+
+    let mut card_json = None;
+    let mut files = Vec::new();
+    let mut total = 0u64;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(bad)?;
+        if file.is_dir() {
+            continue;
+        }
+        // Paths that would escape the archive are skipped
+        let Some(path) = file.enclosed_name() else { continue };
+        let name = path.to_string_lossy().replace('\\', "/");
+
+        let mut data = Vec::new();
+        Read::take(&mut file, MAX_CHARX_BYTES - total + 1).read_to_end(&mut data)?;
+        total += data.len() as u64;
+        if total > MAX_CHARX_BYTES {
+            return Err(AppError::Import("CharX archive is too large".into()));
+        }
+
+        if name == CHARX_CARD {
+            card_json = Some(String::from_utf8(data)
+                .map_err(|_| AppError::Import("card.json is not UTF-8".into()))?);
+        } else {
+            files.push((name, data));
+        }
+    }
+
+    let card_json = card_json.ok_or_else(|| AppError::Import("CharX archive has no card.json".into()))?;
+    Ok(Charx { card_json, files })
+}
+
+pub fn write_charx(card_json: &str, files: &[(String, Vec<u8>)]) -> AppResult<Vec<u8>> {
+    let failed = |e: zip::result::ZipError| AppError::Export(e.to_string());
+    let options = zip::write::SimpleFileOptions::default();
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+
+    zip.start_file(CHARX_CARD, options).map_err(failed)?;
+    zip.write_all(card_json.as_bytes())?;
+    for (path, data) in files {
+        zip.start_file(path.as_str(), options).map_err(failed)?;
+        zip.write_all(data)?;
+    }
+    Ok(zip.finish().map_err(failed)?.into_inner())
+}
+
+/// Archive path of an asset stored in the CharX itself.
+/// The spec spells it `embeded://`; accept the correct spelling too.
+fn embedded_path(asset: &Value) -> Option<String> {
+    let uri = asset.get("uri")?.as_str()?;
+    uri.strip_prefix("embeded://")
+        .or_else(|| uri.strip_prefix("embedded://"))
+        .map(|p| p.trim_start_matches('/').to_string())
+}
+
+fn is_main_icon(asset: &Value) -> bool {
+    asset.get("type").and_then(|v| v.as_str()) == Some("icon")
+        && asset.get("name").and_then(|v| v.as_str()) == Some("main")
+}
+
+/// Every file under `dir`, searched recursively, by its `/`-separated
+/// path relative to `dir`. Symlinked folders are skipped so a link loop
+/// can't send the walk in circles.
+pub fn list_files(dir: &Path) -> AppResult<Vec<(String, PathBuf)>> {
+    fn walk(dir: &Path, base: &Path, out: &mut Vec<(String, PathBuf)>) -> AppResult<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                walk(&path, base, out)?;
+            } else if file_type.is_symlink() && path.is_dir() {
+                continue;
+            } else if let Ok(relative) = path.strip_prefix(base) {
+                out.push((relative.to_string_lossy().replace('\\', "/"), path.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    walk(dir, dir, &mut files)?;
+    Ok(files)
+}
+
+pub struct CardService;
+
+impl CardService {
+    /// Create a character from a parsed card, with a linked lorebook for
+    /// its embedded book
+    pub fn create(db: &Database, parsed: ParsedCard, avatar_path: Option<String>) -> AppResult<Character> {
+        let ParsedCard { mut input, book, hash } = parsed;
+        input.avatar_path = avatar_path;
+        let character = CharacterRepo::create(db, &input)?;
+        CharacterRepo::set_card_hash(db, &character.id, &hash)?;
+        CharacterRevisionService::record(db, &character, RevisionSource::Import)?;
+
+        if let Some(book) = book {
+            let name = book.name.clone().unwrap_or_else(|| format!("{} Lorebook", character.name));
+            let lorebook = WorldInfoService::insert(db, book, Some(&name))?;
+            CharacterRepo::attach_lorebook(db, &character.id, &lorebook.id)?;
+        }
+        Ok(character)
+    }
+
+    /// Overwrite character `id` with a parsed card, keeping its chats. The
+    /// avatar stays unless the card brings one, and an embedded book takes
+    /// the place of the linked lorebook with the same name.
+    pub fn replace(db: &Database, id: &str, parsed: ParsedCard, avatar_path: Option<String>) -> AppResult<Character> {
+        let ParsedCard { mut input, book, hash } = parsed;
+        let before = CharacterRepo::find_by_id(db, id)?;
+        CharacterRevisionService::current(db, &before)?;
+
+        input.avatar_path = avatar_path.or(before.avatar_path);
+        let character = CharacterRepo::replace(db, id, &input)?;
+        CharacterRepo::set_card_hash(db, id, &hash)?;
+        CharacterRevisionService::record(db, &character, RevisionSource::Import)?;
+
+        if let Some(book) = book {
+            let name = book.name.clone().unwrap_or_else(|| format!("{} Lorebook", character.name));
+            // The card's previous book is dropped unless something else uses it
+            for old in LorebookService::list_for_character(db, id)?.iter().filter(|l| l.name == name) {
+                CharacterRepo::detach_lorebook(db, id, &old.id)?;
+                if !old.is_global && !LorebookRepo::is_linked(db, &old.id)? {
+                    LorebookRepo::delete(db, &old.id)?;
+                }
+            }
+            let lorebook = WorldInfoService::insert(db, book, Some(&name))?;
+            CharacterRepo::attach_lorebook(db, id, &lorebook.id)?;
+        }
+        Ok(character)
+    }
+
+    /// A new character, or character `replace` overwritten
+    pub fn save(db: &Database, parsed: ParsedCard, avatar_path: Option<String>, replace: Option<&str>) -> AppResult<Character> {
+        match replace {
+            Some(id) => Self::replace(db, id, parsed, avatar_path),
+            None => Self::create(db, parsed, avatar_path),
+        }
+    }
+
+    /// Import a PNG card; the image itself becomes the avatar
+    pub fn import_png(db: &Database, paths: &AppPaths, png: &[u8]) -> AppResult<Character> {
+        let parsed = parse_card(&read_card(png)?)?;
+        Self::save_png(db, paths, parsed, png, None)
+    }
+
+    /// Save a card read from `png`, with the image as the avatar
+    pub fn save_png(db: &Database, paths: &AppPaths, parsed: ParsedCard, png: &[u8], replace: Option<&str>) -> AppResult<Character> {
+        // Re-encoding leaves the card chunks behind; exports write fresh ones
+        let filename = AvatarService::store(paths, png)?;
+        Self::save(db, parsed, Some(filename.clone()), replace).inspect_err(|_| {
+            let _ = std::fs::remove_file(paths.avatar_file_path(&filename));
+            let _ = std::fs::remove_file(paths.avatar_thumbnail_path(&filename));
+        })
+    }
+
+    /// Import a CharX archive. The main icon becomes the avatar and the
+    /// other files are kept for export.
+    pub fn import_charx(db: &Database, paths: &AppPaths, bytes: &[u8]) -> AppResult<Character> {
+        let Charx { card_json, files } = read_charx(bytes)?;
+        Self::save_charx(db, paths, parse_card(&card_json)?, files, None)
+    }
+
+    /// Save a card read from a CharX archive along with the archive's files
+    pub fn save_charx(
+        db: &Database,
+        paths: &AppPaths,
+        parsed: ParsedCard,
+        mut files: Vec<(String, Vec<u8>)>,
+        replace: Option<&str>,
+    ) -> AppResult<Character> {
+        let icon = parsed.input.card.as_ref()
+            .and_then(|card| card.get("data")?.get("assets")?.as_array())
+            .and_then(|assets| assets.iter().find(|a| is_main_icon(a)))
+            .and_then(embedded_path)
+            .and_then(|icon| files.iter().position(|(name, _)| *name == icon))
+            .map(|i| files.remove(i));
+
+        let avatar_path = match icon {
+            Some((name, data)) => match AvatarService::store(paths, &data) {
+                Ok(filename) => Some(filename),
+                Err(e) => {
+                    tracing::warn!("Skipping unusable CharX icon {}: {}", name, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let character = Self::save(db, parsed, avatar_path, replace)?;
+
+        let dir = paths.character_assets_dir(&character.id);
+        if replace.is_some() && dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        let stored = files.iter().try_for_each(|(name, data)| {
+            let path = dir.join(name);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, data)
+        });
+        if let Err(e) = stored {
+            let _ = std::fs::remove_dir_all(&dir);
+            if replace.is_none() {
+                let _ = CharacterRepo::delete(db, &character.id);
+            }
+            return Err(e.into());
+        }
+
+        Ok(character)
+    }
+
+    /// Card JSON for a character, in the spec it came in unless one is given
+    pub fn export_json(db: &Database, id: &str, spec: Option<CardSpec>) -> AppResult<String> {
+        let character = CharacterRepo::find_by_id(db, id)?;
+        let lorebooks = LorebookService::list_for_character(db, id)?;
+        Ok(serde_json::to_string_pretty(&to_card(&character, &lorebooks, spec))?)
+    }
+
+    /// The character's avatar with its card embedded: `chara` always,
+    /// plus `ccv3` for V3 cards
+    pub fn export_png(db: &Database, paths: &AppPaths, id: &str) -> AppResult<Vec<u8>> {
+        let character = CharacterRepo::find_by_id(db, id)?;
+        let lorebooks = LorebookService::list_for_character(db, id)?;
+
+        let v3 = to_card(&character, &lorebooks, None);
+        let v2 = serde_json::to_string(&to_card(&character, &lorebooks, Some(CardSpec::V2)))?;
+        let v3 = (v3.spec == CardSpec::V3.as_str())
+            .then(|| serde_json::to_string(&v3))
+            .transpose()?;
+
+        let image = match ExportService::read_avatar(paths, &character)? {
+            Some(bytes) => avatars::to_png(&bytes).unwrap_or_else(|e| {
+                tracing::warn!("Avatar for {} can't be converted to PNG ({}); exporting a blank image", character.id, e);
+                placeholder_png()
+            }),
+            None => placeholder_png(),
+        };
+
+        let mut cards = vec![("chara", v2.as_str())];
+        if let Some(v3) = &v3 {
+            cards.push(("ccv3", v3.as_str()));
+        }
+        write_card(&image, &cards)
+    }
+
+    /// A CharX archive with the V3 card, the avatar as its main icon and
+    /// any files kept from an imported archive
+    pub fn export_charx(db: &Database, paths: &AppPaths, id: &str) -> AppResult<Vec<u8>> {
+        let character = CharacterRepo::find_by_id(db, id)?;
+        let lorebooks = LorebookService::list_for_character(db, id)?;
+        let mut card = to_card(&character, &lorebooks, Some(CardSpec::V3));
+
+        let mut files = Vec::new();
+        let dir = paths.character_assets_dir(id);
+        if dir.is_dir() {
+            for (name, path) in list_files(&dir)? {
+                files.push((name, std::fs::read(path)?));
+            }
+        }
+
+        if let Some(avatar) = ExportService::read_avatar(paths, &character)? {
+            let ext = character.avatar_path.as_deref()
+                .and_then(|p| Path::new(p).extension())
+                .and_then(|e| e.to_str())
+                .unwrap_or("png")
+                .to_string();
+
+            let assets = card.data.extra.entry("assets").or_insert_with(|| json!([]));
+            if !assets.is_array() {
+                *assets = json!([]);
+            }
+            let assets = assets.as_array_mut().expect("assets is an array");
+            if !assets.iter().any(is_main_icon) {
+                assets.push(json!({ "type": "icon", "uri": "ccdefault:", "name": "main", "ext": ext }));
+            }
+            let icon = assets.iter_mut().find(|a| is_main_icon(a)).expect("main icon was added");
+
+            // The card may point at the container image, which a CharX doesn't have
+            let path = embedded_path(icon).unwrap_or_else(|| {
+                let path = format!("assets/icon/images/main.{}", ext);
+                icon["uri"] = json!(format!("embeded://{}", path));
+                icon["ext"] = json!(ext);
+                path
+            });
+            files.retain(|(name, _)| *name != path);
+            files.push((path, avatar));
+        }
+
+        write_charx(&serde_json::to_string_pretty(&card)?, &files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn itxt(keyword: &str, text: &str, compress: bool) -> Vec<u8> {
+        let mut data = keyword.as_bytes().to_vec();
+        data.extend_from_slice(&[0, compress as u8, 0]);
+        data.extend_from_slice(b"en\0\0");
+        if compress {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(text.as_bytes()).unwrap();
+            data.extend_from_slice(&encoder.finish().unwrap());
+        } else {
+            data.extend_from_slice(text.as_bytes());
+        }
+        data
+    }
+
+    /// Placeholder image with extra chunks spliced in before IEND
+    fn png_with(extra: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let base = placeholder_png();
+        let iend = base.len() - 12;
+        let mut out = base[..iend].to_vec();
+        for (kind, data) in extra {
+            write_chunk(&mut out, kind, data);
+        }
+        out.extend_from_slice(&base[iend..]);
+        out
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let card = r#"{"spec":"chara_card_v2","data":{"name":"Aldric ✦"}}"#;
+        let png = write_card(&placeholder_png(), &[("chara", card)]).unwrap();
+        assert_eq!(read_card(&png).unwrap(), card);
+
+        // Rewriting replaces the old card instead of adding a second one
+        let png = write_card(&png, &[("chara", "{}")]).unwrap();
+        assert_eq!(read_card(&png).unwrap(), "{}");
+        let count = chunks(&png).unwrap().iter().filter(|c| &c.kind == b"tEXt").count();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_prefers_v3_and_reads_itxt() {
+        let v2 = base64::engine::general_purpose::STANDARD.encode(r#"{"spec":"chara_card_v2"}"#);
+        let v3 = base64::engine::general_purpose::STANDARD.encode(r#"{"spec":"chara_card_v3"}"#);
+        let png = png_with(&[
+            (b"iTXt", itxt("chara", &v2, false)),
+            (b"iTXt", itxt("ccv3", &v3, true)),
+        ]);
+        assert_eq!(read_card(&png).unwrap(), r#"{"spec":"chara_card_v3"}"#);
+    }
+
+    /// What the repository would hand back after storing `input`
+    fn stored(input: CreateCharacterInput) -> Character {
+        let mut fields = serde_json::to_value(&input).unwrap();
+        fields["id"] = json!("c");
+        fields["metadata"] = json!({ "card": input.card });
+        test_character(fields)
+    }
+
+    #[test]
+    fn test_card_round_trip() {
+        let original = json!({
+            "spec": "chara_card_v3",
+            "spec_version": "3.0",
+            "name": "Aldric",
+            "talkativeness": "0.5",
+            "data": {
+                "name": "Aldric",
+                "description": "A knight.",
+                "personality": "Stern",
+                "scenario": "The siege of Aster.",
+                "first_mes": "Halt.",
+                "mes_example": "<START>",
+                "creator_notes": "Notes",
+                "system_prompt": "Stay in character.",
+                "post_history_instructions": "Be brief.",
+                "alternate_greetings": ["Who goes there?"],
+                "tags": ["fantasy"],
+                "creator": "someone",
+                "character_version": "1.2",
+                "extensions": { "depth_prompt": { "depth": 4, "prompt": "x" } },
+                "nickname": "Al",
+                "group_only_greetings": [],
+                "assets": [{ "type": "icon", "uri": "ccdefault:", "name": "main", "ext": "png" }]
+            }
+        });
+
+        let parsed = parse_card(&original.to_string()).unwrap();
+        assert_eq!(parsed.input.scenario, "The siege of Aster.");
+        assert_eq!(parsed.input.description, "A knight.");
+        assert_eq!(parsed.input.post_history_instructions, "Be brief.");
+
+        let character = stored(parsed.input);
+        let exported = serde_json::to_value(to_card(&character, &[], None)).unwrap();
+        assert_eq!(exported, original);
+
+        // Downgrading keeps the V3-only fields where V2 readers ignore them
+        let v2 = serde_json::to_value(to_card(&character, &[], Some(CardSpec::V2))).unwrap();
+        assert_eq!(v2["spec"], "chara_card_v2");
+        assert_eq!(v2["spec_version"], "2.0");
+        assert_eq!(v2["data"]["nickname"], "Al");
+    }
+
+    #[test]
+    fn test_v1_and_nulls() {
+        let v1 = parse_card(r#"{"char_name":"Old","world_scenario":"A tavern","system_prompt":"Hi","fav":true}"#).unwrap();
+        assert_eq!(v1.input.name, "Old");
+        assert_eq!(v1.input.scenario, "A tavern");
+        assert_eq!(v1.input.system_prompt, "Hi");
+        let exported = serde_json::to_value(to_card(&stored(v1.input), &[], None)).unwrap();
+        assert_eq!(exported["fav"], true);
+        assert_eq!(exported["data"]["scenario"], "A tavern");
+
+        let nulls = r#"{"spec":"chara_card_v2","data":{"name":"N","creator_notes":null,"tags":null,"alternate_greetings":null}}"#;
+        assert!(parse_card(nulls).unwrap().input.tags.is_empty());
+    }
+
+    #[test]
+    fn test_charx() {
+        let files = vec![("assets/icon/images/main.png".to_string(), placeholder_png())];
+        let archive = write_charx("{}", &files).unwrap();
+        let charx = read_charx(&archive).unwrap();
+        assert_eq!(charx.card_json, "{}");
+        assert_eq!(charx.files, files);
+        assert!(read_charx(&write_charx_without_card()).is_err());
+    }
+
+    fn write_charx_without_card() -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("other.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        assert!(read_card(b"GIF89a").is_err());
+        assert!(read_card(&placeholder_png()).is_err());
+
+        let mut png = write_card(&placeholder_png(), &[("chara", "{}")]).unwrap();
+        let last = png.len() - 20;
+        png[last] ^= 0xff;
+        assert!(read_card(&png).is_err());
+    }
+
+    #[test]
+    fn test_card_hash_ignores_key_order() {
+        let a: Value = serde_json::from_str(r#"{"spec":"chara_card_v2","data":{"name":"Aria","tags":["a","b"]}}"#).unwrap();
+        let b: Value = serde_json::from_str(r#"{"data":{"tags":["a","b"],"name":"Aria"},"spec":"chara_card_v2"}"#).unwrap();
+        let c: Value = serde_json::from_str(r#"{"data":{"tags":["b","a"],"name":"Aria"},"spec":"chara_card_v2"}"#).unwrap();
+        assert_eq!(card_hash(&a), card_hash(&b));
+        assert_ne!(card_hash(&a), card_hash(&c));
+        assert_eq!(card_hash(&a).len(), 64);
+    }
+}
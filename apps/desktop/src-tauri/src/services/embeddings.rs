@@ -3,10 +3,14 @@
 // Generates and stores vector embeddings for semantic search
 // ============================================
 
+use std::str::FromStr;
+
 use crate::database::Database;
 use crate::entities::{new_id, now_timestamp};
 use crate::error::{AppError, AppResult};
-use crate::sidecar::{generate_embedding, SidecarHandle};
+use crate::repositories::{LorebookRepo, SettingsRepo};
+use crate::services::vector_index::{HnswIndex, VECTOR_INDEX};
+use crate::sidecar::{generate_embedding, generate_embeddings_batch, SidecarHandle};
 
 /// Compute cosine similarity between two vectors
 pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
@@ -44,52 +48,198 @@ fn bytes_to_embedding(bytes: &[u8]) -> Vec<f32> {
         .collect()
 }
 
+/// On-disk encoding of an embedding BLOB
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingEncoding {
+    /// 4 bytes per dimension, lossless
+    F32,
+    /// 2 bytes per dimension, IEEE half precision
+    F16,
+    /// 1 byte per dimension plus a 4-byte f32 scale prefix
+    Int8,
+}
+
+impl EmbeddingEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmbeddingEncoding::F32 => "f32",
+            EmbeddingEncoding::F16 => "f16",
+            EmbeddingEncoding::Int8 => "int8",
+        }
+    }
+
+    /// Encoding configured via the `embeddings.quantization` setting
+    pub fn configured(db: &Database) -> Self {
+        SettingsRepo::get(db, "embeddings.quantization")
+            .ok()
+            .flatten()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(EmbeddingEncoding::F32)
+    }
+
+    pub fn encode(&self, embedding: &[f32]) -> Vec<u8> {
+        match self {
+            EmbeddingEncoding::F32 => embedding_to_bytes(embedding),
+            EmbeddingEncoding::F16 => embedding
+                .iter()
+                .flat_map(|f| half::f16::from_f32(*f).to_le_bytes())
+                .collect(),
+            EmbeddingEncoding::Int8 => {
+                let max_abs = embedding.iter().fold(0.0f32, |m, x| m.max(x.abs()));
+                let scale = if max_abs > 0.0 { max_abs / 127.0 } else { 1.0 };
+                let mut bytes = Vec::with_capacity(4 + embedding.len());
+                bytes.extend_from_slice(&scale.to_le_bytes());
+                bytes.extend(embedding.iter().map(|x| (x / scale).round().clamp(-127.0, 127.0) as i8 as u8));
+                bytes
+            }
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Vec<f32> {
+        match self {
+            EmbeddingEncoding::F32 => bytes_to_embedding(bytes),
+            EmbeddingEncoding::F16 => bytes
+                .chunks(2)
+                .map(|chunk| {
+                    let arr: [u8; 2] = chunk.try_into().unwrap_or([0; 2]);
+                    half::f16::from_le_bytes(arr).to_f32()
+                })
+                .collect(),
+            EmbeddingEncoding::Int8 => {
+                if bytes.len() < 4 {
+                    return Vec::new();
+                }
+                let scale = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                bytes[4..].iter().map(|b| *b as i8 as f32 * scale).collect()
+            }
+        }
+    }
+}
+
+impl FromStr for EmbeddingEncoding {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "f32" | "none" => Ok(EmbeddingEncoding::F32),
+            "f16" => Ok(EmbeddingEncoding::F16),
+            "int8" => Ok(EmbeddingEncoding::Int8),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A vector together with the identity of the model that produced it
+#[derive(Debug, Clone)]
+pub struct Embedding {
+    pub model_id: String,
+    pub vector: Vec<f32>,
+}
+
+/// Something whose embedding needs (re)generating
+#[derive(Debug, Clone)]
+pub struct EmbeddingTarget {
+    pub entity_type: String,
+    pub entity_id: String,
+    pub partition_key: String,
+    pub text: String,
+}
+
+/// Embeddable rows that either have no vector or one from another model.
+/// Lorebook entries missing a vector are only embedded if they use vector
+/// activation; ones embedded before are always kept current.
+const REEMBED_TARGETS_SQL: &str =
+    "SELECT 'memory', m.id, m.character_id, m.content
+     FROM memory_entries m
+     LEFT JOIN embeddings e ON e.entity_type = 'memory' AND e.entity_id = m.id
+     WHERE (e.id IS NULL OR e.model_id != ?1) AND trim(m.content) != ''
+     UNION ALL
+     SELECT 'lorebook', le.id, le.lorebook_id, le.content
+     FROM lorebook_entries le
+     LEFT JOIN embeddings e ON e.entity_type = 'lorebook' AND e.entity_id = le.id
+     WHERE ((e.id IS NULL AND le.activation_mode != 'keyword') OR e.model_id != ?1)
+       AND trim(le.content) != ''";
+
+/// Truncate very long text to avoid memory issues
+fn truncate_for_embedding(text: &str) -> &str {
+    // SAFETY: Find valid UTF-8 boundary to prevent panic on multi-byte chars
+    if text.len() > 8000 {
+        let mut end = 8000;
+        // Walk backwards to find valid UTF-8 character boundary
+        while !text.is_char_boundary(end) && end > 0 {
+            end -= 1;
+        }
+        &text[..end]
+    } else {
+        text
+    }
+}
+
 pub struct EmbeddingService;
 
 impl EmbeddingService {
-    /// Generate embedding for text using the loaded model
+    /// Generate embedding for text using the given model
     pub async fn generate(
         sidecar: &SidecarHandle,
         text: &str,
-    ) -> AppResult<Vec<f32>> {
+    ) -> AppResult<Embedding> {
         if text.trim().is_empty() {
             return Err(AppError::Validation("Cannot generate embedding for empty text".into()));
         }
         
-        // Truncate very long text to avoid memory issues
-        // SAFETY: Find valid UTF-8 boundary to prevent panic on multi-byte chars
-        let truncated = if text.len() > 8000 {
-            let mut end = 8000;
-            // Walk backwards to find valid UTF-8 character boundary
-            while !text.is_char_boundary(end) && end > 0 {
-                end -= 1;
-            }
-            &text[..end]
-        } else {
-            text
-        };
+        let vector = generate_embedding(sidecar, truncate_for_embedding(text)).await?;
+        Ok(Embedding { model_id: sidecar.model_id.clone(), vector })
+    }
+    
+    /// Generate embeddings for several texts in a single request
+    pub async fn generate_batch(
+        sidecar: &SidecarHandle,
+        texts: &[String],
+    ) -> AppResult<Vec<Embedding>> {
+        let truncated: Vec<String> = texts.iter()
+            .map(|t| truncate_for_embedding(t).to_string())
+            .collect();
         
-        generate_embedding(sidecar, truncated).await
+        let vectors = generate_embeddings_batch(sidecar, &truncated).await?;
+        Ok(vectors.into_iter()
+            .map(|vector| Embedding { model_id: sidecar.model_id.clone(), vector })
+            .collect())
     }
     
-    /// Store an embedding in the database
+    /// Store an embedding in the database.
+    /// `partition_key` groups vectors for the ANN index (character id for
+    /// memories, lorebook id for lorebook entries).
     pub fn store(
         db: &Database,
         entity_type: &str,
         entity_id: &str,
-        embedding: &[f32],
+        partition_key: &str,
+        embedding: &Embedding,
     ) -> AppResult<()> {
         let id = new_id();
-        let bytes = embedding_to_bytes(embedding);
-        let dimensions = embedding.len() as i32;
+        let encoding = EmbeddingEncoding::configured(db);
+        let bytes = encoding.encode(&embedding.vector);
+        let dimensions = embedding.vector.len() as i32;
         let now = now_timestamp();
+        let previous = Self::partition_of(db, entity_type, entity_id)?;
         
         db.execute(
-            "INSERT OR REPLACE INTO embeddings (id, entity_type, entity_id, embedding, dimensions, created_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            rusqlite::params![id, entity_type, entity_id, bytes, dimensions, now],
+            "INSERT OR REPLACE INTO embeddings (id, entity_type, entity_id, embedding, dimensions, created_at, partition_key, encoding, model_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![id, entity_type, entity_id, bytes, dimensions, now, partition_key, encoding.as_str(), embedding.model_id],
         )?;
         
+        // A row can move partitions (e.g. entry moved between lorebooks)
+        if let Some(previous) = previous {
+            if previous != partition_key {
+                VECTOR_INDEX.remove(entity_type, &previous, entity_id);
+            }
+        }
+        VECTOR_INDEX.upsert(entity_type, partition_key, &embedding.model_id, entity_id, &embedding.vector);
+        
+        if entity_type == "lorebook" {
+            LorebookRepo::set_has_embedding(db, entity_id, true)?;
+        }
+        
         Ok(())
     }
     
@@ -100,58 +250,173 @@ impl EmbeddingService {
         entity_id: &str,
     ) -> AppResult<Option<Vec<f32>>> {
         let result = db.query_optional(
-            "SELECT embedding FROM embeddings WHERE entity_type = ?1 AND entity_id = ?2",
+            "SELECT embedding, encoding FROM embeddings WHERE entity_type = ?1 AND entity_id = ?2",
             rusqlite::params![entity_type, entity_id],
             |row| {
                 let bytes: Vec<u8> = row.get(0)?;
-                Ok(bytes)
+                let encoding: String = row.get(1)?;
+                Ok((bytes, encoding))
             },
         )?;
         
-        match result {
-            Some(bytes) => Ok(Some(bytes_to_embedding(&bytes))),
-            None => Ok(None),
-        }
+        Ok(result.map(|(bytes, encoding)| {
+            encoding.parse().unwrap_or(EmbeddingEncoding::F32).decode(&bytes)
+        }))
     }
     
-    /// Find similar embeddings using cosine similarity
+    /// Find similar embeddings using the in-memory ANN index.
+    /// Only vectors from the query's model with matching dimensions are
+    /// compared; anything else is stale and waiting to be re-embedded.
+    /// With a partition key only that partition is searched; otherwise every
+    /// partition of the entity type is searched and the results merged.
     /// Returns (entity_id, similarity_score) pairs sorted by similarity
     pub fn find_similar(
         db: &Database,
-        query_embedding: &[f32],
+        query: &Embedding,
         entity_type: &str,
+        partition_key: Option<&str>,
         limit: usize,
         min_similarity: f32,
     ) -> AppResult<Vec<(String, f32)>> {
-        // Get all embeddings of the specified type
+        let partitions = match partition_key {
+            Some(key) => vec![key.to_string()],
+            None => db.query_all(
+                "SELECT DISTINCT partition_key FROM embeddings WHERE entity_type = ?1 AND model_id = ?2",
+                rusqlite::params![entity_type, query.model_id],
+                |row| row.get(0),
+            )?,
+        };
+        
+        let mut results: Vec<(String, f32)> = Vec::new();
+        for partition in partitions {
+            let index = Self::load_partition(db, entity_type, &partition, query)?;
+            let found = index.read().search(&query.vector, limit, min_similarity);
+            results.extend(found);
+        }
+        
+        // Sort by similarity (descending)
+        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        
+        // Limit results
+        results.truncate(limit);
+        
+        Ok(results)
+    }
+    
+    /// Get a partition of the ANN index, building it from the database on first use.
+    /// The DB lock is only held while the rows are read, not while the graph is built.
+    fn load_partition(
+        db: &Database,
+        entity_type: &str,
+        partition_key: &str,
+        query: &Embedding,
+    ) -> AppResult<std::sync::Arc<parking_lot::RwLock<HnswIndex>>> {
+        if let Some(index) = VECTOR_INDEX.get(entity_type, partition_key, &query.model_id) {
+            return Ok(index);
+        }
+        
+        // Started before the read so writes landing after it are replayed at install
+        let load = VECTOR_INDEX.begin_load(entity_type, partition_key, &query.model_id);
         let rows = db.query_all(
-            "SELECT entity_id, embedding FROM embeddings WHERE entity_type = ?1",
-            rusqlite::params![entity_type],
+            "SELECT entity_id, embedding, encoding FROM embeddings
+             WHERE entity_type = ?1 AND partition_key = ?2 AND model_id = ?3 AND dimensions = ?4",
+            rusqlite::params![entity_type, partition_key, query.model_id, query.vector.len() as i32],
             |row| {
                 let entity_id: String = row.get(0)?;
                 let bytes: Vec<u8> = row.get(1)?;
-                Ok((entity_id, bytes))
+                let encoding: String = row.get(2)?;
+                Ok((entity_id, bytes, encoding))
             },
         )?;
         
-        // Calculate similarities
-        let mut results: Vec<(String, f32)> = rows
-            .into_iter()
-            .map(|(id, bytes)| {
-                let embedding = bytes_to_embedding(&bytes);
-                let similarity = cosine_similarity(query_embedding, &embedding);
-                (id, similarity)
-            })
-            .filter(|(_, sim)| *sim >= min_similarity)
-            .collect();
+        let mut index = HnswIndex::new();
+        for (entity_id, bytes, encoding) in rows {
+            let embedding = encoding.parse().unwrap_or(EmbeddingEncoding::F32).decode(&bytes);
+            index.insert(&entity_id, &embedding);
+        }
         
-        // Sort by similarity (descending)
-        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        tracing::debug!("Loaded vector index partition {}/{} ({} vectors)", entity_type, partition_key, index.len());
+        Ok(load.install(index))
+    }
+    
+    /// Partition key of a stored embedding, if any
+    fn partition_of(db: &Database, entity_type: &str, entity_id: &str) -> AppResult<Option<String>> {
+        db.query_optional(
+            "SELECT partition_key FROM embeddings WHERE entity_type = ?1 AND entity_id = ?2",
+            rusqlite::params![entity_type, entity_id],
+            |row| row.get(0),
+        )
+    }
+    
+    /// Re-encode every stored embedding with the given encoding.
+    /// Returns the number of rows rewritten.
+    pub fn requantize_all(db: &Database, encoding: EmbeddingEncoding) -> AppResult<usize> {
+        let rows = db.query_all(
+            "SELECT id, embedding, encoding FROM embeddings WHERE encoding != ?1",
+            rusqlite::params![encoding.as_str()],
+            |row| {
+                let id: String = row.get(0)?;
+                let bytes: Vec<u8> = row.get(1)?;
+                let current: String = row.get(2)?;
+                Ok((id, bytes, current))
+            },
+        )?;
         
-        // Limit results
-        results.truncate(limit);
+        let count = rows.len();
+        db.transaction(|conn| {
+            let mut stmt = conn.prepare("UPDATE embeddings SET embedding = ?1, encoding = ?2 WHERE id = ?3")?;
+            for (id, bytes, current) in &rows {
+                let embedding = current.parse().unwrap_or(EmbeddingEncoding::F32).decode(bytes);
+                stmt.execute(rusqlite::params![encoding.encode(&embedding), encoding.as_str(), id])?;
+            }
+            Ok(())
+        })?;
         
-        Ok(results)
+        // Loaded partitions hold the old precision; reload lazily
+        VECTOR_INDEX.clear();
+        Ok(count)
+    }
+    
+    /// Next batch of rows that need embedding with `model_id`
+    pub fn reembed_targets(db: &Database, model_id: &str, limit: usize) -> AppResult<Vec<EmbeddingTarget>> {
+        db.query_all(
+            &format!("{} LIMIT ?2", REEMBED_TARGETS_SQL),
+            rusqlite::params![model_id, limit as i64],
+            |row| Ok(EmbeddingTarget {
+                entity_type: row.get(0)?,
+                entity_id: row.get(1)?,
+                partition_key: row.get(2)?,
+                text: row.get(3)?,
+            }),
+        )
+    }
+    
+    /// Number of rows that still need embedding with `model_id`
+    pub fn count_reembed_targets(db: &Database, model_id: &str) -> AppResult<i64> {
+        db.query_one(
+            &format!("SELECT COUNT(*) FROM ({})", REEMBED_TARGETS_SQL),
+            rusqlite::params![model_id],
+            |row| row.get(0),
+        )
+    }
+    
+    /// Remove embeddings whose memory or lorebook entry no longer exists
+    pub fn delete_orphans(db: &Database) -> AppResult<usize> {
+        let removed = db.execute(
+            "DELETE FROM embeddings
+             WHERE (entity_type = 'memory' AND entity_id NOT IN (SELECT id FROM memory_entries))
+                OR (entity_type = 'lorebook' AND entity_id NOT IN (SELECT id FROM lorebook_entries))",
+            [],
+        )?;
+        if removed > 0 {
+            VECTOR_INDEX.clear();
+        }
+        Ok(removed)
+    }
+    
+    /// Total number of stored embeddings
+    pub fn count(db: &Database) -> AppResult<i64> {
+        db.query_one("SELECT COUNT(*) FROM embeddings", [], |row| row.get(0))
     }
     
     /// Delete embedding for an entity
@@ -160,10 +425,17 @@ impl EmbeddingService {
         entity_type: &str,
         entity_id: &str,
     ) -> AppResult<()> {
+        let partition = Self::partition_of(db, entity_type, entity_id)?;
         db.execute(
             "DELETE FROM embeddings WHERE entity_type = ?1 AND entity_id = ?2",
             rusqlite::params![entity_type, entity_id],
         )?;
+        if let Some(partition) = partition {
+            VECTOR_INDEX.remove(entity_type, &partition, entity_id);
+        }
+        if entity_type == "lorebook" {
+            LorebookRepo::set_has_embedding(db, entity_id, false)?;
+        }
         Ok(())
     }
     
@@ -202,6 +474,18 @@ mod tests {
         assert!(sim.abs() < 0.0001);
     }
     
+    #[test]
+    fn test_quantized_roundtrip() {
+        let original = vec![0.12, -0.5, 0.33, 0.0, 0.9, -0.91];
+        for encoding in [EmbeddingEncoding::F16, EmbeddingEncoding::Int8] {
+            let restored = encoding.decode(&encoding.encode(&original));
+            assert_eq!(restored.len(), original.len());
+            assert!(cosine_similarity(&original, &restored) > 0.999);
+        }
+        assert_eq!(EmbeddingEncoding::F16.encode(&original).len(), 12);
+        assert_eq!(EmbeddingEncoding::Int8.encode(&original).len(), 10);
+    }
+    
     #[test]
     fn test_embedding_serialization() {
         let original = vec![0.1, 0.2, 0.3, 0.4, 0.5];
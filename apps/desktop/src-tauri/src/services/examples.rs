@@ -0,0 +1,278 @@
+// ============================================
+// Example Dialogues
+// Splits a card's example dialogues into <START> blocks, fits them to
+// the example budget, and turns them into chat messages when asked
+// ============================================
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::entities::*;
+use crate::services::estimate_tokens;
+
+/// A line holding only `<START>`, in any case
+static START: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?im)^[ \t]*<start>[ \t]*$").expect("valid regex")
+});
+
+/// `{{user}}:` and `{{char}}:`, plus the older `<USER>:` and `<BOT>:`
+static SPEAKER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^\s*(\{\{user\}\}|<user>|\{\{char\}\}|<bot>)\s*:\s?").expect("valid regex")
+});
+
+/// One exchange from the examples, as (speaker, text) turns
+pub type ExampleBlock = Vec<(AuthorType, String)>;
+
+/// Where a prompt's examples end up
+#[derive(Debug, PartialEq)]
+pub enum ExamplePlacement {
+    None,
+    SystemPrompt(String),
+    Messages(Vec<ExampleBlock>),
+}
+
+/// The non-empty blocks between `<START>` lines, in order. Text before
+/// the first separator, or a card without any, makes a block too.
+pub fn parse_blocks(text: &str) -> Vec<String> {
+    START.split(text)
+        .map(str::trim)
+        .filter(|block| !block.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whole blocks, in order, within `budget` tokens. A block too big to fit
+/// is passed over so smaller ones after it still get in.
+pub fn fit_blocks(blocks: Vec<String>, budget: i32) -> Vec<String> {
+    let mut used = 0;
+    blocks.into_iter()
+        .filter(|block| {
+            let tokens = estimate_tokens(block);
+            let fits = used + tokens <= budget;
+            if fits {
+                used += tokens;
+            }
+            fits
+        })
+        .collect()
+}
+
+/// A block split into turns at its speaker tags. Untagged lines continue
+/// the turn above; None if the block doesn't start with a `{{user}}` turn,
+/// since examples sent as messages have to open with the user.
+pub fn to_turns(block: &str) -> Option<ExampleBlock> {
+    let mut turns: ExampleBlock = Vec::new();
+    for line in block.lines() {
+        let Some(tag) = SPEAKER.captures(line) else {
+            match turns.last_mut() {
+                Some((_, content)) => {
+                    content.push('\n');
+                    content.push_str(line);
+                }
+                None if line.trim().is_empty() => {}
+                None => return None,
+            }
+            continue;
+        };
+
+        let author = if tag[1].to_ascii_lowercase().contains("user") { AuthorType::User } else { AuthorType::Character };
+        let text = &line[tag[0].len()..];
+        match turns.last_mut() {
+            Some((last, content)) if *last == author => {
+                content.push('\n');
+                content.push_str(text);
+            }
+            _ => turns.push((author, text.to_string())),
+        }
+    }
+
+    for (_, content) in turns.iter_mut() {
+        *content = content.trim().to_string();
+    }
+    turns.retain(|(_, content)| !content.is_empty());
+    match turns.first() {
+        Some((AuthorType::User, _)) => Some(turns),
+        _ => None,
+    }
+}
+
+/// Pick the examples for a prompt. Injected messages stop once `history`
+/// has enough character replies of its own to show the voice; blocks
+/// without speaker tags, or opening with `{{char}}`, can't be messages and
+/// stay in the system prompt.
+pub fn place(example_dialogues: &str, generation: &GenerationSettings, history: &[Message]) -> ExamplePlacement {
+    let blocks = fit_blocks(parse_blocks(example_dialogues), generation.example_dialogue_budget.unwrap_or(500));
+    if blocks.is_empty() {
+        return ExamplePlacement::None;
+    }
+
+    if generation.example_dialogue_mode.unwrap_or_default() == ExampleDialogueMode::Messages {
+        let replies = history.iter().filter(|m| m.author_type == AuthorType::Character).count();
+        if replies as i32 >= generation.example_dialogue_drop_after.unwrap_or(8) {
+            return ExamplePlacement::None;
+        }
+        if let Some(turns) = blocks.iter().map(|block| to_turns(block)).collect::<Option<Vec<_>>>() {
+            return ExamplePlacement::Messages(turns);
+        }
+    }
+
+    let text = blocks.iter().map(|block| format!("<START>\n{}", block)).collect::<Vec<_>>().join("\n");
+    ExamplePlacement::SystemPrompt(text)
+}
+
+/// Example blocks as messages for the prompt, one Vec per block.
+/// `expand` fills in macros.
+pub fn to_messages(blocks: Vec<ExampleBlock>, conversation_id: &str, mut expand: impl FnMut(&str) -> String) -> Vec<Vec<Message>> {
+    blocks.into_iter().enumerate()
+        .map(|(b, turns)| {
+            turns.into_iter().enumerate()
+                .map(|(t, (author_type, text))| {
+                    let content = expand(&text);
+                    Message {
+                        id: format!("example-{}-{}", b, t),
+                        conversation_id: conversation_id.to_string(),
+                        parent_id: None,
+                        author_type,
+                        author_id: None,
+                        token_count: estimate_tokens(&content),
+                        content,
+                        is_active_branch: true,
+                        branch_index: 0,
+                        generation_params: None,
+                        created_at: 0,
+                        metadata: serde_json::json!({ "example": true }),
+                        author_name: None,
+                        sibling_count: None,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Whole blocks, in order, within the `room` the real history left.
+/// Roles alternate: a turn with the same speaker as the one before it,
+/// across blocks, is merged into it, and trailing turns with the speaker
+/// of `next`, the history's first message, are left out.
+pub fn fit_messages(blocks: Vec<Vec<Message>>, room: i32, next: Option<AuthorType>) -> Vec<Message> {
+    let mut used = 0;
+    let mut fitted: Vec<Message> = Vec::new();
+    for block in blocks {
+        let tokens: i32 = block.iter().map(|m| m.token_count).sum();
+        if used + tokens > room {
+            continue;
+        }
+        used += tokens;
+        for message in block {
+            match fitted.last_mut() {
+                Some(last) if last.author_type == message.author_type => {
+                    last.content.push_str("\n\n");
+                    last.content.push_str(&message.content);
+                    last.token_count += message.token_count;
+                }
+                _ => fitted.push(message),
+            }
+        }
+    }
+    while fitted.last().is_some_and(|m| Some(m.author_type) == next) {
+        fitted.pop();
+    }
+    fitted
+}
+
+/// Messages made from the examples rather than said in the chat
+pub fn is_example(message: &Message) -> bool {
+    message.metadata.get("example").and_then(|v| v.as_bool()) == Some(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLES: &str = "<START>\n{{user}}: Hi there.\n{{char}}: *waves* Hello!\nHow are you?\n\
+                            <start>\n<USER>: Sing something.\n<BOT>: *clears her throat*";
+
+    fn generation(mode: ExampleDialogueMode) -> GenerationSettings {
+        let mut generation = Settings::default().generation;
+        generation.example_dialogue_mode = Some(mode);
+        generation
+    }
+
+    #[test]
+    fn test_parse_blocks() {
+        let blocks = parse_blocks(EXAMPLES);
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].starts_with("{{user}}: Hi there."));
+        assert_eq!(parse_blocks("{{char}}: No separators"), vec!["{{char}}: No separators".to_string()]);
+        assert!(parse_blocks("<START>\n\n<START>").is_empty());
+    }
+
+    #[test]
+    fn test_fit_blocks_keeps_whole_blocks() {
+        let blocks = vec!["short one".to_string(), "word ".repeat(200), "short two".to_string()];
+        let fitted = fit_blocks(blocks, 50);
+        assert_eq!(fitted, vec!["short one".to_string(), "short two".to_string()]);
+        assert!(fit_blocks(parse_blocks(EXAMPLES), 0).is_empty());
+    }
+
+    #[test]
+    fn test_to_turns() {
+        let turns = to_turns(&parse_blocks(EXAMPLES)[0]).unwrap();
+        assert_eq!(turns, vec![
+            (AuthorType::User, "Hi there.".to_string()),
+            (AuthorType::Character, "*waves* Hello!\nHow are you?".to_string()),
+        ]);
+        assert!(to_turns("Aria: Hello").is_none());
+        assert!(to_turns("{{char}}: *enters*\n{{user}}: Hi").is_none());
+    }
+
+    #[test]
+    fn test_placement() {
+        let system = place(EXAMPLES, &generation(ExampleDialogueMode::SystemPrompt), &[]);
+        assert!(matches!(system, ExamplePlacement::SystemPrompt(ref text) if text.matches("<START>").count() == 2));
+
+        let ExamplePlacement::Messages(blocks) = place(EXAMPLES, &generation(ExampleDialogueMode::Messages), &[]) else {
+            panic!("expected messages");
+        };
+        let messages = fit_messages(to_messages(blocks, "c1", |t| t.replace("{{char}}", "Aria")), 1000, None);
+        assert_eq!(messages.len(), 4);
+        assert!(messages.iter().all(is_example));
+        assert_eq!(messages[1].author_type, AuthorType::Character);
+
+        // Untagged blocks fall back to the system prompt
+        let untagged = place("Aria: Hello", &generation(ExampleDialogueMode::Messages), &[]);
+        assert!(matches!(untagged, ExamplePlacement::SystemPrompt(_)));
+        assert_eq!(place("", &generation(ExampleDialogueMode::Messages), &[]), ExamplePlacement::None);
+
+        // Dropped once the chat has enough replies of its own
+        let history = to_messages(vec![vec![(AuthorType::Character, "Hi".to_string()); 8]], "c1", str::to_string).remove(0);
+        assert_eq!(place(EXAMPLES, &generation(ExampleDialogueMode::Messages), &history), ExamplePlacement::None);
+    }
+
+    #[test]
+    fn test_fit_messages_alternates_roles() {
+        use AuthorType::{Character, User};
+        let turn = |author: AuthorType, text: &str| (author, text.to_string());
+        let blocks = vec![
+            vec![turn(User, "Knock knock.")],
+            vec![turn(User, "Hi."), turn(Character, "Hello!")],
+            vec![turn(User, "Sing."), turn(Character, "La la.")],
+        ];
+
+        // The history opens with the greeting, so the last reply goes
+        let messages = fit_messages(to_messages(blocks, "c1", str::to_string), 1000, Some(Character));
+        let turns: Vec<_> = messages.iter().map(|m| (m.author_type, m.content.as_str())).collect();
+        assert_eq!(turns, vec![
+            (User, "Knock knock.\n\nHi."),
+            (Character, "Hello!"),
+            (User, "Sing."),
+        ]);
+        assert!(messages.windows(2).all(|pair| pair[0].author_type != pair[1].author_type));
+
+        // Examples that open with the character stay in the system prompt
+        let opens_with_char = "<START>\n{{char}}: *waves*\n{{user}}: Hi.";
+        let placement = place(opens_with_char, &generation(ExampleDialogueMode::Messages), &[]);
+        assert!(matches!(placement, ExamplePlacement::SystemPrompt(_)));
+    }
+}
@@ -0,0 +1,229 @@
+// ============================================
+// Background Job Service
+// Durable queue for memory extraction and summarization
+// ============================================
+
+use crate::database::Database;
+use crate::entities::*;
+use crate::error::{AppError, AppResult};
+use crate::repositories::{JobRepo, MessageRepo};
+use crate::sidecar::SidecarHandle;
+use crate::services::memory::{MemoryService, SummaryService};
+
+/// Summaries shape the very next prompt, so they run before extraction
+const SUMMARIZATION_PRIORITY: i32 = 10;
+const MEMORY_EXTRACTION_PRIORITY: i32 = 5;
+
+/// Let a few turns accumulate so one extraction call covers several messages
+const EXTRACTION_DELAY_SECS: i64 = 30;
+const MAX_BATCH_MESSAGES: usize = 8;
+
+const MAX_ATTEMPTS: i32 = 5;
+const BASE_BACKOFF_SECS: i64 = 30;
+const MAX_BACKOFF_SECS: i64 = 3600;
+
+/// Finished jobs are kept this long so the status view can show them
+const RETENTION_SECS: i64 = 86400;
+
+/// Delay before retrying a job that has failed `attempts` times: 30s, 60s, 120s, ... capped at an hour
+pub fn backoff_secs(attempts: i32) -> i64 {
+    let exponent = (attempts - 1).clamp(0, 16) as u32;
+    BASE_BACKOFF_SECS.saturating_mul(1 << exponent).min(MAX_BACKOFF_SECS)
+}
+
+fn message_ids(job: &BackgroundJob) -> Vec<String> {
+    job.payload.get("messageIds")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default()
+}
+
+pub struct JobService;
+
+impl JobService {
+    fn new_job(
+        job_type: JobType,
+        conversation_id: &str,
+        character_id: Option<&str>,
+        payload: serde_json::Value,
+        priority: i32,
+        next_run_at: i64,
+    ) -> BackgroundJob {
+        let now = now_timestamp();
+        BackgroundJob {
+            id: new_id(),
+            job_type,
+            conversation_id: Some(conversation_id.to_string()),
+            character_id: character_id.map(String::from),
+            payload,
+            status: JobStatus::Pending,
+            priority,
+            attempts: 0,
+            max_attempts: MAX_ATTEMPTS,
+            next_run_at,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Queue memory extraction for messages. Messages for the same
+    /// conversation and character join a pending job until it is full.
+    pub fn enqueue_memory_extraction(
+        db: &Database,
+        conversation_id: &str,
+        character_id: &str,
+        new_message_ids: &[String],
+    ) -> AppResult<()> {
+        if new_message_ids.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(job) = JobRepo::find_pending(db, JobType::MemoryExtraction, conversation_id, Some(character_id))? {
+            let mut ids = message_ids(&job);
+            for id in new_message_ids {
+                if !ids.contains(id) {
+                    ids.push(id.clone());
+                }
+            }
+            if ids.len() <= MAX_BATCH_MESSAGES
+                && JobRepo::update_payload(db, &job.id, &serde_json::json!({ "messageIds": ids }))?
+            {
+                return Ok(());
+            }
+        }
+
+        let job = Self::new_job(
+            JobType::MemoryExtraction,
+            conversation_id,
+            Some(character_id),
+            serde_json::json!({ "messageIds": new_message_ids }),
+            MEMORY_EXTRACTION_PRIORITY,
+            now_timestamp() + EXTRACTION_DELAY_SECS,
+        );
+        JobRepo::enqueue(db, &job)?;
+        Ok(())
+    }
+
+    /// Queue a summarization check for a conversation unless one is already pending
+    pub fn enqueue_summarization(db: &Database, conversation_id: &str) -> AppResult<()> {
+        if JobRepo::find_pending(db, JobType::Summarization, conversation_id, None)?.is_some() {
+            return Ok(());
+        }
+
+        let job = Self::new_job(
+            JobType::Summarization,
+            conversation_id,
+            None,
+            serde_json::json!({}),
+            SUMMARIZATION_PRIORITY,
+            now_timestamp(),
+        );
+        JobRepo::enqueue(db, &job)?;
+        Ok(())
+    }
+
+    /// Requeue jobs interrupted by the last exit and drop old finished ones
+    pub fn recover(db: &Database) -> AppResult<()> {
+        let requeued = JobRepo::requeue_interrupted(db)?;
+        if requeued > 0 {
+            tracing::info!("Requeued {} interrupted background jobs", requeued);
+        }
+        JobRepo::prune_finished(db, now_timestamp() - RETENTION_SECS)?;
+        Ok(())
+    }
+
+    pub fn status(db: &Database) -> AppResult<JobQueueStatus> {
+        Ok(JobQueueStatus {
+            pending: JobRepo::count_by_status(db, JobStatus::Pending)?,
+            processing: JobRepo::count_by_status(db, JobStatus::Processing)?,
+            failed: JobRepo::count_by_status(db, JobStatus::Failed)?,
+            jobs: JobRepo::list_unfinished(db, 50)?,
+        })
+    }
+
+    /// Take the most urgent due job, if any, and mark it processing
+    pub fn claim_next(db: &Database) -> AppResult<Option<BackgroundJob>> {
+        let job = JobRepo::claim_next_ready(db)?;
+        if let Some(job) = &job {
+            tracing::debug!("Running {} job {} (attempt {})", job.job_type.as_str(), job.id, job.attempts);
+        }
+        Ok(job)
+    }
+
+    /// Run a claimed job. Failures are retried with exponential backoff
+    /// until `max_attempts`.
+    pub async fn run(
+        db: &Database,
+        sidecar: &SidecarHandle,
+        embedder: &SidecarHandle,
+        job: &BackgroundJob,
+    ) -> AppResult<()> {
+        match Self::execute(db, sidecar, embedder, job).await {
+            Ok(()) => JobRepo::complete(db, &job.id)?,
+            Err(e) if job.attempts >= job.max_attempts => {
+                tracing::error!("{} job {} failed permanently: {}", job.job_type.as_str(), job.id, e);
+                JobRepo::fail(db, &job.id, &e.to_string(), None)?;
+            }
+            Err(e) => {
+                let delay = backoff_secs(job.attempts);
+                tracing::warn!("{} job {} failed, retrying in {}s: {}", job.job_type.as_str(), job.id, delay, e);
+                JobRepo::fail(db, &job.id, &e.to_string(), Some(now_timestamp() + delay))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Put a job cut short by chat work back in the queue. The attempt
+    /// doesn't count against it.
+    pub fn release(db: &Database, job_id: &str) -> AppResult<()> {
+        JobRepo::release(db, job_id)
+    }
+
+    async fn execute(
+        db: &Database,
+        sidecar: &SidecarHandle,
+        embedder: &SidecarHandle,
+        job: &BackgroundJob,
+    ) -> AppResult<()> {
+        let conversation_id = job.conversation_id.as_deref()
+            .ok_or_else(|| AppError::Validation("Job has no conversation".into()))?;
+
+        match job.job_type {
+            JobType::MemoryExtraction => {
+                let character_id = job.character_id.as_deref()
+                    .ok_or_else(|| AppError::Validation("Memory extraction job has no character".into()))?;
+
+                // Messages deleted since the job was queued are skipped
+                let messages: Vec<Message> = message_ids(job).iter()
+                    .filter_map(|id| MessageRepo::find_by_id(db, id).ok())
+                    .collect();
+
+                MemoryService::process_messages(db, sidecar, embedder, &messages, character_id, conversation_id).await
+            }
+            JobType::Summarization => {
+                SummaryService::maybe_summarize(
+                    db,
+                    sidecar,
+                    conversation_id,
+                    20,   // Summarize every 20 messages
+                    4000, // Or every 4000 tokens
+                ).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        assert_eq!(backoff_secs(1), 30);
+        assert_eq!(backoff_secs(2), 60);
+        assert_eq!(backoff_secs(3), 120);
+        assert_eq!(backoff_secs(20), MAX_BACKOFF_SECS);
+        assert_eq!(backoff_secs(0), 30);
+    }
+}
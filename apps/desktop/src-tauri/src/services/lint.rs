@@ -0,0 +1,311 @@
+// ============================================
+// Character Lint Service
+// Token cost of a card against the context window, and common card
+// mistakes that make chats worse
+// ============================================
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::database::Database;
+use crate::entities::*;
+use crate::error::AppResult;
+use crate::repositories::{CharacterRepo, SettingsRepo};
+use crate::services::estimate_tokens;
+use crate::services::examples::{self, ExamplePlacement};
+
+/// Less than this share of the context left for history gets a warning
+const MIN_HISTORY_SHARE: f32 = 0.25;
+
+/// `{{user}}: ...` lines, or `{{user}}` doing the talking
+static USER_SPEAKS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?im)^\s*\{\{user\}\}\s*:|\{\{user\}\}\s+(says|said|asks|asked|replies|replied|answers|answered|whispers|whispered|shouts|shouted|exclaims|exclaimed)\b")
+        .expect("valid regex")
+});
+
+pub struct CharacterLintService;
+
+impl CharacterLintService {
+    pub fn lint(db: &Database, character_id: &str) -> AppResult<CharacterLintReport> {
+        let character = CharacterRepo::find_by_id(db, character_id)?;
+        let settings = SettingsRepo::get_all(db)?;
+        Ok(lint_character(&character, &settings.generation))
+    }
+}
+
+fn issue(rule: LintRule, severity: LintSeverity, field: Option<&str>, message: String) -> LintIssue {
+    LintIssue { rule, severity, field: field.map(str::to_string), message }
+}
+
+/// Matches `name` as a whole word. `\b` only goes on ends that are word
+/// characters; next to "." or ")" it would demand a letter on the other side.
+fn name_pattern(name: &str) -> Regex {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let start = if is_word(name.chars().next()) { r"\b" } else { "" };
+    let end = if is_word(name.chars().next_back()) { r"\b" } else { "" };
+    Regex::new(&format!("{}{}{}", start, regex::escape(name), end)).expect("escaped name")
+}
+
+/// Cost of each field, counted the way the context builder counts.
+/// Greetings go in once as a chat message; only the longest alternate
+/// greeting is reported since a chat uses one. Examples count only the
+/// blocks that fit their budget, and sit outside the prompt when they go
+/// in as messages.
+fn field_costs(character: &Character, generation: &GenerationSettings) -> Vec<FieldTokenCost> {
+    let mut tags = character.tags.clone();
+    tags.extend(character.genre_tags.iter().cloned());
+    let longest_greeting = character.alternate_greetings.iter().map(|g| estimate_tokens(g)).max().unwrap_or(0);
+
+    let prompt_fields = [
+        ("name", estimate_tokens(&character.name)),
+        ("tags", estimate_tokens(&tags.join(", "))),
+        ("description", estimate_tokens(&character.description)),
+        ("personality", estimate_tokens(&character.personality)),
+        ("speechPatterns", estimate_tokens(&character.speech_patterns)),
+        ("likes", estimate_tokens(&character.likes.join(", "))),
+        ("dislikes", estimate_tokens(&character.dislikes.join(", "))),
+        ("physicalTraits", estimate_tokens(&character.physical_traits)),
+        ("backstory", estimate_tokens(&character.backstory)),
+        ("scenario", estimate_tokens(&character.scenario)),
+        ("systemPrompt", estimate_tokens(&character.system_prompt)),
+    ];
+    let message_fields = [
+        ("firstMessage", estimate_tokens(&character.first_message)),
+        ("alternateGreetings", longest_greeting),
+    ];
+    let examples = match examples::place(&character.example_dialogues, generation, &[]) {
+        ExamplePlacement::SystemPrompt(text) => (estimate_tokens(&text), true),
+        ExamplePlacement::Messages(blocks) => {
+            let tokens = blocks.iter().flatten().map(|(_, text)| estimate_tokens(text)).sum();
+            (tokens, false)
+        }
+        ExamplePlacement::None => (0, true),
+    };
+
+    prompt_fields.into_iter().map(|(field, tokens)| (field, tokens, true))
+        .chain(std::iter::once(("exampleDialogues", examples.0, examples.1)))
+        .chain(message_fields.into_iter().map(|(field, tokens)| (field, tokens, false)))
+        .map(|(field, tokens, in_prompt)| FieldTokenCost { field: field.to_string(), tokens, in_prompt })
+        .collect()
+}
+
+/// Text fields where the name should be `{{char}}`
+fn text_fields(character: &Character) -> Vec<(&'static str, &str)> {
+    let mut fields = vec![
+        ("description", character.description.as_str()),
+        ("personality", character.personality.as_str()),
+        ("scenario", character.scenario.as_str()),
+        ("backstory", character.backstory.as_str()),
+        ("physicalTraits", character.physical_traits.as_str()),
+        ("speechPatterns", character.speech_patterns.as_str()),
+        ("systemPrompt", character.system_prompt.as_str()),
+        ("firstMessage", character.first_message.as_str()),
+        ("exampleDialogues", character.example_dialogues.as_str()),
+    ];
+    fields.extend(character.alternate_greetings.iter().map(|g| ("alternateGreetings", g.as_str())));
+    fields
+}
+
+pub fn lint_character(character: &Character, generation: &GenerationSettings) -> CharacterLintReport {
+    let fields = field_costs(character, generation);
+    let cost = |name: &str| fields.iter().find(|f| f.field == name).map(|f| f.tokens).unwrap_or(0);
+    let prompt_tokens: i32 = fields.iter().filter(|f| f.in_prompt).map(|f| f.tokens).sum();
+
+    let context_size = generation.context_size;
+    let history_tokens = context_size
+        - prompt_tokens
+        - generation.response_reserve.unwrap_or(512)
+        - generation.lorebook_budget.unwrap_or(500)
+        - generation.memory_budget.unwrap_or(400)
+        - generation.summary_budget.unwrap_or(300);
+
+    let mut issues = Vec::new();
+
+    for (field, label, value) in [
+        ("description", "Description", &character.description),
+        ("personality", "Personality", &character.personality),
+        ("firstMessage", "First message", &character.first_message),
+    ] {
+        if value.trim().is_empty() {
+            issues.push(issue(LintRule::EmptyField, LintSeverity::Warning, Some(field), format!("{} is empty", label)));
+        }
+    }
+
+    let name = character.name.trim();
+    if name.chars().count() > 1 {
+        let literal = name_pattern(name);
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for (field, text) in text_fields(character) {
+            let found = literal.find_iter(text).count();
+            match counts.iter_mut().find(|(f, _)| *f == field) {
+                Some((_, count)) => *count += found,
+                None => counts.push((field, found)),
+            }
+        }
+        for (field, count) in counts.into_iter().filter(|(_, count)| *count > 0) {
+            issues.push(issue(LintRule::LiteralName, LintSeverity::Warning, Some(field), format!(
+                "\"{}\" is written out {} time{} instead of {{{{char}}}}",
+                name, count, if count == 1 { "" } else { "s" }
+            )));
+        }
+    }
+
+    if USER_SPEAKS.is_match(&character.first_message) {
+        issues.push(issue(LintRule::UserInGreeting, LintSeverity::Warning, Some("firstMessage"),
+            "The first message speaks for {{user}}; the model will copy that".to_string()));
+    }
+    for (i, greeting) in character.alternate_greetings.iter().enumerate() {
+        if USER_SPEAKS.is_match(greeting) {
+            issues.push(issue(LintRule::UserInGreeting, LintSeverity::Warning, Some("alternateGreetings"),
+                format!("Alternate greeting {} speaks for {{{{user}}}}", i + 1)));
+        }
+    }
+
+    let examples = character.example_dialogues.trim();
+    if !examples.is_empty() && !examples.to_ascii_uppercase().contains("<START>") {
+        issues.push(issue(LintRule::MissingStart, LintSeverity::Warning, Some("exampleDialogues"),
+            "Example dialogues have no <START> separators".to_string()));
+    }
+
+    // Against the whole text: blocks past the budget are left out of chats
+    let example_tokens = estimate_tokens(&character.example_dialogues);
+    let example_budget = generation.example_dialogue_budget.unwrap_or(500);
+    if example_tokens > example_budget {
+        issues.push(issue(LintRule::OverBudget, LintSeverity::Warning, Some("exampleDialogues"), format!(
+            "Example dialogues take {} tokens; the example dialogue budget is {}",
+            example_tokens, example_budget
+        )));
+    }
+    if cost("firstMessage") > generation.max_tokens {
+        issues.push(issue(LintRule::OverBudget, LintSeverity::Info, Some("firstMessage"), format!(
+            "The first message takes {} tokens, more than the reply limit of {}",
+            cost("firstMessage"), generation.max_tokens
+        )));
+    }
+    if history_tokens <= 0 {
+        issues.push(issue(LintRule::OverBudget, LintSeverity::Error, None, format!(
+            "The card takes {} of {} context tokens, leaving no room for chat history",
+            prompt_tokens, context_size
+        )));
+    } else if (history_tokens as f32) < context_size as f32 * MIN_HISTORY_SHARE {
+        issues.push(issue(LintRule::OverBudget, LintSeverity::Warning, None, format!(
+            "Only {} of {} context tokens are left for chat history",
+            history_tokens, context_size
+        )));
+    }
+
+    CharacterLintReport {
+        character_id: character.id.clone(),
+        fields,
+        prompt_tokens,
+        context_size,
+        context_share: if context_size > 0 { prompt_tokens as f32 / context_size as f32 } else { 0.0 },
+        history_tokens: history_tokens.max(0),
+        issues,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A clean card, written with `{{char}}`, with `fields` laid over it
+    fn character(fields: serde_json::Value) -> Character {
+        let mut card = json!({
+            "description": "{{char}} is a bard", "firstMessage": "*{{char}} tunes her lute.* Hello!",
+            "exampleDialogues": "<START>\n{{user}}: Hi\n{{char}}: Hey!",
+        });
+        card.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        test_character(card)
+    }
+
+    fn rules(report: &CharacterLintReport) -> Vec<(LintRule, Option<String>)> {
+        report.issues.iter().map(|i| (i.rule, i.field.clone())).collect()
+    }
+
+    #[test]
+    fn test_clean_card() {
+        let report = lint_character(&character(json!({})), &Settings::default().generation);
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+        assert!(report.prompt_tokens > 0);
+        assert!(report.context_share > 0.0 && report.context_share < 0.1);
+    }
+
+    #[test]
+    fn test_flags_card_mistakes() {
+        let report = lint_character(&character(json!({
+            "personality": "",
+            "description": "Aria is a bard. Ariana is her sister.",
+            "firstMessage": "\"Hello,\" says Aria.\n{{user}}: Hi!",
+            "alternateGreetings": ["{{user}} says hello back."],
+            "exampleDialogues": "{{user}}: Hi\n{{char}}: Hey!",
+        })), &Settings::default().generation);
+
+        let found = rules(&report);
+        assert!(found.contains(&(LintRule::EmptyField, Some("personality".into()))));
+        assert!(found.contains(&(LintRule::LiteralName, Some("description".into()))));
+        assert!(found.contains(&(LintRule::LiteralName, Some("firstMessage".into()))));
+        assert!(found.contains(&(LintRule::UserInGreeting, Some("firstMessage".into()))));
+        assert!(found.contains(&(LintRule::UserInGreeting, Some("alternateGreetings".into()))));
+        assert!(found.contains(&(LintRule::MissingStart, Some("exampleDialogues".into()))));
+        // Ariana is not Aria
+        let description = report.issues.iter()
+            .find(|i| i.rule == LintRule::LiteralName && i.field.as_deref() == Some("description"))
+            .unwrap();
+        assert!(description.message.contains("1 time "));
+    }
+
+    #[test]
+    fn test_name_pattern_edges() {
+        let count = |name: &str, text: &str| name_pattern(name).find_iter(text).count();
+        assert_eq!(count("Aria", "Aria and Ariana"), 1);
+        assert_eq!(count("Mr. Fox", "Mr. Fox. Then Mr. Fox, again."), 2);
+        assert_eq!(count("(Ace)", "They call her (Ace) now."), 1);
+        assert_eq!(count("Zoë", "Zoë, not Zoëy"), 1);
+    }
+
+    #[test]
+    fn test_budget_overruns() {
+        let mut generation = Settings::default().generation;
+        generation.context_size = 2048;
+        let long = "word ".repeat(1200);
+        let report = lint_character(&character(json!({
+            "backstory": long,
+            "exampleDialogues": format!("<START>\n{{{{char}}}}: {}", "word ".repeat(600)),
+        })), &generation);
+
+        let over: Vec<_> = report.issues.iter().filter(|i| i.rule == LintRule::OverBudget).collect();
+        assert!(over.iter().any(|i| i.field.as_deref() == Some("exampleDialogues")));
+        assert!(over.iter().any(|i| i.severity == LintSeverity::Error));
+        assert_eq!(report.history_tokens, 0);
+    }
+
+    #[test]
+    fn test_example_cost_follows_placement() {
+        let example_cost = |report: &CharacterLintReport| {
+            report.fields.iter().find(|f| f.field == "exampleDialogues").map(|f| (f.tokens, f.in_prompt)).unwrap()
+        };
+        let aria = character(json!({
+            "exampleDialogues": format!(
+                "<START>\n{{{{user}}}}: Hi\n{{{{char}}}}: Hey!\n<START>\n{{{{user}}}}: Go on\n{{{{char}}}}: {}",
+                "word ".repeat(600)
+            ),
+        }));
+
+        // Only the block that fits the budget reaches the system prompt
+        let mut generation = Settings::default().generation;
+        generation.example_dialogue_mode = Some(ExampleDialogueMode::SystemPrompt);
+        let (tokens, in_prompt) = example_cost(&lint_character(&aria, &generation));
+        assert!(in_prompt);
+        assert_eq!(tokens, estimate_tokens("<START>\n{{user}}: Hi\n{{char}}: Hey!"));
+
+        // As messages, it costs the prompt nothing
+        generation.example_dialogue_mode = Some(ExampleDialogueMode::Messages);
+        let report = lint_character(&aria, &generation);
+        let (tokens, in_prompt) = example_cost(&report);
+        assert!(!in_prompt && tokens > 0);
+        assert!(report.fields.iter().filter(|f| f.in_prompt).all(|f| f.field != "exampleDialogues"));
+    }
+}
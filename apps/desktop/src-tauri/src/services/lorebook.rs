@@ -0,0 +1,1128 @@
+// ============================================
+// Lorebook Service
+// CRUD plus the activation engine that decides which entries
+// are injected into the prompt
+// ============================================
+
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+use regex::{Regex, RegexBuilder};
+
+use crate::database::Database;
+use crate::entities::*;
+use crate::error::{AppError, AppResult};
+use crate::repositories::{CharacterRepo, ConversationRepo, LorebookRepo};
+use crate::services::embeddings::{Embedding, EmbeddingService};
+use crate::services::estimate_tokens;
+
+/// Helper function for whole-word matching (word boundaries)
+fn match_whole_word(text: &str, keyword: &str) -> bool {
+    let keyword_chars: Vec<char> = keyword.chars().collect();
+    let text_chars: Vec<char> = text.chars().collect();
+
+    if keyword_chars.is_empty() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i <= text_chars.len().saturating_sub(keyword_chars.len()) {
+        // Check if we have a match at position i
+        let matches = text_chars[i..].iter()
+            .take(keyword_chars.len())
+            .zip(keyword_chars.iter())
+            .all(|(a, b)| a == b);
+
+        if matches {
+            // Check word boundaries
+            let before_ok = if i == 0 {
+                true
+            } else {
+                !text_chars[i - 1].is_alphanumeric()
+            };
+
+            let after_ok = if i + keyword_chars.len() >= text_chars.len() {
+                true
+            } else {
+                !text_chars[i + keyword_chars.len()].is_alphanumeric()
+            };
+
+            if before_ok && after_ok {
+                return true;
+            }
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Messages scanned when neither the entry nor its lorebook sets a depth
+pub const DEFAULT_SCAN_DEPTH: usize = 10;
+
+/// Similarity needed for vector activation when the lorebook doesn't set one
+pub const DEFAULT_SIMILARITY_THRESHOLD: f32 = 0.6;
+
+/// Parse a `/pattern/flags` key. Returns None for plain keys. Only
+/// entries with `regex_keys` set read their keys this way.
+/// Flags: i (ignore case), m (multi-line), s (dot matches newline),
+/// x (verbose); g and u are accepted for compatibility and ignored.
+fn parse_regex_key(key: &str) -> Option<Result<Regex, regex::Error>> {
+    let rest = key.strip_prefix('/')?;
+    let end = rest.rfind('/')?;
+    let (pattern, flags) = (&rest[..end], &rest[end + 1..]);
+    if pattern.is_empty() || !flags.chars().all(|c| "gimsux".contains(c)) {
+        return None;
+    }
+
+    Some(
+        RegexBuilder::new(pattern)
+            .case_insensitive(flags.contains('i'))
+            .multi_line(flags.contains('m'))
+            .dot_matches_new_line(flags.contains('s'))
+            .ignore_whitespace(flags.contains('x'))
+            .size_limit(1 << 20)
+            .build(),
+    )
+}
+
+/// Reject regex keys that don't compile, for entries with `regex_keys` set
+pub fn validate_keys(keys: &[String]) -> AppResult<()> {
+    for key in keys {
+        if let Some(Err(e)) = parse_regex_key(key) {
+            return Err(AppError::Validation(format!("Invalid regex key {}: {}", key, e)));
+        }
+    }
+    Ok(())
+}
+
+fn validate_numbers(probability: Option<i32>, timers: [Option<i32>; 3]) -> AppResult<()> {
+    if probability.is_some_and(|p| !(0..=100).contains(&p)) {
+        return Err(AppError::Validation("Probability must be between 0 and 100".to_string()));
+    }
+    if timers.iter().flatten().any(|&n| n < 0) {
+        return Err(AppError::Validation("Sticky, cooldown and delay can't be negative".to_string()));
+    }
+    Ok(())
+}
+
+fn validate_threshold(threshold: Option<f32>) -> AppResult<()> {
+    if threshold.is_some_and(|t| !(0.0..=1.0).contains(&t)) {
+        return Err(AppError::Validation("Similarity threshold must be between 0 and 1".to_string()));
+    }
+    Ok(())
+}
+
+enum Key {
+    Plain,
+    Regex(Regex),
+    Invalid,
+}
+
+/// Matches keys against scan text, compiling each regex key once per run
+#[derive(Default)]
+struct KeyMatcher {
+    keys: HashMap<String, Key>,
+}
+
+impl KeyMatcher {
+    fn matches(&mut self, key: &str, entry: &LorebookEntry, text: &str, text_lower: &str) -> bool {
+        if key.is_empty() {
+            return false;
+        }
+
+        // Entries that haven't opted in match `/.../` keys literally
+        let compiled = if !entry.regex_keys {
+            &Key::Plain
+        } else {
+            self.keys.entry(key.to_string()).or_insert_with(|| match parse_regex_key(key) {
+                None => Key::Plain,
+                Some(Ok(regex)) => Key::Regex(regex),
+                Some(Err(e)) => {
+                    tracing::debug!("Ignoring invalid lorebook regex {}: {}", key, e);
+                    Key::Invalid
+                }
+            })
+        };
+
+        match compiled {
+            // Regex keys carry their own case flag
+            Key::Regex(regex) => regex.is_match(text),
+            Key::Invalid => false,
+            Key::Plain => {
+                let (k, t) = if entry.case_sensitive {
+                    (key.to_string(), text)
+                } else {
+                    (key.to_lowercase(), text_lower)
+                };
+
+                if entry.match_whole_word {
+                    // Use word boundary matching
+                    match_whole_word(t, &k)
+                } else {
+                    // Simple substring match
+                    t.contains(&k)
+                }
+            }
+        }
+    }
+
+    /// Primary key that activates the entry, after applying secondary key logic
+    fn entry_match(&mut self, entry: &LorebookEntry, text: &str, text_lower: &str) -> Option<String> {
+        let primary = entry.keywords.iter()
+            .find(|k| self.matches(k, entry, text, text_lower))?
+            .clone();
+
+        let secondary: Vec<&String> = entry.secondary_keywords.iter()
+            .filter(|k| !k.trim().is_empty())
+            .collect();
+        if secondary.is_empty() {
+            return Some(primary);
+        }
+
+        let hits = secondary.iter().filter(|k| self.matches(k, entry, text, text_lower)).count();
+        let passes = match entry.selective_logic {
+            SelectiveLogic::AndAny => hits > 0,
+            SelectiveLogic::AndAll => hits == secondary.len(),
+            SelectiveLogic::NotAny => hits == 0,
+            SelectiveLogic::NotAll => hits < secondary.len(),
+        };
+        passes.then_some(primary)
+    }
+}
+
+/// Why an entry activated
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /// This keyword matched
+    Keyword(String),
+    Constant,
+    /// Still within its sticky window from an earlier trigger
+    Sticky,
+    /// Embedding similarity to the recent conversation
+    Similarity(f32),
+}
+
+/// An entry that activated, and why
+#[derive(Debug, Clone)]
+pub struct Activation {
+    pub entry: LorebookEntry,
+    pub trigger: Trigger,
+    /// 0 = matched the chat, n = matched content activated at depth n - 1
+    pub depth: usize,
+}
+
+/// Whether an activated entry survives its probability roll
+fn passes_probability(entry: &LorebookEntry, rng: &mut impl Rng) -> bool {
+    entry.probability >= 100 || rng.gen_range(0..100) < entry.probability.max(0)
+}
+
+/// Keep one entry per inclusion group among newly found activations;
+/// returns (kept, lost). Groups that already have a winner from an earlier
+/// pass take no more, and a sticky member keeps its group so the pick
+/// doesn't flip each turn.
+fn resolve_groups(
+    found: Vec<Activation>,
+    won: &mut HashSet<String>,
+    sticky: &HashSet<String>,
+    rng: &mut impl Rng,
+) -> (Vec<Activation>, Vec<Activation>) {
+    let mut groups: HashMap<String, Vec<Activation>> = HashMap::new();
+    let mut kept = Vec::new();
+    let mut lost = Vec::new();
+
+    for activation in found {
+        match activation.entry.inclusion_group.clone() {
+            Some(group) => groups.entry(group).or_default().push(activation),
+            None => kept.push(activation),
+        }
+    }
+
+    for (group, mut members) in groups {
+        if !won.insert(group) {
+            lost.extend(members);
+            continue;
+        }
+
+        let index = if let Some(i) = members.iter().position(|a| sticky.contains(&a.entry.id)) {
+            i
+        } else if members.iter().any(|a| a.entry.group_prioritize) {
+            // First of the highest priority keeps the pick stable
+            let top = members.iter().map(|a| a.entry.priority).max().unwrap_or_default();
+            members.iter().position(|a| a.entry.priority == top).unwrap_or(0)
+        } else {
+            let total: i64 = members.iter().map(|a| a.entry.group_weight.max(0) as i64).sum();
+            if total == 0 {
+                0
+            } else {
+                let mut roll = rng.gen_range(0..total);
+                members.iter()
+                    .position(|a| {
+                        let weight = a.entry.group_weight.max(0) as i64;
+                        if roll < weight {
+                            true
+                        } else {
+                            roll -= weight;
+                            false
+                        }
+                    })
+                    .unwrap_or(0)
+            }
+        };
+        kept.push(members.swap_remove(index));
+        lost.extend(members);
+    }
+
+    (kept, lost)
+}
+
+/// Why a matched entry was left out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    /// Lost its probability roll
+    Probability,
+    /// Another entry of its inclusion group was picked
+    Group,
+}
+
+/// Result of one activation run
+#[derive(Debug, Default)]
+pub struct ActivationOutcome {
+    /// Sorted by priority, highest first
+    pub activated: Vec<Activation>,
+    /// Entries that matched but were left out, and why
+    pub rejected: Vec<(Activation, Rejection)>,
+}
+
+/// Timed effect state for one run, derived from past triggers
+#[derive(Debug, Default)]
+pub struct TimedEffects {
+    /// Within their sticky window: active without a keyword hit
+    pub sticky: HashSet<String>,
+    /// Cooling down or still delayed: cannot activate this run
+    pub blocked: HashSet<String>,
+}
+
+/// Work out sticky / cooldown / delay state from recorded triggers, given
+/// as (entry_id, anchor_message_id). `history_ids` is the active branch,
+/// oldest first; triggers anchored elsewhere are ignored, which is what
+/// rolls the state back when the user switches branches.
+pub fn timed_effects(
+    entries: &[LorebookEntry],
+    history_ids: &[&str],
+    triggers: &[(String, String)],
+) -> TimedEffects {
+    let position: HashMap<&str, usize> = history_ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+
+    let mut last_trigger: HashMap<&str, usize> = HashMap::new();
+    for (entry_id, anchor) in triggers {
+        if let Some(&pos) = position.get(anchor.as_str()) {
+            let last = last_trigger.entry(entry_id.as_str()).or_insert(pos);
+            *last = (*last).max(pos);
+        }
+    }
+
+    let newest = history_ids.len().saturating_sub(1);
+    let mut effects = TimedEffects::default();
+
+    for entry in entries {
+        if (history_ids.len() as i64) < entry.delay as i64 {
+            effects.blocked.insert(entry.id.clone());
+            continue;
+        }
+
+        let Some(&pos) = last_trigger.get(entry.id.as_str()) else {
+            continue;
+        };
+        // 0 means the trigger was on this same message (e.g. a regenerate)
+        let since = (newest - pos) as i64;
+        let sticky = entry.sticky.max(0) as i64;
+
+        if sticky > 0 && since <= sticky {
+            effects.sticky.insert(entry.id.clone());
+        } else if since > 0 && since <= sticky + entry.cooldown.max(0) as i64 {
+            effects.blocked.insert(entry.id.clone());
+        }
+    }
+
+    effects
+}
+
+/// Decide which entries activate for a chat history (oldest message first).
+/// Constant and sticky entries always activate and entries cooling down
+/// or delayed never do (see `timed_effects`); others need a keyword hit within
+/// their own window of recent messages (`scan_depth`), or for vector entries
+/// an entry in `similar` (entry id -> similarity to the recent chat). Each hit then rolls
+/// against the entry's probability, and only one entry per inclusion group
+/// is kept. Activated entries' content is scanned again for further keyword
+/// hits, up to `max_depth` extra passes. Entries with `exclude_recursion`
+/// only activate from the chat itself; entries with `prevent_recursion`
+/// never trigger others.
+pub fn activate_entries(
+    entries: &[LorebookEntry],
+    history: &[&str],
+    max_depth: usize,
+    effects: &TimedEffects,
+    similar: &HashMap<String, f32>,
+    rng: &mut impl Rng,
+) -> ActivationOutcome {
+    let mut matcher = KeyMatcher::default();
+    let mut activated: Vec<Activation> = Vec::new();
+    let mut rejected = Vec::new();
+    // Entries that activated or already had their roll this run
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut won_groups: HashSet<String> = HashSet::new();
+
+    // Chat windows are shared between entries with the same scan depth
+    let mut windows: HashMap<usize, (String, String)> = HashMap::new();
+    let mut recursion_text = String::new();
+
+    for depth in 0..=max_depth {
+        let recursion_lower = recursion_text.to_lowercase();
+        let mut found = Vec::new();
+
+        for entry in entries {
+            if !entry.is_enabled || seen.contains(entry.id.as_str()) {
+                continue;
+            }
+            if depth > 0 && entry.exclude_recursion {
+                continue;
+            }
+            if effects.blocked.contains(&entry.id) {
+                continue;
+            }
+
+            let sticky = effects.sticky.contains(&entry.id);
+            let trigger = if depth == 0 && sticky {
+                Some(Trigger::Sticky)
+            } else if depth == 0 && entry.constant {
+                Some(Trigger::Constant)
+            } else {
+                let keyword = if entry.activation_mode == ActivationMode::Vector {
+                    None
+                } else if depth == 0 {
+                    let scan_depth = entry.scan_depth.map(|d| d.max(0) as usize).unwrap_or(DEFAULT_SCAN_DEPTH);
+                    let (text, lower) = windows.entry(scan_depth).or_insert_with(|| {
+                        let text = history.iter().rev().take(scan_depth).copied().collect::<Vec<_>>().join(" ");
+                        let lower = text.to_lowercase();
+                        (text, lower)
+                    });
+                    matcher.entry_match(entry, text, lower)
+                } else {
+                    matcher.entry_match(entry, &recursion_text, &recursion_lower)
+                };
+
+                // Similarity is measured against the chat, so it only counts on the first pass
+                keyword.map(Trigger::Keyword).or_else(|| {
+                    let similarity = similar.get(&entry.id).filter(|_| depth == 0 && entry.activation_mode.uses_vectors());
+                    similarity.map(|&s| Trigger::Similarity(s))
+                })
+            };
+
+            if let Some(trigger) = trigger {
+                // One roll per run, so a failed entry isn't retried by recursion
+                seen.insert(&entry.id);
+                // Sticky entries already passed their roll when they triggered
+                let activation = Activation { entry: entry.clone(), trigger, depth };
+                if sticky || passes_probability(entry, rng) {
+                    found.push(activation);
+                } else {
+                    rejected.push((activation, Rejection::Probability));
+                }
+            }
+        }
+
+        let (found, lost) = resolve_groups(found, &mut won_groups, &effects.sticky, rng);
+        rejected.extend(lost.into_iter().map(|a| (a, Rejection::Group)));
+        if found.is_empty() {
+            break;
+        }
+
+        // Only newly activated content is scanned on the next pass
+        recursion_text = found.iter()
+            .filter(|a| !a.entry.prevent_recursion)
+            .map(|a| a.entry.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        activated.extend(found);
+
+        if recursion_text.is_empty() {
+            break;
+        }
+    }
+
+    // Ties keep chat matches ahead of recursive ones, then entry order
+    let order: HashMap<&str, usize> = entries.iter().enumerate().map(|(i, e)| (e.id.as_str(), i)).collect();
+    activated.sort_by_key(|a| (std::cmp::Reverse(a.entry.priority), a.depth, order.get(a.entry.id.as_str()).copied()));
+    ActivationOutcome { activated, rejected }
+}
+
+/// Cut text to at most `max_tokens` estimated tokens, on a word boundary
+/// where possible
+pub fn truncate_to_tokens(text: &str, max_tokens: i32) -> &str {
+    if estimate_tokens(text) <= max_tokens {
+        return text;
+    }
+
+    let boundaries: Vec<usize> = text.char_indices()
+        .filter(|(_, c)| c.is_whitespace())
+        .map(|(i, _)| i)
+        .collect();
+
+    // Longest prefix ending at a word boundary that still fits
+    let fits = boundaries.partition_point(|&end| estimate_tokens(&text[..end]) <= max_tokens);
+    if fits > 0 {
+        return text[..boundaries[fits - 1]].trim_end();
+    }
+
+    // No usable word boundary (e.g. CJK), so cut between characters
+    let chars: Vec<usize> = text.char_indices().map(|(i, _)| i).skip(1).collect();
+    let fits = chars.partition_point(|&end| estimate_tokens(&text[..end]) <= max_tokens);
+    match fits {
+        0 => "",
+        n => &text[..chars[n - 1]],
+    }
+}
+
+/// How an activated entry fared against the token budgets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fit {
+    Whole,
+    /// Cut down to the entry's own token budget
+    Truncated,
+    /// Didn't fit in what was left of the lorebook budget
+    OverBudget,
+}
+
+/// Apply per-entry token caps, then take entries in order until the
+/// lorebook budget runs out. Every entry comes back with how it fared.
+pub fn fit_to_budget(entries: Vec<LorebookEntry>, budget: i32) -> Vec<(LorebookEntry, Fit)> {
+    let mut used = 0;
+    let mut exhausted = false;
+
+    entries.into_iter()
+        .map(|mut entry| {
+            let mut fit = Fit::Whole;
+            if let Some(cap) = entry.token_budget.filter(|&cap| cap > 0) {
+                let truncated = truncate_to_tokens(&entry.content, cap);
+                if truncated.len() < entry.content.len() {
+                    entry.content = truncated.to_string();
+                    fit = Fit::Truncated;
+                }
+            }
+
+            let tokens = estimate_tokens(&entry.content);
+            if exhausted || entry.content.is_empty() || used + tokens > budget {
+                // Lower priority entries don't jump the queue once one is cut
+                exhausted |= !entry.content.is_empty();
+                return (entry, Fit::OverBudget);
+            }
+            used += tokens;
+            (entry, fit)
+        })
+        .collect()
+}
+
+/// Lore picked for a prompt, plus a trace of how it was picked
+#[derive(Debug, Default)]
+pub struct LoreSelection {
+    pub before_system: Vec<String>,
+    pub after_system: Vec<String>,
+    pub trace: Vec<LoreTraceEntry>,
+}
+
+fn trace_entry(activation: &Activation, status: LoreTraceStatus, content: &str) -> LoreTraceEntry {
+    let (trigger, keyword, similarity) = match &activation.trigger {
+        Trigger::Keyword(k) => ("keyword", Some(k.clone()), None),
+        Trigger::Constant => ("constant", None, None),
+        Trigger::Sticky => ("sticky", None, None),
+        Trigger::Similarity(s) => ("vector", None, Some(*s)),
+    };
+    let inserted = matches!(status, LoreTraceStatus::Inserted | LoreTraceStatus::Truncated);
+
+    LoreTraceEntry {
+        entry_id: activation.entry.id.clone(),
+        lorebook_id: activation.entry.lorebook_id.clone(),
+        name: activation.entry.name.clone(),
+        trigger: trigger.to_string(),
+        keyword,
+        similarity,
+        depth: activation.depth as i32,
+        status,
+        position: inserted.then(|| activation.entry.insertion_position.clone()),
+        tokens: if inserted { estimate_tokens(content) } else { 0 },
+    }
+}
+
+pub struct LorebookService;
+
+impl LorebookService {
+    pub fn create(db: &Database, input: CreateLorebookInput) -> AppResult<Lorebook> {
+        let name = input.name.trim();
+        if name.is_empty() { return Err(AppError::Validation("Name required".to_string())); }
+        validate_threshold(input.similarity_threshold)?;
+
+        let sanitized = CreateLorebookInput { name: name.to_string(), ..input };
+        LorebookRepo::create(db, &sanitized)
+    }
+
+    pub fn get(db: &Database, id: &str) -> AppResult<Lorebook> {
+        LorebookRepo::find_by_id(db, id)
+    }
+
+    pub fn list(db: &Database) -> AppResult<Vec<Lorebook>> {
+        LorebookRepo::find_all(db)
+    }
+
+    pub fn update(db: &Database, id: &str, input: UpdateLorebookInput) -> AppResult<Lorebook> {
+        validate_threshold(input.similarity_threshold)?;
+        LorebookRepo::update(db, id, &input)
+    }
+
+    pub fn delete(db: &Database, id: &str) -> AppResult<()> {
+        LorebookRepo::find_by_id(db, id)?;
+        LorebookRepo::delete(db, id)
+    }
+
+    pub fn create_entry(db: &Database, input: CreateEntryInput) -> AppResult<LorebookEntry> {
+        // Constant and vector-only entries don't need keywords
+        let needs_keywords = !input.constant.unwrap_or(false)
+            && input.activation_mode.unwrap_or_default() != ActivationMode::Vector;
+        if input.keywords.is_empty() && needs_keywords {
+            return Err(AppError::Validation("Keyword required".to_string()));
+        }
+        if input.content.trim().is_empty() { return Err(AppError::Validation("Content required".to_string())); }
+        if input.regex_keys.unwrap_or(false) {
+            validate_keys(&input.keywords)?;
+            validate_keys(input.secondary_keywords.as_deref().unwrap_or_default())?;
+        }
+        validate_numbers(input.probability, [input.sticky, input.cooldown, input.delay])?;
+
+        LorebookRepo::find_by_id(db, &input.lorebook_id)?;
+        LorebookRepo::create_entry(db, &input)
+    }
+
+    pub fn update_entry(db: &Database, id: &str, input: UpdateEntryInput) -> AppResult<LorebookEntry> {
+        // Turning regex keys on checks the keys already stored too
+        let current = LorebookRepo::find_entry(db, id)?;
+        if input.regex_keys.unwrap_or(current.regex_keys) {
+            validate_keys(input.keywords.as_ref().unwrap_or(&current.keywords))?;
+            validate_keys(input.secondary_keywords.as_ref().unwrap_or(&current.secondary_keywords))?;
+        }
+        validate_numbers(input.probability, [input.sticky, input.cooldown, input.delay])?;
+        let entry = LorebookRepo::update_entry(db, id, &input)?;
+
+        // New content needs a new vector; the embedding worker picks it up
+        if input.content.is_some() && entry.has_embedding {
+            EmbeddingService::delete(db, "lorebook", id)?;
+            return LorebookRepo::find_entry(db, id);
+        }
+        Ok(entry)
+    }
+
+    pub fn delete_entry(db: &Database, id: &str) -> AppResult<()> {
+        EmbeddingService::delete(db, "lorebook", id)?;
+        LorebookRepo::delete_entry(db, id)
+    }
+
+    pub fn attach_to_conversation(db: &Database, conv_id: &str, lb_id: &str) -> AppResult<()> {
+        ConversationRepo::find_by_id(db, conv_id)?;
+        LorebookRepo::find_by_id(db, lb_id)?;
+        ConversationRepo::attach_lorebook(db, conv_id, lb_id)
+    }
+
+    pub fn detach_from_conversation(db: &Database, conv_id: &str, lb_id: &str) -> AppResult<()> {
+        ConversationRepo::detach_lorebook(db, conv_id, lb_id)
+    }
+
+    pub fn attach_to_character(db: &Database, character_id: &str, lb_id: &str) -> AppResult<()> {
+        CharacterRepo::find_by_id(db, character_id)?;
+        LorebookRepo::find_by_id(db, lb_id)?;
+        CharacterRepo::attach_lorebook(db, character_id, lb_id)
+    }
+
+    pub fn detach_from_character(db: &Database, character_id: &str, lb_id: &str) -> AppResult<()> {
+        CharacterRepo::detach_lorebook(db, character_id, lb_id)
+    }
+
+    pub fn list_for_character(db: &Database, character_id: &str) -> AppResult<Vec<Lorebook>> {
+        CharacterRepo::find_lorebook_ids(db, character_id)?
+            .iter()
+            .map(|id| LorebookRepo::find_by_id(db, id))
+            .collect()
+    }
+
+    /// Recreate an exported lorebook, entries included, under a new id
+    pub fn restore(db: &Database, lorebook: &Lorebook) -> AppResult<Lorebook> {
+        let created = LorebookRepo::create(db, &CreateLorebookInput {
+            name: lorebook.name.clone(),
+            description: Some(lorebook.description.clone()),
+            is_global: Some(lorebook.is_global),
+            scan_depth: lorebook.scan_depth,
+            similarity_threshold: lorebook.similarity_threshold,
+        })?;
+        LorebookRepo::update_metadata(db, &created.id, &lorebook.metadata)?;
+
+        for entry in &lorebook.entries {
+            LorebookRepo::create_entry(db, &CreateEntryInput {
+                lorebook_id: created.id.clone(),
+                name: entry.name.clone(),
+                keywords: entry.keywords.clone(),
+                content: entry.content.clone(),
+                priority: Some(entry.priority),
+                case_sensitive: Some(entry.case_sensitive),
+                match_whole_word: Some(entry.match_whole_word),
+                insertion_position: Some(entry.insertion_position.clone()),
+                token_budget: entry.token_budget,
+                regex_keys: Some(entry.regex_keys),
+                exclude_recursion: Some(entry.exclude_recursion),
+                prevent_recursion: Some(entry.prevent_recursion),
+                secondary_keywords: Some(entry.secondary_keywords.clone()),
+                selective_logic: Some(entry.selective_logic),
+                scan_depth: entry.scan_depth,
+                constant: Some(entry.constant),
+                probability: Some(entry.probability),
+                inclusion_group: entry.inclusion_group.clone(),
+                group_weight: Some(entry.group_weight),
+                group_prioritize: Some(entry.group_prioritize),
+                sticky: Some(entry.sticky),
+                cooldown: Some(entry.cooldown),
+                delay: Some(entry.delay),
+                activation_mode: Some(entry.activation_mode),
+                is_enabled: Some(entry.is_enabled),
+                metadata: Some(entry.metadata.clone()),
+            })?;
+        }
+
+        LorebookRepo::find_by_id(db, &created.id)
+    }
+
+    /// Global lorebooks, plus enabled lorebooks attached to the conversation
+    /// or linked to any of its characters
+    fn lorebooks_for_conversation(db: &Database, conv_id: &str) -> AppResult<Vec<Lorebook>> {
+        let conversation = ConversationRepo::find_by_id(db, conv_id)?;
+
+        let mut lorebook_ids = conversation.lorebook_ids.clone();
+        for character in &conversation.characters {
+            lorebook_ids.extend(CharacterRepo::find_lorebook_ids(db, &character.id)?);
+        }
+
+        let mut lorebooks = LorebookRepo::find_global(db)?;
+        for lb_id in &lorebook_ids {
+            if let Ok(lb) = LorebookRepo::find_by_id(db, lb_id) {
+                if lb.is_enabled { lorebooks.push(lb); }
+            }
+        }
+
+        // A lorebook can be global, attached and linked all at once
+        let mut seen = HashSet::new();
+        lorebooks.retain(|lb| seen.insert(lb.id.clone()));
+        Ok(lorebooks)
+    }
+
+    /// Vector entries whose embedding is close enough to the query, by similarity
+    fn similar_entries(db: &Database, lorebooks: &[Lorebook], query: &Embedding) -> AppResult<HashMap<String, f32>> {
+        let mut similar = HashMap::new();
+
+        for lb in lorebooks {
+            let vector_ids: HashSet<&str> = lb.entries.iter()
+                .filter(|e| e.is_enabled && e.activation_mode.uses_vectors())
+                .map(|e| e.id.as_str())
+                .collect();
+            if vector_ids.is_empty() {
+                continue;
+            }
+
+            let threshold = lb.similarity_threshold.unwrap_or(DEFAULT_SIMILARITY_THRESHOLD);
+            let hits = EmbeddingService::find_similar(db, query, "lorebook", Some(&lb.id), lb.entries.len(), threshold)?;
+            similar.extend(hits.into_iter().filter(|(id, _)| vector_ids.contains(id.as_str())));
+        }
+
+        Ok(similar)
+    }
+
+    /// Run activation for a conversation's active branch, recursing up to
+    /// `max_depth` passes. `query` embeds the recent chat for vector entries;
+    /// without it only keywords are used. Fresh triggers of timed entries
+    /// are recorded against the newest message.
+    pub fn activate(
+        db: &Database,
+        conv_id: &str,
+        messages: &[Message],
+        query: Option<&Embedding>,
+        max_depth: usize,
+    ) -> AppResult<ActivationOutcome> {
+        let lorebooks = Self::lorebooks_for_conversation(db, conv_id)?;
+        let similar = match query {
+            Some(query) => Self::similar_entries(db, &lorebooks, query)?,
+            None => HashMap::new(),
+        };
+
+        // Entries without their own scan depth inherit the lorebook's
+        let entries: Vec<LorebookEntry> = lorebooks.into_iter()
+            .flat_map(|lb| {
+                let scan_depth = lb.scan_depth;
+                lb.entries.into_iter().map(move |mut e| {
+                    e.scan_depth = e.scan_depth.or(scan_depth);
+                    e
+                })
+            })
+            .collect();
+
+        let history: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+        let history_ids: Vec<&str> = messages.iter().map(|m| m.id.as_str()).collect();
+
+        let triggers = LorebookRepo::find_activations(db, conv_id)?;
+        let effects = timed_effects(&entries, &history_ids, &triggers);
+        let outcome = activate_entries(&entries, &history, max_depth, &effects, &similar, &mut rand::thread_rng());
+
+        if let Some(anchor) = messages.last() {
+            let triggered: Vec<&str> = outcome.activated.iter()
+                .filter(|a| a.entry.sticky > 0 || a.entry.cooldown > 0)
+                .filter(|a| a.trigger != Trigger::Sticky)
+                .map(|a| a.entry.id.as_str())
+                .collect();
+            LorebookRepo::record_activations(db, conv_id, &anchor.id, &triggered)?;
+        }
+
+        Ok(outcome)
+    }
+
+    /// Pick the lore for a prompt within `budget` tokens, split by
+    /// insertion position, with a trace of every matched entry. `expand`
+    /// fills in macros before entries are counted, so the budget holds
+    /// for the text that goes in.
+    pub fn select(
+        db: &Database,
+        conv_id: &str,
+        messages: &[Message],
+        query: Option<&Embedding>,
+        max_depth: usize,
+        budget: i32,
+        mut expand: impl FnMut(&str) -> String,
+    ) -> AppResult<LoreSelection> {
+        let outcome = Self::activate(db, conv_id, messages, query, max_depth)?;
+        let mut selection = LoreSelection::default();
+
+        let expanded = outcome.activated.iter()
+            .map(|a| LorebookEntry { content: expand(&a.entry.content), ..a.entry.clone() })
+            .collect();
+        let fitted = fit_to_budget(expanded, budget);
+        for (activation, (entry, fit)) in outcome.activated.iter().zip(fitted) {
+            let status = match fit {
+                Fit::Whole => LoreTraceStatus::Inserted,
+                Fit::Truncated => LoreTraceStatus::Truncated,
+                Fit::OverBudget => LoreTraceStatus::OverBudget,
+            };
+            selection.trace.push(trace_entry(activation, status, &entry.content));
+
+            if fit == Fit::OverBudget {
+                continue;
+            }
+            if entry.insertion_position == "before_system" {
+                selection.before_system.push(entry.content);
+            } else {
+                selection.after_system.push(entry.content);
+            }
+        }
+
+        for (activation, rejection) in &outcome.rejected {
+            let status = match rejection {
+                Rejection::Probability => LoreTraceStatus::FailedProbability,
+                Rejection::Group => LoreTraceStatus::LostGroup,
+            };
+            selection.trace.push(trace_entry(activation, status, ""));
+        }
+
+        Ok(selection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn entry(id: &str, keywords: &[&str], content: &str) -> LorebookEntry {
+        LorebookEntry {
+            id: id.to_string(),
+            lorebook_id: "lb".to_string(),
+            name: id.to_string(),
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            secondary_keywords: vec![],
+            selective_logic: SelectiveLogic::AndAny,
+            content: content.to_string(),
+            priority: 50,
+            is_enabled: true,
+            case_sensitive: false,
+            match_whole_word: true,
+            insertion_position: "after_system".to_string(),
+            token_budget: None,
+            regex_keys: false,
+            exclude_recursion: false,
+            prevent_recursion: false,
+            scan_depth: None,
+            constant: false,
+            probability: 100,
+            inclusion_group: None,
+            group_weight: 100,
+            group_prioritize: false,
+            sticky: 0,
+            cooldown: 0,
+            delay: 0,
+            activation_mode: ActivationMode::Keyword,
+            has_embedding: false,
+            created_at: 0,
+            metadata: serde_json::json!({}),
+        }
+    }
+
+    fn activate(entries: &[LorebookEntry], history: &[&str], max_depth: usize) -> Vec<Activation> {
+        activate_entries(entries, history, max_depth, &TimedEffects::default(), &HashMap::new(), &mut StdRng::seed_from_u64(7)).activated
+    }
+
+    fn ids(activations: &[Activation]) -> Vec<(&str, usize)> {
+        activations.iter().map(|a| (a.entry.id.as_str(), a.depth)).collect()
+    }
+
+    fn world() -> Vec<LorebookEntry> {
+        vec![
+            entry("aster", &["Aster"], "The Kingdom of Aster is ruled by King Aldric."),
+            entry("aldric", &["Aldric"], "King Aldric is old and guarded by the Silver Order."),
+            entry("order", &["Silver Order"], "The Silver Order are paladins."),
+        ]
+    }
+
+    #[test]
+    fn test_recursion_pulls_in_related_entries() {
+        let activated = activate(&world(), &["We ride for Aster at dawn."], 3);
+        assert_eq!(ids(&activated), vec![("aster", 0), ("aldric", 1), ("order", 2)]);
+    }
+
+    #[test]
+    fn test_recursion_depth_limit() {
+        assert_eq!(ids(&activate(&world(), &["Aster"], 0)), vec![("aster", 0)]);
+        assert_eq!(ids(&activate(&world(), &["Aster"], 1)), vec![("aster", 0), ("aldric", 1)]);
+    }
+
+    #[test]
+    fn test_recursion_flags() {
+        let mut entries = world();
+        entries[1].exclude_recursion = true;
+        assert_eq!(ids(&activate(&entries, &["Aster"], 3)), vec![("aster", 0)]);
+        // Excluded entries still activate from the chat
+        assert_eq!(ids(&activate(&entries, &["Aldric"], 3)), vec![("aldric", 0), ("order", 1)]);
+
+        let mut entries = world();
+        entries[0].prevent_recursion = true;
+        assert_eq!(ids(&activate(&entries, &["Aster"], 3)), vec![("aster", 0)]);
+    }
+
+    #[test]
+    fn test_disabled_entries_never_activate() {
+        let mut entries = world();
+        entries[0].is_enabled = false;
+        assert!(activate(&entries, &["Aster"], 3).is_empty());
+    }
+
+    #[test]
+    fn test_regex_keys() {
+        let mut entries = vec![
+            entry("dragon", &["/drag(on|ons)\\b/i"], "Dragons hoard gold."),
+            entry("literal", &["/not a regex"], "Plain key."),
+        ];
+        entries[0].regex_keys = true;
+        entries[1].regex_keys = true;
+        assert_eq!(ids(&activate(&entries, &["Two DRAGONS circle"], 0)), vec![("dragon", 0)]);
+        assert!(activate(&entries, &["dragoon"], 0).is_empty());
+        assert_eq!(ids(&activate(&entries, &["this is /not a regex"], 0)), vec![("literal", 0)]);
+
+        // Without the opt-in a slashed key is just text
+        let legacy = vec![entry("path", &["/usr/bin/"], "A path.")];
+        assert_eq!(ids(&activate(&legacy, &["Check /usr/bin/ first"], 0)), vec![("path", 0)]);
+        assert!(activate(&legacy, &["usr bin"], 0).is_empty());
+
+        assert!(validate_keys(&["/[unclosed/".to_string()]).is_err());
+        assert!(validate_keys(&["/ok/i".to_string(), "plain".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn test_secondary_key_logic() {
+        let mut e = entry("castle", &["castle"], "The castle.");
+        e.secondary_keywords = vec!["night".to_string(), "storm".to_string()];
+
+        let cases = [
+            (SelectiveLogic::AndAny, [false, true, true]),
+            (SelectiveLogic::AndAll, [false, false, true]),
+            (SelectiveLogic::NotAny, [true, false, false]),
+            (SelectiveLogic::NotAll, [true, true, false]),
+        ];
+        let texts = ["the castle", "the castle at night", "the castle at night in a storm"];
+
+        for (logic, expected) in cases {
+            e.selective_logic = logic;
+            for (text, expect) in texts.iter().zip(expected) {
+                let hit = !activate(std::slice::from_ref(&e), &[text], 0).is_empty();
+                assert_eq!(hit, expect, "{:?} on {:?}", logic, text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scan_depth() {
+        let history = ["Aster", "one", "two", "three"];
+        assert_eq!(ids(&activate(&world(), &history, 0)), vec![("aster", 0)]);
+
+        let mut entries = world();
+        entries[0].scan_depth = Some(3);
+        assert!(activate(&entries, &history, 0).is_empty());
+        entries[0].scan_depth = Some(4);
+        assert_eq!(ids(&activate(&entries, &history, 0)), vec![("aster", 0)]);
+    }
+
+    #[test]
+    fn test_constant_entries() {
+        let mut entries = world();
+        entries[2].constant = true;
+        assert_eq!(ids(&activate(&entries, &["nothing relevant"], 3)), vec![("order", 0)]);
+    }
+
+    #[test]
+    fn test_probability() {
+        let mut entries = world();
+        entries[0].probability = 0;
+        let outcome = activate_entries(&entries, &["Aster"], 3, &TimedEffects::default(), &HashMap::new(), &mut StdRng::seed_from_u64(0));
+        assert!(outcome.activated.is_empty());
+        assert_eq!(outcome.rejected[0].1, Rejection::Probability);
+
+        // Roughly half of the runs activate a 50% entry
+        entries[0].probability = 50;
+        let mut rng = StdRng::seed_from_u64(1);
+        let hits = (0..1000)
+            .filter(|_| !activate_entries(&entries, &["Aster"], 0, &TimedEffects::default(), &HashMap::new(), &mut rng).activated.is_empty())
+            .count();
+        assert!((400..600).contains(&hits), "{}", hits);
+    }
+
+    #[test]
+    fn test_inclusion_groups() {
+        let mut entries = vec![
+            entry("tavern_a", &["tavern"], "The Gilded Goose."),
+            entry("tavern_b", &["tavern"], "The Rusty Anchor."),
+            entry("tavern_c", &["tavern"], "The Black Boar."),
+        ];
+        for e in &mut entries {
+            e.inclusion_group = Some("tavern".to_string());
+        }
+        let outcome = activate_entries(&entries, &["a tavern"], 0, &TimedEffects::default(), &HashMap::new(), &mut StdRng::seed_from_u64(0));
+        assert_eq!(outcome.activated.len(), 1);
+        assert_eq!(outcome.rejected.len(), 2);
+        assert!(outcome.rejected.iter().all(|(_, r)| *r == Rejection::Group));
+
+        entries[1].priority = 90;
+        entries[2].group_prioritize = true;
+        assert_eq!(ids(&activate(&entries, &["a tavern"], 0)), vec![("tavern_b", 0)]);
+
+        // Zero weight is never picked at random
+        entries[2].group_prioritize = false;
+        entries[0].group_weight = 0;
+        entries[1].group_weight = 0;
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..50 {
+            let picked = activate_entries(&entries, &["a tavern"], 0, &TimedEffects::default(), &HashMap::new(), &mut rng).activated;
+            assert_eq!(ids(&picked), vec![("tavern_c", 0)]);
+        }
+    }
+
+    #[test]
+    fn test_fit_to_budget() {
+        let mut long = entry("long", &[], &"word ".repeat(200));
+        long.token_budget = Some(20);
+        let short = entry("short", &[], "A short note.");
+
+        let fitted = fit_to_budget(vec![long.clone(), short.clone()], 1000);
+        assert_eq!(fitted.iter().map(|(_, fit)| *fit).collect::<Vec<_>>(), vec![Fit::Truncated, Fit::Whole]);
+        assert!(estimate_tokens(&fitted[0].0.content) <= 20);
+        assert!(!fitted[0].0.content.ends_with(' '));
+
+        // Once an entry doesn't fit, later ones are dropped too
+        long.token_budget = None;
+        let fitted = fit_to_budget(vec![long, short], 30);
+        assert!(fitted.iter().all(|(_, fit)| *fit == Fit::OverBudget));
+    }
+
+    fn trigger(entry_id: &str, anchor: &str) -> (String, String) {
+        (entry_id.to_string(), anchor.to_string())
+    }
+
+    #[test]
+    fn test_sticky_and_cooldown() {
+        let mut e = entry("aster", &["Aster"], "Aster.");
+        e.sticky = 2;
+        e.cooldown = 2;
+        let entries = vec![e];
+        let triggers = vec![trigger("aster", "m1")];
+        let branch = ["m0", "m1", "m2", "m3", "m4", "m5", "m6"];
+
+        let state = |newest: usize| {
+            let effects = timed_effects(&entries, &branch[..=newest], &triggers);
+            (effects.sticky.contains("aster"), effects.blocked.contains("aster"))
+        };
+        assert_eq!(state(1), (true, false));
+        assert_eq!(state(3), (true, false));
+        assert_eq!(state(4), (false, true));
+        assert_eq!(state(5), (false, true));
+        assert_eq!(state(6), (false, false));
+
+        // Sticky entries activate without their keyword
+        let effects = timed_effects(&entries, &branch[..=2], &triggers);
+        let activated = activate_entries(&entries, &["no match"], 0, &effects, &HashMap::new(), &mut StdRng::seed_from_u64(0)).activated;
+        assert_eq!(ids(&activated), vec![("aster", 0)]);
+
+        // Cooling down entries don't, even with a keyword hit
+        let effects = timed_effects(&entries, &branch[..=4], &triggers);
+        assert!(activate_entries(&entries, &["Aster"], 0, &effects, &HashMap::new(), &mut StdRng::seed_from_u64(0)).activated.is_empty());
+    }
+
+    #[test]
+    fn test_delay() {
+        let mut e = entry("aster", &["Aster"], "Aster.");
+        e.delay = 3;
+        let entries = vec![e];
+        assert!(timed_effects(&entries, &["m0", "m1"], &[]).blocked.contains("aster"));
+        assert!(timed_effects(&entries, &["m0", "m1", "m2"], &[]).blocked.is_empty());
+    }
+
+    #[test]
+    fn test_timers_follow_active_branch() {
+        let mut e = entry("aster", &["Aster"], "Aster.");
+        e.sticky = 5;
+        let entries = vec![e];
+        let triggers = vec![trigger("aster", "b1")];
+
+        assert!(timed_effects(&entries, &["m0", "b1", "b2"], &triggers).sticky.contains("aster"));
+        // After switching to a sibling branch the trigger no longer counts
+        assert!(timed_effects(&entries, &["m0", "c1", "c2"], &triggers).sticky.is_empty());
+    }
+
+    #[test]
+    fn test_similarity_activation() {
+        let mut entries = world();
+        entries[0].activation_mode = ActivationMode::Vector;
+        entries[1].activation_mode = ActivationMode::Both;
+        let similar = HashMap::from([("aster".to_string(), 0.8), ("aldric".to_string(), 0.7)]);
+        let run = |history: &[&str]| {
+            activate_entries(&entries, history, 0, &TimedEffects::default(), &similar, &mut StdRng::seed_from_u64(0)).activated
+        };
+
+        let activated = run(&["the old king"]);
+        assert_eq!(ids(&activated), vec![("aster", 0), ("aldric", 0)]);
+        assert_eq!(activated[0].trigger, Trigger::Similarity(0.8));
+
+        // Vector-only entries ignore their keywords
+        let activated = activate_entries(&entries, &["Aster and Aldric"], 0, &TimedEffects::default(), &HashMap::new(), &mut StdRng::seed_from_u64(0)).activated;
+        assert_eq!(ids(&activated), vec![("aldric", 0)]);
+        assert_eq!(activated[0].trigger, Trigger::Keyword("Aldric".to_string()));
+    }
+}
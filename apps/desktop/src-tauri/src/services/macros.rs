@@ -0,0 +1,495 @@
+// ============================================
+// Macro Service
+// Expands {{macros}} in cards, lore, greetings and prompts
+// ============================================
+
+use chrono::{DateTime, Local, TimeZone};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{Map, Value};
+
+use crate::database::Database;
+use crate::entities::*;
+use crate::error::AppResult;
+use crate::repositories::ConversationRepo;
+
+/// Conversation metadata key holding `{{setvar}}` values
+const VARIABLES_KEY: &str = "variables";
+
+/// Dice limits, so `{{roll:99999d99999}}` can't stall a prompt build
+/// and `{{roll:d6+9223372036854775807}}` can't overflow
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1_000_000;
+const MAX_MODIFIER: i64 = 1_000_000;
+
+/// Macros nested deeper than this are left as written, so a run of `{{`
+/// in a card can't blow up a prompt build
+const MAX_DEPTH: usize = 16;
+
+/// Stable string hash (FNV-1a) for seeds; unlike `DefaultHasher` it
+/// won't change between Rust releases
+fn stable_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Everything macros can refer to
+#[derive(Debug, Clone, Default)]
+pub struct MacroContext {
+    pub char_name: String,
+    pub user_name: String,
+    pub last_message: String,
+    pub last_user_message: String,
+    pub last_char_message: String,
+    /// When the user wrote before their newest message, for
+    /// `{{idle_duration}}`
+    pub last_user_at: Option<i64>,
+    /// Seeds `{{random}}` and `{{roll}}`
+    pub seed: u64,
+    /// Seeds `{{pick}}`, which stays the same for the whole conversation
+    pub pick_seed: u64,
+}
+
+impl MacroContext {
+    /// Context for a conversation's active branch. Random macros are
+    /// seeded by the newest message, so regenerating a reply sees the
+    /// same rolls while every new turn gets fresh ones. Idle time runs
+    /// from the user message before the newest one, which is usually the
+    /// message being answered.
+    pub fn for_conversation(conversation_id: &str, char_name: &str, user_name: &str, messages: &[Message]) -> Self {
+        let last_of = |author: AuthorType| messages.iter().rev().find(|m| m.author_type == author);
+        let anchor = messages.last().map(|m| m.id.as_str()).unwrap_or(conversation_id);
+
+        Self {
+            last_message: messages.last().map(|m| m.content.clone()).unwrap_or_default(),
+            last_user_message: last_of(AuthorType::User).map(|m| m.content.clone()).unwrap_or_default(),
+            last_char_message: last_of(AuthorType::Character).map(|m| m.content.clone()).unwrap_or_default(),
+            last_user_at: messages.iter().rev()
+                .filter(|m| m.author_type == AuthorType::User)
+                .nth(1)
+                .map(|m| m.created_at),
+            ..Self::seeded(conversation_id, anchor, char_name, user_name)
+        }
+    }
+
+    /// Context with no history, random macros seeded by `anchor_id`
+    /// (e.g. the greeting message being written)
+    pub fn seeded(conversation_id: &str, anchor_id: &str, char_name: &str, user_name: &str) -> Self {
+        Self {
+            char_name: char_name.to_string(),
+            user_name: user_name.to_string(),
+            seed: stable_hash(anchor_id),
+            pick_seed: stable_hash(conversation_id),
+            ..Default::default()
+        }
+    }
+}
+
+pub struct MacroEngine {
+    context: MacroContext,
+    now: DateTime<Local>,
+    rng: StdRng,
+    variables: Map<String, Value>,
+    variables_changed: bool,
+    /// How many times each `{{pick}}` body has been seen, so repeats differ
+    picks: std::collections::HashMap<String, u64>,
+}
+
+impl MacroEngine {
+    pub fn new(context: MacroContext, variables: Map<String, Value>) -> Self {
+        Self::at(context, variables, Local::now())
+    }
+
+    /// Engine with a fixed clock
+    pub fn at(context: MacroContext, variables: Map<String, Value>, now: DateTime<Local>) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(context.seed),
+            context,
+            now,
+            variables,
+            variables_changed: false,
+            picks: Default::default(),
+        }
+    }
+
+    /// Engine for building a conversation's prompt, with its saved variables
+    pub fn for_conversation(conversation: &Conversation, char_name: &str, user_name: &str, messages: &[Message]) -> Self {
+        let variables = conversation.metadata.get(VARIABLES_KEY)
+            .and_then(|v| v.as_object())
+            .cloned()
+            .unwrap_or_default();
+        Self::new(MacroContext::for_conversation(&conversation.id, char_name, user_name, messages), variables)
+    }
+
+    /// Write back variables changed by `{{setvar}}`
+    pub fn save_variables(&self, db: &Database, conversation: &Conversation) -> AppResult<()> {
+        if !self.variables_changed {
+            return Ok(());
+        }
+        let mut metadata = conversation.metadata.clone();
+        if !metadata.is_object() {
+            metadata = Value::Object(Map::new());
+        }
+        metadata[VARIABLES_KEY] = Value::Object(self.variables.clone());
+        ConversationRepo::update_metadata(db, &conversation.id, &metadata)
+    }
+
+    pub fn variables(&self) -> &Map<String, Value> {
+        &self.variables
+    }
+
+    /// Expand every macro in `text`. Unknown macros are left as written,
+    /// and expanded values are never expanded again. Nested macros are
+    /// expanded first, so `{{setvar::x::{{roll:d6}}}}` works; past
+    /// `MAX_DEPTH` levels the text is kept as written.
+    pub fn expand(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        // Bodies of the macros open at `pos`, innermost last, with what
+        // was nested in them already expanded
+        let mut open: Vec<String> = Vec::new();
+        // A comment or a macro nested too deep, taken whole without running
+        // anything in it: how many `{{` are unclosed and whether it's kept
+        let mut skipping: Option<(usize, bool)> = None;
+        // Start of the text not yet copied
+        let mut pos = 0;
+        let mut scan = 0;
+
+        while let Some(found) = text[scan..].find(['{', '}']).map(|i| scan + i) {
+            let opens = text[found..].starts_with("{{");
+            if !opens && !text[found..].starts_with("}}") {
+                scan = found + 1;
+                continue;
+            }
+            scan = found + 2;
+
+            if let Some((depth, keep)) = skipping.as_mut() {
+                if opens {
+                    *depth += 1;
+                } else {
+                    *depth -= 1;
+                }
+                if *depth == 0 {
+                    if *keep {
+                        self.emit(&mut out, &mut open, &text[pos..scan], true);
+                    }
+                    skipping = None;
+                    pos = scan;
+                }
+                continue;
+            }
+
+            self.emit(&mut out, &mut open, &text[pos..found], true);
+            if opens {
+                let comment = text[scan..].starts_with("//");
+                if comment || open.len() >= MAX_DEPTH {
+                    skipping = Some((1, !comment));
+                    pos = found;
+                    continue;
+                }
+                open.push(String::new());
+            } else {
+                match open.pop() {
+                    Some(body) => {
+                        let value = self.evaluate(&body).unwrap_or_else(|| format!("{{{{{}}}}}", body));
+                        self.emit(&mut out, &mut open, &value, false);
+                    }
+                    None => out.push_str("}}"),
+                }
+            }
+            pos = scan;
+        }
+
+        // Whatever is still open was never closed and stays as written
+        self.emit(&mut out, &mut open, &text[pos..], true);
+        while let Some(body) = open.pop() {
+            self.emit(&mut out, &mut open, &format!("{{{{{}", body), false);
+        }
+        out
+    }
+
+    /// Add `text` to the innermost open macro, or to the output. `raw` text
+    /// comes from the input, so at the top level it gets `replace_tags`.
+    fn emit(&self, out: &mut String, open: &mut [String], text: &str, raw: bool) {
+        match open.last_mut() {
+            Some(body) => body.push_str(text),
+            None if raw => out.push_str(&self.replace_tags(text)),
+            None => out.push_str(text),
+        }
+    }
+
+    /// Older `<user>`/`<char>` spellings from TavernAI cards
+    fn replace_tags(&self, text: &str) -> String {
+        if !text.contains('<') {
+            return text.to_string();
+        }
+        text.replace("<user>", &self.context.user_name)
+            .replace("<USER>", &self.context.user_name)
+            .replace("<char>", &self.context.char_name)
+            .replace("<CHAR>", &self.context.char_name)
+            .replace("<BOT>", &self.context.char_name)
+    }
+
+    /// Value of one macro body; None if it isn't a macro we know
+    fn evaluate(&mut self, body: &str) -> Option<String> {
+        let body = body.trim();
+        let (name, args): (&str, Vec<&str>) = if let Some((name, rest)) = body.split_once("::") {
+            (name, rest.split("::").collect())
+        } else if let Some((name, rest)) = body.split_once(':') {
+            (name, vec![rest])
+        } else if let Some((name, rest)) = body.split_once(char::is_whitespace) {
+            (name, vec![rest])
+        } else {
+            (body, Vec::new())
+        };
+
+        let value = match name.trim().to_lowercase().as_str() {
+            "char" | "bot" => self.context.char_name.clone(),
+            "user" => self.context.user_name.clone(),
+            "newline" => "\n".to_string(),
+            "time" => self.now.format("%-I:%M %p").to_string(),
+            "date" => self.now.format("%B %-d, %Y").to_string(),
+            "weekday" => self.now.format("%A").to_string(),
+            "isotime" => self.now.format("%H:%M").to_string(),
+            "isodate" => self.now.format("%Y-%m-%d").to_string(),
+            "idle_duration" => self.idle_duration(),
+            "lastmessage" => self.context.last_message.clone(),
+            "lastusermessage" => self.context.last_user_message.clone(),
+            "lastcharmessage" => self.context.last_char_message.clone(),
+            "random" => {
+                let options = Self::options(&args);
+                if options.is_empty() {
+                    return None;
+                }
+                options[self.rng.gen_range(0..options.len())].to_string()
+            }
+            "pick" => {
+                let options = Self::options(&args);
+                if options.is_empty() {
+                    return None;
+                }
+                let seen = self.picks.entry(body.to_string()).or_insert(0);
+                let seed = self.context.pick_seed ^ stable_hash(body) ^ *seen;
+                *seen += 1;
+                options[StdRng::seed_from_u64(seed).gen_range(0..options.len())].to_string()
+            }
+            "roll" => self.roll(args.first()?.trim())?.to_string(),
+            "setvar" => {
+                let key = args.first()?.trim();
+                let value = args.get(1..).map(|v| v.join("::")).unwrap_or_default();
+                if self.variables.get(key).and_then(|v| v.as_str()) != Some(value.as_str()) {
+                    self.variables.insert(key.to_string(), Value::String(value));
+                    self.variables_changed = true;
+                }
+                String::new()
+            }
+            "getvar" => match self.variables.get(args.first()?.trim()) {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Null) | None => String::new(),
+                Some(other) => other.to_string(),
+            },
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// `random::a::b` lists with `::`; the older `random:a,b` with commas
+    fn options<'a>(args: &[&'a str]) -> Vec<&'a str> {
+        match args {
+            [] => Vec::new(),
+            [single] => single.split(',').map(str::trim).collect(),
+            many => many.to_vec(),
+        }
+    }
+
+    /// `2d6`, `d20`, `3d8+2`, or a bare `20` for one d20
+    fn roll(&mut self, formula: &str) -> Option<i64> {
+        let formula: String = formula.chars().filter(|c| !c.is_whitespace()).collect();
+        let formula = formula.to_lowercase();
+        let (dice, modifier) = match formula.find(['+', '-']) {
+            Some(i) => (&formula[..i], formula[i..].parse::<i64>().ok()?),
+            None => (formula.as_str(), 0),
+        };
+        let (count, sides) = match dice.split_once('d') {
+            Some((count, sides)) => (
+                if count.is_empty() { 1 } else { count.parse::<u32>().ok()? },
+                sides.parse::<u32>().ok()?,
+            ),
+            None => (1, dice.parse::<u32>().ok()?),
+        };
+        if count == 0 || sides == 0 || count > MAX_DICE || sides > MAX_SIDES || !(-MAX_MODIFIER..=MAX_MODIFIER).contains(&modifier) {
+            return None;
+        }
+        let total: i64 = (0..count).map(|_| self.rng.gen_range(1..=sides as i64)).sum();
+        Some(total + modifier)
+    }
+
+    fn idle_duration(&self) -> String {
+        let Some(since) = self.context.last_user_at else {
+            return "just now".to_string();
+        };
+        let Some(since) = Local.timestamp_opt(since, 0).single() else {
+            return "just now".to_string();
+        };
+        humanize_duration((self.now - since).num_seconds())
+    }
+}
+
+/// "a few seconds", "5 minutes", "an hour", "3 days"
+pub fn humanize_duration(seconds: i64) -> String {
+    let seconds = seconds.max(0);
+    let (amount, unit) = match seconds {
+        0..=44 => return "a few seconds".to_string(),
+        45..=3599 => ((seconds + 30) / 60, "minute"),
+        3600..=86_399 => ((seconds + 1800) / 3600, "hour"),
+        86_400..=2_591_999 => ((seconds + 43_200) / 86_400, "day"),
+        2_592_000..=31_535_999 => ((seconds + 1_296_000) / 2_592_000, "month"),
+        _ => ((seconds + 15_768_000) / 31_536_000, "year"),
+    };
+    match (amount, unit) {
+        (1, "hour") => "an hour".to_string(),
+        (1, unit) => format!("a {}", unit),
+        (n, unit) => format!("{} {}s", n, unit),
+    }
+}
+
+/// Swap in names only. For model output, which must not run macros.
+pub fn replace_names(text: &str, char_name: &str, user_name: &str) -> String {
+    text.replace("{{user}}", user_name)
+        .replace("{{User}}", user_name)
+        .replace("{{char}}", char_name)
+        .replace("{{Char}}", char_name)
+        .replace("<user>", user_name)
+        .replace("<char>", char_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine(seed: u64) -> MacroEngine {
+        let context = MacroContext {
+            char_name: "Aria".into(),
+            user_name: "Sam".into(),
+            last_message: "See you {{user}}".into(),
+            last_user_at: Some(Local.with_ymd_and_hms(2024, 3, 4, 9, 0, 0).unwrap().timestamp()),
+            seed,
+            pick_seed: 7,
+            ..Default::default()
+        };
+        let now = Local.with_ymd_and_hms(2024, 3, 4, 11, 5, 0).unwrap();
+        MacroEngine::at(context, Map::new(), now)
+    }
+
+    #[test]
+    fn test_names_and_time() {
+        let mut e = engine(1);
+        assert_eq!(e.expand("{{char}} greets {{User}}, <USER>"), "Aria greets Sam, Sam");
+        assert_eq!(e.expand("{{weekday}}, {{date}} at {{time}}"), "Monday, March 4, 2024 at 11:05 AM");
+        assert_eq!(e.expand("{{isodate}} {{isotime}}"), "2024-03-04 11:05");
+        assert_eq!(e.expand("idle {{idle_duration}}"), "idle 2 hours");
+        // Values are inserted as-is, not expanded again
+        assert_eq!(e.expand("{{lastMessage}}"), "See you {{user}}");
+    }
+
+    #[test]
+    fn test_idle_skips_the_message_being_answered() {
+        let now = Local.with_ymd_and_hms(2024, 3, 4, 11, 5, 0).unwrap();
+        let message = |author_type: AuthorType, minutes_ago: i64| Message {
+            id: format!("m{}", minutes_ago),
+            conversation_id: "c1".into(),
+            parent_id: None,
+            author_type,
+            author_id: None,
+            content: String::new(),
+            is_active_branch: true,
+            branch_index: 0,
+            token_count: 0,
+            generation_params: None,
+            created_at: now.timestamp() - minutes_ago * 60,
+            metadata: serde_json::json!({}),
+            author_name: None,
+            sibling_count: None,
+        };
+        let history = [
+            message(AuthorType::User, 180),
+            message(AuthorType::Character, 179),
+            message(AuthorType::User, 0),
+        ];
+
+        let context = MacroContext::for_conversation("c1", "Aria", "Sam", &history);
+        assert_eq!(MacroEngine::at(context, Map::new(), now).expand("{{idle_duration}}"), "3 hours");
+        let context = MacroContext::for_conversation("c1", "Aria", "Sam", &history[2..]);
+        assert_eq!(MacroEngine::at(context, Map::new(), now).expand("{{idle_duration}}"), "just now");
+    }
+
+    #[test]
+    fn test_comments_and_unknown() {
+        let mut e = engine(1);
+        assert_eq!(e.expand("a{{// note {{setvar::x::1}} }}b"), "ab");
+        assert!(e.variables().is_empty());
+        assert_eq!(e.expand("{{mystery}} {{ unclosed"), "{{mystery}} {{ unclosed");
+        assert_eq!(e.expand("{{a {{char}} }} }} <user>"), "{{a Aria }} }} Sam");
+        assert_eq!(e.expand("{{a {{char}}"), "{{a Aria");
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let mut e = engine(1);
+        // Past the cap, text is kept as written rather than expanded
+        let deep = format!("{}char{}", "{{".repeat(20), "}}".repeat(20));
+        assert_eq!(e.expand(&deep), deep);
+        let within = format!("{}char{}", "{{".repeat(3), "}}".repeat(3));
+        assert_eq!(e.expand(&within), "{{{{Aria}}}}");
+
+        // A flood of openers neither overflows the stack nor crawls
+        let flood = format!("{}x}}}}", "{{".repeat(200_000));
+        assert_eq!(e.expand(&flood), flood);
+    }
+
+    #[test]
+    fn test_random_is_seeded() {
+        let text = "{{random::a::b::c::d}} {{random:e,f,g}} {{roll:3d6+2}} {{roll 20}}";
+        let first = engine(42).expand(text);
+        assert_eq!(engine(42).expand(text), first);
+
+        let runs: std::collections::HashSet<String> = (0..20).map(|seed| engine(seed).expand(text)).collect();
+        assert!(runs.len() > 1);
+
+        for seed in 0..50 {
+            let total: i64 = engine(seed).expand("{{roll:2d6}}").parse().unwrap();
+            assert!((2..=12).contains(&total));
+        }
+        assert_eq!(engine(1).expand("{{roll:0d6}}"), "{{roll:0d6}}");
+        assert_eq!(engine(1).expand("{{roll:1000d6}}"), "{{roll:1000d6}}");
+        assert_eq!(engine(1).expand("{{roll:d6+9223372036854775807}}"), "{{roll:d6+9223372036854775807}}");
+        assert_eq!(engine(1).expand("{{roll:d6-9223372036854775808}}"), "{{roll:d6-9223372036854775808}}");
+    }
+
+    #[test]
+    fn test_pick_ignores_message_seed() {
+        let text = "{{pick::red::green::blue::gold}}";
+        let picked = engine(1).expand(text);
+        assert!((2..30).all(|seed| engine(seed).expand(text) == picked));
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut e = engine(3);
+        assert_eq!(e.expand("{{setvar::mood::calm}}{{getvar::mood}}"), "calm");
+        assert_eq!(e.expand("[{{getvar::missing}}]"), "[]");
+
+        e.expand("{{setvar::die::{{roll:d6}}}}");
+        let rolled: i64 = e.expand("{{getvar::die}}").parse().unwrap();
+        assert!((1..=6).contains(&rolled));
+        assert!(e.variables_changed);
+    }
+
+    #[test]
+    fn test_humanize_duration() {
+        assert_eq!(humanize_duration(10), "a few seconds");
+        assert_eq!(humanize_duration(60), "a minute");
+        assert_eq!(humanize_duration(3600), "an hour");
+        assert_eq!(humanize_duration(3 * 86_400), "3 days");
+    }
+}
@@ -5,12 +5,13 @@
 
 use crate::database::Database;
 use crate::entities::{new_id, now_timestamp};
-use crate::error::AppResult;
+use crate::error::{AppError, AppResult};
 use crate::sidecar::SidecarHandle;
-use crate::services::embeddings::EmbeddingService;
+use crate::services::embeddings::{Embedding, EmbeddingService};
 use crate::repositories::MessageRepo;
-use crate::entities::AuthorType;
+use crate::entities::{AuthorType, Message};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 
 // ============================================
@@ -100,7 +101,7 @@ impl MemoryService {
         for attempt in 1..=2 {
             match EmbeddingService::generate(sidecar, content).await {
                 Ok(embedding) => {
-                    match EmbeddingService::store(db, "memory", &memory.id, &embedding) {
+                    match EmbeddingService::store(db, "memory", &memory.id, character_id, &embedding) {
                         Ok(_) => {
                             embedding_stored = true;
                             break;
@@ -155,27 +156,22 @@ impl MemoryService {
         // Generate query embedding
         let query_embedding = EmbeddingService::generate(sidecar, query).await?;
         
-        // Find similar embeddings
+        // Find similar embeddings in this character's partition
         let similar = EmbeddingService::find_similar(
             db,
             &query_embedding,
             "memory",
-            limit * 2, // Get more for filtering by character
+            Some(character_id),
+            limit,
             min_similarity,
         )?;
         
-        // Fetch matching memories and filter by character
-        let mut results = Vec::new();
-        for (memory_id, similarity) in similar {
-            if let Ok(memory) = Self::get_by_id(db, &memory_id) {
-                if memory.character_id == character_id {
-                    results.push((memory, similarity));
-                    if results.len() >= limit {
-                        break;
-                    }
-                }
-            }
-        }
+        let results = similar
+            .into_iter()
+            .filter_map(|(memory_id, similarity)| {
+                Self::get_by_id(db, &memory_id).ok().map(|memory| (memory, similarity))
+            })
+            .collect();
         
         Ok(results)
     }
@@ -185,7 +181,7 @@ impl MemoryService {
     pub fn retrieve_relevant_sync_with_recency(
         db: &Database,
         character_id: &str,
-        query_embedding: Option<&[f32]>,
+        query_embedding: Option<&Embedding>,
         limit: usize,
         min_similarity: f32,
     ) -> AppResult<Vec<(MemoryEntry, f32)>> {
@@ -219,7 +215,7 @@ impl MemoryService {
     pub fn retrieve_relevant_sync(
         db: &Database,
         character_id: &str,
-        query_embedding: Option<&[f32]>,
+        query_embedding: Option<&Embedding>,
         limit: usize,
         min_similarity: f32,
     ) -> AppResult<Vec<(MemoryEntry, f32)>> {
@@ -229,21 +225,17 @@ impl MemoryService {
                 db,
                 embedding,
                 "memory",
-                limit * 2,
+                Some(character_id),
+                limit,
                 min_similarity,
             )?;
             
-            let mut results = Vec::new();
-            for (memory_id, similarity) in similar {
-                if let Ok(memory) = Self::get_by_id(db, &memory_id) {
-                    if memory.character_id == character_id {
-                        results.push((memory, similarity));
-                        if results.len() >= limit {
-                            break;
-                        }
-                    }
-                }
-            }
+            let results = similar
+                .into_iter()
+                .filter_map(|(memory_id, similarity)| {
+                    Self::get_by_id(db, &memory_id).ok().map(|memory| (memory, similarity))
+                })
+                .collect();
             Ok(results)
         } else {
             // Fallback to importance/recency based
@@ -372,23 +364,36 @@ fn is_contradicting_fact(existing: &str, new: &str) -> bool {
 }
 
 impl MemoryService {
-    /// Extract and store important facts from a message with robust parsing and deduplication
-    pub async fn process_message(
+    /// Extract and store important facts from a batch of messages with one LLM call,
+    /// using robust parsing and deduplication
+    pub async fn process_messages(
         db: &Database,
         sidecar: &SidecarHandle,
-        content: &str,
+        embedder: &SidecarHandle,
+        messages: &[Message],
         character_id: &str,
         conversation_id: &str,
-        source_message_id: &str,
     ) -> AppResult<()> {
         // Skip very short messages
-        if content.trim().len() < 15 {
+        let messages: Vec<&Message> = messages.iter()
+            .filter(|m| m.content.trim().len() >= 15)
+            .collect();
+        if messages.is_empty() {
             return Ok(());
         }
+        
+        let transcript = messages.iter()
+            .map(|m| format!("{}: \"{}\"",
+                if m.author_type == AuthorType::User { "User" } else { "Character" },
+                m.content.replace('"', "'")
+            ))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let source_messages: Vec<String> = messages.iter().map(|m| m.id.clone()).collect();
 
         // IMPROVED: Comprehensive extraction prompt that captures ALL fact types
         let prompt = format!(
-            r#"Extract important facts from this message that should be remembered long-term.
+            r#"Extract important facts from these messages that should be remembered long-term.
 
 CATEGORIES TO EXTRACT:
 1. USER FACTS: Name, age, job, location, preferences, background, relationships
@@ -409,10 +414,11 @@ Examples:
 - "*sighs with relief*" -> ["Emotional: User expressed relief"]
 - "How's the weather?" -> []
 
-Message: "{}"
+Messages:
+{}
 
 JSON array:"#,
-            content.replace('"', "'")
+            transcript
         );
 
         let messages = vec![serde_json::json!({
@@ -434,7 +440,7 @@ JSON array:"#,
             return Ok(());
         }
 
-        tracing::info!("Extracted {} facts from message {}", facts.len(), source_message_id);
+        tracing::info!("Extracted {} facts from {} messages", facts.len(), source_messages.len());
 
         // Get existing memories for deduplication and contradiction check
         let existing = Self::get_for_character(db, character_id, 100)?;
@@ -473,12 +479,12 @@ JSON array:"#,
             // Store with embedding
             let _ = Self::create_with_embedding(
                 db,
-                sidecar,
+                embedder,
                 character_id,
                 fact_trimmed,
                 Some(conversation_id),
                 0.5,
-                vec![source_message_id.to_string()],
+                source_messages.clone(),
             ).await;
         }
 
@@ -514,9 +520,18 @@ fn extract_json_array(text: &str) -> Vec<String> {
 
 // ============================================
 // Summary Service
-// Manages conversation summaries
+// Manages conversation summaries. Level 0 summaries cover a run of
+// messages; once enough pile up, the oldest roll up into a chapter
+// summary one level higher (children keep a parent_id link).
 // ============================================
 
+/// Summaries of one level rolled into each chapter
+const ROLLUP_SIZE: usize = 5;
+/// Highest chapter level; summaries at this level are never rolled up
+const MAX_SUMMARY_LEVEL: i32 = 3;
+
+const SUMMARY_COLUMNS: &str = "id, conversation_id, content, message_range_start, message_range_end, message_count, token_count, created_at, level, parent_id, is_edited, updated_at";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ConversationSummary {
@@ -528,6 +543,74 @@ pub struct ConversationSummary {
     pub message_count: i32,
     pub token_count: i32,
     pub created_at: i64,
+    /// 0 = covers messages, 1+ = chapter covering lower-level summaries
+    pub level: i32,
+    /// Chapter this summary was rolled up into
+    pub parent_id: Option<String>,
+    /// Hand-edited by the user; regenerate to discard the edit
+    pub is_edited: bool,
+    pub updated_at: i64,
+}
+
+impl ConversationSummary {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        let created_at: i64 = row.get(7)?;
+        Ok(Self {
+            id: row.get(0)?,
+            conversation_id: row.get(1)?,
+            content: row.get(2)?,
+            message_range_start: row.get(3)?,
+            message_range_end: row.get(4)?,
+            message_count: row.get::<_, Option<i32>>(5)?.unwrap_or(0),
+            token_count: row.get::<_, Option<i32>>(6)?.unwrap_or(0),
+            created_at,
+            level: row.get(8)?,
+            parent_id: row.get(9)?,
+            is_edited: row.get::<_, i32>(10)? != 0,
+            updated_at: row.get::<_, Option<i64>>(11)?.unwrap_or(created_at),
+        })
+    }
+}
+
+/// Pick the most detailed set of summaries that fits the token budget.
+/// Starts from the top-level summaries (dropping the oldest if even those
+/// don't fit), then repeatedly swaps a chapter for its children, most
+/// recent first, while the extra detail still fits.
+/// Returns summaries in chronological order.
+pub fn select_within_budget(summaries: &[ConversationSummary], budget: i32) -> Vec<ConversationSummary> {
+    let ids: HashSet<&str> = summaries.iter().map(|s| s.id.as_str()).collect();
+    let mut children: HashMap<&str, Vec<&ConversationSummary>> = HashMap::new();
+    let mut selected: Vec<&ConversationSummary> = Vec::new();
+
+    // Input is chronological, so children lists come out in order too
+    for summary in summaries {
+        match summary.parent_id.as_deref().filter(|p| ids.contains(p)) {
+            Some(parent) => children.entry(parent).or_default().push(summary),
+            None => selected.push(summary),
+        }
+    }
+
+    let mut used: i32 = selected.iter().map(|s| s.token_count).sum();
+    while used > budget && !selected.is_empty() {
+        used -= selected.remove(0).token_count;
+    }
+
+    loop {
+        let expandable = (0..selected.len()).rev().find_map(|i| {
+            let kids = children.get(selected[i].id.as_str())?;
+            let extra = kids.iter().map(|k| k.token_count).sum::<i32>() - selected[i].token_count;
+            (used + extra <= budget).then_some((i, extra))
+        });
+
+        let Some((i, extra)) = expandable else {
+            break;
+        };
+        used += extra;
+        let kids = children[selected[i].id.as_str()].clone();
+        selected.splice(i..=i, kids);
+    }
+
+    selected.into_iter().cloned().collect()
 }
 
 pub struct SummaryService;
@@ -540,6 +623,7 @@ impl SummaryService {
         content: &str,
         message_range: Option<(&str, &str)>,
         message_count: i32,
+        level: i32,
     ) -> AppResult<ConversationSummary> {
         let id = new_id();
         let now = now_timestamp();
@@ -551,9 +635,9 @@ impl SummaryService {
         };
         
         db.execute(
-            "INSERT INTO conversation_summaries (id, conversation_id, content, message_range_start, message_range_end, message_count, token_count, created_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-            rusqlite::params![id, conversation_id, content, range_start, range_end, message_count, token_count, now],
+            "INSERT INTO conversation_summaries (id, conversation_id, content, message_range_start, message_range_end, message_count, token_count, created_at, level, is_edited, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, 0, ?8)",
+            rusqlite::params![id, conversation_id, content, range_start, range_end, message_count, token_count, now, level],
         )?;
         
         Ok(ConversationSummary {
@@ -565,48 +649,109 @@ impl SummaryService {
             message_count,
             token_count,
             created_at: now,
+            level,
+            parent_id: None,
+            is_edited: false,
+            updated_at: now,
         })
     }
     
-    /// Get summaries for a conversation
-    pub fn get_for_conversation(
-        db: &Database,
-        conversation_id: &str,
-        token_budget: i32,
-    ) -> AppResult<Vec<ConversationSummary>> {
-        let summaries = db.query_all(
-            "SELECT id, conversation_id, content, message_range_start, message_range_end, message_count, token_count, created_at
-             FROM conversation_summaries
-             WHERE conversation_id = ?1
-             ORDER BY created_at DESC",
+    pub fn get_by_id(db: &Database, id: &str) -> AppResult<ConversationSummary> {
+        db.query_one(
+            &format!("SELECT {} FROM conversation_summaries WHERE id = ?1", SUMMARY_COLUMNS),
+            rusqlite::params![id],
+            ConversationSummary::from_row,
+        )
+    }
+    
+    /// All summaries of a conversation at every level, in story order
+    pub fn list_for_conversation(db: &Database, conversation_id: &str) -> AppResult<Vec<ConversationSummary>> {
+        let mut summaries = db.query_all(
+            &format!(
+                "SELECT {} FROM conversation_summaries WHERE conversation_id = ?1 ORDER BY created_at ASC, level ASC",
+                SUMMARY_COLUMNS
+            ),
             rusqlite::params![conversation_id],
-            |row| Ok(ConversationSummary {
-                id: row.get(0)?,
-                conversation_id: row.get(1)?,
-                content: row.get(2)?,
-                message_range_start: row.get(3)?,
-                message_range_end: row.get(4)?,
-                message_count: row.get(5)?,
-                token_count: row.get(6)?,
-                created_at: row.get(7)?,
-            }),
+            ConversationSummary::from_row,
         )?;
+        Self::sort_by_story(&mut summaries);
+        Ok(summaries)
+    }
+    
+    /// Summaries of the active branch, in chronological order
+    fn list_for_active_branch(db: &Database, conversation_id: &str) -> AppResult<Vec<ConversationSummary>> {
+        let on_branch: HashSet<String> = MessageRepo::find_active_branch(db, conversation_id)?
+            .into_iter()
+            .map(|m| m.id)
+            .collect();
         
-        // Select summaries within budget
-        let mut result = Vec::new();
-        let mut used_tokens = 0;
+        let mut summaries = Self::list_for_conversation(db, conversation_id)?;
+        // Summaries of other branches stay stored but don't describe this story
+        summaries.retain(|s| s.message_range_end.as_ref().is_none_or(|end| on_branch.contains(end)));
+        Self::sort_by_story(&mut summaries);
+        Ok(summaries)
+    }
+    
+    /// Chapters are created after their children, so order by the story
+    /// position each summary covers, a chapter just before its children
+    fn sort_by_story(summaries: &mut [ConversationSummary]) {
+        let start_of = Self::chapter_starts(summaries);
+        summaries.sort_by_key(|s| {
+            (start_of.get(&s.id).copied().unwrap_or(s.created_at), std::cmp::Reverse(s.level))
+        });
+    }
+    
+    /// Creation time of the earliest level 0 summary under each chapter
+    fn chapter_starts(summaries: &[ConversationSummary]) -> HashMap<String, i64> {
+        let mut starts: HashMap<String, i64> = HashMap::new();
+        let mut by_level: Vec<&ConversationSummary> = summaries.iter().collect();
+        by_level.sort_by_key(|s| s.level);
         
-        for summary in summaries {
-            if used_tokens + summary.token_count > token_budget {
-                break;
+        for summary in by_level {
+            let start = starts.get(summary.id.as_str()).copied().unwrap_or(summary.created_at);
+            starts.insert(summary.id.clone(), start);
+            if let Some(parent) = &summary.parent_id {
+                let entry = starts.entry(parent.clone()).or_insert(start);
+                *entry = (*entry).min(start);
             }
-            used_tokens += summary.token_count;
-            result.push(summary);
+        }
+        starts
+    }
+    
+    /// Get the best mix of chapter and detailed summaries that fits the budget
+    pub fn get_for_conversation(
+        db: &Database,
+        conversation_id: &str,
+        token_budget: i32,
+    ) -> AppResult<Vec<ConversationSummary>> {
+        let summaries = Self::list_for_active_branch(db, conversation_id)?;
+        Ok(select_within_budget(&summaries, token_budget))
+    }
+    
+    /// Replace a summary's text by hand
+    pub fn update_content(db: &Database, id: &str, content: &str) -> AppResult<ConversationSummary> {
+        let content = content.trim();
+        if content.is_empty() {
+            return Err(AppError::Validation("Summary cannot be empty".into()));
         }
         
-        // Reverse to get chronological order
-        result.reverse();
-        Ok(result)
+        db.execute(
+            "UPDATE conversation_summaries SET content = ?1, token_count = ?2, is_edited = 1, updated_at = ?3 WHERE id = ?4",
+            rusqlite::params![content, crate::services::estimate_tokens(content), now_timestamp(), id],
+        )?;
+        Self::get_by_id(db, id)
+    }
+    
+    /// Delete one summary. Children of a deleted chapter become top-level again.
+    pub fn delete(db: &Database, id: &str) -> AppResult<()> {
+        db.transaction(|conn| {
+            conn.execute(
+                "UPDATE conversation_summaries SET parent_id = NULL WHERE parent_id = ?1",
+                rusqlite::params![id],
+            )?;
+            conn.execute("DELETE FROM conversation_summaries WHERE id = ?1", rusqlite::params![id])?;
+            Ok(())
+        })
     }
     
     /// Delete summaries for a conversation
@@ -617,6 +762,117 @@ impl SummaryService {
         )?;
         Ok(())
     }
+    
+    fn format_transcript(messages: &[Message]) -> String {
+        messages.iter()
+            .map(|m| format!("{}: {}", 
+                if m.author_type == AuthorType::User { "User" } else { "Character" },
+                m.content
+            ))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+    
+    async fn summarize_messages(sidecar: &SidecarHandle, messages: &[Message]) -> AppResult<String> {
+        let prompt = format!(
+            "Summarize this conversation in 2-3 sentences, focusing on key topics and any important facts learned about the user:\n\n{}\n\nSummary:",
+            Self::format_transcript(messages)
+        );
+        
+        let llm_messages = vec![serde_json::json!({
+            "role": "user",
+            "content": prompt
+        })];
+        
+        let text = crate::sidecar::generate_text_oneshot(sidecar, llm_messages, 0.3, 200).await?;
+        Ok(text.trim().to_string())
+    }
+    
+    async fn summarize_chapter(sidecar: &SidecarHandle, parts: &[ConversationSummary]) -> AppResult<String> {
+        let prompt = format!(
+            "Condense these consecutive summaries of one conversation into a single chapter summary of 3-4 sentences. Keep key events, important facts about the user and unresolved threads:\n\n{}\n\nChapter summary:",
+            parts.iter().map(|p| format!("- {}", p.content)).collect::<Vec<_>>().join("\n")
+        );
+        
+        let llm_messages = vec![serde_json::json!({
+            "role": "user",
+            "content": prompt
+        })];
+        
+        let text = crate::sidecar::generate_text_oneshot(sidecar, llm_messages, 0.3, 300).await?;
+        Ok(text.trim().to_string())
+    }
+    
+    /// Rewrite a summary from its source: the covered messages for level 0,
+    /// the child summaries for chapters. Clears the edited flag.
+    pub async fn regenerate(db: &Database, sidecar: &SidecarHandle, id: &str) -> AppResult<ConversationSummary> {
+        let summary = Self::get_by_id(db, id)?;
+        
+        let content = if summary.level == 0 {
+            let messages = MessageRepo::find_active_branch(db, &summary.conversation_id)?;
+            let position = |id: &Option<String>| id.as_ref().and_then(|id| messages.iter().position(|m| &m.id == id));
+            let (Some(start), Some(end)) = (position(&summary.message_range_start), position(&summary.message_range_end)) else {
+                return Err(AppError::Validation("Summarized messages are not on the active branch".into()));
+            };
+            Self::summarize_messages(sidecar, &messages[start..=end]).await?
+        } else {
+            let children: Vec<ConversationSummary> = Self::list_for_conversation(db, &summary.conversation_id)?
+                .into_iter()
+                .filter(|s| s.parent_id.as_deref() == Some(id))
+                .collect();
+            if children.is_empty() {
+                return Err(AppError::Validation("Chapter has no summaries left to regenerate from".into()));
+            }
+            Self::summarize_chapter(sidecar, &children).await?
+        };
+        
+        db.execute(
+            "UPDATE conversation_summaries SET content = ?1, token_count = ?2, is_edited = 0, updated_at = ?3 WHERE id = ?4",
+            rusqlite::params![content, crate::services::estimate_tokens(&content), now_timestamp(), id],
+        )?;
+        Self::get_by_id(db, id)
+    }
+    
+    /// Roll the oldest top-level summaries of each level into chapters.
+    /// The newest summaries of a level always stay detailed.
+    pub async fn roll_up(db: &Database, sidecar: &SidecarHandle, conversation_id: &str) -> AppResult<usize> {
+        let mut created = 0;
+        
+        for level in 0..MAX_SUMMARY_LEVEL {
+            let roots: Vec<ConversationSummary> = Self::list_for_active_branch(db, conversation_id)?
+                .into_iter()
+                .filter(|s| s.level == level && s.parent_id.is_none())
+                .collect();
+            
+            for parts in roots.chunks(ROLLUP_SIZE) {
+                // A partial chunk, or a full one with nothing newer, waits for more summaries
+                if parts.len() < ROLLUP_SIZE || parts.last().map(|p| &p.id) == roots.last().map(|r| &r.id) {
+                    break;
+                }
+                
+                let content = Self::summarize_chapter(sidecar, parts).await?;
+                let range = parts[0].message_range_start.as_deref()
+                    .zip(parts[parts.len() - 1].message_range_end.as_deref());
+                let message_count = parts.iter().map(|p| p.message_count).sum();
+                
+                let chapter = Self::create(db, conversation_id, &content, range, message_count, level + 1)?;
+                db.transaction(|conn| {
+                    for part in parts {
+                        conn.execute(
+                            "UPDATE conversation_summaries SET parent_id = ?1 WHERE id = ?2",
+                            rusqlite::params![chapter.id, part.id],
+                        )?;
+                    }
+                    Ok(())
+                })?;
+                
+                tracing::info!("Rolled {} level {} summaries into a chapter", parts.len(), level);
+                created += 1;
+            }
+        }
+        
+        Ok(created)
+    }
 
     /// Check if summarization is needed and create summary if so
     pub async fn maybe_summarize(
@@ -628,20 +884,16 @@ impl SummaryService {
     ) -> AppResult<Option<ConversationSummary>> {
         let messages = MessageRepo::find_active_branch(db, conversation_id)?;
         
-        // Get last summary to find unsummarized messages
-        let existing_summaries = Self::get_for_conversation(db, conversation_id, 10000)?;
-        let last_summarized_id = existing_summaries.first()
-            .and_then(|s| s.message_range_end.clone());
-        
-        // Find messages after last summary
-        let unsummarized: Vec<_> = if let Some(ref last_id) = last_summarized_id {
-            let mut found = false;
-            messages.iter().filter(|m| {
-                if m.id == *last_id { found = true; return false; }
-                found
-            }).collect()
-        } else {
-            messages.iter().collect()
+        // Resume after the latest message already covered by a summary on this branch
+        let covered_until = Self::list_for_conversation(db, conversation_id)?
+            .iter()
+            .filter(|s| s.level == 0)
+            .filter_map(|s| s.message_range_end.as_ref())
+            .filter_map(|end| messages.iter().position(|m| &m.id == end))
+            .max();
+        let unsummarized = match covered_until {
+            Some(index) => &messages[index + 1..],
+            None => &messages[..],
         };
         
         // Calculate token count
@@ -658,28 +910,7 @@ impl SummaryService {
         }
         let to_summarize = &unsummarized[..unsummarized.len() - 5];
         
-        // Build prompt for summarization
-        let messages_text = to_summarize.iter()
-            .map(|m| format!("{}: {}", 
-                if m.author_type == AuthorType::User { "User" } else { "Character" },
-                m.content
-            ))
-            .collect::<Vec<_>>()
-            .join("\n");
-        
-        let prompt = format!(
-            "Summarize this conversation in 2-3 sentences, focusing on key topics and any important facts learned about the user:\n\n{}\n\nSummary:",
-            messages_text
-        );
-        
-        let llm_messages = vec![serde_json::json!({
-            "role": "user",
-            "content": prompt
-        })];
-        
-        let summary_text = crate::sidecar::generate_text_oneshot(
-            sidecar, llm_messages, 0.3, 200
-        ).await?;
+        let summary_text = Self::summarize_messages(sidecar, to_summarize).await?;
         
         // Store summary
         let first_id = to_summarize.first().map(|m| m.id.as_str());
@@ -692,9 +923,12 @@ impl SummaryService {
             &summary_text,
             range,
             to_summarize.len() as i32,
+            0,
         )?;
         
         tracing::info!("Created summary for {} messages", to_summarize.len());
+        
+        Self::roll_up(db, sidecar, conversation_id).await?;
         Ok(Some(summary))
     }
 }
@@ -732,4 +966,65 @@ mod tests {
         let result = extract_json_array(input);
         assert_eq!(result.len(), 0);
     }
+    
+    fn summary(id: &str, tokens: i32, level: i32, parent: Option<&str>) -> ConversationSummary {
+        ConversationSummary {
+            id: id.to_string(),
+            conversation_id: "c".to_string(),
+            content: id.to_string(),
+            message_range_start: None,
+            message_range_end: None,
+            message_count: 0,
+            token_count: tokens,
+            created_at: 0,
+            level,
+            parent_id: parent.map(String::from),
+            is_edited: false,
+            updated_at: 0,
+        }
+    }
+    
+    fn ids(selected: &[ConversationSummary]) -> Vec<&str> {
+        selected.iter().map(|s| s.id.as_str()).collect()
+    }
+    
+    #[test]
+    fn test_select_expands_recent_chapters_first() {
+        let summaries = vec![
+            summary("a1", 50, 0, Some("A")),
+            summary("a2", 50, 0, Some("A")),
+            summary("A", 60, 1, None),
+            summary("b1", 50, 0, Some("B")),
+            summary("b2", 50, 0, Some("B")),
+            summary("B", 60, 1, None),
+            summary("c", 50, 0, None),
+        ];
+        
+        // Everything fits in full detail
+        assert_eq!(ids(&select_within_budget(&summaries, 1000)), vec!["a1", "a2", "b1", "b2", "c"]);
+        // Only room to expand the most recent chapter
+        assert_eq!(ids(&select_within_budget(&summaries, 230)), vec!["A", "b1", "b2", "c"]);
+        // Only the top level fits
+        assert_eq!(ids(&select_within_budget(&summaries, 170)), vec!["A", "B", "c"]);
+        // Oldest top-level summaries are dropped first
+        assert_eq!(ids(&select_within_budget(&summaries, 110)), vec!["B", "c"]);
+    }
+    
+    #[test]
+    fn test_story_order_puts_chapters_before_children() {
+        let at = |mut summary: ConversationSummary, created_at: i64| {
+            summary.created_at = created_at;
+            summary
+        };
+        // As stored: by creation time, each chapter after what it rolls up
+        let mut summaries = vec![
+            at(summary("a1", 50, 0, Some("A")), 1),
+            at(summary("a2", 50, 0, Some("A")), 2),
+            at(summary("b1", 50, 0, None), 3),
+            at(summary("A", 60, 1, None), 4),
+            at(summary("c1", 50, 0, None), 5),
+        ];
+        SummaryService::sort_by_story(&mut summaries);
+        assert_eq!(ids(&summaries), vec!["A", "a1", "a2", "b1", "c1"]);
+    }
 }
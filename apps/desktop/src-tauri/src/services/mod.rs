@@ -2,8 +2,22 @@
 // Services Module
 // ============================================
 
+pub mod avatars;
+pub mod batch_import;
+pub mod cards;
 pub mod embeddings;
+pub mod examples;
+pub mod jobs;
+pub mod lint;
+pub mod lorebook;
+pub mod macros;
 pub mod memory;
+pub mod refinement;
+pub mod retrieval;
+pub mod revisions;
+pub mod tags;
+pub mod vector_index;
+pub mod world_info;
 
 use crate::database::Database;
 use crate::entities::*;
@@ -11,10 +25,22 @@ use crate::repositories::*;
 use crate::error::{AppError, AppResult};
 use crate::setup::paths::AppPaths;
 use crate::state::AppState;
+use rand::Rng;
 use rusqlite::params;
 
+pub use cards::CardService;
+pub use avatars::AvatarService;
+pub use batch_import::BatchImportService;
 pub use embeddings::EmbeddingService;
+pub use jobs::JobService;
+pub use lint::CharacterLintService;
+pub use lorebook::LorebookService;
 pub use memory::{MemoryService as LongTermMemoryService, MemoryEntry, SummaryService, ConversationSummary};
+pub use refinement::RefinementService;
+pub use retrieval::RetrievalService;
+pub use revisions::CharacterRevisionService;
+pub use tags::TagService;
+pub use world_info::WorldInfoService;
 
 // ============================================
 // Character Service
@@ -35,12 +61,15 @@ impl CharacterService {
             return Err(AppError::Validation("Description is too long".to_string()));
         }
         
+        let source = input.source.unwrap_or_default();
         let sanitized_input = CreateCharacterInput {
             name: name.to_string(),
             ..input
         };
         
-        CharacterRepo::create(db, &sanitized_input)
+        let character = CharacterRepo::create(db, &sanitized_input)?;
+        CharacterRevisionService::record(db, &character, source)?;
+        Ok(character)
     }
     
     pub fn get(db: &Database, id: &str) -> AppResult<Character> {
@@ -51,13 +80,30 @@ impl CharacterService {
         CharacterRepo::find_all(db)
     }
     
+    /// Search, filter, sort and page the character library
+    pub fn query(db: &Database, query: CharacterQuery) -> AppResult<CharacterPage> {
+        const DEFAULT_PAGE_SIZE: u32 = 50;
+        const MAX_PAGE_SIZE: u32 = 200;
+        
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let fts_query = query.search.as_deref().and_then(retrieval::build_fts_prefix_query);
+        CharacterRepo::query(db, &query, fts_query.as_deref(), limit)
+    }
+    
     pub fn update(db: &Database, id: &str, input: UpdateCharacterInput) -> AppResult<Character> {
         if let Some(ref name) = input.name {
             if name.trim().is_empty() {
                 return Err(AppError::Validation("Name cannot be empty".to_string()));
             }
         }
-        CharacterRepo::update(db, id, &input)
+        
+        // Make sure the version being replaced is on record
+        let before = CharacterRepo::find_by_id(db, id)?;
+        CharacterRevisionService::current(db, &before)?;
+        
+        let character = CharacterRepo::update(db, id, &input)?;
+        CharacterRevisionService::record(db, &character, input.source.unwrap_or_default())?;
+        Ok(character)
     }
     
     pub fn delete(db: &Database, id: &str) -> AppResult<()> {
@@ -70,39 +116,8 @@ impl CharacterService {
             return Err(AppError::Import("Character card data too large".to_string()));
         }
         
-        // Try V2
-        if let Ok(card) = serde_json::from_str::<CharacterCardV2>(json_data) {
-            let input = CreateCharacterInput {
-                name: card.data.name.trim().to_string(),
-                description: if card.data.scenario.is_empty() { card.data.description } else { format!("{}\n\nScenario: {}", card.data.description, card.data.scenario) },
-                personality: card.data.personality,
-                system_prompt: card.data.system_prompt,
-                first_message: card.data.first_mes,
-                example_dialogues: card.data.mes_example,
-                avatar_path,
-                tags: card.data.tags.into_iter().take(20).collect(),
-                ..Default::default()
-            };
-            return CharacterRepo::create(db, &input);
-        }
-        
-        // Try V1
-        if let Ok(card) = serde_json::from_str::<CharacterCardV1>(json_data) {
-            let input = CreateCharacterInput {
-                name: card.name.trim().to_string(),
-                description: if card.scenario.is_empty() { card.description } else { format!("{}\n\nScenario: {}", card.description, card.scenario) },
-                personality: card.personality,
-                system_prompt: String::new(),
-                first_message: card.first_mes,
-                example_dialogues: card.mes_example,
-                avatar_path,
-                tags: vec![],
-                ..Default::default()
-            };
-            return CharacterRepo::create(db, &input);
-        }
-        
-        Err(AppError::Import("Invalid character card format".to_string()))
+        let parsed = cards::parse_card(json_data)?;
+        CardService::create(db, parsed, avatar_path)
     }
 }
 
@@ -165,6 +180,39 @@ impl PersonaService {
 // Conversation Service
 // ============================================
 
+/// A character's non-empty greetings with their greeting index:
+/// 0 is `first_message`, then `alternate_greetings` in order
+fn greetings(character: &Character) -> Vec<(usize, String)> {
+    std::iter::once(&character.first_message)
+        .chain(&character.alternate_greetings)
+        .enumerate()
+        .filter(|(_, g)| !g.trim().is_empty())
+        .map(|(i, g)| (i, g.clone()))
+        .collect()
+}
+
+/// Position in `greetings` of the one a new chat opens on: the requested
+/// greeting index, a random pick, or the first
+fn pick_greeting(
+    greetings: &[(usize, String)],
+    greeting_index: Option<usize>,
+    random: bool,
+    rng: &mut impl Rng,
+) -> AppResult<Option<usize>> {
+    if random {
+        if greeting_index.is_some() {
+            return Err(AppError::Validation("Choose a greeting index or a random greeting, not both".to_string()));
+        }
+        return Ok((!greetings.is_empty()).then(|| rng.gen_range(0..greetings.len())));
+    }
+    match greeting_index {
+        Some(index) => greetings.iter().position(|(i, _)| *i == index)
+            .map(Some)
+            .ok_or_else(|| AppError::Validation(format!("Character has no greeting {}", index))),
+        None => Ok((!greetings.is_empty()).then_some(0)),
+    }
+}
+
 pub struct ConversationService;
 
 impl ConversationService {
@@ -180,13 +228,16 @@ impl ConversationService {
         }
         
         // Resolve persona
-        let persona_id = match input.persona_id {
-            Some(ref id) => {
-                PersonaRepo::find_by_id(db, id)?;
-                Some(id.clone())
-            }
-            None => PersonaRepo::find_default(db)?.map(|p| p.id),
+        let persona = match input.persona_id {
+            Some(ref id) => Some(PersonaRepo::find_by_id(db, id)?),
+            None => PersonaRepo::find_default(db)?,
         };
+        let persona_id = persona.as_ref().map(|p| p.id.clone());
+        let user_name = persona.map(|p| p.name).unwrap_or_else(|| "User".to_string());
+        
+        // Every greeting becomes a root message; the chosen one is active
+        let greetings = greetings(&characters[0]);
+        let active_greeting = pick_greeting(&greetings, input.greeting_index, input.random_greeting, &mut rand::thread_rng())?;
         
         // Determine title
         let title = input.title.clone().unwrap_or_else(|| {
@@ -219,22 +270,35 @@ impl ConversationService {
                 ).map_err(AppError::Database)?;
             }
             
-            // 3. Create First Message (if applicable)
+            // 3. Create Greetings as sibling roots (if applicable)
             let first_char = &characters[0];
             let mut active_message_id: Option<String> = None;
+            let mut variables = serde_json::Map::new();
             
-            if !first_char.first_message.is_empty() {
+            for (branch_index, (greeting_index, greeting)) in greetings.iter().enumerate() {
                 let msg_id = new_id();
-                let token_count = estimate_tokens(&first_char.first_message);
+                let is_active = active_greeting == Some(branch_index);
+                
+                // Macros are written into the greeting once, seeded by its message id
+                let context = macros::MacroContext::seeded(&id, &msg_id, &first_char.name, &user_name);
+                let mut engine = macros::MacroEngine::new(context, serde_json::Map::new());
+                let greeting = engine.expand(greeting);
+                let token_count = estimate_tokens(&greeting);
+                if is_active {
+                    variables = engine.variables().clone();
+                }
+                let metadata = serde_json::json!({ "greetingIndex": greeting_index }).to_string();
                 
                 conn.execute(
                     "INSERT INTO messages (id, conversation_id, parent_id, author_type, author_id, content,
                      is_active_branch, branch_index, token_count, created_at, metadata)
-                     VALUES (?1, ?2, NULL, 'character', ?3, ?4, 1, 0, ?5, ?6, '{}')",
-                    rusqlite::params![msg_id, id, first_char.id, first_char.first_message, token_count, now],
+                     VALUES (?1, ?2, NULL, 'character', ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    rusqlite::params![msg_id, id, first_char.id, greeting, is_active, branch_index as i32, token_count, now, metadata],
                 ).map_err(AppError::Database)?;
                 
-                active_message_id = Some(msg_id);
+                if is_active {
+                    active_message_id = Some(msg_id);
+                }
             }
             
             // 4. Update Active Message
@@ -245,6 +309,18 @@ impl ConversationService {
                 ).map_err(AppError::Database)?;
             }
             
+            // 5. Keep variables the active greeting set
+            let metadata = if variables.is_empty() {
+                serde_json::Value::Object(Default::default())
+            } else {
+                let metadata = serde_json::json!({ "variables": variables });
+                conn.execute(
+                    "UPDATE conversations SET metadata = ?1 WHERE id = ?2",
+                    rusqlite::params![metadata.to_string(), id],
+                ).map_err(AppError::Database)?;
+                metadata
+            };
+            
             Ok(Conversation {
                 id,
                 title,
@@ -254,7 +330,7 @@ impl ConversationService {
                 created_at: now,
                 updated_at: now,
                 deleted_at: None,
-                metadata: serde_json::Value::Object(Default::default()),
+                metadata,
                 characters,
                 lorebook_ids: vec![],
             })
@@ -318,6 +394,15 @@ impl ConversationService {
 // Message Service
 // ============================================
 
+/// The sibling to show once `deleted_id` is gone: the next branch, or
+/// the one before it when it was the last. `siblings` are in branch order.
+fn replacement_branch<'a>(siblings: &'a [Message], deleted_id: &str) -> Option<&'a Message> {
+    let Some(position) = siblings.iter().position(|s| s.id == deleted_id) else {
+        return siblings.first();
+    };
+    siblings.get(position + 1).or_else(|| position.checked_sub(1).and_then(|p| siblings.get(p)))
+}
+
 pub struct MessageService;
 
 impl MessageService {
@@ -474,13 +559,16 @@ impl MessageService {
         let message = MessageRepo::find_by_id(db, message_id)?;
         
         if message.is_active_branch {
-            if let Some(ref parent_id) = message.parent_id {
-                let siblings = MessageRepo::find_siblings(db, message_id)?;
-                if let Some(alt) = siblings.iter().find(|s| s.id != message_id) {
+            // Alternate greetings are sibling roots, so roots switch the same way
+            let siblings = MessageRepo::find_siblings(db, message_id)?;
+            match (replacement_branch(&siblings, message_id), &message.parent_id) {
+                (Some(alt), _) => {
                     MessageRepo::switch_to_branch(db, &alt.id)?;
-                } else {
+                }
+                (None, Some(parent_id)) => {
                     ConversationRepo::update_active_message(db, &message.conversation_id, parent_id)?;
                 }
+                (None, None) => {}
             }
         }
         
@@ -496,148 +584,6 @@ impl MessageService {
     }
 }
 
-// ============================================
-// Lorebook Service
-// ============================================
-
-/// Helper function for whole-word matching (word boundaries)
-fn match_whole_word(text: &str, keyword: &str) -> bool {
-    let keyword_chars: Vec<char> = keyword.chars().collect();
-    let text_chars: Vec<char> = text.chars().collect();
-    
-    if keyword_chars.is_empty() {
-        return false;
-    }
-    
-    let mut i = 0;
-    while i <= text_chars.len().saturating_sub(keyword_chars.len()) {
-        // Check if we have a match at position i
-        let matches = text_chars[i..].iter()
-            .take(keyword_chars.len())
-            .zip(keyword_chars.iter())
-            .all(|(a, b)| a == b);
-        
-        if matches {
-            // Check word boundaries
-            let before_ok = if i == 0 {
-                true
-            } else {
-                !text_chars[i - 1].is_alphanumeric()
-            };
-            
-            let after_ok = if i + keyword_chars.len() >= text_chars.len() {
-                true
-            } else {
-                !text_chars[i + keyword_chars.len()].is_alphanumeric()
-            };
-            
-            if before_ok && after_ok {
-                return true;
-            }
-        }
-        i += 1;
-    }
-    false
-}
-
-pub struct LorebookService;
-
-impl LorebookService {
-    pub fn create(db: &Database, input: CreateLorebookInput) -> AppResult<Lorebook> {
-        let name = input.name.trim();
-        if name.is_empty() { return Err(AppError::Validation("Name required".to_string())); }
-        
-        let sanitized = CreateLorebookInput { name: name.to_string(), ..input };
-        LorebookRepo::create(db, &sanitized)
-    }
-    
-    pub fn get(db: &Database, id: &str) -> AppResult<Lorebook> {
-        LorebookRepo::find_by_id(db, id)
-    }
-    
-    pub fn list(db: &Database) -> AppResult<Vec<Lorebook>> {
-        LorebookRepo::find_all(db)
-    }
-    
-    pub fn update(db: &Database, id: &str, input: UpdateLorebookInput) -> AppResult<Lorebook> {
-        LorebookRepo::update(db, id, &input)
-    }
-    
-    pub fn delete(db: &Database, id: &str) -> AppResult<()> {
-        LorebookRepo::find_by_id(db, id)?;
-        LorebookRepo::delete(db, id)
-    }
-    
-    pub fn create_entry(db: &Database, input: CreateEntryInput) -> AppResult<LorebookEntry> {
-        if input.keywords.is_empty() { return Err(AppError::Validation("Keyword required".to_string())); }
-        if input.content.trim().is_empty() { return Err(AppError::Validation("Content required".to_string())); }
-        
-        LorebookRepo::find_by_id(db, &input.lorebook_id)?;
-        LorebookRepo::create_entry(db, &input)
-    }
-    
-    pub fn update_entry(db: &Database, id: &str, input: UpdateEntryInput) -> AppResult<LorebookEntry> {
-        LorebookRepo::update_entry(db, id, &input)
-    }
-    
-    pub fn delete_entry(db: &Database, id: &str) -> AppResult<()> {
-        LorebookRepo::delete_entry(db, id)
-    }
-    
-    pub fn attach_to_conversation(db: &Database, conv_id: &str, lb_id: &str) -> AppResult<()> {
-        ConversationRepo::find_by_id(db, conv_id)?;
-        LorebookRepo::find_by_id(db, lb_id)?;
-        ConversationRepo::attach_lorebook(db, conv_id, lb_id)
-    }
-    
-    pub fn detach_from_conversation(db: &Database, conv_id: &str, lb_id: &str) -> AppResult<()> {
-        ConversationRepo::detach_lorebook(db, conv_id, lb_id)
-    }
-    
-    pub fn find_matching_entries(db: &Database, conv_id: &str, text: &str) -> AppResult<Vec<LorebookEntry>> {
-        let conversation = ConversationRepo::find_by_id(db, conv_id)?;
-        let mut all_entries = Vec::new();
-        
-        let global = LorebookRepo::find_global(db)?;
-        for lb in global { all_entries.extend(lb.entries); }
-        
-        for lb_id in &conversation.lorebook_ids {
-            if let Ok(lb) = LorebookRepo::find_by_id(db, lb_id) {
-                if lb.is_enabled { all_entries.extend(lb.entries); }
-            }
-        }
-        
-        let text_lower = text.to_lowercase();
-        let matched: Vec<LorebookEntry> = all_entries.into_iter().filter(|entry| {
-            if !entry.is_enabled { return false; }
-            entry.keywords.iter().any(|kw| {
-                let k = if entry.case_sensitive { kw.clone() } else { kw.to_lowercase() };
-                let t = if entry.case_sensitive { text.to_string() } else { text_lower.clone() };
-                
-                if entry.match_whole_word {
-                    // Use word boundary matching
-                    match_whole_word(&t, &k)
-                } else {
-                    // Simple substring match
-                    t.contains(&k)
-                }
-            })
-        }).collect();
-        
-        // Deduplicate and sort
-        let mut seen = std::collections::HashSet::new();
-        let mut unique = Vec::new();
-        for m in matched {
-            if seen.insert(m.id.clone()) {
-                unique.push(m);
-            }
-        }
-        unique.sort_by(|a, b| b.priority.cmp(&a.priority));
-        
-        Ok(unique)
-    }
-}
-
 // ============================================
 // Settings Service
 // ============================================
@@ -662,16 +608,6 @@ impl SettingsService {
 // Memory Service
 // ============================================
 
-/// Replace {{user}} and {{char}} placeholders with actual names
-fn replace_placeholders(text: &str, char_name: &str, user_name: &str) -> String {
-    text.replace("{{char}}", char_name)
-        .replace("{{user}}", user_name)
-        .replace("<char>", char_name)
-        .replace("<user>", user_name)
-        .replace("{{Char}}", char_name)
-        .replace("{{User}}", user_name)
-}
-
 pub struct MemoryService;
 
 impl MemoryService {
@@ -704,9 +640,13 @@ impl MemoryService {
         let summary_budget = settings.generation.summary_budget.unwrap_or(300);
         let response_reserve = settings.generation.response_reserve.unwrap_or(512);
         
-        // Get user name early for placeholder replacement
+        // Get user name early for macro expansion
         let user_name = persona.as_ref().map(|p| p.name.clone()).unwrap_or("User".to_string());
         
+        // Card text is expanded piece by piece; the trained instruction
+        // below keeps its literal {{user}}
+        let mut macros = macros::MacroEngine::for_conversation(&conversation, &character.name, &user_name, &messages);
+        
         // ============================================================
         // BUILD SYSTEM PROMPT MATCHING GRPO MODEL'S TRAINED FORMAT
         // The model expects: Character, Tags, Personality, Scenario, Instruction
@@ -749,7 +689,7 @@ impl MemoryService {
         let mut personality_parts = Vec::new();
         
         if !character.personality.is_empty() {
-            let personality = replace_placeholders(&character.personality, &character.name, &user_name);
+            let personality = macros.expand(&character.personality);
             personality_parts.push(personality);
         }
         
@@ -769,12 +709,12 @@ impl MemoryService {
         }
         
         if !character.description.is_empty() {
-            let description = replace_placeholders(&character.description, &character.name, &user_name);
+            let description = macros.expand(&character.description);
             personality_parts.push(description);
         }
         
         if !character.backstory.is_empty() {
-            let backstory = replace_placeholders(&character.backstory, &character.name, &user_name);
+            let backstory = macros.expand(&character.backstory);
             personality_parts.push(format!("Background: {}", backstory));
         }
         
@@ -786,18 +726,18 @@ impl MemoryService {
         let mut scenario_parts = Vec::new();
         
         if !character.scenario.is_empty() {
-            let scenario = replace_placeholders(&character.scenario, &character.name, &user_name);
+            let scenario = macros.expand(&character.scenario);
             scenario_parts.push(format!("- **Setting:** {}", scenario));
         }
         
         if !character.system_prompt.is_empty() {
-            let custom_prompt = replace_placeholders(&character.system_prompt, &character.name, &user_name);
+            let custom_prompt = macros.expand(&character.system_prompt);
             scenario_parts.push(format!("- {}", custom_prompt));
         }
         
         if let Some(p) = &persona {
             if !p.description.is_empty() {
-                scenario_parts.push(format!("- {{{{user}}}} ({}) is: {}", p.name, p.description));
+                scenario_parts.push(format!("- {{{{user}}}} ({}) is: {}", p.name, macros.expand(&p.description)));
             }
         }
         
@@ -806,9 +746,16 @@ impl MemoryService {
         }
         
         // ====== MEMORIES ======
-        let memories = LongTermMemoryService::retrieve_relevant_sync(
+        // Lexical-only here; the async builder adds the vector side
+        let query = messages.iter().rev().take(5)
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        
+        let memories = RetrievalService::search_memories(
             db,
             &character.id,
+            &query,
             None,
             15,
             0.4,
@@ -842,33 +789,27 @@ impl MemoryService {
         }
         
         // ====== Lorebook ======
-        let recent_text = messages.iter().rev().take(10).map(|m| m.content.as_str()).collect::<Vec<_>>().join(" ");
-        let lore_entries = LorebookService::find_matching_entries(db, conv_id, &recent_text)?;
-        
-        let mut used_lore_tokens = 0;
-        let mut before_sys = Vec::new();
-        let mut after_sys = Vec::new();
-        
-        for entry in lore_entries {
-            let tokens = estimate_tokens(&entry.content);
-            if used_lore_tokens + tokens > lorebook_budget { break; }
-            
-            if entry.insertion_position == "before_system" {
-                before_sys.push(entry.content);
-            } else {
-                after_sys.push(entry.content);
-            }
-            used_lore_tokens += tokens;
-        }
+        let recursion_depth = settings.generation.lorebook_recursion_depth.unwrap_or(3).max(0) as usize;
+        let lore = LorebookService::select(
+            db, conv_id, &messages, None, recursion_depth, lorebook_budget, |text| macros.expand(text),
+        )?;
         
-        if !after_sys.is_empty() {
-            system_parts.push(format!("World information:\n{}", after_sys.join("\n")));
+        if !lore.after_system.is_empty() {
+            system_parts.push(format!("World information:\n{}", lore.after_system.join("\n")));
         }
         
         // ====== Example Dialogue ======
-        if !character.example_dialogues.is_empty() {
-            let examples = replace_placeholders(&character.example_dialogues, &character.name, &user_name);
-            system_parts.push(format!("Example of {}'s writing style:\n{}", character.name, examples));
+        // Whole <START> blocks within the example budget
+        let mut example_blocks = Vec::new();
+        match examples::place(&character.example_dialogues, &settings.generation, &messages) {
+            examples::ExamplePlacement::SystemPrompt(text) => {
+                let text = macros.expand(&text);
+                system_parts.push(format!("Example of {}'s writing style:\n{}", character.name, text));
+            }
+            examples::ExamplePlacement::Messages(blocks) => {
+                example_blocks = examples::to_messages(blocks, conv_id, |text| macros.expand(text));
+            }
+            examples::ExamplePlacement::None => {}
         }
         
         // ====== FINAL INSTRUCTION (matches model training) ======
@@ -880,12 +821,16 @@ impl MemoryService {
 
         // Assemble final system prompt
         let mut final_parts = Vec::new();
-        final_parts.extend(before_sys);
+        final_parts.extend(lore.before_system);
         final_parts.extend(system_parts);
         
         let final_system = final_parts.join("\n\n");
         let sys_tokens = estimate_tokens(&final_system);
         
+        if let Err(e) = macros.save_variables(db, &conversation) {
+            tracing::warn!("Failed to save macro variables for {}: {}", conv_id, e);
+        }
+        
         // ====== Tier 5: Conversation History ======
         let available = max_tokens - sys_tokens - response_reserve;
         let mut history = Vec::new();
@@ -899,12 +844,19 @@ impl MemoryService {
         }
         history.reverse();
         
+        // Injected examples only get the room the real history left
+        let next = history.first().map(|m| m.author_type);
+        let example_messages = examples::fit_messages(example_blocks, available - history_tokens, next);
+        history_tokens += example_messages.iter().map(|m| m.token_count).sum::<i32>();
+        history.splice(0..0, example_messages);
+        
         Ok(ContextResult {
             system_prompt: final_system,
             messages: history,
             character_name: character.name.clone(),
             persona_name: user_name.clone(),
             total_tokens: sys_tokens + history_tokens,
+            lore_trace: lore.trace,
         })
     }
 }
@@ -916,6 +868,8 @@ pub struct ContextResult {
     pub character_name: String,
     pub persona_name: String,
     pub total_tokens: i32,
+    /// How lorebook entries were picked for this prompt
+    pub lore_trace: Vec<LoreTraceEntry>,
 }
 
 impl MemoryService {
@@ -932,7 +886,7 @@ impl MemoryService {
     /// 7. RESPONSE REQUIREMENTS (reinforced at end)
     pub async fn build_context_async(
         db: &Database,
-        sidecar: &crate::sidecar::SidecarHandle,
+        embedder: &crate::sidecar::SidecarHandle,
         conv_id: &str,
         max_tokens: i32,
     ) -> AppResult<ContextResult> {
@@ -953,9 +907,13 @@ impl MemoryService {
         let summary_budget = settings.generation.summary_budget.unwrap_or(300);
         let response_reserve = settings.generation.response_reserve.unwrap_or(512);
         
-        // Get user name early for placeholder replacement
+        // Get user name early for macro expansion
         let user_name = persona.as_ref().map(|p| p.name.clone()).unwrap_or("User".to_string());
         
+        // Card text is expanded piece by piece; the trained instruction
+        // below keeps its literal {{user}}
+        let mut macros = macros::MacroEngine::for_conversation(&conversation, &character.name, &user_name, &messages);
+        
         // ============================================================
         // BUILD SYSTEM PROMPT MATCHING GRPO MODEL'S TRAINED FORMAT
         // The model expects: Character, Tags, Personality, Scenario, Instruction
@@ -1001,7 +959,7 @@ impl MemoryService {
         let mut personality_parts = Vec::new();
         
         if !character.personality.is_empty() {
-            let personality = replace_placeholders(&character.personality, &character.name, &user_name);
+            let personality = macros.expand(&character.personality);
             personality_parts.push(personality);
         }
         
@@ -1021,12 +979,12 @@ impl MemoryService {
         }
         
         if !character.description.is_empty() {
-            let description = replace_placeholders(&character.description, &character.name, &user_name);
+            let description = macros.expand(&character.description);
             personality_parts.push(description);
         }
         
         if !character.backstory.is_empty() {
-            let backstory = replace_placeholders(&character.backstory, &character.name, &user_name);
+            let backstory = macros.expand(&character.backstory);
             personality_parts.push(format!("Background: {}", backstory));
         }
         
@@ -1039,20 +997,20 @@ impl MemoryService {
         let mut scenario_parts = Vec::new();
         
         if !character.scenario.is_empty() {
-            let scenario = replace_placeholders(&character.scenario, &character.name, &user_name);
+            let scenario = macros.expand(&character.scenario);
             scenario_parts.push(format!("- **Setting:** {}", scenario));
         }
         
         // Add character's custom system prompt as additional context
         if !character.system_prompt.is_empty() {
-            let custom_prompt = replace_placeholders(&character.system_prompt, &character.name, &user_name);
+            let custom_prompt = macros.expand(&character.system_prompt);
             scenario_parts.push(format!("- {}", custom_prompt));
         }
         
         // Add user context
         if let Some(p) = &persona {
             if !p.description.is_empty() {
-                scenario_parts.push(format!("- {{{{user}}}} ({}) is: {}", p.name, p.description));
+                scenario_parts.push(format!("- {{{{user}}}} ({}) is: {}", p.name, macros.expand(&p.description)));
             }
         }
         
@@ -1068,15 +1026,17 @@ impl MemoryService {
             .join(" ");
         
         let query_embedding = if !query.is_empty() {
-            crate::sidecar::generate_embedding(sidecar, &query).await.ok()
+            EmbeddingService::generate(embedder, &query).await.ok()
         } else {
             None
         };
         
-        let memories = LongTermMemoryService::retrieve_relevant_sync(
+        // Hybrid search: BM25 keeps exact names/facts findable even without embeddings
+        let memories = RetrievalService::search_memories(
             db,
             &character.id,
-            query_embedding.as_deref(),
+            &query,
+            query_embedding.as_ref(),
             15,
             0.4,
         ).unwrap_or_default();
@@ -1109,33 +1069,28 @@ impl MemoryService {
         }
         
         // ====== Lorebook ======
-        let recent_text = messages.iter().rev().take(10).map(|m| m.content.as_str()).collect::<Vec<_>>().join(" ");
-        let lore_entries = LorebookService::find_matching_entries(db, conv_id, &recent_text)?;
-        
-        let mut used_lore_tokens = 0;
-        let mut before_sys = Vec::new();
-        let mut after_sys = Vec::new();
-        
-        for entry in lore_entries {
-            let tokens = estimate_tokens(&entry.content);
-            if used_lore_tokens + tokens > lorebook_budget { break; }
-            
-            if entry.insertion_position == "before_system" {
-                before_sys.push(entry.content);
-            } else {
-                after_sys.push(entry.content);
-            }
-            used_lore_tokens += tokens;
-        }
+        let recursion_depth = settings.generation.lorebook_recursion_depth.unwrap_or(3).max(0) as usize;
+        let lore = LorebookService::select(
+            db, conv_id, &messages, query_embedding.as_ref(), recursion_depth, lorebook_budget,
+            |text| macros.expand(text),
+        )?;
         
-        if !after_sys.is_empty() {
-            system_parts.push(format!("World information:\n{}", after_sys.join("\n")));
+        if !lore.after_system.is_empty() {
+            system_parts.push(format!("World information:\n{}", lore.after_system.join("\n")));
         }
         
         // ====== Example Dialogue ======
-        if !character.example_dialogues.is_empty() {
-            let examples = replace_placeholders(&character.example_dialogues, &character.name, &user_name);
-            system_parts.push(format!("Example of {}'s writing style:\n{}", character.name, examples));
+        // Whole <START> blocks within the example budget
+        let mut example_blocks = Vec::new();
+        match examples::place(&character.example_dialogues, &settings.generation, &messages) {
+            examples::ExamplePlacement::SystemPrompt(text) => {
+                let text = macros.expand(&text);
+                system_parts.push(format!("Example of {}'s writing style:\n{}", character.name, text));
+            }
+            examples::ExamplePlacement::Messages(blocks) => {
+                example_blocks = examples::to_messages(blocks, conv_id, |text| macros.expand(text));
+            }
+            examples::ExamplePlacement::None => {}
         }
         
         // ====== FINAL INSTRUCTION ======
@@ -1148,12 +1103,16 @@ impl MemoryService {
 
         // Assemble final system prompt (preserving lorebook position logic)
         let mut final_parts = Vec::new();
-        final_parts.extend(before_sys);  // Lorebook entries that go before system
+        final_parts.extend(lore.before_system);  // Lorebook entries that go before system
         final_parts.extend(system_parts);
         
         let final_system = final_parts.join("\n\n");
         let sys_tokens = estimate_tokens(&final_system);
         
+        if let Err(e) = macros.save_variables(db, &conversation) {
+            tracing::warn!("Failed to save macro variables for {}: {}", conv_id, e);
+        }
+        
         // Debug log the full prompt for inspection
         tracing::debug!("=== FULL SYSTEM PROMPT ({} tokens) ===\n{}\n=== END SYSTEM PROMPT ===", 
             sys_tokens, final_system);
@@ -1171,6 +1130,12 @@ impl MemoryService {
         }
         history.reverse();
         
+        // Injected examples only get the room the real history left
+        let next = history.first().map(|m| m.author_type);
+        let example_messages = examples::fit_messages(example_blocks, available - history_tokens, next);
+        history_tokens += example_messages.iter().map(|m| m.token_count).sum::<i32>();
+        history.splice(0..0, example_messages);
+        
         tracing::info!("Context built: {} system tokens, {} history tokens, {} messages", 
             sys_tokens, history_tokens, history.len());
         
@@ -1180,6 +1145,7 @@ impl MemoryService {
             character_name: character.name.clone(),
             persona_name: user_name.clone(),
             total_tokens: sys_tokens + history_tokens,
+            lore_trace: lore.trace,
         })
     }
 }
@@ -1190,28 +1156,35 @@ impl MemoryService {
 
 pub struct ExportService;
 impl ExportService {
+    /// Raw bytes of a character's avatar, if it has one on disk
+    pub fn read_avatar(paths: &AppPaths, character: &Character) -> AppResult<Option<Vec<u8>>> {
+        let Some(ref path) = character.avatar_path else { return Ok(None) };
+        let full = paths.avatar_file_path(path);
+        
+        // SECURITY: Validate path is within avatars directory to prevent path traversal
+        let canonical = full.canonicalize().unwrap_or(full.clone());
+        let avatars_canonical = paths.avatars_dir.canonicalize().unwrap_or(paths.avatars_dir.clone());
+        
+        if !canonical.starts_with(&avatars_canonical) {
+            tracing::warn!("Blocked path traversal attempt in avatar export: {:?}", path);
+            Ok(None)
+        } else if full.exists() {
+            Ok(Some(std::fs::read(full)?))
+        } else { Ok(None) }
+    }
+    
     pub fn export_character(db: &Database, paths: &AppPaths, id: &str) -> AppResult<ExportedCharacter> {
         let character = CharacterRepo::find_by_id(db, id)?;
-        let avatar_base64 = if let Some(ref path) = character.avatar_path {
-            let full = paths.avatar_file_path(path);
-            
-            // SECURITY: Validate path is within avatars directory to prevent path traversal
-            let canonical = full.canonicalize().unwrap_or(full.clone());
-            let avatars_canonical = paths.avatars_dir.canonicalize().unwrap_or(paths.avatars_dir.clone());
-            
-            if !canonical.starts_with(&avatars_canonical) {
-                tracing::warn!("Blocked path traversal attempt in avatar export: {:?}", path);
-                None
-            } else if full.exists() {
-                let data = std::fs::read(full)?;
-                Some(format!("data:image/png;base64,{}", base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &data)))
-            } else { None }
-        } else { None };
+        let avatar_base64 = Self::read_avatar(paths, &character)?.map(|data| {
+            let mime = avatars::mime_type(&data).unwrap_or("application/octet-stream");
+            format!("data:{};base64,{}", mime, base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &data))
+        });
         
         Ok(ExportedCharacter {
             glee_export_version: "1.0".into(),
             export_type: "character".into(),
             exported_at: chrono::Utc::now().to_rfc3339(),
+            lorebooks: LorebookService::list_for_character(db, &character.id)?,
             character,
             avatar_base64
         })
@@ -1234,14 +1207,10 @@ impl ExportService {
     
     pub fn import_character(db: &Database, paths: &AppPaths, data: &str) -> AppResult<Character> {
         let exported: ExportedCharacter = serde_json::from_str(data)?;
-        let avatar_path = if let Some(b64) = &exported.avatar_base64 {
-            let id = new_id();
-            let fname = format!("{}.png", id);
-            let raw = b64.split(',').last().unwrap_or(b64);
-            let bytes = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, raw).map_err(|e| AppError::Import(e.to_string()))?;
-            std::fs::write(paths.avatar_file_path(&fname), bytes)?;
-            Some(fname)
-        } else { None };
+        let avatar_path = match &exported.avatar_base64 {
+            Some(b64) => Some(AvatarService::store(paths, &avatars::decode_base64(b64)?)?),
+            None => None,
+        };
         
         let input = CreateCharacterInput {
             name: exported.character.name,
@@ -1254,7 +1223,14 @@ impl ExportService {
             tags: exported.character.tags,
             ..Default::default()
         };
-        CharacterRepo::create(db, &input)
+        let character = CharacterRepo::create(db, &input)?;
+        CharacterRevisionService::record(db, &character, RevisionSource::Import)?;
+
+        for lorebook in &exported.lorebooks {
+            let restored = LorebookService::restore(db, lorebook)?;
+            CharacterRepo::attach_lorebook(db, &character.id, &restored.id)?;
+        }
+        Ok(character)
     }
     
     pub fn import_data(db: &Database, paths: &AppPaths, data: &str) -> AppResult<String> {
@@ -1422,4 +1398,76 @@ pub fn estimate_tokens(text: &str) -> i32 {
     }
 
     (tokens.ceil() as i32).max(1)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use serde_json::json;
+
+    use super::*;
+
+    fn aria(first_message: &str, alternates: &[&str]) -> Character {
+        test_character(json!({ "firstMessage": first_message, "alternateGreetings": alternates }))
+    }
+
+    fn root(id: &str, branch_index: i32) -> Message {
+        Message {
+            id: id.to_string(),
+            conversation_id: "conv".to_string(),
+            parent_id: None,
+            author_type: AuthorType::Character,
+            author_id: Some("c1".to_string()),
+            content: id.to_string(),
+            is_active_branch: branch_index == 0,
+            branch_index,
+            token_count: 1,
+            generation_params: None,
+            created_at: 0,
+            metadata: json!({}),
+            author_name: None,
+            sibling_count: None,
+        }
+    }
+
+    #[test]
+    fn test_greeting_index_maps_to_root() {
+        // Empty greetings get no root, so later indexes shift down
+        let greetings = greetings(&aria("Hello!", &["  ", "Evening.", "Morning."]));
+        assert_eq!(greetings.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0, 2, 3]);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(pick_greeting(&greetings, None, false, &mut rng).unwrap(), Some(0));
+        assert_eq!(pick_greeting(&greetings, Some(2), false, &mut rng).unwrap(), Some(1));
+        assert_eq!(pick_greeting(&greetings, Some(3), false, &mut rng).unwrap(), Some(2));
+
+        // No first message: the first alternate opens the chat
+        let alternates_only = super::greetings(&aria("", &["Evening."]));
+        assert_eq!(alternates_only, vec![(1, "Evening.".to_string())]);
+        assert_eq!(pick_greeting(&alternates_only, None, false, &mut rng).unwrap(), Some(0));
+        assert_eq!(pick_greeting(&[], None, true, &mut rng).unwrap(), None);
+    }
+
+    #[test]
+    fn test_greeting_choice_rejected() {
+        let greetings = greetings(&aria("Hello!", &["", "Morning."]));
+        let mut rng = StdRng::seed_from_u64(1);
+        // Out of range, and an index whose greeting is empty
+        assert!(matches!(pick_greeting(&greetings, Some(5), false, &mut rng), Err(AppError::Validation(_))));
+        assert!(matches!(pick_greeting(&greetings, Some(1), false, &mut rng), Err(AppError::Validation(_))));
+        assert!(matches!(pick_greeting(&greetings, Some(0), true, &mut rng), Err(AppError::Validation(_))));
+
+        let random = pick_greeting(&greetings, None, true, &mut rng).unwrap().unwrap();
+        assert!(random < greetings.len());
+    }
+
+    #[test]
+    fn test_deleted_root_switches_to_sibling_root() {
+        let roots = vec![root("first", 0), root("evening", 1), root("morning", 2)];
+        assert_eq!(replacement_branch(&roots, "first").map(|m| m.id.as_str()), Some("evening"));
+        assert_eq!(replacement_branch(&roots, "evening").map(|m| m.id.as_str()), Some("morning"));
+        assert_eq!(replacement_branch(&roots, "morning").map(|m| m.id.as_str()), Some("evening"));
+        assert!(replacement_branch(&roots[..1], "first").is_none());
+    }
+}
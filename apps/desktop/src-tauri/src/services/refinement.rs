@@ -0,0 +1,296 @@
+// ============================================
+// Character Refinement Service
+// Regenerates or rewrites one field of a character, with the rest of the
+// card as context. Results are proposals; nothing is saved here.
+// ============================================
+
+use crate::database::Database;
+use crate::entities::*;
+use crate::error::{AppError, AppResult};
+use crate::repositories::CharacterRepo;
+use crate::sidecar::SidecarHandle;
+
+/// Context fields longer than this are cut short in the prompt
+const MAX_CONTEXT_CHARS: usize = 1500;
+
+pub struct RefinementService;
+
+impl RefinementService {
+    /// Ask the model for a new version of `input.field`
+    pub async fn propose(
+        db: &Database,
+        sidecar: &SidecarHandle,
+        input: RefineCharacterFieldInput,
+    ) -> AppResult<CharacterFieldProposal> {
+        let character = CharacterRepo::find_by_id(db, &input.character_id)?;
+        let before = current_value(&character, input.field, input.greeting_index)?;
+        let instruction = input.instruction.as_deref().map(str::trim).filter(|i| !i.is_empty());
+
+        let prompt = build_prompt(&character, input.field, &before, instruction);
+        let messages = vec![serde_json::json!({
+            "role": "user",
+            "content": prompt
+        })];
+        // A fresh take can wander further than a directed rewrite
+        let temperature = if instruction.is_some() { 0.7 } else { 0.9 };
+        let text = crate::sidecar::generate_text_oneshot(sidecar, messages, temperature, max_tokens(input.field)).await?;
+
+        let after = clean_output(&text, input.field);
+        if after.is_empty() {
+            return Err(AppError::Llm("The model returned nothing usable".to_string()));
+        }
+        let update = to_update(&character, input.field, input.greeting_index, after.clone());
+        Ok(CharacterFieldProposal {
+            character_id: character.id,
+            field: input.field,
+            before,
+            after,
+            update,
+        })
+    }
+}
+
+fn label(field: RefinableField) -> &'static str {
+    match field {
+        RefinableField::Description => "description",
+        RefinableField::Personality => "personality",
+        RefinableField::Scenario => "scenario",
+        RefinableField::Backstory => "backstory",
+        RefinableField::PhysicalTraits => "physical traits",
+        RefinableField::SpeechPatterns => "speech patterns",
+        RefinableField::ExampleDialogues => "example dialogues",
+        RefinableField::FirstMessage => "first message",
+        RefinableField::AlternateGreeting => "alternate greeting",
+    }
+}
+
+/// What a good value for the field looks like
+fn guidance(field: RefinableField) -> &'static str {
+    match field {
+        RefinableField::Description => "A short paragraph on who {{char}} is.",
+        RefinableField::Personality => "{{char}}'s traits, temperament and habits.",
+        RefinableField::Scenario => "The setting and circumstances the roleplay starts in.",
+        RefinableField::Backstory => "{{char}}'s history and the events that shaped them.",
+        RefinableField::PhysicalTraits => "{{char}}'s appearance, clothing and body language.",
+        RefinableField::SpeechPatterns => "How {{char}} talks: tone, vocabulary, verbal tics and accent.",
+        RefinableField::ExampleDialogues => {
+            "Two or three short exchanges. Start each with a line containing only <START>, \
+             then alternate lines beginning with {{user}}: and {{char}}:."
+        }
+        RefinableField::FirstMessage | RefinableField::AlternateGreeting => {
+            "An in-character opening message that sets the scene and gives {{user}} something \
+             to respond to. Write only {{char}}'s words and actions, never {{user}}'s."
+        }
+    }
+}
+
+fn max_tokens(field: RefinableField) -> i32 {
+    match field {
+        RefinableField::ExampleDialogues => 1024,
+        RefinableField::FirstMessage | RefinableField::AlternateGreeting => 768,
+        _ => 512,
+    }
+}
+
+/// The field's value as it stands; empty for a new alternate greeting
+fn current_value(character: &Character, field: RefinableField, greeting_index: Option<usize>) -> AppResult<String> {
+    let value = match field {
+        RefinableField::Description => &character.description,
+        RefinableField::Personality => &character.personality,
+        RefinableField::Scenario => &character.scenario,
+        RefinableField::Backstory => &character.backstory,
+        RefinableField::PhysicalTraits => &character.physical_traits,
+        RefinableField::SpeechPatterns => &character.speech_patterns,
+        RefinableField::ExampleDialogues => &character.example_dialogues,
+        RefinableField::FirstMessage => &character.first_message,
+        RefinableField::AlternateGreeting => match greeting_index {
+            Some(i) => character.alternate_greetings.get(i)
+                .ok_or_else(|| AppError::Validation(format!("Character has no alternate greeting {}", i + 1)))?,
+            None => return Ok(String::new()),
+        },
+    };
+    Ok(value.clone())
+}
+
+fn clip(text: &str) -> String {
+    match text.char_indices().nth(MAX_CONTEXT_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// The card minus the field being written
+fn card_context(character: &Character, field: RefinableField) -> String {
+    let lists = |items: &[String]| items.join(", ");
+    let fields = [
+        (Some(RefinableField::Description), "Description", character.description.clone()),
+        (Some(RefinableField::Personality), "Personality", character.personality.clone()),
+        (Some(RefinableField::Scenario), "Scenario", character.scenario.clone()),
+        (Some(RefinableField::Backstory), "Backstory", character.backstory.clone()),
+        (Some(RefinableField::PhysicalTraits), "Physical traits", character.physical_traits.clone()),
+        (Some(RefinableField::SpeechPatterns), "Speech patterns", character.speech_patterns.clone()),
+        (None, "Likes", lists(&character.likes)),
+        (None, "Dislikes", lists(&character.dislikes)),
+        (None, "Tags", lists(&character.tags)),
+        (Some(RefinableField::FirstMessage), "First message", character.first_message.clone()),
+        (Some(RefinableField::ExampleDialogues), "Example dialogues", character.example_dialogues.clone()),
+    ];
+
+    let mut context = format!("Name: {}", character.name);
+    for (source, name, value) in fields {
+        if source == Some(field) || value.trim().is_empty() {
+            continue;
+        }
+        context.push_str(&format!("\n{}: {}", name, clip(value.trim())));
+    }
+    context
+}
+
+pub fn build_prompt(character: &Character, field: RefinableField, before: &str, instruction: Option<&str>) -> String {
+    let label = label(field);
+    let mut prompt = format!(
+        "You are helping write a roleplay character card. Refer to the character as {{{{char}}}} \
+         and to the person they talk with as {{{{user}}}}.\n\nCharacter card:\n{}\n",
+        card_context(character, field)
+    );
+    if !before.trim().is_empty() {
+        prompt.push_str(&format!("\nCurrent {}:\n{}\n", label, before.trim()));
+    }
+
+    let task = match (instruction, before.trim().is_empty()) {
+        (Some(instruction), false) => format!(
+            "Rewrite the {} following this instruction: {}\nKeep whatever the instruction doesn't ask to change.",
+            label, instruction
+        ),
+        (Some(instruction), true) => format!("Write the {} following this instruction: {}", label, instruction),
+        (None, _) if field == RefinableField::AlternateGreeting => format!(
+            "Write a new {} that opens a different scene from the first message.", label
+        ),
+        (None, _) => format!("Write a new {} for {}, consistent with the rest of the card.", label, character.name),
+    };
+    prompt.push_str(&format!(
+        "\n{}\n{}\n\nReply with the new {} only: no heading, commentary or surrounding quotes.",
+        task, guidance(field), label
+    ));
+    prompt
+}
+
+/// Strip what models like to wrap answers in: code fences, a heading
+/// line, quotes around the whole thing
+pub fn clean_output(text: &str, field: RefinableField) -> String {
+    let mut text = text.trim();
+    if let Some(inner) = text.strip_prefix("```") {
+        let inner = inner.split_once('\n').map(|(_, rest)| rest).unwrap_or("");
+        text = inner.trim_end().trim_end_matches("```").trim();
+    }
+
+    if let Some((first, rest)) = text.split_once('\n') {
+        if is_heading(first, field) {
+            text = rest.trim();
+        }
+    }
+
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') && !text[1..text.len() - 1].contains('"') {
+        text = text[1..text.len() - 1].trim();
+    }
+
+    if field == RefinableField::ExampleDialogues && !text.is_empty() && !text.contains("<START>") {
+        return format!("<START>\n{}", text);
+    }
+    text.to_string()
+}
+
+/// "Backstory:", "**New speech patterns:**" and the like: the field's
+/// label, nothing else
+fn is_heading(line: &str, field: RefinableField) -> bool {
+    let heading = line.trim().trim_matches(|c| c == '*' || c == '#').trim();
+    let Some(heading) = heading.strip_suffix(':') else { return false };
+    let heading = heading.trim_end_matches('*').trim().to_lowercase();
+    let heading = heading.strip_prefix("new ").or_else(|| heading.strip_prefix("updated ")).unwrap_or(&heading);
+    heading == label(field)
+}
+
+/// The change as an update, ready for `update_character`
+pub fn to_update(character: &Character, field: RefinableField, greeting_index: Option<usize>, value: String) -> UpdateCharacterInput {
+    let mut update = UpdateCharacterInput { source: Some(RevisionSource::Generated), ..Default::default() };
+    match field {
+        RefinableField::Description => update.description = Some(value),
+        RefinableField::Personality => update.personality = Some(value),
+        RefinableField::Scenario => update.scenario = Some(value),
+        RefinableField::Backstory => update.backstory = Some(value),
+        RefinableField::PhysicalTraits => update.physical_traits = Some(value),
+        RefinableField::SpeechPatterns => update.speech_patterns = Some(value),
+        RefinableField::ExampleDialogues => update.example_dialogues = Some(value),
+        RefinableField::FirstMessage => update.first_message = Some(value),
+        RefinableField::AlternateGreeting => {
+            let mut greetings = character.alternate_greetings.clone();
+            match greeting_index.filter(|&i| i < greetings.len()) {
+                Some(i) => greetings[i] = value,
+                None => greetings.push(value),
+            }
+            update.alternate_greetings = Some(greetings);
+        }
+    }
+    update
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn character() -> Character {
+        test_character(json!({
+            "firstMessage": "*Aria tunes her lute.* Hello!", "tags": ["fantasy"],
+            "backstory": "Raised in a tavern", "alternateGreetings": ["Hi there"],
+        }))
+    }
+
+    #[test]
+    fn test_prompt_leaves_out_target_field() {
+        let aria = character();
+        let prompt = build_prompt(&aria, RefinableField::Backstory, &aria.backstory, Some("make her more sarcastic"));
+        assert!(prompt.contains("Description: A bard"));
+        assert!(!prompt.contains("Backstory: Raised"));
+        assert!(prompt.contains("Current backstory:\nRaised in a tavern"));
+        assert!(prompt.contains("make her more sarcastic"));
+        assert!(prompt.contains("{{char}}"));
+    }
+
+    #[test]
+    fn test_clean_output() {
+        assert_eq!(clean_output("**Backstory:**\nShe ran away.", RefinableField::Backstory), "She ran away.");
+        assert_eq!(clean_output("```\nShe ran away.\n```", RefinableField::Backstory), "She ran away.");
+        assert_eq!(clean_output("\"Well, hello.\"", RefinableField::FirstMessage), "Well, hello.");
+        assert_eq!(
+            clean_output("\"Hi,\" she says. \"Sit.\"", RefinableField::FirstMessage),
+            "\"Hi,\" she says. \"Sit.\""
+        );
+        assert_eq!(clean_output("## New speech patterns:\nClipped.", RefinableField::SpeechPatterns), "Clipped.");
+        assert_eq!(clean_output("Updated Backstory:\nShe ran away.", RefinableField::Backstory), "She ran away.");
+        // Only the field's own label is a heading
+        assert_eq!(
+            clean_output("She said three things:\nGo. Stay. Wait.", RefinableField::Backstory),
+            "She said three things:\nGo. Stay. Wait."
+        );
+        assert_eq!(
+            clean_output("Personality:\nShe ran away.", RefinableField::Backstory),
+            "Personality:\nShe ran away."
+        );
+        assert_eq!(
+            clean_output("{{user}}: Hi\n{{char}}: Hey.", RefinableField::ExampleDialogues),
+            "<START>\n{{user}}: Hi\n{{char}}: Hey."
+        );
+    }
+
+    #[test]
+    fn test_greeting_updates() {
+        let aria = character();
+        let replaced = to_update(&aria, RefinableField::AlternateGreeting, Some(0), "Welcome".into());
+        assert_eq!(replaced.alternate_greetings, Some(vec!["Welcome".to_string()]));
+        let added = to_update(&aria, RefinableField::AlternateGreeting, None, "Welcome".into());
+        assert_eq!(added.alternate_greetings, Some(vec!["Hi there".to_string(), "Welcome".to_string()]));
+        assert_eq!(added.source, Some(RevisionSource::Generated));
+        assert!(added.description.is_none());
+    }
+}
@@ -0,0 +1,296 @@
+// ============================================
+// Hybrid Retrieval Service
+// Combines FTS5 (BM25) and vector search with reciprocal rank fusion
+// ============================================
+
+use std::collections::{HashMap, HashSet};
+
+use crate::database::Database;
+use crate::entities::LorebookEntry;
+use crate::error::AppResult;
+use crate::repositories::LorebookRepo;
+use crate::services::embeddings::{Embedding, EmbeddingService};
+use crate::services::memory::{MemoryEntry, MemoryService};
+
+/// Standard RRF damping constant (Cormack et al.)
+pub const RRF_K: f32 = 60.0;
+
+/// Max number of query terms passed to FTS5
+const MAX_QUERY_TERMS: usize = 32;
+
+/// Very common words that only add noise to an OR query
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "do", "for", "from", "had", "has",
+    "have", "he", "her", "his", "i", "if", "in", "is", "it", "its", "me", "my", "of", "on",
+    "or", "she", "so", "that", "the", "their", "them", "then", "there", "they", "this", "to",
+    "was", "we", "were", "what", "with", "you", "your",
+];
+
+/// Build a safe FTS5 MATCH expression from free text.
+/// Every term is quoted so user text can never be parsed as FTS syntax.
+/// Returns None if the text has no searchable terms.
+pub fn build_fts_query(text: &str) -> Option<String> {
+    let mut seen = HashSet::new();
+    let terms: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .map(|t| t.to_lowercase())
+        .filter(|t| t.chars().count() >= 2 && !STOPWORDS.contains(&t.as_str()))
+        .filter(|t| seen.insert(t.clone()))
+        .take(MAX_QUERY_TERMS)
+        .map(|t| format!("\"{}\"", t))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" OR "))
+    }
+}
+
+/// Build an FTS5 MATCH expression for search-as-you-type: every term must
+/// appear, each as a prefix ("ari bard" finds "Aria, the wandering bard").
+/// Returns None if the text has no searchable terms.
+pub fn build_fts_prefix_query(text: &str) -> Option<String> {
+    let mut seen = HashSet::new();
+    let terms: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .filter(|t| seen.insert(t.clone()))
+        .take(MAX_QUERY_TERMS)
+        .map(|t| format!("\"{}\"*", t))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Fuse several ranked id lists into one using reciprocal rank fusion.
+/// score(d) = sum over lists of 1 / (k + rank(d)), with rank starting at 1.
+pub fn reciprocal_rank_fusion(ranked_lists: &[Vec<String>], k: f32) -> Vec<(String, f32)> {
+    let mut scores: HashMap<String, f32> = HashMap::new();
+    let mut first_seen: HashMap<String, usize> = HashMap::new();
+    let mut order = 0;
+
+    for list in ranked_lists {
+        for (rank, id) in list.iter().enumerate() {
+            *scores.entry(id.clone()).or_insert(0.0) += 1.0 / (k + rank as f32 + 1.0);
+            first_seen.entry(id.clone()).or_insert_with(|| {
+                order += 1;
+                order
+            });
+        }
+    }
+
+    let mut fused: Vec<(String, f32)> = scores.into_iter().collect();
+    // Ties keep the order in which ids were first seen so results are stable
+    fused.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| first_seen[&a.0].cmp(&first_seen[&b.0]))
+    });
+    fused
+}
+
+pub struct RetrievalService;
+
+impl RetrievalService {
+    /// BM25-ranked memory ids for a character
+    pub fn lexical_memories(
+        db: &Database,
+        character_id: &str,
+        query: &str,
+        limit: usize,
+    ) -> AppResult<Vec<String>> {
+        let Some(fts_query) = build_fts_query(query) else {
+            return Ok(Vec::new());
+        };
+
+        db.query_all(
+            "SELECT memory_id FROM memory_entries_fts
+             WHERE memory_entries_fts MATCH ?1 AND character_id = ?2
+             ORDER BY bm25(memory_entries_fts)
+             LIMIT ?3",
+            rusqlite::params![fts_query, character_id, limit as i64],
+            |row| row.get(0),
+        )
+    }
+
+    /// Hybrid memory search: BM25 and vector rankings fused with RRF.
+    /// Works with lexical matches alone when no query embedding is available,
+    /// and falls back to importance ordering when neither side finds anything.
+    pub fn search_memories(
+        db: &Database,
+        character_id: &str,
+        query: &str,
+        query_embedding: Option<&Embedding>,
+        limit: usize,
+        min_similarity: f32,
+    ) -> AppResult<Vec<(MemoryEntry, f32)>> {
+        let candidates = limit * 3;
+        let lexical = Self::lexical_memories(db, character_id, query, candidates).unwrap_or_else(|e| {
+            tracing::warn!("Memory FTS query failed: {}", e);
+            Vec::new()
+        });
+
+        let semantic = match query_embedding {
+            Some(embedding) => EmbeddingService::find_similar(
+                db,
+                embedding,
+                "memory",
+                Some(character_id),
+                candidates,
+                min_similarity,
+            )?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect(),
+            None => Vec::new(),
+        };
+
+        if lexical.is_empty() && semantic.is_empty() {
+            let memories = MemoryService::get_for_character(db, character_id, limit)?;
+            return Ok(memories.into_iter().map(|m| (m, 0.0)).collect());
+        }
+
+        let mut results = Vec::new();
+        for (id, score) in reciprocal_rank_fusion(&[lexical, semantic], RRF_K) {
+            if let Ok(memory) = MemoryService::get_by_id(db, &id) {
+                results.push((memory, score));
+                if results.len() >= limit {
+                    break;
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// BM25-ranked lorebook entry ids restricted to the given lorebooks
+    pub fn lexical_lorebook_entries(
+        db: &Database,
+        lorebook_ids: &[String],
+        query: &str,
+        limit: usize,
+    ) -> AppResult<Vec<String>> {
+        if lorebook_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let Some(fts_query) = build_fts_query(query) else {
+            return Ok(Vec::new());
+        };
+
+        let placeholders = lorebook_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+        let sql = format!(
+            "SELECT entry_id FROM lorebook_entries_fts
+             WHERE lorebook_entries_fts MATCH ? AND lorebook_id IN ({})
+             ORDER BY bm25(lorebook_entries_fts)
+             LIMIT ?",
+            placeholders
+        );
+
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(fts_query)];
+        for id in lorebook_ids {
+            params.push(Box::new(id.clone()));
+        }
+        params.push(Box::new(limit as i64));
+
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        db.query_all(&sql, params_refs.as_slice(), |row| row.get(0))
+    }
+
+    /// Hybrid lorebook entry search over the given lorebooks.
+    /// Vector results only exist for entries that have been embedded.
+    pub fn search_lorebook_entries(
+        db: &Database,
+        lorebook_ids: &[String],
+        query: &str,
+        query_embedding: Option<&Embedding>,
+        limit: usize,
+        min_similarity: f32,
+    ) -> AppResult<Vec<(LorebookEntry, f32)>> {
+        let candidates = limit * 3;
+        let lexical = Self::lexical_lorebook_entries(db, lorebook_ids, query, candidates).unwrap_or_else(|e| {
+            tracing::warn!("Lorebook FTS query failed: {}", e);
+            Vec::new()
+        });
+
+        let semantic = match query_embedding {
+            Some(embedding) => {
+                // One index partition per lorebook; merge by similarity
+                let mut similar = Vec::new();
+                for lorebook_id in lorebook_ids {
+                    similar.extend(EmbeddingService::find_similar(
+                        db,
+                        embedding,
+                        "lorebook",
+                        Some(lorebook_id),
+                        candidates,
+                        min_similarity,
+                    )?);
+                }
+                similar.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+                similar.into_iter().take(candidates).map(|(id, _)| id).collect()
+            }
+            None => Vec::new(),
+        };
+
+        let mut results = Vec::new();
+        for (id, score) in reciprocal_rank_fusion(&[lexical, semantic], RRF_K) {
+            if let Ok(entry) = LorebookRepo::find_entry(db, &id) {
+                results.push((entry, score));
+                if results.len() >= limit {
+                    break;
+                }
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_fts_query_quotes_terms() {
+        let query = build_fts_query("Where is the \"dragon\" OR* sword?").unwrap();
+        assert_eq!(query, "\"where\" OR \"dragon\" OR \"sword\"");
+    }
+
+    #[test]
+    fn test_build_fts_query_empty() {
+        assert!(build_fts_query("  ... the a ?!").is_none());
+    }
+
+    #[test]
+    fn test_build_fts_prefix_query() {
+        assert_eq!(build_fts_prefix_query("Ari, the \"bard\"*").unwrap(), "\"ari\"* \"the\"* \"bard\"*");
+        assert!(build_fts_prefix_query(" -- ").is_none());
+    }
+
+    #[test]
+    fn test_rrf_rewards_agreement() {
+        let lexical = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let semantic = vec!["c".to_string(), "d".to_string()];
+        let fused = reciprocal_rank_fusion(&[lexical, semantic], RRF_K);
+
+        // "c" appears in both lists so it should outrank everything else
+        assert_eq!(fused[0].0, "c");
+        assert_eq!(fused.len(), 4);
+        // Ties between single-list items at the same rank keep first-seen order
+        assert_eq!(fused[1].0, "a");
+    }
+
+    #[test]
+    fn test_rrf_single_list_preserves_order() {
+        let lexical = vec!["x".to_string(), "y".to_string(), "z".to_string()];
+        let fused = reciprocal_rank_fusion(&[lexical, Vec::new()], RRF_K);
+        let ids: Vec<&str> = fused.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["x", "y", "z"]);
+    }
+}
@@ -0,0 +1,153 @@
+// ============================================
+// Character Revision Service
+// Snapshot history for characters: list, diff and roll back
+// ============================================
+
+use rusqlite::Connection;
+use serde_json::Value;
+
+use crate::database::Database;
+use crate::entities::*;
+use crate::error::AppResult;
+use crate::repositories::{CharacterRepo, CharacterRevisionRepo};
+
+/// Bookkeeping fields that change on every save and say nothing about the character
+const IGNORED_FIELDS: &[&str] = &["id", "isBundled", "createdAt", "updatedAt", "deletedAt", "metadata"];
+
+pub struct CharacterRevisionService;
+
+impl CharacterRevisionService {
+    /// Record the character as it is now, unless that is already its
+    /// latest revision
+    pub fn record(db: &Database, character: &Character, source: RevisionSource) -> AppResult<CharacterRevision> {
+        db.transaction(|conn| Self::record_with_conn(conn, character, source))
+    }
+
+    /// `record`, inside a caller's transaction
+    pub fn record_with_conn(conn: &Connection, character: &Character, source: RevisionSource) -> AppResult<CharacterRevision> {
+        if let Some(latest) = CharacterRevisionRepo::find_latest_with_conn(conn, &character.id)? {
+            if diff_characters(&latest.snapshot, character).is_empty() {
+                return Ok(latest);
+            }
+        }
+        CharacterRevisionRepo::create_with_conn(conn, character, source)
+    }
+
+    /// The revision matching the character as stored. Characters saved
+    /// before revisions existed get a baseline on first use.
+    pub fn current(db: &Database, character: &Character) -> AppResult<i32> {
+        Self::record(db, character, RevisionSource::Manual).map(|r| r.revision)
+    }
+
+    pub fn list(db: &Database, character_id: &str) -> AppResult<Vec<CharacterRevision>> {
+        CharacterRepo::find_by_id(db, character_id)?;
+        CharacterRevisionRepo::find_by_character(db, character_id)
+    }
+
+    /// Fields changed from revision `from` to revision `to`, or to the
+    /// character as it is now when `to` is None
+    pub fn diff(db: &Database, character_id: &str, from: i32, to: Option<i32>) -> AppResult<Vec<CharacterFieldChange>> {
+        let before = CharacterRevisionRepo::find(db, character_id, from)?.snapshot;
+        let after = match to {
+            Some(to) => CharacterRevisionRepo::find(db, character_id, to)?.snapshot,
+            None => CharacterRepo::find_by_id(db, character_id)?,
+        };
+        Ok(diff_characters(&before, &after))
+    }
+
+    /// Bring back an older revision. The restore is itself a new revision,
+    /// so it can be undone the same way.
+    pub fn restore(db: &Database, character_id: &str, revision: i32) -> AppResult<Character> {
+        let current = CharacterRepo::find_by_id(db, character_id)?;
+        Self::current(db, &current)?;
+
+        let snapshot = CharacterRevisionRepo::find(db, character_id, revision)?.snapshot;
+        let restored = CharacterRepo::restore(db, &snapshot)?;
+        Self::record(db, &restored, RevisionSource::Restore)?;
+        Ok(restored)
+    }
+}
+
+/// Field-by-field differences between two versions of a character.
+/// Metadata keys that mirror a character field are reported once under
+/// the field; the rest show up as `metadata.<key>`.
+pub fn diff_characters(before: &Character, after: &Character) -> Vec<CharacterFieldChange> {
+    let before = serde_json::to_value(before).unwrap_or_default();
+    let after = serde_json::to_value(after).unwrap_or_default();
+    let empty = serde_json::Map::new();
+    let fields = |v: &Value| v.as_object().cloned().unwrap_or_default();
+    let (before_fields, after_fields) = (fields(&before), fields(&after));
+
+    let mut changes: Vec<CharacterFieldChange> = after_fields.iter()
+        .filter(|(key, _)| !IGNORED_FIELDS.contains(&key.as_str()))
+        .filter(|(key, value)| before_fields.get(*key) != Some(*value))
+        .map(|(key, value)| CharacterFieldChange {
+            field: key.clone(),
+            before: before_fields.get(key).cloned().unwrap_or(Value::Null),
+            after: value.clone(),
+        })
+        .collect();
+
+    let before_meta = before["metadata"].as_object().unwrap_or(&empty);
+    let after_meta = after["metadata"].as_object().unwrap_or(&empty);
+    let mut keys: Vec<&String> = before_meta.keys().chain(after_meta.keys())
+        .filter(|key| !after_fields.contains_key(*key))
+        .collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let (old, new) = (before_meta.get(key), after_meta.get(key));
+        if old != new {
+            changes.push(CharacterFieldChange {
+                field: format!("metadata.{}", key),
+                before: old.cloned().unwrap_or(Value::Null),
+                after: new.cloned().unwrap_or(Value::Null),
+            });
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn character() -> Character {
+        test_character(json!({
+            "tags": ["fantasy"], "metadata": { "scenario": "A tavern" }, "scenario": "A tavern",
+        }))
+    }
+
+    #[test]
+    fn test_diff_reports_changed_fields() {
+        let before = character();
+        let mut after = before.clone();
+        after.updated_at = 99;
+        assert!(diff_characters(&before, &after).is_empty());
+
+        after.personality = "Gloomy".into();
+        after.scenario = "A crypt".into();
+        after.metadata = json!({ "scenario": "A crypt", "card": { "nickname": "Ari" } });
+
+        let fields: Vec<_> = diff_characters(&before, &after).into_iter().map(|c| c.field).collect();
+        assert_eq!(fields.len(), 3);
+        assert!(fields.contains(&"personality".to_string()));
+        assert!(fields.contains(&"scenario".to_string()));
+        assert!(fields.contains(&"metadata.card".to_string()));
+    }
+
+    #[test]
+    fn test_diff_values() {
+        let before = character();
+        let mut after = before.clone();
+        after.tags.push("music".into());
+
+        let changes = diff_characters(&before, &after);
+        assert_eq!(changes, vec![CharacterFieldChange {
+            field: "tags".into(),
+            before: json!(["fantasy"]),
+            after: json!(["fantasy", "music"]),
+        }]);
+    }
+}
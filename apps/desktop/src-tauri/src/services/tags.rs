@@ -0,0 +1,180 @@
+// ============================================
+// Tag Service
+// Library-wide tag management: rename, merge, delete and bulk edits
+// ============================================
+
+use rusqlite::Connection;
+
+use crate::database::Database;
+use crate::entities::*;
+use crate::error::{AppError, AppResult};
+use crate::repositories::{CharacterRepo, TagRepo};
+use crate::services::CharacterRevisionService;
+
+pub struct TagService;
+
+impl TagService {
+    pub fn list(db: &Database, kind: Option<TagKind>) -> AppResult<Vec<Tag>> {
+        TagRepo::find_all(db, kind)
+    }
+
+    /// Rename a tag on every character that has it
+    pub fn rename(db: &Database, id: i64, name: &str) -> AppResult<Tag> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(AppError::Validation("Tag name cannot be empty".to_string()));
+        }
+        let tag = TagRepo::find_by_id(db, id)?;
+        if let Some(existing) = TagRepo::find_by_name(db, tag.kind, name)? {
+            if existing.id != id {
+                return Err(AppError::Validation(format!("Tag \"{}\" already exists; merge instead", existing.name)));
+            }
+        }
+
+        db.transaction(|conn| {
+            // Renaming the row first lets the link triggers find it under the new name
+            TagRepo::rename_with_conn(conn, id, name)?;
+            Self::rewrite_characters(conn, &tag, |tags| replace_tag(tags, &tag.name, Some(name)))
+        })?;
+        TagRepo::find_by_id(db, id)
+    }
+
+    /// Fold `source_ids` into `target_id`; the sources are deleted
+    pub fn merge(db: &Database, source_ids: &[i64], target_id: i64) -> AppResult<Tag> {
+        let target = TagRepo::find_by_id(db, target_id)?;
+        let mut sources = Vec::new();
+        for &source_id in source_ids.iter().filter(|&&id| id != target_id) {
+            let source = TagRepo::find_by_id(db, source_id)?;
+            if source.kind != target.kind {
+                return Err(AppError::Validation(format!("Cannot merge {} into a {}", source.kind.as_str(), target.kind.as_str())));
+            }
+            sources.push(source);
+        }
+
+        db.transaction(|conn| {
+            for source in &sources {
+                Self::rewrite_characters(conn, source, |tags| replace_tag(tags, &source.name, Some(&target.name)))?;
+                TagRepo::delete_with_conn(conn, source.id)?;
+            }
+            Ok(())
+        })?;
+        TagRepo::find_by_id(db, target_id)
+    }
+
+    /// Remove a tag from every character and forget it
+    pub fn delete(db: &Database, id: i64) -> AppResult<()> {
+        let tag = TagRepo::find_by_id(db, id)?;
+        db.transaction(|conn| {
+            Self::rewrite_characters(conn, &tag, |tags| replace_tag(tags, &tag.name, None))?;
+            TagRepo::delete_with_conn(conn, id)
+        })
+    }
+
+    /// Add and remove tags on several characters. Returns the characters
+    /// that changed.
+    pub fn bulk_edit(db: &Database, input: BulkTagEditInput) -> AppResult<Vec<Character>> {
+        db.transaction(|conn| {
+            let mut changed = Vec::new();
+            for id in &input.character_ids {
+                let character = CharacterRepo::find_by_id_with_conn(conn, id)?;
+                let current = Self::tags_of(&character, input.kind);
+                let edited = edit_tags(current, &input.remove, &input.add);
+                if edited != *current {
+                    changed.push(Self::set_tags(conn, &character, input.kind, &edited)?);
+                }
+            }
+            Ok(changed)
+        })
+    }
+
+    fn rewrite_characters(conn: &Connection, tag: &Tag, edit: impl Fn(&[String]) -> Vec<String>) -> AppResult<()> {
+        for id in TagRepo::find_character_ids_with_conn(conn, tag.id)? {
+            let character = CharacterRepo::find_by_id_with_conn(conn, &id)?;
+            let current = Self::tags_of(&character, tag.kind);
+            let edited = edit(current);
+            if edited != *current {
+                Self::set_tags(conn, &character, tag.kind, &edited)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn tags_of(character: &Character, kind: TagKind) -> &Vec<String> {
+        match kind {
+            TagKind::Tag => &character.tags,
+            TagKind::Genre => &character.genre_tags,
+        }
+    }
+
+    /// Revisions are kept the way a character update keeps them: the
+    /// version being replaced is on record, then the new one
+    fn set_tags(conn: &Connection, before: &Character, kind: TagKind, tags: &[String]) -> AppResult<Character> {
+        CharacterRevisionService::record_with_conn(conn, before, RevisionSource::Manual)?;
+        let character = CharacterRepo::set_tags_with_conn(conn, &before.id, kind, tags)?;
+        CharacterRevisionService::record_with_conn(conn, &character, RevisionSource::Manual)?;
+        Ok(character)
+    }
+}
+
+fn same_tag(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+/// Swap `old` for `new` in place (keeping order, since the first tag shows
+/// up in the prompt), or drop it when `new` is None. Never leaves duplicates.
+pub fn replace_tag(tags: &[String], old: &str, new: Option<&str>) -> Vec<String> {
+    let mut result: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = if same_tag(tag, old) {
+            match new {
+                Some(new) => new.to_string(),
+                None => continue,
+            }
+        } else {
+            tag.clone()
+        };
+        if !result.iter().any(|t| same_tag(t, &tag)) {
+            result.push(tag);
+        }
+    }
+    result
+}
+
+/// Drop everything in `remove`, then append whatever in `add` is missing
+pub fn edit_tags(tags: &[String], remove: &[String], add: &[String]) -> Vec<String> {
+    let mut result: Vec<String> = tags.iter()
+        .filter(|t| !remove.iter().any(|r| same_tag(t, r)))
+        .cloned()
+        .collect();
+    for tag in add.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+        if !result.iter().any(|t| same_tag(t, tag)) {
+            result.push(tag.to_string());
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_replace_tag_keeps_order() {
+        let list = tags(&["Fantasy", "music", "Magic"]);
+        assert_eq!(replace_tag(&list, "fantasy", Some("High Fantasy")), tags(&["High Fantasy", "music", "Magic"]));
+        assert_eq!(replace_tag(&list, "MUSIC", None), tags(&["Fantasy", "Magic"]));
+        // Merging into a tag the character already has
+        assert_eq!(replace_tag(&list, "Magic", Some("fantasy")), tags(&["Fantasy", "music"]));
+    }
+
+    #[test]
+    fn test_edit_tags() {
+        let list = tags(&["Fantasy", "music"]);
+        assert_eq!(edit_tags(&list, &tags(&["Music"]), &tags(&[" romance ", "fantasy", ""])), tags(&["Fantasy", "romance"]));
+        assert_eq!(edit_tags(&list, &[], &[]), list);
+    }
+}
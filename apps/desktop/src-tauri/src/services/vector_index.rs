@@ -0,0 +1,540 @@
+// ============================================
+// Vector Index
+// In-memory HNSW graph per (entity_type, partition) for ANN search
+// ============================================
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::{Arc, LazyLock};
+
+use parking_lot::{Mutex, RwLock};
+
+/// Max neighbors per node on upper layers
+const M: usize = 16;
+/// Max neighbors per node on layer 0
+const M0: usize = 32;
+const EF_CONSTRUCTION: usize = 100;
+const EF_SEARCH: usize = 64;
+/// Below this many live vectors an exact scan is both faster and exact
+const BRUTE_FORCE_LIMIT: usize = 256;
+
+/// Process-wide index, lazily populated per partition from the embeddings table
+pub static VECTOR_INDEX: LazyLock<VectorIndexRegistry> = LazyLock::new(VectorIndexRegistry::default);
+
+/// Normalize in place so cosine similarity becomes a dot product
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Cosine distance between two normalized vectors (0 = identical, 2 = opposite).
+/// Mismatched dimensions are treated as unrelated.
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 1.0;
+    }
+    1.0 - a.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f32>()
+}
+
+/// (distance, node) pair ordered by distance
+#[derive(Clone, Copy, PartialEq)]
+struct Scored(f32, usize);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+struct Node {
+    id: String,
+    vector: Vec<f32>,
+    neighbors: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+/// Hierarchical navigable small world graph over normalized vectors.
+/// Removals are tombstoned and compacted once they outnumber live nodes.
+pub struct HnswIndex {
+    nodes: Vec<Node>,
+    ids: HashMap<String, usize>,
+    entry_point: Option<usize>,
+    max_level: usize,
+    deleted: usize,
+    rng: u64,
+}
+
+impl Default for HnswIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HnswIndex {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            ids: HashMap::new(),
+            entry_point: None,
+            max_level: 0,
+            deleted: 0,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains_key(id)
+    }
+
+    /// xorshift64* - deterministic, good enough for level assignment
+    fn next_random(&mut self) -> f64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let value = self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn random_level(&mut self) -> usize {
+        let ml = 1.0 / (M as f64).ln();
+        let r = self.next_random().max(f64::MIN_POSITIVE);
+        ((-r.ln()) * ml).floor() as usize
+    }
+
+    fn max_neighbors(level: usize) -> usize {
+        if level == 0 { M0 } else { M }
+    }
+
+    /// Insert or replace a vector
+    pub fn insert(&mut self, id: &str, vector: &[f32]) {
+        self.remove(id);
+
+        let mut vector = vector.to_vec();
+        normalize(&mut vector);
+
+        let level = self.random_level();
+        let idx = self.nodes.len();
+        self.nodes.push(Node {
+            id: id.to_string(),
+            vector,
+            neighbors: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.ids.insert(id.to_string(), idx);
+
+        let Some(mut entry) = self.entry_point else {
+            self.entry_point = Some(idx);
+            self.max_level = level;
+            return;
+        };
+
+        let query = self.nodes[idx].vector.clone();
+
+        // Greedy descent through layers above the new node's level
+        for layer in (level + 1..=self.max_level).rev() {
+            entry = self.greedy_closest(&query, entry, layer);
+        }
+
+        for layer in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(&query, entry, EF_CONSTRUCTION, layer);
+            let max = Self::max_neighbors(layer);
+            let selected: Vec<usize> = candidates.iter().take(max).map(|s| s.1).collect();
+
+            self.nodes[idx].neighbors[layer] = selected.clone();
+            for neighbor in selected {
+                self.nodes[neighbor].neighbors[layer].push(idx);
+                if self.nodes[neighbor].neighbors[layer].len() > max {
+                    self.prune(neighbor, layer, max);
+                }
+            }
+
+            if let Some(best) = candidates.first() {
+                entry = best.1;
+            }
+        }
+
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry_point = Some(idx);
+        }
+    }
+
+    /// Keep only the closest `max` links of a node on a layer
+    fn prune(&mut self, node: usize, layer: usize, max: usize) {
+        let base = &self.nodes[node].vector;
+        let mut scored: Vec<Scored> = self.nodes[node].neighbors[layer]
+            .iter()
+            .map(|&n| Scored(distance(base, &self.nodes[n].vector), n))
+            .collect();
+        scored.sort();
+        scored.truncate(max);
+        self.nodes[node].neighbors[layer] = scored.into_iter().map(|s| s.1).collect();
+    }
+
+    fn greedy_closest(&self, query: &[f32], mut current: usize, layer: usize) -> usize {
+        let mut best = distance(query, &self.nodes[current].vector);
+        loop {
+            let mut changed = false;
+            for &n in &self.nodes[current].neighbors[layer] {
+                let d = distance(query, &self.nodes[n].vector);
+                if d < best {
+                    best = d;
+                    current = n;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return current;
+            }
+        }
+    }
+
+    /// Beam search on one layer, returns candidates sorted by distance
+    fn search_layer(&self, query: &[f32], entry: usize, ef: usize, layer: usize) -> Vec<Scored> {
+        let mut visited = HashSet::new();
+        visited.insert(entry);
+
+        let start = Scored(distance(query, &self.nodes[entry].vector), entry);
+        // Min-heap of nodes to expand, max-heap of current results
+        let mut candidates = BinaryHeap::new();
+        candidates.push(std::cmp::Reverse(start));
+        let mut results = BinaryHeap::new();
+        results.push(start);
+
+        while let Some(std::cmp::Reverse(current)) = candidates.pop() {
+            let worst = results.peek().map(|s: &Scored| s.0).unwrap_or(f32::MAX);
+            if current.0 > worst && results.len() >= ef {
+                break;
+            }
+
+            for &n in &self.nodes[current.1].neighbors[layer] {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let d = distance(query, &self.nodes[n].vector);
+                let worst = results.peek().map(|s| s.0).unwrap_or(f32::MAX);
+                if results.len() < ef || d < worst {
+                    candidates.push(std::cmp::Reverse(Scored(d, n)));
+                    results.push(Scored(d, n));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        results.into_sorted_vec()
+    }
+
+    /// Tombstone a vector. Returns true if it was present.
+    pub fn remove(&mut self, id: &str) -> bool {
+        let Some(idx) = self.ids.remove(id) else {
+            return false;
+        };
+        self.nodes[idx].deleted = true;
+        self.deleted += 1;
+
+        if self.deleted > 64 && self.deleted > self.ids.len() {
+            self.compact();
+        }
+        true
+    }
+
+    /// Rebuild the graph from live nodes only
+    fn compact(&mut self) {
+        let live: Vec<(String, Vec<f32>)> = self
+            .nodes
+            .drain(..)
+            .filter(|n| !n.deleted)
+            .map(|n| (n.id, n.vector))
+            .collect();
+
+        self.ids.clear();
+        self.entry_point = None;
+        self.max_level = 0;
+        self.deleted = 0;
+
+        for (id, vector) in live {
+            self.insert(&id, &vector);
+        }
+    }
+
+    /// Top-k most similar vectors as (id, cosine similarity), best first
+    pub fn search(&self, query: &[f32], k: usize, min_similarity: f32) -> Vec<(String, f32)> {
+        if k == 0 || self.ids.is_empty() {
+            return Vec::new();
+        }
+
+        let mut query = query.to_vec();
+        normalize(&mut query);
+
+        let scored: Vec<Scored> = if self.ids.len() <= BRUTE_FORCE_LIMIT {
+            let mut all: Vec<Scored> = self
+                .ids
+                .values()
+                .map(|&idx| Scored(distance(&query, &self.nodes[idx].vector), idx))
+                .collect();
+            all.sort();
+            all
+        } else {
+            let Some(mut entry) = self.entry_point else {
+                return Vec::new();
+            };
+            for layer in (1..=self.max_level).rev() {
+                entry = self.greedy_closest(&query, entry, layer);
+            }
+            // Widen the beam by the tombstone count so deleted nodes can't starve results
+            let ef = EF_SEARCH.max(k * 2) + self.deleted.min(EF_SEARCH);
+            self.search_layer(&query, entry, ef, 0)
+        };
+
+        scored
+            .into_iter()
+            .filter(|s| !self.nodes[s.1].deleted)
+            .map(|s| (self.nodes[s.1].id.clone(), 1.0 - s.0))
+            .filter(|(_, sim)| *sim >= min_similarity)
+            .take(k)
+            .collect()
+    }
+}
+
+// ============================================
+// Registry
+// ============================================
+
+/// (entity_type, partition_key, model_id)
+type PartitionKey = (String, String, String);
+
+/// A vector added (Some) or removed (None) while a partition was loading
+type PendingWrite = (String, Option<Vec<f32>>);
+
+/// Writes seen while a partition is read from the database, replayed
+/// onto it at install so none fall between the read and the install
+struct PendingLoad {
+    key: PartitionKey,
+    writes: Mutex<Vec<PendingWrite>>,
+}
+
+/// Partitions of the ANN index keyed by entity type, partition key
+/// (e.g. character id) and embedding model. Partitions load on first use.
+#[derive(Default)]
+pub struct VectorIndexRegistry {
+    partitions: RwLock<HashMap<PartitionKey, Arc<RwLock<HnswIndex>>>>,
+    loading: Mutex<Vec<Arc<PendingLoad>>>,
+}
+
+/// A partition being loaded. Start it before reading rows, then hand the
+/// built index to `install`; dropping it abandons the load.
+pub struct PartitionLoad<'a> {
+    registry: &'a VectorIndexRegistry,
+    pending: Arc<PendingLoad>,
+}
+
+impl PartitionLoad<'_> {
+    /// Install the loaded partition with any writes made since the load
+    /// began. If another caller raced us, keep theirs.
+    pub fn install(self, mut index: HnswIndex) -> Arc<RwLock<HnswIndex>> {
+        // Writers record under the read lock, so nothing lands between the replay and the insert
+        let mut partitions = self.registry.partitions.write();
+        self.registry.loading.lock().retain(|p| !Arc::ptr_eq(p, &self.pending));
+        for (id, vector) in self.pending.writes.lock().drain(..) {
+            match vector {
+                Some(vector) => index.insert(&id, &vector),
+                None => {
+                    index.remove(&id);
+                }
+            }
+        }
+        partitions
+            .entry(self.pending.key.clone())
+            .or_insert_with(|| Arc::new(RwLock::new(index)))
+            .clone()
+    }
+}
+
+impl Drop for PartitionLoad<'_> {
+    fn drop(&mut self) {
+        self.registry.loading.lock().retain(|p| !Arc::ptr_eq(p, &self.pending));
+    }
+}
+
+impl VectorIndexRegistry {
+    fn key(entity_type: &str, partition: &str, model_id: &str) -> PartitionKey {
+        (entity_type.to_string(), partition.to_string(), model_id.to_string())
+    }
+
+    pub fn get(&self, entity_type: &str, partition: &str, model_id: &str) -> Option<Arc<RwLock<HnswIndex>>> {
+        self.partitions.read().get(&Self::key(entity_type, partition, model_id)).cloned()
+    }
+
+    /// Start loading a partition. Writes to it from here on are kept for `install`.
+    pub fn begin_load(&self, entity_type: &str, partition: &str, model_id: &str) -> PartitionLoad<'_> {
+        let pending = Arc::new(PendingLoad {
+            key: Self::key(entity_type, partition, model_id),
+            writes: Mutex::new(Vec::new()),
+        });
+        self.loading.lock().push(pending.clone());
+        PartitionLoad { registry: self, pending }
+    }
+
+    /// Add a vector to a partition if that partition is loaded, removing it
+    /// from partitions of other models. Unloaded partitions pick the row up
+    /// from the database when first queried.
+    pub fn upsert(&self, entity_type: &str, partition: &str, model_id: &str, id: &str, vector: &[f32]) {
+        let partitions = self.partitions.read();
+        for ((kind, key, model), index) in partitions.iter() {
+            if kind == entity_type && key == partition {
+                if model == model_id {
+                    index.write().insert(id, vector);
+                } else {
+                    index.write().remove(id);
+                }
+            }
+        }
+        self.record(entity_type, partition, id, |model| (model == model_id).then(|| vector.to_vec()));
+    }
+
+    /// Remove a vector from every loaded model's copy of a partition
+    pub fn remove(&self, entity_type: &str, partition: &str, id: &str) {
+        let partitions = self.partitions.read();
+        for ((kind, key, _), index) in partitions.iter() {
+            if kind == entity_type && key == partition {
+                index.write().remove(id);
+            }
+        }
+        self.record(entity_type, partition, id, |_| None);
+    }
+
+    /// Queue a write for loads in progress on the partition.
+    /// Callers hold the partitions read lock.
+    fn record(&self, entity_type: &str, partition: &str, id: &str, vector_for: impl Fn(&str) -> Option<Vec<f32>>) {
+        for pending in self.loading.lock().iter() {
+            let (kind, key, model) = &pending.key;
+            if kind == entity_type && key == partition {
+                pending.writes.lock().push((id.to_string(), vector_for(model)));
+            }
+        }
+    }
+
+    /// Drop every loaded partition so they reload from the database
+    pub fn clear(&self) {
+        self.partitions.write().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_vectors(count: usize, dims: usize, seed: u64) -> Vec<Vec<f32>> {
+        let mut state = seed;
+        (0..count)
+            .map(|_| {
+                (0..dims)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        ((state >> 33) as f32 / (1u64 << 31) as f32) - 0.5
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn brute_force(vectors: &[Vec<f32>], query: &[f32], k: usize) -> Vec<String> {
+        let mut q = query.to_vec();
+        normalize(&mut q);
+        let mut scored: Vec<(usize, f32)> = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let mut v = v.clone();
+                normalize(&mut v);
+                (i, distance(&q, &v))
+            })
+            .collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1));
+        scored.into_iter().take(k).map(|(i, _)| i.to_string()).collect()
+    }
+
+    #[test]
+    fn test_hnsw_recall_against_brute_force() {
+        let vectors = random_vectors(1000, 32, 7);
+        let mut index = HnswIndex::new();
+        for (i, v) in vectors.iter().enumerate() {
+            index.insert(&i.to_string(), v);
+        }
+
+        let queries = random_vectors(20, 32, 99);
+        let mut hits = 0;
+        for query in &queries {
+            let expected = brute_force(&vectors, query, 10);
+            let found: HashSet<String> = index.search(query, 10, -1.0).into_iter().map(|(id, _)| id).collect();
+            hits += expected.iter().filter(|id| found.contains(*id)).count();
+        }
+
+        // Expect at least 90% recall@10
+        assert!(hits >= 180, "recall too low: {}/200", hits);
+    }
+
+    #[test]
+    fn test_hnsw_remove_and_replace() {
+        let mut index = HnswIndex::new();
+        index.insert("a", &[1.0, 0.0]);
+        index.insert("b", &[0.0, 1.0]);
+        assert!(index.remove("a"));
+        assert!(!index.remove("a"));
+
+        let results = index.search(&[1.0, 0.0], 5, -1.0);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, "b");
+
+        index.insert("b", &[1.0, 0.0]);
+        let results = index.search(&[1.0, 0.0], 5, 0.9);
+        assert_eq!(results.len(), 1);
+        assert!((results[0].1 - 1.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_writes_during_load_are_replayed() {
+        let registry = VectorIndexRegistry::default();
+        let load = registry.begin_load("memory", "c1", "m");
+
+        // Rows read before these writes
+        let mut index = HnswIndex::new();
+        index.insert("a", &[1.0, 0.0]);
+        index.insert("b", &[0.0, 1.0]);
+
+        registry.upsert("memory", "c1", "m", "c", &[1.0, 1.0]);
+        registry.remove("memory", "c1", "a");
+        registry.upsert("memory", "c2", "m", "d", &[1.0, 0.0]);
+
+        let index = load.install(index);
+        let index = index.read();
+        assert!(!index.contains("a"));
+        assert!(index.contains("b") && index.contains("c"));
+        assert!(!index.contains("d"));
+        assert!(registry.loading.lock().is_empty());
+
+        // An abandoned load stops collecting writes
+        drop(registry.begin_load("memory", "c2", "m"));
+        assert!(registry.loading.lock().is_empty());
+    }
+}
@@ -0,0 +1,696 @@
+// ============================================
+// World Info Service
+// Import and export of SillyTavern World Info files and
+// Character Card V2 `character_book` lorebooks
+// ============================================
+
+use serde_json::{json, Map, Value};
+
+use crate::database::Database;
+use crate::entities::*;
+use crate::error::{AppError, AppResult};
+use crate::repositories::LorebookRepo;
+use crate::services::lorebook::validate_keys;
+
+/// Metadata key holding fields we don't map, so exports can restore them
+const EXTRA_KEY: &str = "worldInfo";
+
+/// SillyTavern `selectiveLogic` values
+fn logic_from_st(value: i64) -> SelectiveLogic {
+    match value {
+        1 => SelectiveLogic::NotAll,
+        2 => SelectiveLogic::NotAny,
+        3 => SelectiveLogic::AndAll,
+        _ => SelectiveLogic::AndAny,
+    }
+}
+
+fn logic_to_st(logic: SelectiveLogic) -> i64 {
+    match logic {
+        SelectiveLogic::AndAny => 0,
+        SelectiveLogic::NotAll => 1,
+        SelectiveLogic::NotAny => 2,
+        SelectiveLogic::AndAll => 3,
+    }
+}
+
+fn take(obj: &mut Map<String, Value>, key: &str) -> Option<Value> {
+    obj.remove(key).filter(|v| !v.is_null())
+}
+
+fn take_bool(obj: &mut Map<String, Value>, key: &str) -> Option<bool> {
+    take(obj, key).and_then(|v| v.as_bool())
+}
+
+fn take_i32(obj: &mut Map<String, Value>, key: &str) -> Option<i32> {
+    take(obj, key)
+        .and_then(|v| v.as_i64().or_else(|| v.as_f64().map(|f| f.round() as i64)))
+        .map(|n| n.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+}
+
+fn take_string(obj: &mut Map<String, Value>, key: &str) -> Option<String> {
+    take(obj, key).and_then(|v| v.as_str().map(String::from))
+}
+
+/// Key lists are arrays, but some exporters write a comma separated string
+fn take_keys(obj: &mut Map<String, Value>, key: &str) -> Vec<String> {
+    let keys = match take(obj, key) {
+        Some(Value::Array(items)) => items.iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect(),
+        Some(Value::String(s)) => s.split(',').map(String::from).collect(),
+        _ => Vec::new(),
+    };
+    keys.into_iter()
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty())
+        .collect()
+}
+
+/// A lorebook read from a foreign format, ready to insert
+#[derive(Debug)]
+pub struct ParsedLorebook {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub scan_depth: Option<i32>,
+    /// Unmapped top-level fields
+    pub extra: Map<String, Value>,
+    /// `lorebook_id` is filled in on insert
+    pub entries: Vec<CreateEntryInput>,
+}
+
+/// Fields SillyTavern keeps on World Info entries, and inside
+/// `extensions` on character book entries (snake_case there)
+struct StFields<'a> {
+    position: &'a str,
+    exclude_recursion: &'a str,
+    prevent_recursion: &'a str,
+    probability: &'a str,
+    use_probability: &'a str,
+    selective_logic: &'a str,
+    group: &'a str,
+    group_override: &'a str,
+    group_weight: &'a str,
+    scan_depth: &'a str,
+    case_sensitive: &'a str,
+    match_whole_words: &'a str,
+    vectorized: &'a str,
+}
+
+const WORLD_INFO_FIELDS: StFields<'static> = StFields {
+    position: "position",
+    exclude_recursion: "excludeRecursion",
+    prevent_recursion: "preventRecursion",
+    probability: "probability",
+    use_probability: "useProbability",
+    selective_logic: "selectiveLogic",
+    group: "group",
+    group_override: "groupOverride",
+    group_weight: "groupWeight",
+    scan_depth: "scanDepth",
+    case_sensitive: "caseSensitive",
+    match_whole_words: "matchWholeWords",
+    vectorized: "vectorized",
+};
+
+const EXTENSION_FIELDS: StFields<'static> = StFields {
+    position: "position",
+    exclude_recursion: "exclude_recursion",
+    prevent_recursion: "prevent_recursion",
+    probability: "probability",
+    use_probability: "useProbability",
+    selective_logic: "selectiveLogic",
+    group: "group",
+    group_override: "group_override",
+    group_weight: "group_weight",
+    scan_depth: "scan_depth",
+    case_sensitive: "case_sensitive",
+    match_whole_words: "match_whole_words",
+    vectorized: "vectorized",
+};
+
+/// Apply SillyTavern's own entry settings on top of `input`
+fn read_st_fields(obj: &mut Map<String, Value>, fields: &StFields, input: &mut CreateEntryInput) {
+    // Numeric positions: 0 = before character definitions, the rest follow them.
+    // The number itself stays in the leftovers so exports keep the exact slot.
+    if let Some(position) = obj.get(fields.position).and_then(|v| v.as_i64()) {
+        input.insertion_position = Some(if position == 0 { "before_system" } else { "after_system" }.to_string());
+    }
+    if let Some(v) = take_bool(obj, fields.exclude_recursion) { input.exclude_recursion = Some(v); }
+    if let Some(v) = take_bool(obj, fields.prevent_recursion) { input.prevent_recursion = Some(v); }
+
+    let probability = take_i32(obj, fields.probability);
+    let use_probability = take_bool(obj, fields.use_probability).unwrap_or(true);
+    if use_probability {
+        if let Some(p) = probability { input.probability = Some(p.clamp(0, 100)); }
+    }
+
+    if let Some(v) = take(obj, fields.selective_logic).and_then(|v| v.as_i64()) {
+        input.selective_logic = Some(logic_from_st(v));
+    }
+    if let Some(v) = take_string(obj, fields.group).filter(|g| !g.trim().is_empty()) {
+        input.inclusion_group = Some(v);
+    }
+    if let Some(v) = take_bool(obj, fields.group_override) { input.group_prioritize = Some(v); }
+    if let Some(v) = take_i32(obj, fields.group_weight) { input.group_weight = Some(v.max(0)); }
+    if let Some(v) = take_i32(obj, fields.scan_depth) { input.scan_depth = Some(v.max(0)); }
+    if let Some(v) = take_bool(obj, fields.case_sensitive) { input.case_sensitive = Some(v); }
+    if let Some(v) = take_bool(obj, fields.match_whole_words) { input.match_whole_word = Some(v); }
+    if take_bool(obj, fields.vectorized).unwrap_or(false) {
+        input.activation_mode = Some(ActivationMode::Both);
+    }
+    if let Some(v) = take_i32(obj, "sticky") { input.sticky = Some(v.max(0)); }
+    if let Some(v) = take_i32(obj, "cooldown") { input.cooldown = Some(v.max(0)); }
+    if let Some(v) = take_i32(obj, "delay") { input.delay = Some(v.max(0)); }
+}
+
+fn blank_entry() -> CreateEntryInput {
+    CreateEntryInput {
+        lorebook_id: String::new(),
+        name: String::new(),
+        keywords: Vec::new(),
+        content: String::new(),
+        priority: None,
+        // SillyTavern's defaults, which differ from ours
+        case_sensitive: Some(false),
+        match_whole_word: Some(false),
+        insertion_position: None,
+        token_budget: None,
+        // SillyTavern reads `/pattern/flags` keys as regexes
+        regex_keys: Some(true),
+        exclude_recursion: None,
+        prevent_recursion: None,
+        secondary_keywords: None,
+        selective_logic: None,
+        scan_depth: None,
+        constant: None,
+        probability: None,
+        inclusion_group: None,
+        group_weight: None,
+        group_prioritize: None,
+        sticky: None,
+        cooldown: None,
+        delay: None,
+        activation_mode: None,
+        is_enabled: None,
+        metadata: None,
+    }
+}
+
+/// Secondary keys only count when the entry is selective
+fn set_secondary(obj: &mut Map<String, Value>, key: &str, selective: bool, input: &mut CreateEntryInput) {
+    if selective {
+        input.secondary_keywords = Some(take_keys(obj, key));
+    }
+}
+
+fn finish_entry(mut input: CreateEntryInput, extra: Map<String, Value>) -> CreateEntryInput {
+    if !extra.is_empty() {
+        input.metadata = Some(json!({ EXTRA_KEY: extra }));
+    }
+    input
+}
+
+fn parse_world_info_entry(mut obj: Map<String, Value>) -> CreateEntryInput {
+    let mut input = blank_entry();
+    input.keywords = take_keys(&mut obj, "key");
+    input.content = take_string(&mut obj, "content").unwrap_or_default();
+    input.name = take_string(&mut obj, "comment").unwrap_or_default();
+    input.priority = take_i32(&mut obj, "order");
+    input.constant = take_bool(&mut obj, "constant");
+    input.is_enabled = take_bool(&mut obj, "disable").map(|d| !d);
+
+    let selective = obj.get("selective").and_then(|v| v.as_bool()).unwrap_or(true);
+    set_secondary(&mut obj, "keysecondary", selective, &mut input);
+    if input.secondary_keywords.is_some() {
+        obj.remove("selective");
+    }
+
+    read_st_fields(&mut obj, &WORLD_INFO_FIELDS, &mut input);
+    finish_entry(input, obj)
+}
+
+fn parse_character_book_entry(mut obj: Map<String, Value>) -> CreateEntryInput {
+    let mut input = blank_entry();
+    input.keywords = take_keys(&mut obj, "keys");
+    input.content = take_string(&mut obj, "content").unwrap_or_default();
+    input.name = take_string(&mut obj, "name")
+        .filter(|n| !n.is_empty())
+        .or_else(|| take_string(&mut obj, "comment"))
+        .unwrap_or_default();
+    input.priority = take_i32(&mut obj, "insertion_order");
+    input.constant = take_bool(&mut obj, "constant");
+    input.is_enabled = take_bool(&mut obj, "enabled");
+    if let Some(v) = take_bool(&mut obj, "case_sensitive") { input.case_sensitive = Some(v); }
+
+    let selective = obj.get("selective").and_then(|v| v.as_bool()).unwrap_or(false);
+    set_secondary(&mut obj, "secondary_keys", selective, &mut input);
+    if input.secondary_keywords.is_some() {
+        obj.remove("selective");
+    }
+
+    if let Some(position) = obj.get("position").and_then(|v| v.as_str()) {
+        let position = if position == "before_char" { "before_system" } else { "after_system" };
+        input.insertion_position = Some(position.to_string());
+    }
+
+    // SillyTavern's extra settings live under extensions
+    if let Some(Value::Object(mut ext)) = obj.remove("extensions") {
+        read_st_fields(&mut ext, &EXTENSION_FIELDS, &mut input);
+        if !ext.is_empty() {
+            obj.insert("extensions".to_string(), Value::Object(ext));
+        }
+    }
+
+    finish_entry(input, obj)
+}
+
+/// Refuse a book with regex keys that don't compile here, naming the
+/// entries, rather than import entries that could never fire
+fn check_keys(entries: &[CreateEntryInput]) -> AppResult<()> {
+    let problems: Vec<String> = entries.iter().enumerate()
+        .filter(|(_, entry)| entry.regex_keys.unwrap_or(false))
+        .filter_map(|(i, entry)| {
+            let error = validate_keys(&entry.keywords)
+                .and_then(|_| validate_keys(entry.secondary_keywords.as_deref().unwrap_or_default()))
+                .err()?;
+            let name = if entry.name.trim().is_empty() { format!("Entry {}", i + 1) } else { format!("\"{}\"", entry.name) };
+            Some(match error {
+                AppError::Validation(message) => format!("{}: {}", name, message),
+                other => format!("{}: {}", name, other),
+            })
+        })
+        .collect();
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(AppError::Import(format!("Lorebook has keys that can't be read: {}", problems.join("; "))))
+}
+
+/// Read a World Info file, a character book, or a whole V2 card with an
+/// embedded character book
+pub fn parse(value: &Value) -> AppResult<ParsedLorebook> {
+    let book = value.get("data")
+        .and_then(|d| d.get("character_book"))
+        .or_else(|| value.get("character_book"))
+        .unwrap_or(value);
+
+    let mut obj = book.as_object()
+        .cloned()
+        .ok_or_else(|| AppError::Import("Lorebook must be a JSON object".into()))?;
+
+    let entries: Vec<CreateEntryInput> = match obj.remove("entries") {
+        // World Info: { "0": {...}, "1": {...} }, in display order when given
+        Some(Value::Object(map)) => {
+            let mut items: Vec<Map<String, Value>> = map.into_iter()
+                .filter_map(|(_, v)| v.as_object().cloned())
+                .collect();
+            items.sort_by_key(|e| {
+                e.get("displayIndex").or_else(|| e.get("uid")).and_then(|v| v.as_i64()).unwrap_or(i64::MAX)
+            });
+            items.into_iter().map(parse_world_info_entry).collect()
+        }
+        Some(Value::Array(items)) => items.into_iter()
+            .filter_map(|v| match v {
+                Value::Object(entry) => Some(entry),
+                _ => None,
+            })
+            .map(|entry| {
+                // Older World Info exports used an array too
+                if entry.contains_key("key") {
+                    parse_world_info_entry(entry)
+                } else {
+                    parse_character_book_entry(entry)
+                }
+            })
+            .collect(),
+        _ => return Err(AppError::Import("No lorebook entries found".into())),
+    };
+    check_keys(&entries)?;
+
+    Ok(ParsedLorebook {
+        name: take_string(&mut obj, "name").filter(|n| !n.trim().is_empty()),
+        description: take_string(&mut obj, "description"),
+        scan_depth: take_i32(&mut obj, "scan_depth"),
+        extra: obj,
+        entries,
+    })
+}
+
+/// Fields we didn't map when the entry was imported
+fn extra_of(metadata: &Value) -> Map<String, Value> {
+    metadata.get(EXTRA_KEY)
+        .and_then(|v| v.as_object())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Numeric SillyTavern position, keeping the original slot when it still agrees
+fn st_position(entry: &LorebookEntry, extra: &Map<String, Value>) -> i64 {
+    let before = entry.insertion_position == "before_system";
+    match extra.get("position").and_then(|v| v.as_i64()) {
+        Some(p) if (p == 0) == before => p,
+        _ if before => 0,
+        _ => 1,
+    }
+}
+
+/// SillyTavern's settings for an entry, under the given field names
+fn write_st_fields(obj: &mut Map<String, Value>, fields: &StFields, entry: &LorebookEntry, position: i64) {
+    obj.insert(fields.position.into(), json!(position));
+    obj.insert(fields.exclude_recursion.into(), json!(entry.exclude_recursion));
+    obj.insert(fields.prevent_recursion.into(), json!(entry.prevent_recursion));
+    obj.insert(fields.probability.into(), json!(entry.probability));
+    obj.insert(fields.use_probability.into(), json!(entry.probability < 100));
+    obj.insert(fields.selective_logic.into(), json!(logic_to_st(entry.selective_logic)));
+    obj.insert(fields.group.into(), json!(entry.inclusion_group.clone().unwrap_or_default()));
+    obj.insert(fields.group_override.into(), json!(entry.group_prioritize));
+    obj.insert(fields.group_weight.into(), json!(entry.group_weight));
+    obj.insert(fields.scan_depth.into(), json!(entry.scan_depth));
+    obj.insert(fields.case_sensitive.into(), json!(entry.case_sensitive));
+    obj.insert(fields.match_whole_words.into(), json!(entry.match_whole_word));
+    obj.insert(fields.vectorized.into(), json!(entry.activation_mode.uses_vectors()));
+    obj.insert("sticky".into(), json!(entry.sticky));
+    obj.insert("cooldown".into(), json!(entry.cooldown));
+    obj.insert("delay".into(), json!(entry.delay));
+}
+
+/// Top-level object with the lorebook's own unmapped fields restored
+fn book_object(lorebook: &Lorebook) -> Map<String, Value> {
+    let mut obj = extra_of(&lorebook.metadata);
+    obj.insert("name".into(), json!(lorebook.name));
+    obj.insert("description".into(), json!(lorebook.description));
+    if let Some(depth) = lorebook.scan_depth {
+        obj.insert("scan_depth".into(), json!(depth));
+    }
+    obj
+}
+
+/// SillyTavern World Info JSON
+pub fn to_world_info(lorebook: &Lorebook) -> Value {
+    let mut entries = Map::new();
+    let mut used_uids = std::collections::HashSet::new();
+
+    for (index, entry) in lorebook.entries.iter().enumerate() {
+        let mut obj = extra_of(&entry.metadata);
+
+        // Keep imported uids where they are still unique
+        let uid = obj.get("uid")
+            .and_then(|v| v.as_i64())
+            .filter(|uid| !used_uids.contains(uid))
+            .unwrap_or_else(|| (0..).find(|n| !used_uids.contains(n)).unwrap_or(index as i64));
+        used_uids.insert(uid);
+
+        let position = st_position(entry, &obj);
+        obj.insert("uid".into(), json!(uid));
+        obj.insert("key".into(), json!(entry.keywords));
+        obj.insert("comment".into(), json!(entry.name));
+        obj.insert("content".into(), json!(entry.content));
+        obj.insert("order".into(), json!(entry.priority));
+        obj.insert("constant".into(), json!(entry.constant));
+        obj.insert("disable".into(), json!(!entry.is_enabled));
+        if !entry.secondary_keywords.is_empty() {
+            obj.insert("keysecondary".into(), json!(entry.secondary_keywords));
+            obj.insert("selective".into(), json!(true));
+        }
+        obj.entry("displayIndex").or_insert(json!(index));
+        write_st_fields(&mut obj, &WORLD_INFO_FIELDS, entry, position);
+
+        entries.insert(uid.to_string(), Value::Object(obj));
+    }
+
+    let mut book = book_object(lorebook);
+    book.insert("entries".into(), Value::Object(entries));
+    Value::Object(book)
+}
+
+/// Character Card V2 `character_book`
+pub fn to_character_book(lorebook: &Lorebook) -> Value {
+    let entries: Vec<Value> = lorebook.entries.iter().enumerate()
+        .map(|(index, entry)| {
+            let mut obj = extra_of(&entry.metadata);
+            let mut ext = obj.remove("extensions")
+                .and_then(|v| v.as_object().cloned())
+                .unwrap_or_default();
+
+            let position = st_position(entry, &ext);
+            obj.insert("id".into(), json!(index));
+            obj.insert("keys".into(), json!(entry.keywords));
+            obj.insert("secondary_keys".into(), json!(entry.secondary_keywords));
+            obj.insert("selective".into(), json!(!entry.secondary_keywords.is_empty()));
+            obj.insert("name".into(), json!(entry.name));
+            obj.insert("comment".into(), json!(entry.name));
+            obj.insert("content".into(), json!(entry.content));
+            obj.insert("insertion_order".into(), json!(entry.priority));
+            obj.insert("enabled".into(), json!(entry.is_enabled));
+            obj.insert("constant".into(), json!(entry.constant));
+            obj.insert("case_sensitive".into(), json!(entry.case_sensitive));
+            obj.insert("position".into(), json!(if position == 0 { "before_char" } else { "after_char" }));
+
+            write_st_fields(&mut ext, &EXTENSION_FIELDS, entry, position);
+            obj.insert("extensions".into(), Value::Object(ext));
+            Value::Object(obj)
+        })
+        .collect();
+
+    let mut book = book_object(lorebook);
+    book.entry("extensions").or_insert(json!({}));
+    book.insert("entries".into(), Value::Array(entries));
+    Value::Object(book)
+}
+
+pub struct WorldInfoService;
+
+impl WorldInfoService {
+    /// Create a lorebook from World Info or character book JSON.
+    /// `name` overrides the name in the file (World Info files have none).
+    pub fn import(db: &Database, data: &str, name: Option<&str>) -> AppResult<Lorebook> {
+        let value: Value = serde_json::from_str(data)
+            .map_err(|e| AppError::Import(format!("Invalid JSON: {}", e)))?;
+        let parsed = parse(&value)?;
+        Self::insert(db, parsed, name)
+    }
+
+    /// Insert a parsed lorebook and its entries
+    pub fn insert(db: &Database, parsed: ParsedLorebook, name: Option<&str>) -> AppResult<Lorebook> {
+        let name = name.map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(String::from)
+            .or(parsed.name)
+            .unwrap_or_else(|| "Imported Lorebook".to_string());
+
+        let lorebook = LorebookRepo::create(db, &CreateLorebookInput {
+            name,
+            description: parsed.description,
+            is_global: Some(false),
+            scan_depth: parsed.scan_depth,
+            similarity_threshold: None,
+        })?;
+
+        let result = (|| {
+            if !parsed.extra.is_empty() {
+                LorebookRepo::update_metadata(db, &lorebook.id, &json!({ EXTRA_KEY: parsed.extra }))?;
+            }
+            // Entries are taken as-is: an entry with no keys simply never fires,
+            // the same as it did in the app it came from
+            for mut input in parsed.entries {
+                input.lorebook_id = lorebook.id.clone();
+                LorebookRepo::create_entry(db, &input)?;
+            }
+            LorebookRepo::find_by_id(db, &lorebook.id)
+        })();
+
+        if result.is_err() {
+            let _ = LorebookRepo::delete(db, &lorebook.id);
+        }
+        result
+    }
+
+    pub fn export(db: &Database, id: &str, format: LorebookFormat) -> AppResult<Value> {
+        let lorebook = LorebookRepo::find_by_id(db, id)?;
+        Ok(match format {
+            LorebookFormat::WorldInfo => to_world_info(&lorebook),
+            LorebookFormat::CharacterBook => to_character_book(&lorebook),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build the stored entry an import would produce
+    fn stored(input: CreateEntryInput) -> LorebookEntry {
+        LorebookEntry {
+            id: "e".into(),
+            lorebook_id: "lb".into(),
+            name: input.name,
+            keywords: input.keywords,
+            secondary_keywords: input.secondary_keywords.unwrap_or_default(),
+            selective_logic: input.selective_logic.unwrap_or_default(),
+            content: input.content,
+            priority: input.priority.unwrap_or(50),
+            is_enabled: input.is_enabled.unwrap_or(true),
+            case_sensitive: input.case_sensitive.unwrap_or(false),
+            match_whole_word: input.match_whole_word.unwrap_or(true),
+            insertion_position: input.insertion_position.unwrap_or_else(|| "after_system".into()),
+            token_budget: input.token_budget,
+            regex_keys: input.regex_keys.unwrap_or(false),
+            exclude_recursion: input.exclude_recursion.unwrap_or(false),
+            prevent_recursion: input.prevent_recursion.unwrap_or(false),
+            scan_depth: input.scan_depth,
+            constant: input.constant.unwrap_or(false),
+            probability: input.probability.unwrap_or(100),
+            inclusion_group: input.inclusion_group,
+            group_weight: input.group_weight.unwrap_or(100),
+            group_prioritize: input.group_prioritize.unwrap_or(false),
+            sticky: input.sticky.unwrap_or(0),
+            cooldown: input.cooldown.unwrap_or(0),
+            delay: input.delay.unwrap_or(0),
+            activation_mode: input.activation_mode.unwrap_or_default(),
+            has_embedding: false,
+            created_at: 0,
+            metadata: input.metadata.unwrap_or_else(|| json!({})),
+        }
+    }
+
+    fn book(parsed: ParsedLorebook) -> Lorebook {
+        Lorebook {
+            id: "lb".into(),
+            name: parsed.name.unwrap_or_default(),
+            description: parsed.description.unwrap_or_default(),
+            is_global: false,
+            is_enabled: true,
+            created_at: 0,
+            updated_at: 0,
+            deleted_at: None,
+            scan_depth: parsed.scan_depth,
+            similarity_threshold: None,
+            metadata: json!({ EXTRA_KEY: parsed.extra }),
+            entries: parsed.entries.into_iter().map(stored).collect(),
+        }
+    }
+
+    fn world_info() -> Value {
+        json!({
+            "entries": {
+                "3": {
+                    "uid": 3, "key": ["Aster", "/kingdom of \\w+/i"], "keysecondary": ["king"],
+                    "comment": "Aster", "content": "The Kingdom of Aster.", "constant": false,
+                    "selective": true, "selectiveLogic": 3, "order": 120, "position": 4, "depth": 2,
+                    "disable": false, "probability": 40, "useProbability": true, "group": "realms",
+                    "groupOverride": true, "groupWeight": 70, "sticky": 2, "cooldown": null,
+                    "caseSensitive": null, "matchWholeWords": true, "vectorized": true,
+                    "automationId": "abc", "displayIndex": 0
+                },
+                "7": {
+                    "uid": 7, "key": [], "keysecondary": ["unused"], "comment": "Always",
+                    "content": "Magic is rare.", "constant": true, "selective": false,
+                    "order": 100, "position": 0, "disable": true, "useProbability": false,
+                    "probability": 10, "displayIndex": 1
+                }
+            },
+            "originalData": { "kept": true }
+        })
+    }
+
+    #[test]
+    fn test_world_info_import() {
+        let parsed = parse(&world_info()).unwrap();
+        assert_eq!(parsed.entries.len(), 2);
+        assert!(parsed.extra.contains_key("originalData"));
+
+        let aster = &parsed.entries[0];
+        assert_eq!(aster.keywords, vec!["Aster", "/kingdom of \\w+/i"]);
+        assert_eq!(aster.secondary_keywords.as_deref(), Some(&["king".to_string()][..]));
+        assert_eq!(aster.selective_logic, Some(SelectiveLogic::AndAll));
+        assert_eq!(aster.priority, Some(120));
+        assert_eq!(aster.insertion_position.as_deref(), Some("after_system"));
+        assert_eq!(aster.probability, Some(40));
+        assert_eq!(aster.inclusion_group.as_deref(), Some("realms"));
+        assert_eq!(aster.group_prioritize, Some(true));
+        assert_eq!(aster.sticky, Some(2));
+        assert_eq!(aster.activation_mode, Some(ActivationMode::Both));
+        let extra = &aster.metadata.as_ref().unwrap()[EXTRA_KEY];
+        assert_eq!(extra["automationId"], "abc");
+        assert_eq!(extra["position"], 4);
+
+        let always = &parsed.entries[1];
+        assert_eq!(always.constant, Some(true));
+        assert_eq!(always.is_enabled, Some(false));
+        assert_eq!(always.insertion_position.as_deref(), Some("before_system"));
+        // Probability is off, and secondary keys don't apply without `selective`
+        assert_eq!(always.probability, None);
+        assert_eq!(always.secondary_keywords, None);
+    }
+
+    #[test]
+    fn test_invalid_regex_keys_refused() {
+        let mut value = world_info();
+        value["entries"]["3"]["keysecondary"] = json!(["/[unclosed/"]);
+        let Err(AppError::Import(message)) = parse(&value) else {
+            panic!("expected the import to be refused");
+        };
+        assert!(message.contains("\"Aster\""), "{}", message);
+        assert!(message.contains("/[unclosed/"), "{}", message);
+    }
+
+    #[test]
+    fn test_world_info_round_trip() {
+        let exported = to_world_info(&book(parse(&world_info()).unwrap()));
+        let aster = &exported["entries"]["3"];
+        assert_eq!(aster["position"], 4);
+        assert_eq!(aster["depth"], 2);
+        assert_eq!(aster["automationId"], "abc");
+        assert_eq!(aster["selectiveLogic"], 3);
+        assert_eq!(aster["groupWeight"], 70);
+        assert_eq!(exported["entries"]["7"]["keysecondary"], json!(["unused"]));
+        assert_eq!(exported["originalData"]["kept"], true);
+
+        // A second pass gives the same entries
+        let again = parse(&exported).unwrap();
+        assert_eq!(again.entries.len(), 2);
+        assert_eq!(again.entries[0].keywords, parse(&world_info()).unwrap().entries[0].keywords);
+    }
+
+    #[test]
+    fn test_character_book() {
+        let card = json!({
+            "spec": "chara_card_v2",
+            "data": {
+                "name": "Aldric",
+                "character_book": {
+                    "name": "Aster",
+                    "scan_depth": 6,
+                    "token_budget": 800,
+                    "extensions": {},
+                    "entries": [{
+                        "keys": ["Aster"], "content": "The Kingdom of Aster.", "enabled": true,
+                        "insertion_order": 10, "position": "before_char", "selective": true,
+                        "secondary_keys": ["king"], "id": 1,
+                        "extensions": { "selectiveLogic": 2, "exclude_recursion": true, "weight": 5 }
+                    }]
+                }
+            }
+        });
+
+        let parsed = parse(&card).unwrap();
+        assert_eq!(parsed.name.as_deref(), Some("Aster"));
+        assert_eq!(parsed.scan_depth, Some(6));
+        assert_eq!(parsed.extra["token_budget"], 800);
+
+        let entry = &parsed.entries[0];
+        assert_eq!(entry.insertion_position.as_deref(), Some("before_system"));
+        assert_eq!(entry.selective_logic, Some(SelectiveLogic::NotAny));
+        assert_eq!(entry.exclude_recursion, Some(true));
+
+        let exported = to_character_book(&book(parsed));
+        assert_eq!(exported["token_budget"], 800);
+        let entry = &exported["entries"][0];
+        assert_eq!(entry["position"], "before_char");
+        assert_eq!(entry["secondary_keys"], json!(["king"]));
+        assert_eq!(entry["extensions"]["weight"], 5);
+        assert_eq!(entry["extensions"]["selectiveLogic"], 2);
+    }
+}
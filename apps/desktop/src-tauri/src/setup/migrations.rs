@@ -4,6 +4,24 @@ use crate::error::AppResult;
 const MIGRATION_001: &str = include_str!("../../migrations/001_initial_schema.sql");
 const MIGRATION_005: &str = include_str!("../../migrations/005_embeddings.sql");
 const MIGRATION_006: &str = include_str!("../../migrations/006_fix_schema.sql");
+const MIGRATION_007: &str = include_str!("../../migrations/007_fts_search.sql");
+const MIGRATION_008: &str = include_str!("../../migrations/008_embedding_partitions.sql");
+const MIGRATION_009: &str = include_str!("../../migrations/009_embedding_models.sql");
+const MIGRATION_010: &str = include_str!("../../migrations/010_background_jobs.sql");
+const MIGRATION_011: &str = include_str!("../../migrations/011_summary_hierarchy.sql");
+const MIGRATION_012: &str = include_str!("../../migrations/012_lorebook_recursion.sql");
+const MIGRATION_013: &str = include_str!("../../migrations/013_lorebook_keys.sql");
+const MIGRATION_014: &str = include_str!("../../migrations/014_lorebook_groups.sql");
+const MIGRATION_015: &str = include_str!("../../migrations/015_lorebook_timed_effects.sql");
+const MIGRATION_016: &str = include_str!("../../migrations/016_lorebook_semantic.sql");
+const MIGRATION_017: &str = include_str!("../../migrations/017_character_lorebooks.sql");
+const MIGRATION_018: &str = include_str!("../../migrations/018_character_revisions.sql");
+const MIGRATION_019: &str = include_str!("../../migrations/019_character_search.sql");
+const MIGRATION_020: &str = include_str!("../../migrations/020_tags.sql");
+const MIGRATION_021: &str = include_str!("../../migrations/021_character_card_hash.sql");
+const MIGRATION_022: &str = include_str!("../../migrations/022_fts_rowid_maps.sql");
+const MIGRATION_023: &str = include_str!("../../migrations/023_lorebook_regex_keys.sql");
+const MIGRATION_024: &str = include_str!("../../migrations/024_character_fts_rowids.sql");
 
 pub fn run_migrations(db: &Database) -> AppResult<()> {
     // Check if migrations table exists
@@ -69,6 +87,249 @@ pub fn run_migrations(db: &Database) -> AppResult<()> {
         })?;
     }
     
+    // Apply migration 7 (Full-text search indexes) - wrapped in transaction
+    if !applied.contains(&7) {
+        tracing::info!("Applying migration 007_fts_search");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_007)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (7, '007_fts_search', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 8 (Embedding partitions + quantization) - wrapped in transaction
+    if !applied.contains(&8) {
+        tracing::info!("Applying migration 008_embedding_partitions");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_008)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (8, '008_embedding_partitions', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 9 (Embedding model identity) - wrapped in transaction
+    if !applied.contains(&9) {
+        tracing::info!("Applying migration 009_embedding_models");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_009)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (9, '009_embedding_models', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 10 (Background jobs) - wrapped in transaction
+    if !applied.contains(&10) {
+        tracing::info!("Applying migration 010_background_jobs");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_010)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (10, '010_background_jobs', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 11 (Summary hierarchy) - wrapped in transaction
+    if !applied.contains(&11) {
+        tracing::info!("Applying migration 011_summary_hierarchy");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_011)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (11, '011_summary_hierarchy', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 12 (Lorebook recursion flags) - wrapped in transaction
+    if !applied.contains(&12) {
+        tracing::info!("Applying migration 012_lorebook_recursion");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_012)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (12, '012_lorebook_recursion', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 13 (Lorebook keys) - wrapped in transaction
+    if !applied.contains(&13) {
+        tracing::info!("Applying migration 013_lorebook_keys");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_013)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (13, '013_lorebook_keys', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 14 (Lorebook groups) - wrapped in transaction
+    if !applied.contains(&14) {
+        tracing::info!("Applying migration 014_lorebook_groups");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_014)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (14, '014_lorebook_groups', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 15 (Lorebook timed effects) - wrapped in transaction
+    if !applied.contains(&15) {
+        tracing::info!("Applying migration 015_lorebook_timed_effects");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_015)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (15, '015_lorebook_timed_effects', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 16 (Semantic lorebook activation) - wrapped in transaction
+    if !applied.contains(&16) {
+        tracing::info!("Applying migration 016_lorebook_semantic");
+        db.transaction_mut(|conn| {
+            // 004_lorebook_vectors.sql was never registered, but may have been run by hand
+            let has_column: bool = conn.query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('lorebook_entries') WHERE name = 'has_embedding'",
+                [],
+                |row| row.get(0),
+            )?;
+            if !has_column {
+                conn.execute_batch("ALTER TABLE lorebook_entries ADD COLUMN has_embedding INTEGER NOT NULL DEFAULT 0;")?;
+            }
+            conn.execute_batch(MIGRATION_016)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (16, '016_lorebook_semantic', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 17 (Character lorebooks) - wrapped in transaction
+    if !applied.contains(&17) {
+        tracing::info!("Applying migration 017_character_lorebooks");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_017)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (17, '017_character_lorebooks', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 18 (Character revisions) - wrapped in transaction
+    if !applied.contains(&18) {
+        tracing::info!("Applying migration 018_character_revisions");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_018)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (18, '018_character_revisions', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 19 (Character search) - wrapped in transaction
+    if !applied.contains(&19) {
+        tracing::info!("Applying migration 019_character_search");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_019)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (19, '019_character_search', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 20 (Normalized tags) - wrapped in transaction
+    if !applied.contains(&20) {
+        tracing::info!("Applying migration 020_tags");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_020)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (20, '020_tags', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 21 (Card hashes for duplicate detection) - wrapped in transaction
+    if !applied.contains(&21) {
+        tracing::info!("Applying migration 021_character_card_hash");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_021)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (21, '021_character_card_hash', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 22 (FTS rowid maps) - wrapped in transaction
+    if !applied.contains(&22) {
+        tracing::info!("Applying migration 022_fts_rowid_maps");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_022)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (22, '022_fts_rowid_maps', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 23 (opt-in regex keys) - wrapped in transaction
+    if !applied.contains(&23) {
+        tracing::info!("Applying migration 023_lorebook_regex_keys");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_023)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (23, '023_lorebook_regex_keys', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
+    // Apply migration 24 (character FTS rowids) - wrapped in transaction
+    if !applied.contains(&24) {
+        tracing::info!("Applying migration 024_character_fts_rowids");
+        db.transaction_mut(|conn| {
+            conn.execute_batch(MIGRATION_024)?;
+            conn.execute(
+                "INSERT INTO _migrations (id, name, applied_at) VALUES (24, '024_character_fts_rowids', strftime('%s', 'now'))",
+                [],
+            )?;
+            Ok(())
+        })?;
+    }
+    
     // Safety check: ensure embeddings table exists (handles corrupted/incomplete migrations)
     let embeddings_exists: bool = db.query_one(
         "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name='embeddings'",
@@ -86,10 +347,16 @@ pub fn run_migrations(db: &Database) -> AppResult<()> {
                 embedding BLOB NOT NULL,
                 dimensions INTEGER NOT NULL,
                 created_at INTEGER NOT NULL,
+                partition_key TEXT NOT NULL DEFAULT '',
+                encoding TEXT NOT NULL DEFAULT 'f32'
+                    CHECK (encoding IN ('f32', 'f16', 'int8')),
+                model_id TEXT NOT NULL DEFAULT '',
                 UNIQUE(entity_type, entity_id)
             );
             CREATE INDEX IF NOT EXISTS idx_embeddings_entity ON embeddings(entity_type, entity_id);
-            CREATE INDEX IF NOT EXISTS idx_embeddings_type ON embeddings(entity_type);"
+            CREATE INDEX IF NOT EXISTS idx_embeddings_type ON embeddings(entity_type);
+            CREATE INDEX IF NOT EXISTS idx_embeddings_partition ON embeddings(entity_type, partition_key);
+            CREATE INDEX IF NOT EXISTS idx_embeddings_model ON embeddings(model_id);"
         )?;
         tracing::info!("Embeddings table created successfully");
     }
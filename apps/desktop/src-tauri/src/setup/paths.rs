@@ -7,6 +7,10 @@ pub struct AppPaths {
     pub data_dir: PathBuf,
     pub database_path: PathBuf,
     pub avatars_dir: PathBuf,
+    /// Small copies of avatars for list views, named like the avatar
+    pub avatar_thumbnails_dir: PathBuf,
+    /// Files bundled with imported CharX cards, one directory per character
+    pub card_assets_dir: PathBuf,
     pub models_dir: PathBuf,
     pub exports_dir: PathBuf,
     pub logs_dir: PathBuf,
@@ -22,6 +26,8 @@ impl AppPaths {
         let paths = Self {
             database_path: data_dir.join("glee.db"),
             avatars_dir: data_dir.join("avatars"),
+            avatar_thumbnails_dir: data_dir.join("avatars").join("thumbnails"),
+            card_assets_dir: data_dir.join("card_assets"),
             models_dir: data_dir.join("models"),
             exports_dir: data_dir.join("exports"),
             logs_dir: data_dir.join("logs"),
@@ -31,6 +37,8 @@ impl AppPaths {
         // Create directories
         std::fs::create_dir_all(&paths.data_dir)?;
         std::fs::create_dir_all(&paths.avatars_dir)?;
+        std::fs::create_dir_all(&paths.avatar_thumbnails_dir)?;
+        std::fs::create_dir_all(&paths.card_assets_dir)?;
         std::fs::create_dir_all(&paths.models_dir)?;
         std::fs::create_dir_all(&paths.exports_dir)?;
         std::fs::create_dir_all(&paths.logs_dir)?;
@@ -46,6 +54,14 @@ impl AppPaths {
         self.avatars_dir.join(filename)
     }
     
+    pub fn avatar_thumbnail_path(&self, filename: &str) -> PathBuf {
+        self.avatar_thumbnails_dir.join(filename)
+    }
+    
+    pub fn character_assets_dir(&self, character_id: &str) -> PathBuf {
+        self.card_assets_dir.join(character_id)
+    }
+    
     pub fn default_model_path(&self) -> PathBuf {
         self.models_dir.join("model.gguf")
     }
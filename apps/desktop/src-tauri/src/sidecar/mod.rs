@@ -15,6 +15,7 @@ use crate::error::{AppError, AppResult};
 use serde::Deserialize;
 
 const DEFAULT_SIDECAR_PORT: u16 = 8384;
+const DEFAULT_EMBEDDING_PORT: u16 = 8385;
 const DEFAULT_STOP_SEQUENCES: &[&str] = &["<|im_end|>", "<|im_start|>", "</s>", "<|end|>", "<|eot_id|>"];
 
 // ============================================
@@ -45,6 +46,8 @@ pub struct SidecarHandle {
     cancel_token: CancellationToken,
     /// Stop tokens detected from model metadata
     pub detected_stop_tokens: Arc<Mutex<Option<Vec<String>>>>,
+    /// Identity of the loaded model (GGUF file name), recorded with each embedding
+    pub model_id: String,
 }
 
 impl SidecarHandle {
@@ -142,6 +145,27 @@ fn find_sidecar_binary(app_handle: &AppHandle, custom_path: Option<&str>) -> App
     )))
 }
 
+/// Stable identifier for a model file, used to tell embeddings from different models apart.
+/// Size and modification time go in with the name so a different model saved
+/// under the same file name doesn't reuse the old vectors.
+pub fn model_identity(model_path: &Path) -> String {
+    let name = model_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| model_path.to_string_lossy().to_string());
+
+    let Ok(metadata) = std::fs::metadata(model_path) else {
+        return name;
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!("{}@{:x}-{:x}", name, metadata.len(), modified)
+}
+
 fn is_port_free(port: u16) -> bool {
     use std::net::TcpListener;
     TcpListener::bind(("127.0.0.1", port)).is_ok()
@@ -301,6 +325,7 @@ pub async fn start_sidecar(
         process: Arc::new(Mutex::new(Some(child))),
         cancel_token: CancellationToken::new(),
         detected_stop_tokens: Arc::new(Mutex::new(None)),
+        model_id: model_identity(model_path),
     };
     
     // Wait for sidecar to become healthy
@@ -356,6 +381,103 @@ pub async fn start_sidecar(
     ))
 }
 
+/// Start a second llama-server dedicated to a small embedding model.
+/// Runs on CPU so the chat model keeps the GPU, and emits no model:* events
+/// since the UI only tracks the chat model.
+pub async fn start_embedding_sidecar(
+    app_handle: &AppHandle,
+    model_path: &Path,
+    sidecar_path: Option<&str>,
+) -> AppResult<SidecarHandle> {
+    if !model_path.exists() {
+        return Err(AppError::NotFound(format!(
+            "Embedding model file not found: {}",
+            model_path.display()
+        )));
+    }
+    
+    let sidecar_binary = find_sidecar_binary(app_handle, sidecar_path)?;
+    let port = find_available_port(DEFAULT_EMBEDDING_PORT);
+    
+    tracing::info!("Starting embedding sidecar with model {:?} on port {}", model_path, port);
+    
+    let mut cmd = Command::new(&sidecar_binary);
+    
+    if let Some(parent_dir) = sidecar_binary.parent() {
+        cmd.current_dir(parent_dir);
+    }
+    
+    cmd.arg("--model").arg(model_path)
+        .arg("--host").arg("127.0.0.1")
+        .arg("--port").arg(port.to_string())
+        .arg("--ctx-size").arg("8192")
+        .arg("--batch-size").arg("8192")
+        .arg("--ubatch-size").arg("8192")
+        .arg("--n-gpu-layers").arg("0")
+        .arg("--parallel").arg("4")
+        .arg("--embeddings")
+        .arg("--pooling").arg("mean")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    
+    #[cfg(target_os = "windows")]
+    {
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+        cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+    
+    let mut child = cmd.spawn()
+        .map_err(|e| AppError::Sidecar(format!("Failed to start embedding sidecar: {}", e)))?;
+    
+    // Drain output so the pipes never block; embedding servers are chatty
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(async move {
+            use tokio::io::{AsyncBufReadExt, BufReader};
+            let mut reader = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = reader.next_line().await {
+                tracing::trace!("[embedding-server stdout] {:.200}", line);
+            }
+        });
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(async move {
+            use tokio::io::{AsyncBufReadExt, BufReader};
+            let mut reader = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = reader.next_line().await {
+                if line.contains("error") || line.contains("ERROR") {
+                    tracing::warn!("[embedding-server] {:.300}", line);
+                } else {
+                    tracing::trace!("[embedding-server] {:.200}", line);
+                }
+            }
+        });
+    }
+    
+    let handle = SidecarHandle {
+        port,
+        base_url: format!("http://127.0.0.1:{}", port),
+        process: Arc::new(Mutex::new(Some(child))),
+        cancel_token: CancellationToken::new(),
+        detected_stop_tokens: Arc::new(Mutex::new(None)),
+        model_id: model_identity(model_path),
+    };
+    
+    for _ in 0..60 {
+        if health_check(&handle).await {
+            tracing::info!("Embedding sidecar ready ({})", handle.model_id);
+            return Ok(handle);
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+    
+    stop_sidecar(handle).await?;
+    
+    Err(AppError::Sidecar(
+        "Embedding sidecar failed to become healthy within timeout. Check that the model supports embeddings.".to_string()
+    ))
+}
+
 pub async fn stop_sidecar(handle: SidecarHandle) -> AppResult<()> {
     handle.cancel_token.cancel();
     
@@ -567,6 +689,65 @@ pub async fn generate_embedding(handle: &SidecarHandle, text: &str) -> AppResult
     Err(AppError::Llm("Failed to parse embedding response: unknown format".into()))
 }
 
+/// Pull a vector out of one embedding result item.
+/// Handles both `"embedding": [[...]]` (per-token/pooled nesting) and `"embedding": [...]`.
+fn embedding_from_item(item: &serde_json::Value) -> Option<Vec<f32>> {
+    let outer = item.get("embedding")?.as_array()?;
+    let values = match outer.first().and_then(|v| v.as_array()) {
+        Some(inner) => inner,
+        None => outer,
+    };
+    let vector: Vec<f32> = values.iter()
+        .filter_map(|v| v.as_f64().map(|f| f as f32))
+        .collect();
+    if vector.is_empty() { None } else { Some(vector) }
+}
+
+/// Generate embeddings for several texts in one `/embedding` request.
+/// Results are returned in input order.
+pub async fn generate_embeddings_batch(handle: &SidecarHandle, texts: &[String]) -> AppResult<Vec<Vec<f32>>> {
+    if texts.is_empty() {
+        return Ok(Vec::new());
+    }
+    
+    let client = reqwest::Client::new();
+    let url = format!("{}/embedding", handle.base_url);
+    
+    let response = client
+        .post(&url)
+        .json(&serde_json::json!({ "content": texts }))
+        .timeout(std::time::Duration::from_secs(120))
+        .send()
+        .await
+        .map_err(|e| AppError::Llm(format!("Batch embedding request failed: {}", e)))?;
+    
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(AppError::Llm(format!("Batch embedding error ({}): {}", status, error_text)));
+    }
+    
+    let json: serde_json::Value = response.json().await
+        .map_err(|e| AppError::Llm(format!("Invalid JSON in batch embedding response: {}", e)))?;
+    
+    // llama-server returns a top-level array; OpenAI-style servers nest it under "data"
+    let items = json.as_array()
+        .or_else(|| json.get("data").and_then(|d| d.as_array()))
+        .ok_or_else(|| AppError::Llm("Failed to parse batch embedding response: unknown format".into()))?;
+    
+    let mut results: Vec<Option<Vec<f32>>> = vec![None; texts.len()];
+    for (position, item) in items.iter().enumerate() {
+        let index = item.get("index").and_then(|i| i.as_u64()).map(|i| i as usize).unwrap_or(position);
+        if let (Some(slot), Some(vector)) = (results.get_mut(index), embedding_from_item(item)) {
+            *slot = Some(vector);
+        }
+    }
+    
+    results.into_iter()
+        .enumerate()
+        .map(|(i, r)| r.ok_or_else(|| AppError::Llm(format!("Batch embedding response missing item {}", i))))
+        .collect()
+}
 
 #[derive(Debug, Clone)]
 pub enum GenerationEvent {
@@ -12,6 +12,12 @@ pub enum QueueMessage {
     Stop,
 }
 
+pub enum EmbeddingMessage {
+    /// Re-embed vectors produced by another model (or never embedded)
+    Reembed,
+    Stop,
+}
+
 pub enum DownloadMessage {
     Start { id: String },
     Pause { id: String },
@@ -25,8 +31,10 @@ pub struct AppState {
     pub db: Database,
     pub paths: AppPaths,
     sidecar: Arc<RwLock<Option<SidecarHandle>>>,
+    embedding_sidecar: Arc<RwLock<Option<SidecarHandle>>>,
     pub queue_tx: mpsc::Sender<QueueMessage>,
     pub download_tx: mpsc::Sender<DownloadMessage>,
+    pub embedding_tx: mpsc::Sender<EmbeddingMessage>,
     generating: Arc<RwLock<Option<GenerationState>>>,
     shutdown_notify: Arc<Notify>,
 }
@@ -45,14 +53,17 @@ impl AppState {
         paths: AppPaths,
         queue_tx: mpsc::Sender<QueueMessage>,
         download_tx: mpsc::Sender<DownloadMessage>,
+        embedding_tx: mpsc::Sender<EmbeddingMessage>,
         shutdown_notify: Arc<Notify>,
     ) -> Self {
         Self {
             db,
             paths,
             sidecar: Arc::new(RwLock::new(None)),
+            embedding_sidecar: Arc::new(RwLock::new(None)),
             queue_tx,
             download_tx,
+            embedding_tx,
             generating: Arc::new(RwLock::new(None)),
             shutdown_notify,
         }
@@ -72,6 +83,7 @@ impl AppState {
         // Send stop messages to workers
         let _ = self.queue_tx.try_send(QueueMessage::Stop);
         let _ = self.download_tx.try_send(DownloadMessage::Stop);
+        let _ = self.embedding_tx.try_send(EmbeddingMessage::Stop);
     }
     
     pub fn shutdown_signal(&self) -> Arc<Notify> {
@@ -98,6 +110,24 @@ impl AppState {
         self.sidecar.write().take()
     }
     
+    pub fn get_embedding_sidecar(&self) -> Option<SidecarHandle> {
+        self.embedding_sidecar.read().clone()
+    }
+    
+    pub fn set_embedding_sidecar(&self, handle: Option<SidecarHandle>) {
+        *self.embedding_sidecar.write() = handle;
+    }
+    
+    pub fn take_embedding_sidecar(&self) -> Option<SidecarHandle> {
+        self.embedding_sidecar.write().take()
+    }
+    
+    /// Handle to use for embeddings: the dedicated embedding model if one is
+    /// running, otherwise the chat model
+    pub fn embedding_handle(&self) -> Option<SidecarHandle> {
+        self.get_embedding_sidecar().or_else(|| self.get_sidecar())
+    }
+    
     // ==================== Generation State ====================
     
     pub fn is_generating(&self) -> bool {
@@ -0,0 +1,175 @@
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
+use tauri::{AppHandle, Emitter};
+
+use crate::services::EmbeddingService;
+use crate::state::{AppState, EmbeddingMessage};
+
+/// Texts per /embedding request
+const BATCH_SIZE: usize = 16;
+/// Consecutive failed batches before the job gives up until the next trigger
+const MAX_FAILURES: u32 = 5;
+
+pub async fn run(
+    state: AppState,
+    app_handle: AppHandle,
+    mut rx: mpsc::Receiver<EmbeddingMessage>,
+    shutdown: Arc<Notify>,
+) {
+    tracing::info!("Embedding worker started");
+
+    loop {
+        tokio::select! {
+            biased;
+
+            _ = shutdown.notified() => {
+                tracing::info!("Embedding worker received shutdown signal");
+                break;
+            }
+
+            msg = rx.recv() => {
+                match msg {
+                    Some(EmbeddingMessage::Reembed) => {
+                        if !reembed(&state, &app_handle, &mut rx).await {
+                            break;
+                        }
+                    }
+                    Some(EmbeddingMessage::Stop) | None => {
+                        tracing::info!("Embedding worker stopping");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    tracing::info!("Embedding worker stopped");
+}
+
+/// Queue a re-embed if any vectors are missing or came from another model
+pub fn request_reembed_if_stale(state: &AppState) {
+    let Some(embedder) = state.embedding_handle() else {
+        return;
+    };
+
+    match EmbeddingService::count_reembed_targets(&state.db, &embedder.model_id) {
+        Ok(0) => {}
+        Ok(pending) => {
+            tracing::warn!(
+                "{} embeddings are missing or from a different model than {}; re-embedding in background",
+                pending,
+                embedder.model_id
+            );
+            let _ = state.embedding_tx.try_send(EmbeddingMessage::Reembed);
+        }
+        Err(e) => tracing::warn!("Failed to count stale embeddings: {}", e),
+    }
+}
+
+fn emit_progress(app_handle: &AppHandle, model_id: &str, done: usize, remaining: i64) {
+    let _ = app_handle.emit("embeddings:reembed", serde_json::json!({
+        "modelId": model_id,
+        "done": done,
+        "remaining": remaining,
+    }));
+}
+
+/// Re-embed everything that is stale for the current embedding model.
+/// Progress lives in the database (a row is done once it carries the new
+/// model id), so an interrupted run simply picks up where it left off.
+/// Returns false if the worker was asked to stop.
+async fn reembed(
+    state: &AppState,
+    app_handle: &AppHandle,
+    rx: &mut mpsc::Receiver<EmbeddingMessage>,
+) -> bool {
+    match EmbeddingService::delete_orphans(&state.db) {
+        Ok(0) => {}
+        Ok(removed) => tracing::info!("Removed {} orphaned embeddings", removed),
+        Err(e) => tracing::warn!("Failed to remove orphaned embeddings: {}", e),
+    }
+
+    let mut done = 0;
+    let mut failures = 0;
+
+    loop {
+        // Drain control messages; repeated Reembed requests fold into this run
+        while let Ok(msg) = rx.try_recv() {
+            if let EmbeddingMessage::Stop = msg {
+                return false;
+            }
+        }
+
+        // Chat generation has priority over background work
+        if state.is_generating() {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            continue;
+        }
+
+        // Re-read each batch so a model swap mid-run retargets the job
+        let Some(embedder) = state.embedding_handle() else {
+            tracing::info!("No embedding model loaded; pausing re-embed");
+            return true;
+        };
+
+        let targets = match EmbeddingService::reembed_targets(&state.db, &embedder.model_id, BATCH_SIZE) {
+            Ok(t) => t,
+            Err(e) => {
+                tracing::error!("Failed to load re-embed batch: {}", e);
+                return true;
+            }
+        };
+
+        if targets.is_empty() {
+            if done > 0 {
+                tracing::info!("Re-embedded {} entries with {}", done, embedder.model_id);
+            }
+            emit_progress(app_handle, &embedder.model_id, done, 0);
+            return true;
+        }
+
+        let texts: Vec<String> = targets.iter().map(|t| t.text.clone()).collect();
+        let embeddings = match EmbeddingService::generate_batch(&embedder, &texts).await {
+            Ok(e) if e.len() == targets.len() => e,
+            Ok(e) => {
+                tracing::warn!("Embedding batch returned {} vectors for {} texts", e.len(), targets.len());
+                Vec::new()
+            }
+            Err(e) => {
+                tracing::warn!("Embedding batch failed: {}", e);
+                Vec::new()
+            }
+        };
+
+        let mut stored = 0;
+        for (target, embedding) in targets.iter().zip(embeddings.iter()) {
+            match EmbeddingService::store(
+                &state.db,
+                &target.entity_type,
+                &target.entity_id,
+                &target.partition_key,
+                embedding,
+            ) {
+                Ok(()) => stored += 1,
+                Err(e) => tracing::warn!("Failed to store embedding for {} {}: {}", target.entity_type, target.entity_id, e),
+            }
+        }
+        done += stored;
+
+        // Anything not stored stays stale and would be retried immediately
+        if stored < targets.len() {
+            failures += 1;
+            if failures >= MAX_FAILURES {
+                tracing::error!("Re-embed giving up after {} failed batches; will resume on next start", failures);
+                return true;
+            }
+            // Exponential backoff: 2s, 4s, 8s, ...
+            tokio::time::sleep(std::time::Duration::from_secs(1 << failures)).await;
+            continue;
+        }
+        failures = 0;
+
+        let remaining = EmbeddingService::count_reembed_targets(&state.db, &embedder.model_id).unwrap_or(0);
+        emit_progress(app_handle, &embedder.model_id, done, remaining);
+    }
+}
@@ -1,2 +1,3 @@
 pub mod queue_worker;
-pub mod download_worker;
\ No newline at end of file
+pub mod download_worker;
+pub mod embedding_worker;
\ No newline at end of file
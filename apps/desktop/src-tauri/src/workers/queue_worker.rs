@@ -4,7 +4,7 @@ use tauri::{AppHandle, Emitter};
 
 use crate::entities::*;
 use crate::repositories::*;
-use crate::services::{MemoryService, LongTermMemoryService, SummaryService, estimate_tokens};
+use crate::services::{CharacterRevisionService, JobService, MemoryService, estimate_tokens};
 use crate::sidecar::{self, GenerationEvent};
 use crate::state::{AppState, QueueMessage};
 
@@ -18,6 +18,14 @@ pub async fn run(
 ) {
     tracing::info!("Queue worker started");
     
+    if let Err(e) = JobService::recover(&state.db) {
+        tracing::warn!("Failed to recover background jobs: {}", e);
+    }
+    
+    // Background jobs run as their own task so chat requests are never
+    // stuck behind them; a waiting chat task cuts the job short
+    let mut background: Option<BackgroundRun> = None;
+    
     loop {
         tokio::select! {
             biased;
@@ -30,7 +38,10 @@ pub async fn run(
             msg = rx.recv() => {
                 match msg {
                     Some(QueueMessage::Process) => {
-                        process_queue(&state, &app_handle).await;
+                        preempt_background_job(&state, &mut background);
+                        if !is_busy(&background) {
+                            process_queue(&state, &app_handle).await;
+                        }
                     }
                     Some(QueueMessage::Stop) | None => {
                         tracing::info!("Queue worker stopping");
@@ -43,14 +54,93 @@ pub async fn run(
                 if state.check_generation_timeout(GENERATION_TIMEOUT_SECS) {
                     tracing::warn!("Generation timed out after {} seconds", GENERATION_TIMEOUT_SECS);
                 }
-                process_queue(&state, &app_handle).await;
+                preempt_background_job(&state, &mut background);
+                // A job still running means no chat task is waiting, and the
+                // health check shouldn't race its generation
+                if !is_busy(&background) {
+                    process_queue(&state, &app_handle).await;
+                }
+                start_background_job(&state, &mut background);
             }
         }
     }
     
+    if let Some(run) = background.take() {
+        run.cancel(&state);
+    }
     tracing::info!("Queue worker stopped");
 }
 
+/// A background job running on its own task
+struct BackgroundRun {
+    job_id: String,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl BackgroundRun {
+    /// Stop the job and put it back in the queue
+    fn cancel(self, state: &AppState) {
+        self.handle.abort();
+        if let Err(e) = JobService::release(&state.db, &self.job_id) {
+            tracing::warn!("Failed to requeue background job {}: {}", self.job_id, e);
+        }
+    }
+}
+
+fn is_busy(background: &Option<BackgroundRun>) -> bool {
+    background.as_ref().is_some_and(|run| !run.handle.is_finished())
+}
+
+/// Cancel the running background job if a chat task is waiting for the model
+fn preempt_background_job(state: &AppState, background: &mut Option<BackgroundRun>) {
+    if !is_busy(background) {
+        return;
+    }
+    if matches!(QueueRepo::get_next_pending(&state.db), Ok(Some(_))) {
+        if let Some(run) = background.take() {
+            tracing::info!("Pausing background job {} for a chat request", run.job_id);
+            run.cancel(state);
+        }
+    }
+}
+
+/// Start one due background job, but only while the model is idle and no chat task is waiting
+fn start_background_job(state: &AppState, background: &mut Option<BackgroundRun>) {
+    if is_busy(background) {
+        return;
+    }
+    *background = None;
+    
+    let Some(sidecar) = state.get_sidecar() else {
+        return;
+    };
+    if state.is_generating() {
+        return;
+    }
+    if !matches!(QueueRepo::get_next_pending(&state.db), Ok(None)) {
+        return;
+    }
+    
+    let job = match JobService::claim_next(&state.db) {
+        Ok(Some(job)) => job,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::warn!("Background job error: {}", e);
+            return;
+        }
+    };
+    
+    let embedder = state.embedding_handle().unwrap_or_else(|| sidecar.clone());
+    let job_state = state.clone();
+    let job_id = job.id.clone();
+    let handle = tokio::spawn(async move {
+        if let Err(e) = JobService::run(&job_state.db, &sidecar, &embedder, &job).await {
+            tracing::warn!("Background job error: {}", e);
+        }
+    });
+    *background = Some(BackgroundRun { job_id, handle });
+}
+
 async fn process_queue(state: &AppState, app_handle: &AppHandle) {
     // Check if model is loaded
     let sidecar = match state.get_sidecar() {
@@ -130,9 +220,12 @@ async fn process_queue(state: &AppState, app_handle: &AppHandle) {
         }
     };
     
+    // Embeddings come from the dedicated embedding model when one is loaded
+    let embedder = state.embedding_handle().unwrap_or_else(|| sidecar.clone());
+    
     let context = match MemoryService::build_context_async(
         &state.db,
-        &sidecar,
+        &embedder,
         &task.conversation_id,
         settings.generation.context_size
     ).await {
@@ -143,6 +236,14 @@ async fn process_queue(state: &AppState, app_handle: &AppHandle) {
         }
     };
     
+    let character_revision = match CharacterRevisionService::current(&state.db, &character) {
+        Ok(revision) => Some(revision),
+        Err(e) => {
+            tracing::warn!("Failed to record character revision: {}", e);
+            None
+        }
+    };
+    
     // Create placeholder message
     let message_id = new_id();
     let message = Message {
@@ -165,7 +266,12 @@ async fn process_queue(state: &AppState, app_handle: &AppHandle) {
             "top_p": settings.generation.top_p,
         })),
         created_at: now_timestamp(),
-        metadata: serde_json::Value::Object(Default::default()),
+        // Kept with the reply so the UI can show which lore and which
+        // version of the character it used
+        metadata: serde_json::json!({
+            "loreTrace": context.lore_trace,
+            "characterRevision": character_revision,
+        }),
         author_name: Some(character.name.clone()),
         sibling_count: None,
     };
@@ -178,6 +284,14 @@ async fn process_queue(state: &AppState, app_handle: &AppHandle) {
     // Update conversation active message
     let _ = ConversationRepo::update_active_message(&state.db, &task.conversation_id, &message_id);
     
+    if !context.lore_trace.is_empty() {
+        let _ = app_handle.emit("lorebook:activated", LorebookActivatedEvent {
+            conversation_id: task.conversation_id.clone(),
+            message_id: message_id.clone(),
+            entries: context.lore_trace.clone(),
+        });
+    }
+    
     // Atomically try to start generation - prevents race condition
     let cancel_token = match state.try_start_generation(message_id.clone(), task.conversation_id.clone()) {
         Some(token) => token,
@@ -197,7 +311,7 @@ async fn process_queue(state: &AppState, app_handle: &AppHandle) {
     
     // Extract previous character messages for repetition detection
     let previous_character_messages: Vec<String> = context.messages.iter()
-        .filter(|m| m.author_type == AuthorType::Character)
+        .filter(|m| m.author_type == AuthorType::Character && !crate::services::examples::is_example(m))
         .map(|m| m.content.clone())
         .collect();
     
@@ -221,14 +335,11 @@ async fn process_queue(state: &AppState, app_handle: &AppHandle) {
     
     match generation_result {
         Ok(full_content) => {
-            // Replace placeholders in response with actual names
-            let processed_content = full_content
-                .replace("{{user}}", &context.persona_name)
-                .replace("{{User}}", &context.persona_name)
-                .replace("{{char}}", &context.character_name)
-                .replace("{{Char}}", &context.character_name)
-                .replace("<user>", &context.persona_name)
-                .replace("<char>", &context.character_name);
+            // Replace placeholders in response with actual names; model
+            // output never runs other macros
+            let processed_content = crate::services::macros::replace_names(
+                &full_content, &context.character_name, &context.persona_name,
+            );
             
             // Update message with full content
             let token_count = estimate_tokens(&processed_content);
@@ -247,53 +358,25 @@ async fn process_queue(state: &AppState, app_handle: &AppHandle) {
                 });
             }
             
-            // Trigger summarization if needed (non-blocking)
-            let db_for_summary = state.db.clone();
-            let sidecar_for_summary = sidecar.clone();
-            let conv_id_for_summary = task.conversation_id.clone();
-            tokio::spawn(async move {
-                if let Err(e) = SummaryService::maybe_summarize(
-                    &db_for_summary,
-                    &sidecar_for_summary,
-                    &conv_id_for_summary,
-                    20,   // Summarize every 20 messages
-                    4000, // Or every 4000 tokens
-                ).await {
-                    tracing::warn!("Summarization failed: {}", e);
-                }
-            });
-
-            // Extract memories from BOTH user (parent) and character (current) messages
-            let messages_to_process = vec![
-                task.parent_message_id.clone(),
-                Some(message_id.clone()),
-            ];
+            // Summarization and memory extraction run later as background jobs,
+            // once no chat generation needs the model
+            if let Err(e) = JobService::enqueue_summarization(&state.db, &task.conversation_id) {
+                tracing::warn!("Failed to queue summarization: {}", e);
+            }
             
-            for msg_id_opt in messages_to_process {
-                if let Some(msg_id) = msg_id_opt {
-                     let db_clone = state.db.clone();
-                     let sidecar_clone = sidecar.clone();
-                     let msg_id_clone = msg_id.clone();
-                     let character_id_clone = character.id.clone();
-                     let conversation_id_clone = task.conversation_id.clone();
-                     
-                     tokio::spawn(async move {
-                        if let Ok(msg) = MessageRepo::find_by_id(&db_clone, &msg_id_clone) {
-                            tracing::info!("Starting memory extraction for message {}", msg.id);
-                            if let Err(e) = LongTermMemoryService::process_message(
-                                &db_clone,
-                                &sidecar_clone,
-                                &msg.content,
-                                &character_id_clone,
-                                &conversation_id_clone,
-                                &msg.id
-                            ).await {
-                                tracing::warn!("Memory extraction failed: {}", e);
-                            }
-                        }
-                     });
-                 }
-             }
+            // Extract memories from BOTH user (parent) and character (current) messages
+            let messages_to_process: Vec<String> = [task.parent_message_id.clone(), Some(message_id.clone())]
+                .into_iter()
+                .flatten()
+                .collect();
+            if let Err(e) = JobService::enqueue_memory_extraction(
+                &state.db,
+                &task.conversation_id,
+                &character.id,
+                &messages_to_process,
+            ) {
+                tracing::warn!("Failed to queue memory extraction: {}", e);
+            }
             
             tracing::info!("Task {} completed successfully", task.id);
         }
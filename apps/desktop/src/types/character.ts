@@ -34,6 +34,9 @@ export interface Character {
   genreTags: string[];
 }
 
+// What changed a character, as recorded on its revisions
+export type RevisionSource = 'manual' | 'import' | 'generated' | 'restore';
+
 export interface CreateCharacterInput {
   name: string;
   description?: string;
@@ -62,6 +65,9 @@ export interface CreateCharacterInput {
   povType?: string;
   rating?: string;
   genreTags?: string[];
+
+  // Set on generated characters so their first revision says so
+  source?: RevisionSource;
 }
 
 export interface UpdateCharacterInput {